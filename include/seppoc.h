@@ -0,0 +1,48 @@
+/* C interface to the seppolang compiler. Build the library with
+ *
+ *     cargo rustc --lib --release --features capi --crate-type cdylib
+ *
+ * and link against target/release/libseppolang.so (.dylib on macOS). */
+#ifndef SEPPOC_H
+#define SEPPOC_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SEPPO_OK 0
+#define SEPPO_ERROR 1
+
+#define SEPPO_EMIT_EXE 0
+#define SEPPO_EMIT_OBJ 1
+#define SEPPO_EMIT_LLVM_IR 2
+
+typedef struct SeppoOptions {
+    /* Output path without extension, "main" when NULL */
+    const char *output;
+    /* One of the SEPPO_EMIT_* constants */
+    int32_t emit;
+    /* 0 to 3 like -O, or -1 for the default of 2 */
+    int32_t opt_level;
+    /* Target triple, the host when NULL */
+    const char *target;
+    /* Nonzero to reject ceppo blocks and external programs, as with
+     * --no-extern */
+    int32_t no_extern;
+} SeppoOptions;
+
+/* Compile seppo source. options may be NULL for the defaults. Returns
+ * SEPPO_OK, or SEPPO_ERROR with the diagnostic stored in *out_err when
+ * out_err is not NULL, to be released with seppo_string_free. */
+int32_t seppo_compile(const char *source, const SeppoOptions *options, char **out_err);
+
+/* Release a string returned by seppo_compile. NULL is ignored. */
+void seppo_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,114 @@
+use crate::error::Result;
+use crate::jit::Jit;
+use crate::testing::functions_with_prefix;
+use crate::types::SeppoExpr;
+use inkwell::context::Context;
+use std::time::Duration;
+
+/// Functions whose name starts with this are run by `seppoc bench`
+pub const BENCH_PREFIX: &str = "bench_";
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Samples run and thrown away before measuring
+    pub warmup_samples: usize,
+    pub samples: usize,
+    /// Each sample repeats the function until it takes about this long
+    pub sample_time: Duration,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            warmup_samples: 3,
+            samples: 20,
+            sample_time: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Per-iteration wall time of one benchmark, in nanoseconds
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub name: String,
+    pub iterations_per_sample: u64,
+    pub median: f64,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub std_dev: f64,
+}
+
+/// Names of the benchmark functions in a program, in source order
+pub fn bench_functions(program: &SeppoExpr) -> Vec<String> {
+    functions_with_prefix(program, BENCH_PREFIX)
+}
+
+/// Compile a program with the JIT and measure its benchmarks whose name
+/// contains `filter`, or all of them. The timing loop is generated code, so
+/// the measurement doesn't include the cost of calling into the JIT.
+pub fn run_benches(
+    program: &SeppoExpr,
+    filter: Option<&str>,
+    options: &BenchOptions,
+) -> Result<Vec<BenchResult>> {
+    let names: Vec<String> = bench_functions(program)
+        .into_iter()
+        .filter(|name| filter.is_none_or(|filter| name.contains(filter)))
+        .collect();
+
+    let context = Context::create();
+    let mut codegen = Jit::codegen(&context, program)?;
+    let harnesses = names
+        .iter()
+        .map(|name| codegen.add_bench_harness(name))
+        .collect::<Result<Vec<_>>>()?;
+    let jit = Jit::from_codegen(codegen)?;
+
+    names
+        .into_iter()
+        .zip(harnesses)
+        .map(|(name, harness)| measure(&jit, name, &harness, options))
+        .collect()
+}
+
+fn measure(jit: &Jit, name: String, harness: &str, options: &BenchOptions) -> Result<BenchResult> {
+    // Double the iteration count until one sample takes long enough that
+    // timer resolution doesn't matter
+    let target = options.sample_time.as_nanos() as i64;
+    let mut iterations: i64 = 1;
+    while jit.call(harness, &[iterations])? < target && iterations < 1 << 40 {
+        iterations *= 2;
+    }
+
+    for _ in 0..options.warmup_samples {
+        jit.call(harness, &[iterations])?;
+    }
+
+    let mut samples = Vec::with_capacity(options.samples);
+    for _ in 0..options.samples.max(1) {
+        let elapsed = jit.call(harness, &[iterations])?;
+        samples.push(elapsed as f64 / iterations as f64);
+    }
+    samples.sort_by(f64::total_cmp);
+
+    let count = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / count;
+    let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / count;
+    let mid = samples.len() / 2;
+    let median = if samples.len() % 2 == 0 {
+        (samples[mid - 1] + samples[mid]) / 2.0
+    } else {
+        samples[mid]
+    };
+
+    Ok(BenchResult {
+        name,
+        iterations_per_sample: iterations as u64,
+        median,
+        mean,
+        min: samples[0],
+        max: samples[samples.len() - 1],
+        std_dev: variance.sqrt(),
+    })
+}
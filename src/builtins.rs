@@ -0,0 +1,206 @@
+use crate::error::{Result, SeppoError};
+use crate::types::SeppoExpr;
+
+/// Functions every program can call without defining them, with their
+/// number of arguments. A function of the same name defined by the program
+/// takes precedence.
+///
+/// - `len(s)`: length of string `s` in bytes
+/// - `substr(s, start, len)`: a new string with up to `len` bytes of `s`
+///   from byte offset `start`, clamped to the string
+/// - `char_at(s, i)`: the byte at offset `i`, or -1 when out of range
+/// - `spawn(f, arg)`: run `f(arg)` in a new thread, where `f` names a
+///   function taking one argument, giving a handle for `join`
+/// - `join(handle)`: wait for a spawned thread to finish and give what its
+///   function returned
+/// - `mutex()`, `lock(m)`, `unlock(m)`: a new mutex, and locking and
+///   unlocking it
+/// - `condvar()`, `wait(c, m)`, `notify(c)`: a new condition variable,
+///   waiting on it with mutex `m` locked, and waking every thread waiting
+///   on it
+/// - `sleep_ms(n)`: sleep for `n` milliseconds
+/// - `yield()`: let other threads run
+/// - `alloc(n)`, `realloc(p, n)`, `free(p)`: malloc, realloc and free,
+///   with addresses as integers
+/// - `load(p)`, `store(p, v)`: read and write the 64-bit integer at `p`
+/// - `atomic(v)`: a new 64-bit integer starting at `v`, for the atomic
+///   operations, which also take other addresses of 64-bit integers
+/// - `atomic_load(a)`, `atomic_store(a, v)`, `atomic_add(a, n)`: read,
+///   write and add to the integer at `a`, `atomic_add` giving the value
+///   before the add
+/// - `cas(a, expected, new)`: store `new` at `a` if it holds `expected`,
+///   giving what it held before
+/// - `dlopen(path)`, `dlsym(lib, name)`: load a shared library and look up
+///   a symbol in it, giving 0 when they fail
+/// - `call(f, args...)`: call the function at address `f` with any number
+///   of arguments, as a C function taking and returning 64-bit integers
+/// - `flush()`: write out what is buffered for stdout and stderr
+/// - `Some(v)`: an Option holding `v`, and `None` one holding nothing
+/// - `unwrap(o)`, `unwrap_or(o, v)`: the value Option `o` holds, panicking
+///   or giving `v` when it is `None`
+/// - `is_some(o)`, `is_none(o)`: whether Option `o` holds a value, as bools
+/// - `i64x4(a, b, c, d)`: a vector of four 64-bit integers
+/// - `lane(v, i)`: element `i` of vector `v`
+/// - `shuffle(a, b, i, j, k, l)`: a vector of the elements `i`, `j`, `k`
+///   and `l` of `a` followed by `b`, counting from 0
+/// - `load_i64x4(p)`, `store_i64x4(p, v)`: read and write the four 64-bit
+///   integers at `p`
+///
+/// `sizeof` and `typeof` are replaced by constants before these are
+/// checked, see `introspect`, and `include_str` by the file it names, see
+/// `include`.
+pub(crate) const BUILTINS: &[(&str, usize)] = &[
+    ("len", 1),
+    ("substr", 3),
+    ("char_at", 2),
+    ("spawn", 2),
+    ("join", 1),
+    ("mutex", 0),
+    ("lock", 1),
+    ("unlock", 1),
+    ("condvar", 0),
+    ("wait", 2),
+    ("notify", 1),
+    ("sleep_ms", 1),
+    ("yield", 0),
+    ("atomic", 1),
+    ("atomic_load", 1),
+    ("atomic_store", 2),
+    ("atomic_add", 2),
+    ("cas", 3),
+    ("alloc", 1),
+    ("realloc", 2),
+    ("free", 1),
+    ("load", 1),
+    ("store", 2),
+    ("dlopen", 1),
+    ("dlsym", 2),
+    // At least the function
+    ("call", 1),
+    ("flush", 0),
+    ("Some", 1),
+    ("unwrap", 1),
+    ("unwrap_or", 2),
+    ("is_some", 1),
+    ("is_none", 1),
+    ("i64x4", 4),
+    ("lane", 2),
+    ("shuffle", 6),
+    ("load_i64x4", 1),
+    ("store_i64x4", 2),
+];
+
+/// The empty Option, a name rather than a call
+pub(crate) const NONE: &str = "None";
+
+/// Builtins that take more arguments than their count in `BUILTINS`
+const VARIADIC_BUILTINS: &[&str] = &["call"];
+
+/// The builtins for threads and scheduling, which need POSIX rather than
+/// just libc
+const THREAD_BUILTINS: &[&str] = &[
+    "spawn", "join", "mutex", "lock", "unlock", "condvar", "wait", "notify", "sleep_ms", "yield",
+];
+
+pub(crate) fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|(builtin, _)| *builtin == name)
+}
+
+const ATOMIC_BUILTINS: &[&str] = &["atomic", "atomic_load", "atomic_store", "atomic_add", "cas"];
+
+const MEMORY_BUILTINS: &[&str] = &["alloc", "realloc", "free", "load", "store"];
+
+const DYNAMIC_BUILTINS: &[&str] = &["dlopen", "dlsym", "call"];
+
+const IO_BUILTINS: &[&str] = &["flush"];
+
+/// The builtins making and taking apart Options, whose first argument the
+/// type checker requires to be one, except for `Some`
+const OPTION_BUILTINS: &[&str] = &["Some", "unwrap", "unwrap_or", "is_some", "is_none"];
+
+/// The builtins making, taking apart and moving vectors
+const VECTOR_BUILTINS: &[&str] = &["i64x4", "lane", "shuffle", "load_i64x4", "store_i64x4"];
+
+/// Elements of an `i64x4`
+pub(crate) const LANES: u32 = 4;
+
+pub(crate) fn is_thread_builtin(name: &str) -> bool {
+    THREAD_BUILTINS.contains(&name)
+}
+
+pub(crate) fn is_atomic_builtin(name: &str) -> bool {
+    ATOMIC_BUILTINS.contains(&name)
+}
+
+pub(crate) fn is_memory_builtin(name: &str) -> bool {
+    MEMORY_BUILTINS.contains(&name)
+}
+
+pub(crate) fn is_dynamic_builtin(name: &str) -> bool {
+    DYNAMIC_BUILTINS.contains(&name)
+}
+
+pub(crate) fn is_io_builtin(name: &str) -> bool {
+    IO_BUILTINS.contains(&name)
+}
+
+pub(crate) fn is_option_builtin(name: &str) -> bool {
+    OPTION_BUILTINS.contains(&name)
+}
+
+pub(crate) fn is_vector_builtin(name: &str) -> bool {
+    VECTOR_BUILTINS.contains(&name)
+}
+
+/// The element numbers of a call to `lane` or `shuffle`, which have to be
+/// literals, as LLVM takes them as constants: below `LANES` for `lane`,
+/// and below twice that for `shuffle`, which picks from two vectors
+pub(crate) fn lanes(name: &str, args: &[SeppoExpr]) -> Result<Vec<u32>> {
+    check_arity(name, args.len())?;
+    let (skip, limit) = match name {
+        "lane" => (1, LANES),
+        _ => (2, LANES * 2),
+    };
+    args[skip..]
+        .iter()
+        .map(|arg| match arg {
+            SeppoExpr::Number(n) if (0..limit as i64).contains(n) => Ok(*n as u32),
+            _ => Err(SeppoError::Sema(format!(
+                "{} takes element numbers from 0 to {} as literals",
+                name,
+                limit - 1
+            ))),
+        })
+        .collect()
+}
+
+/// Check the argument count of a call to builtin `name`
+pub(crate) fn check_arity(name: &str, args: usize) -> Result<()> {
+    match BUILTINS.iter().find(|(builtin, _)| *builtin == name) {
+        Some((_, arity)) if VARIADIC_BUILTINS.contains(&name) && args < *arity => {
+            Err(SeppoError::Sema(format!(
+                "Builtin {} expects at least {} arguments, got {}",
+                name, arity, args
+            )))
+        }
+        Some((_, arity)) if *arity != args && !VARIADIC_BUILTINS.contains(&name) => {
+            Err(SeppoError::Sema(format!(
+                "Builtin {} expects {} arguments, got {}",
+                name, arity, args
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The name of the function a call to `spawn` runs, its first argument
+pub(crate) fn spawned_function(args: &[SeppoExpr]) -> Result<&str> {
+    check_arity("spawn", args.len())?;
+    match &args[0] {
+        SeppoExpr::Variable(name) => Ok(name),
+        _ => Err(SeppoError::Sema(SPAWN_NEEDS_FUNCTION.to_string())),
+    }
+}
+
+pub(crate) const SPAWN_NEEDS_FUNCTION: &str =
+    "The first argument of spawn must be the name of a function";
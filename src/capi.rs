@@ -0,0 +1,137 @@
+use crate::diagnostic::Diagnostic;
+use crate::driver::{Driver, Input, OutputKind};
+use crate::error::{Result, SeppoError};
+use crate::target::TargetSpec;
+use inkwell::OptimizationLevel;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+pub const SEPPO_OK: i32 = 0;
+pub const SEPPO_ERROR: i32 = 1;
+
+pub const SEPPO_EMIT_EXE: i32 = 0;
+pub const SEPPO_EMIT_OBJ: i32 = 1;
+pub const SEPPO_EMIT_LLVM_IR: i32 = 2;
+
+/// `SeppoOptions` of `include/seppoc.h`, the C interface for tools that
+/// compile in-process instead of running `seppoc`. Null strings pick the
+/// same defaults as `seppoc build`.
+#[repr(C)]
+pub struct SeppoOptions {
+    /// Output path without extension, `main` when null
+    pub output: *const c_char,
+    /// One of the `SEPPO_EMIT_*` constants
+    pub emit: i32,
+    /// 0 to 3 like `-O`, or -1 for the default of 2
+    pub opt_level: i32,
+    /// Target triple, the host when null
+    pub target: *const c_char,
+    /// Nonzero to reject ceppo blocks and external programs, as with
+    /// `--no-extern`
+    pub no_extern: i32,
+}
+
+/// Compile the NUL-terminated seppo `source`. Returns `SEPPO_OK`, or
+/// `SEPPO_ERROR` with the rendered diagnostic stored in `*out_err` when
+/// `out_err` is not null, to be released with `seppo_string_free`.
+///
+/// # Safety
+///
+/// `source` and the strings in `options` must be valid NUL-terminated
+/// strings, and `options` null or a valid `SeppoOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn seppo_compile(
+    source: *const c_char,
+    options: *const SeppoOptions,
+    out_err: *mut *mut c_char,
+) -> i32 {
+    if !out_err.is_null() {
+        *out_err = std::ptr::null_mut();
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| compile(source, options.as_ref())));
+    let message = match result {
+        Ok(Ok(())) => return SEPPO_OK,
+        Ok(Err(message)) => message,
+        Err(_) => "error: internal compiler error, please file a bug report\n".to_string(),
+    };
+    if !out_err.is_null() {
+        // Diagnostics don't contain NUL bytes, but never fail to report one
+        let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+        *out_err = message.into_raw();
+    }
+    SEPPO_ERROR
+}
+
+/// Release a string returned by `seppo_compile`. Null is ignored.
+///
+/// # Safety
+///
+/// `string` must come from `seppo_compile` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn seppo_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Run the compiler, rendering any error as a diagnostic that quotes the
+/// source when it points into it
+unsafe fn compile(
+    source: *const c_char,
+    options: Option<&SeppoOptions>,
+) -> std::result::Result<(), String> {
+    let render = |error: SeppoError, source: Option<&str>| {
+        Diagnostic::from_error(&error).render(source, false)
+    };
+    let text = string_arg(source, "source")
+        .and_then(|text| text.ok_or_else(|| config("source must not be null")))
+        .map_err(|e| render(e, None))?;
+    let driver = driver(&text, options).map_err(|e| render(e, None))?;
+    driver.run().map(|_| ()).map_err(|e| render(e, Some(&text)))
+}
+
+unsafe fn driver(text: &str, options: Option<&SeppoOptions>) -> Result<Driver> {
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: text.to_string(),
+    }]);
+    driver.dump_ir = false;
+    let Some(options) = options else {
+        return Ok(driver);
+    };
+
+    driver.output = string_arg(options.output, "output")?.map(PathBuf::from);
+    driver.output_kind = match options.emit {
+        SEPPO_EMIT_EXE => OutputKind::Executable,
+        SEPPO_EMIT_OBJ => OutputKind::Object,
+        SEPPO_EMIT_LLVM_IR => OutputKind::LlvmIr,
+        other => return Err(config(&format!("Unknown emit kind {}", other))),
+    };
+    driver.opt_level = match options.opt_level {
+        0 => OptimizationLevel::None,
+        1 => OptimizationLevel::Less,
+        -1 | 2 => OptimizationLevel::Default,
+        3 => OptimizationLevel::Aggressive,
+        other => return Err(config(&format!("Unknown optimization level {}", other))),
+    };
+    if let Some(triple) = string_arg(options.target, "target")? {
+        driver.target = TargetSpec::from_triple(&triple)?;
+    }
+    driver.no_extern = options.no_extern != 0;
+    Ok(driver)
+}
+
+unsafe fn string_arg(string: *const c_char, name: &str) -> Result<Option<String>> {
+    if string.is_null() {
+        return Ok(None);
+    }
+    match CStr::from_ptr(string).to_str() {
+        Ok(string) => Ok(Some(string.to_string())),
+        Err(_) => Err(config(&format!("{} is not valid UTF-8", name))),
+    }
+}
+
+fn config(message: &str) -> SeppoError {
+    SeppoError::Config(message.to_string())
+}
@@ -0,0 +1,333 @@
+use crate::target::TargetSpec;
+
+/// A C type as far as calls from seppo care about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CType {
+    Void,
+    Int {
+        bits: u32,
+        signed: bool,
+    },
+    Pointer,
+    /// Floating point, structs passed by value and types we don't know,
+    /// spelled as in the source
+    Unsupported(String),
+}
+
+impl CType {
+    pub(crate) fn is_supported(&self) -> bool {
+        !matches!(self, CType::Unsupported(_))
+    }
+}
+
+/// A function a ceppo block defines or declares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CSignature {
+    pub(crate) name: String,
+    pub(crate) ret: CType,
+    pub(crate) params: Vec<CType>,
+    /// Ends in `...` and takes any number of further arguments
+    pub(crate) variadic: bool,
+}
+
+/// The functions of a ceppo block that seppo code can call: definitions and
+/// prototypes at the top level, except `static` ones, which don't link.
+/// Types are sized for `target`, where `long` and `size_t` differ.
+///
+/// This is not a C parser. It skips comments, the preprocessor and
+/// literals, and reads the declarator of each top-level `name(...)` that
+/// is followed by a body or a semicolon, which covers the usual C.
+pub(crate) fn parse_signatures(code: &str, target: &TargetSpec) -> Vec<CSignature> {
+    let model = DataModel::for_target(target);
+    let mut signatures = Vec::new();
+    let mut decl: Vec<String> = Vec::new();
+    let mut depth = 0usize;
+    for token in tokenize(code) {
+        match token.as_str() {
+            "{" => {
+                if depth == 0 {
+                    signatures.extend(parse_declaration(&decl, &model));
+                    decl.clear();
+                }
+                depth += 1;
+            }
+            "}" => depth = depth.saturating_sub(1),
+            ";" if depth == 0 => {
+                signatures.extend(parse_declaration(&decl, &model));
+                decl.clear();
+            }
+            _ if depth == 0 => decl.push(token),
+            _ => {}
+        }
+    }
+    signatures
+}
+
+/// Sizes of the C integer types that depend on the target
+struct DataModel {
+    long_bits: u32,
+    pointer_bits: u32,
+}
+
+impl DataModel {
+    fn for_target(target: &TargetSpec) -> Self {
+        let triple = target.triple();
+        let pointer_bits = if triple.starts_with("wasm32")
+            || triple.starts_with("i386")
+            || triple.starts_with("i686")
+            || triple.starts_with("arm-")
+            || triple.starts_with("armv7")
+        {
+            32
+        } else {
+            64
+        };
+        // 64-bit Windows keeps `long` at 32 bits
+        let long_bits = if triple.contains("windows") {
+            32
+        } else {
+            pointer_bits
+        };
+        Self {
+            long_bits,
+            pointer_bits,
+        }
+    }
+}
+
+/// Identifiers, numbers and punctuation, with comments, preprocessor lines
+/// and the contents of literals left out
+fn tokenize(code: &str) -> Vec<String> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line_start = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '#' && line_start {
+            // Directives run to the end of the line, unless continued
+            while i < chars.len() && chars[i] != '\n' {
+                if chars[i] == '\\' && chars.get(i + 1) == Some(&'\n') {
+                    i += 1;
+                }
+                i += 1;
+            }
+            continue;
+        }
+        line_start = false;
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push("literal".to_string());
+        } else if c.is_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else if c == '.' && chars.get(i + 1) == Some(&'.') && chars.get(i + 2) == Some(&'.') {
+            tokens.push("...".to_string());
+            i += 3;
+        } else {
+            tokens.push(c.to_string());
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// The function a top-level declaration declares, if it declares one
+fn parse_declaration(decl: &[String], model: &DataModel) -> Option<CSignature> {
+    let decl = without_attributes(decl);
+    if decl.iter().any(|t| t == "static" || t == "typedef") {
+        return None;
+    }
+    let open = decl.iter().position(|t| t == "(")?;
+    let close = matching_paren(&decl, open)?;
+    // Anything after the parameter list, like `= ...` or a second list for
+    // a function pointer, is not a plain function
+    if close + 1 != decl.len() || open < 2 {
+        return None;
+    }
+    let name = &decl[open - 1];
+    if !is_identifier(name) {
+        return None;
+    }
+
+    let mut params = Vec::new();
+    let mut variadic = false;
+    let inner = &decl[open + 1..close];
+    if !(inner.is_empty() || inner == ["void"]) {
+        for param in split_params(inner) {
+            if param == ["..."] {
+                variadic = true;
+            } else {
+                params.push(parse_type(param, true, model));
+            }
+        }
+    }
+    Some(CSignature {
+        name: name.clone(),
+        ret: parse_type(&decl[..open - 1], false, model),
+        params,
+        variadic,
+    })
+}
+
+/// Drop `__attribute__((...))` and `__declspec(...)`
+fn without_attributes(decl: &[String]) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < decl.len() {
+        if matches!(decl[i].as_str(), "__attribute__" | "__declspec")
+            && decl.get(i + 1).is_some_and(|t| t == "(")
+        {
+            i = matching_paren(decl, i + 1).map_or(decl.len(), |end| end + 1);
+            continue;
+        }
+        tokens.push(decl[i].clone());
+        i += 1;
+    }
+    tokens
+}
+
+fn matching_paren(tokens: &[String], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token.as_str() {
+            "(" => depth += 1,
+            ")" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_params(tokens: &[String]) -> Vec<&[String]> {
+    let mut params = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.as_str() {
+            "(" | "[" => depth += 1,
+            ")" | "]" => depth -= 1,
+            "," if depth == 0 => {
+                params.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&tokens[start..]);
+    params
+}
+
+fn is_identifier(token: &str) -> bool {
+    token
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+}
+
+const QUALIFIERS: &[&str] = &[
+    "const",
+    "volatile",
+    "restrict",
+    "__restrict",
+    "__restrict__",
+    "extern",
+    "inline",
+    "__inline",
+    "__inline__",
+    "register",
+    "_Noreturn",
+];
+
+const BASIC_TYPES: &[&str] = &[
+    "void", "char", "short", "int", "long", "signed", "unsigned", "float", "double", "_Bool",
+];
+
+/// The type of a return value, or of a parameter, which may be named
+fn parse_type(tokens: &[String], is_param: bool, model: &DataModel) -> CType {
+    if tokens.iter().any(|t| t == "*" || t == "[" || t == "(") {
+        return CType::Pointer;
+    }
+    let mut words: Vec<&str> = tokens
+        .iter()
+        .map(String::as_str)
+        .filter(|t| !QUALIFIERS.contains(t))
+        .collect();
+    if is_param && words.len() > 1 && !BASIC_TYPES.contains(words.last().unwrap()) {
+        words.pop();
+    }
+    let spelling = words.join(" ");
+    let int = |bits, signed| CType::Int { bits, signed };
+    match words.as_slice() {
+        ["void"] => return CType::Void,
+        ["_Bool" | "bool"] => return int(8, false),
+        ["int8_t"] => return int(8, true),
+        ["uint8_t"] => return int(8, false),
+        ["int16_t"] => return int(16, true),
+        ["uint16_t"] => return int(16, false),
+        ["int32_t"] => return int(32, true),
+        ["uint32_t"] => return int(32, false),
+        ["int64_t" | "intmax_t"] => return int(64, true),
+        ["uint64_t" | "uintmax_t"] => return int(64, false),
+        ["ssize_t" | "ptrdiff_t" | "intptr_t"] => return int(model.pointer_bits, true),
+        ["size_t" | "uintptr_t"] => return int(model.pointer_bits, false),
+        _ => {}
+    }
+    if words.is_empty()
+        || !words.iter().all(|w| {
+            matches!(
+                *w,
+                "char" | "short" | "int" | "long" | "signed" | "unsigned"
+            )
+        })
+    {
+        return CType::Unsupported(spelling);
+    }
+    let longs = words.iter().filter(|w| **w == "long").count() as u32;
+    let bits = if words.contains(&"char") {
+        8
+    } else if words.contains(&"short") {
+        16
+    } else if longs >= 2 {
+        64
+    } else if longs == 1 {
+        model.long_bits
+    } else {
+        32
+    };
+    int(bits, !words.contains(&"unsigned"))
+}
@@ -1,105 +1,1278 @@
+use crate::builtins;
+use crate::cdecl::{parse_signatures, CSignature, CType};
+use crate::coverage::{COVERAGE_FILE_VAR, DEFAULT_COVERAGE_FILE};
+use crate::debuginfo::DebugInfo;
+use crate::error::{Result, SeppoError};
+use crate::escape::{promote_allocations, STACK_ALLOC};
+use crate::ice;
+use crate::inline::inline_functions;
+use crate::jit::hot_symbol;
+use crate::manifest::fnv1a;
+use crate::sandbox;
+use crate::sema::{check_entry, undefined};
+use crate::target::TargetSpec;
+use crate::temps::TempArtifacts;
+use crate::timing::{self, Phase};
 use crate::types::*;
-use anyhow::{anyhow, Result};
+use inkwell::attributes::{Attribute, AttributeLoc};
+use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
-use inkwell::targets::{CodeModel, FileType, RelocMode, Target, TargetMachine};
-use inkwell::values::{BasicValue, FunctionValue, IntValue, PointerValue};
-use std::collections::HashMap;
-use std::env;
+use inkwell::debug_info::DISubprogram;
+use inkwell::intrinsics::Intrinsic;
+use inkwell::llvm_sys::core::{LLVMMDNodeInContext2, LLVMMetadataAsValue, LLVMValueAsMetadata};
+use inkwell::llvm_sys::debuginfo::{LLVMMetadataReplaceAllUsesWith, LLVMTemporaryMDNode};
+use inkwell::module::{FlagBehavior, Linkage, Module};
+use inkwell::passes::PassBuilderOptions;
+use inkwell::targets::{CodeModel, FileType, RelocMode, Target, TargetMachine, TargetTriple};
+use inkwell::types::{BasicType, BasicTypeEnum, FunctionType, IntType, VectorType};
+use inkwell::values::{
+    AnyValue, AsValueRef, BasicMetadataValueEnum, BasicValue, BasicValueEnum, CallSiteValue,
+    FunctionValue, GlobalValue, IntValue, MetadataValue, PointerValue, VectorValue,
+};
+use inkwell::{AtomicOrdering, AtomicRMWBinOp, IntPredicate, OptimizationLevel};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use std::process;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
 
 pub struct CodeGen<'ctx> {
     context: &'ctx Context,
     module: Module<'ctx>,
     builder: Builder<'ctx>,
     variables: HashMap<String, PointerValue<'ctx>>,
+    /// Variables of the current function that only ever hold a condition,
+    /// kept as an `i1` in their slot
+    bool_variables: HashSet<String>,
+    /// Variables of the current function holding an Option or a vector,
+    /// by the type of their slot, see `option_type` and `vector_type`
+    wide_variables: HashMap<String, IntType<'ctx>>,
     functions: HashMap<String, FunctionValue<'ctx>>,
+    /// The globals of the program, see `declare_globals`
+    globals: HashMap<String, GlobalValue<'ctx>>,
+    /// C types of the functions of ceppo blocks, see `gen_c_call`
+    c_functions: HashMap<String, CSignature>,
     current_function: Option<FunctionValue<'ctx>>,
+    tail_call: Option<TailCall<'ctx>>,
     c_object_files: Vec<std::path::PathBuf>,
+    /// Bitcode of ceppo blocks compiled for LTO, linked into the module by
+    /// `link_time_optimize` instead of being handed to the linker
+    c_bitcode_files: Vec<std::path::PathBuf>,
+    lto: bool,
+    coverage: bool,
+    /// One execution counter per instrumented statement, with its location
+    coverage_counters: Vec<(String, usize, GlobalValue<'ctx>)>,
+    sanitizers: Vec<Sanitizer>,
+    frame_pointers: bool,
+    stack_protector: Option<StackProtector>,
+    stack_probes: bool,
+    /// Position-independent executable or not, `None` for the toolchain's
+    /// default
+    pie: Option<bool>,
+    /// How stdout buffers, `None` for what libc picks
+    stdout_buffering: Option<StdoutBuffering>,
+    /// Move allocations that don't escape to the stack, see
+    /// `set_heap_to_stack`
+    heap_to_stack: bool,
+    /// How `/` rounds and overflows, see `set_division`
+    division_rounding: DivisionRounding,
+    division_overflow: Option<DivisionOverflow>,
+    /// Bytes of stack for the main thread and spawned threads, `None` for
+    /// the system's default
+    stack_size: Option<u64>,
+    profiling: bool,
+    debug_info: bool,
+    /// Created with the first function that has a location
+    debug: Option<DebugInfo<'ctx>>,
+    /// Debug info of the function being generated
+    debug_scope: Option<DISubprogram<'ctx>>,
+    overflow_checks: bool,
+    dump_ir: bool,
+    /// Address of the function pointer each seppo function is called
+    /// through, see `set_hot_slots`
+    hot_slots: HashMap<String, usize>,
+    hot_generation: usize,
+    /// Location of the statement being generated, for sanitizer reports
+    location: Option<(String, usize)>,
+    target: TargetSpec,
+    opt_level: OptimizationLevel,
+    options: CodeGenOptions,
+    c_compiler: Option<String>,
+    c_flags: Vec<String>,
+    ceppo_builds: Vec<CeppoBuild>,
+    /// Source name and first statement location of each function, by
+    /// symbol, for reporting IR that fails verification
+    function_sources: HashMap<String, (String, Option<(String, usize)>)>,
+    /// One constant global per distinct string, see `string_constant`
+    strings: HashMap<String, GlobalValue<'ctx>>,
+    /// Functions declared with `export fn`, which keep their names
+    exported: HashSet<String>,
+    /// Declared types of the functions that have them, see `function_type`
+    signatures: HashMap<String, Signature>,
+    /// Functions marked `#[constant_time]`, whose conditionals are selects
+    constant_time: HashSet<String>,
+    /// Whether the function being generated is one of them
+    branchless: bool,
+    /// Set by any checked `+`, `-` or `*` of a constant_time function that
+    /// overflowed, which is checked once when it returns instead of after
+    /// every operation
+    overflowed: Option<PointerValue<'ctx>>,
+    /// LLVM attributes of the functions with attributes like `#[cold]`
+    function_attributes: HashMap<String, Vec<&'static str>>,
+    /// Count of the `#[unroll(n)]` before the `for` loop about to be
+    /// generated
+    unroll: Option<u32>,
+    /// Whether any loop has an unroll hint, see `finish`
+    unroll_hints: bool,
+    /// Where ceppo blocks are compiled, see `set_temps`
+    temps: Rc<TempArtifacts>,
+    /// Directory keeping ceppo objects between builds, see `set_ceppo_cache`
+    ceppo_cache: Option<PathBuf>,
+    /// What each C compiler command printed for `--version`
+    compiler_versions: HashMap<String, String>,
+}
+
+/// How one ceppo block was compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CeppoBuild {
+    pub object: PathBuf,
+    pub compiler: String,
+    /// Every argument but the input and output files
+    pub flags: Vec<String>,
+}
+
+/// Runtime checks compiled into the program and its ceppo blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sanitizer {
+    /// AddressSanitizer, invalid memory accesses in ceppo blocks
+    Address,
+    /// UndefinedBehaviorSanitizer, overflowing arithmetic and division by
+    /// zero in seppo code and any undefined behavior in ceppo blocks
+    Undefined,
+}
+
+impl Sanitizer {
+    /// Name used by `-fsanitize=`
+    pub fn name(self) -> &'static str {
+        match self {
+            Sanitizer::Address => "address",
+            Sanitizer::Undefined => "undefined",
+        }
+    }
+}
+
+/// Which LLVM middle-end passes run on the module once it is generated.
+/// Independent of the optimization level, which is what the target machine
+/// uses when it writes objects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PassPreset {
+    /// The IR as generated
+    #[default]
+    None,
+    /// Promote variables to registers, simplify, hoist loop-invariant code
+    /// out of loops and remove redundant loads and computations
+    Default,
+    /// `Default` followed by unrolling loops
+    Aggressive,
+}
+
+impl PassPreset {
+    /// The pipeline in the syntax of LLVM's `-passes`
+    fn pipeline(self) -> Option<&'static str> {
+        match self {
+            PassPreset::None => None,
+            PassPreset::Default => Some(
+                "function(sroa,early-cse,instcombine,simplifycfg,loop-mssa(licm),gvn,simplifycfg)",
+            ),
+            PassPreset::Aggressive => Some(
+                "function(sroa,early-cse,instcombine,simplifycfg,loop-mssa(licm),gvn,\
+                 loop-unroll<O3>,instcombine,simplifycfg)",
+            ),
+        }
+    }
+}
+
+/// Options for embedders that shape the generated code rather than what
+/// gets built
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeGenOptions {
+    pub passes: PassPreset,
+}
+
+/// Which functions get a canary checked before they return, so that
+/// overflowing a buffer on the stack stops the program instead of
+/// overwriting the return address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackProtector {
+    /// Functions with arrays or locals whose address is taken
+    Strong,
+    /// Every function
+    All,
+}
+
+impl StackProtector {
+    /// Name used by `-fstack-protector-`
+    pub fn name(self) -> &'static str {
+        match self {
+            StackProtector::Strong => "strong",
+            StackProtector::All => "all",
+        }
+    }
+
+    /// The LLVM function attribute
+    fn attribute(self) -> &'static str {
+        match self {
+            StackProtector::Strong => "sspstrong",
+            StackProtector::All => "sspreq",
+        }
+    }
+}
+
+/// How stdout holds on to output before writing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdoutBuffering {
+    /// Every print is written right away
+    Unbuffered,
+    /// Written at each newline
+    Line,
+    /// Written when the buffer fills, on `flush()` and at exit
+    Full,
+}
+
+impl StdoutBuffering {
+    /// The `setvbuf` mode, `_IONBF`, `_IOLBF` or `_IOFBF`, which are the
+    /// same on glibc, musl and Apple platforms
+    fn mode(self) -> u64 {
+        match self {
+            StdoutBuffering::Full => 0,
+            StdoutBuffering::Line => 1,
+            StdoutBuffering::Unbuffered => 2,
+        }
+    }
+}
+
+/// Which way `/` rounds a quotient that isn't whole
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DivisionRounding {
+    /// Toward zero, so `-7 / 2` is -3, as in C
+    #[default]
+    Truncate,
+    /// Toward negative infinity, so `-7 / 2` is -4, as in Python
+    Floor,
+}
+
+/// What `/` does when the quotient doesn't fit, which only happens for
+/// `i64::MIN / -1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DivisionOverflow {
+    /// Stop the program, as on division by zero
+    Trap,
+    /// Wrap around to `i64::MIN`
+    Wrap,
+}
+
+/// Name of the cache directory of a ceppo block. Anything that changes
+/// the object changes the hash: the code, the compiler, its version and
+/// its flags, which include the target and whether it builds bitcode. The
+/// hash is the same for every build of seppoc.
+fn ceppo_hash(code: &str, compiler: &str, version: &str, flags: &[String]) -> String {
+    let mut key = vec![code, compiler, version];
+    key.extend(flags.iter().map(String::as_str));
+    format!("{:016x}", fnv1a(&key.join("\0")))
+}
+
+/// Whether a ceppo block includes headers of its own, which the cache can't
+/// tell have changed
+fn includes_local_header(code: &str) -> bool {
+    code.lines().any(|line| {
+        line.trim_start()
+            .strip_prefix('#')
+            .and_then(|directive| directive.trim_start().strip_prefix("include"))
+            .is_some_and(|path| path.trim_start().starts_with('"'))
+    })
+}
+
+/// Where self-recursive tail calls of the current function jump to
+struct TailCall<'ctx> {
+    function: String,
+    params: Vec<PointerValue<'ctx>>,
+    start: BasicBlock<'ctx>,
 }
 
 impl<'ctx> CodeGen<'ctx> {
     pub fn new(context: &'ctx Context, module_name: &str) -> Self {
+        Self::with_target(context, module_name, TargetSpec::native())
+    }
+
+    pub fn with_target(context: &'ctx Context, module_name: &str, target: TargetSpec) -> Self {
         let module = context.create_module(module_name);
+        module.set_triple(&TargetTriple::create(target.triple()));
         let builder = context.create_builder();
 
-        // Add printf declaration
-        let i32_type = context.i32_type();
-        let printf_type = i32_type.fn_type(&[context.ptr_type(0.into()).into()], true);
-        module.add_function("printf", printf_type, None);
-
-        Self {
+        let mut codegen = Self {
             context,
             module,
             builder,
             variables: HashMap::new(),
+            bool_variables: HashSet::new(),
+            wide_variables: HashMap::new(),
             functions: HashMap::new(),
+            globals: HashMap::new(),
+            c_functions: HashMap::new(),
             current_function: None,
+            tail_call: None,
             c_object_files: Vec::new(),
+            c_bitcode_files: Vec::new(),
+            lto: false,
+            coverage: false,
+            coverage_counters: Vec::new(),
+            sanitizers: Vec::new(),
+            frame_pointers: false,
+            stack_protector: None,
+            stack_probes: false,
+            pie: None,
+            stdout_buffering: None,
+            heap_to_stack: true,
+            division_rounding: DivisionRounding::default(),
+            division_overflow: None,
+            stack_size: None,
+            profiling: false,
+            debug_info: false,
+            debug: None,
+            debug_scope: None,
+            overflow_checks: false,
+            dump_ir: false,
+            hot_slots: HashMap::new(),
+            hot_generation: 0,
+            location: None,
+            target,
+            opt_level: OptimizationLevel::Default,
+            options: CodeGenOptions::default(),
+            c_compiler: None,
+            c_flags: Vec::new(),
+            ceppo_builds: Vec::new(),
+            function_sources: HashMap::new(),
+            strings: HashMap::new(),
+            exported: HashSet::new(),
+            signatures: HashMap::new(),
+            constant_time: HashSet::new(),
+            branchless: false,
+            overflowed: None,
+            function_attributes: HashMap::new(),
+            unroll: None,
+            unroll_hints: false,
+            temps: Rc::default(),
+            ceppo_cache: None,
+            compiler_versions: HashMap::new(),
+        };
+
+        if codegen.target.is_wasm() {
+            codegen.declare_wasi_runtime();
+        } else {
+            // Add printf declaration
+            let i32_type = context.i32_type();
+            let printf_type = i32_type.fn_type(&[context.ptr_type(0.into()).into()], true);
+            codegen.module.add_function("printf", printf_type, None);
+        }
+
+        codegen
+    }
+
+    pub fn target(&self) -> &TargetSpec {
+        &self.target
+    }
+
+    /// Optimization level used by the target machine when writing objects
+    pub fn set_opt_level(&mut self, opt_level: OptimizationLevel) {
+        self.opt_level = opt_level;
+    }
+
+    /// Run the passes of `options` on the module after generating it
+    pub fn set_options(&mut self, options: CodeGenOptions) {
+        self.options = options;
+    }
+
+    /// Extra arguments for the C compiler that builds ceppo blocks
+    pub fn set_c_flags(&mut self, c_flags: Vec<String>) {
+        self.c_flags = c_flags;
+    }
+
+    /// Build ceppo blocks with `compiler` instead of `$CC`, see
+    /// `TargetSpec::c_compiler_with`. Blocks compiled for LTO always use
+    /// clang.
+    pub fn set_c_compiler(&mut self, compiler: Option<String>) {
+        self.c_compiler = compiler;
+    }
+
+    /// Compile ceppo blocks in directories of `temps`, so they live as long
+    /// as the compilation instead of this code generator
+    pub(crate) fn set_temps(&mut self, temps: Rc<TempArtifacts>) {
+        self.temps = temps;
+    }
+
+    /// Compile ceppo blocks in `dir` instead, one directory per block named
+    /// after a hash of its code, compiler and flags, and reuse the object
+    /// when an earlier build already compiled the same block. Blocks with
+    /// `#include "..."` are always compiled, as their headers may change.
+    pub fn set_ceppo_cache(&mut self, dir: Option<PathBuf>) {
+        self.ceppo_cache = dir;
+    }
+
+    /// What `program` prints for `--version` with `flags`, so that the
+    /// cache misses after the compiler is upgraded
+    fn compiler_version(&mut self, program: &str, flags: &[String]) -> Result<String> {
+        let command = format!("{} {}", program, flags.join(" "));
+        if let Some(version) = self.compiler_versions.get(&command) {
+            return Ok(version.clone());
+        }
+        let output = Command::new(program)
+            .args(flags)
+            .arg("--version")
+            .output()?;
+        let version = String::from_utf8_lossy(&output.stdout).into_owned();
+        self.compiler_versions.insert(command, version.clone());
+        Ok(version)
+    }
+
+    /// The compiler and flags used for each ceppo block so far
+    pub fn ceppo_builds(&self) -> &[CeppoBuild] {
+        &self.ceppo_builds
+    }
+
+    /// Compile ceppo blocks to bitcode so `link_time_optimize` can merge them
+    /// into the module. Requires clang.
+    pub fn set_lto(&mut self, lto: bool) {
+        self.lto = lto;
+    }
+
+    /// Count how often each statement with a `Location` runs. Executables
+    /// append the counts to `seppo.cov`, or `$SEPPO_COVERAGE_FILE`, when
+    /// they exit. Modules compiled with `compile_library` instead get a
+    /// `seppo.coverage_hits(i)` function returning counter `i`.
+    pub fn set_coverage(&mut self, coverage: bool) {
+        self.coverage = coverage;
+    }
+
+    /// Instrument the program with sanitizers. Their reports point at the
+    /// statements of programs parsed with `parse_with_locations`.
+    pub fn set_sanitizers(&mut self, sanitizers: Vec<Sanitizer>) {
+        self.sanitizers = sanitizers;
+    }
+
+    fn sanitizes(&self, sanitizer: Sanitizer) -> bool {
+        self.sanitizers.contains(&sanitizer)
+    }
+
+    /// Keep frame pointers in every function so profilers and debuggers can
+    /// walk the stack without unwind information
+    pub fn set_frame_pointers(&mut self, frame_pointers: bool) {
+        self.frame_pointers = frame_pointers;
+    }
+
+    /// Guard the functions of the program and its ceppo blocks with stack
+    /// canaries
+    pub fn set_stack_protector(&mut self, stack_protector: Option<StackProtector>) {
+        self.stack_protector = stack_protector;
+    }
+
+    /// Touch every page of stack frames larger than a page as they are
+    /// allocated, so a frame can't reach past the guard page below the
+    /// stack into other memory
+    pub fn set_stack_probes(&mut self, stack_probes: bool) {
+        self.stack_probes = stack_probes;
+    }
+
+    /// Build a position-independent executable, or one loaded at a fixed
+    /// address, whatever the C compiler and linker do by default. Objects,
+    /// ceppo blocks and the link all follow it, as the code of a PIE can't
+    /// be linked into a fixed executable and the other way around.
+    pub fn set_pie(&mut self, pie: Option<bool>) {
+        self.pie = pie;
+    }
+
+    /// Buffer stdout as set, with `setvbuf` before seppo runs. libc buffers
+    /// it by line on a terminal and in full otherwise.
+    pub fn set_stdout_buffering(&mut self, stdout_buffering: Option<StdoutBuffering>) {
+        self.stdout_buffering = stdout_buffering;
+    }
+
+    /// Move `alloc`s of a constant size whose address doesn't leave the
+    /// function to its stack frame when optimizing, see
+    /// `promote_allocations`. On by default.
+    pub fn set_heap_to_stack(&mut self, heap_to_stack: bool) {
+        self.heap_to_stack = heap_to_stack;
+    }
+
+    /// How `/` rounds, and what it does on overflow. `None` leaves
+    /// `i64::MIN / -1` unchecked like the rest of the arithmetic, unless
+    /// overflow checks or UBSan are on; it traps on x86 and wraps on
+    /// AArch64. `Trap` checks for division by zero as well.
+    pub fn set_division(&mut self, rounding: DivisionRounding, overflow: Option<DivisionOverflow>) {
+        self.division_rounding = rounding;
+        self.division_overflow = overflow;
+    }
+
+    /// Stack size in bytes for the main thread and threads from `spawn`,
+    /// rounded up to a multiple of `STACK_SIZE_GRANULE`. Apple linkers
+    /// reserve the main thread's stack in the executable; elsewhere the
+    /// kernel sizes it from `ulimit -s`, so `main` runs seppo on a thread
+    /// of this size instead.
+    pub fn set_stack_size(&mut self, stack_size: Option<u64>) {
+        self.stack_size =
+            stack_size.map(|size| size.max(1).div_ceil(STACK_SIZE_GRANULE) * STACK_SIZE_GRANULE);
+    }
+
+    /// Frame pointers plus unwind tables, debug info for ceppo blocks and
+    /// an exported symbol table, so tools like perf and Instruments can
+    /// name every frame of a profile
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+    }
+
+    /// Emit DWARF line tables for programs parsed with
+    /// `parse_with_locations`, and build ceppo blocks with `-g`
+    pub fn set_debug_info(&mut self, debug_info: bool) {
+        self.debug_info = debug_info;
+    }
+
+    /// Stop the program with a message when `+`, `-` or `*` overflow or `/`
+    /// divides by zero, instead of wrapping around. `#[constant_time]`
+    /// functions stop when they return, so they don't branch on their data.
+    pub fn set_overflow_checks(&mut self, overflow_checks: bool) {
+        self.overflow_checks = overflow_checks;
+    }
+
+    /// Print the LLVM IR of every module compiled to stdout, off by default
+    pub fn set_dump_ir(&mut self, dump_ir: bool) {
+        self.dump_ir = dump_ir;
+    }
+
+    /// Call the seppo functions named in `slots` through the function
+    /// pointer stored at each address, so a JIT can swap in new versions
+    /// while the program runs. Functions defined in a module of a later
+    /// `generation` get symbols apart from the versions already loaded.
+    pub(crate) fn set_hot_slots(&mut self, slots: HashMap<String, usize>, generation: usize) {
+        self.hot_slots = slots;
+        self.hot_generation = generation;
+    }
+
+    /// Declare a function defined in another module as `symbol`, so calls
+    /// to it compile
+    pub(crate) fn declare_function(&mut self, name: &str, symbol: &str, params: usize) {
+        if self.functions.contains_key(name) || self.module.get_function(symbol).is_some() {
+            return;
+        }
+        let fn_type = self.function_type(name, params);
+        let function = self
+            .module
+            .add_function(symbol, fn_type, Some(Linkage::External));
+        self.functions.insert(name.to_string(), function);
+    }
+
+    /// Keep the names of the `export fn`s of `program`
+    pub(crate) fn export_functions(&mut self, program: &SeppoExpr) {
+        self.exported.extend(exported_functions(program));
+    }
+
+    /// Keep the types the functions of `program` declare, see
+    /// `function_type`, which of them are `#[constant_time]` and their
+    /// LLVM attributes
+    pub(crate) fn declare_types(&mut self, program: &SeppoExpr) {
+        self.signatures.extend(declared_signatures(program));
+        self.constant_time
+            .extend(marked_functions(program, CONSTANT_TIME_ATTRIBUTE));
+        for (attribute, kind) in LLVM_ATTRIBUTES {
+            for name in marked_functions(program, attribute) {
+                self.function_attributes
+                    .entry(name)
+                    .or_default()
+                    .push(*kind);
+            }
+        }
+    }
+
+    /// Add the globals of `program` to the module, `thread_local` for
+    /// the `threadlocal` ones. Modules of hot reload only declare them, so
+    /// they use the ones the program started with.
+    pub(crate) fn declare_globals(&mut self, program: &SeppoExpr) -> Result<()> {
+        let i64_type = self.context.i64_type();
+        for global in globals(program)? {
+            let symbol = format!("{}{}", GLOBAL_PREFIX, global.name);
+            let value = self.module.add_global(i64_type, None, &symbol);
+            if self.hot_generation == 0 {
+                value.set_initializer(&i64_type.const_int(global.value as u64, false));
+            }
+            value.set_constant(!global.mutable);
+            value.set_thread_local(global.threadlocal);
+            self.globals.insert(global.name, value);
+        }
+        Ok(())
+    }
+
+    /// The LLVM type of seppo function `name`. Values are 64-bit integers,
+    /// except those a signature declares `string`, which are pointers, and
+    /// `option`, see `option_type`.
+    fn function_type(&self, name: &str, params: usize) -> FunctionType<'ctx> {
+        let signature = self.signatures.get(name);
+        let param_types: Vec<_> = (0..params)
+            .map(|i| {
+                let declared = signature.and_then(|s| s.params.get(i).copied().flatten());
+                self.value_type(declared).into()
+            })
+            .collect();
+        self.value_type(signature.and_then(|s| s.returns))
+            .fn_type(&param_types, false)
+    }
+
+    fn value_type(&self, declared: Option<SeppoType>) -> BasicTypeEnum<'ctx> {
+        match declared {
+            Some(SeppoType::String) => self.context.ptr_type(0.into()).into(),
+            Some(SeppoType::Int) | None => self.context.i64_type().into(),
+            Some(SeppoType::Option) => self.option_type().into(),
+            Some(SeppoType::I64x4) => self.vector_type().into(),
+        }
+    }
+
+    /// Options are an `i128` holding the value in its low 64 bits and
+    /// whether there is one in bit 64, so `None` is 0
+    fn option_type(&self) -> IntType<'ctx> {
+        self.context.i128_type()
+    }
+
+    /// Vectors are passed around as an `i256` like Options are as an
+    /// `i128`, and taken as the `<4 x i64>` of `lanes_type` by the
+    /// element-wise operations and the vector builtins
+    fn vector_type(&self) -> IntType<'ctx> {
+        self.context.custom_width_int_type(256)
+    }
+
+    /// The four 64-bit integers of a vector, as LLVM operates on them
+    fn lanes_type(&self) -> VectorType<'ctx> {
+        self.context.i64_type().vec_type(builtins::LANES)
+    }
+
+    /// `value`, which seppo code keeps as an integer, as a value of `ty`
+    fn int_to_value(
+        &self,
+        builder: &Builder<'ctx>,
+        value: IntValue<'ctx>,
+        ty: BasicTypeEnum<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>> {
+        Ok(match ty {
+            BasicTypeEnum::PointerType(ptr_type) => {
+                builder.build_int_to_ptr(value, ptr_type, "addr")?.into()
+            }
+            // The 0 functions return by default, which for an Option is None
+            BasicTypeEnum::IntType(int_type)
+                if int_type.get_bit_width() > value.get_type().get_bit_width() =>
+            {
+                builder.build_int_z_extend(value, int_type, "widen")?.into()
+            }
+            _ => value.into(),
+        })
+    }
+
+    /// A parameter or result of a seppo function as an integer
+    fn value_to_int(
+        &self,
+        builder: &Builder<'ctx>,
+        value: BasicValueEnum<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        Ok(match value {
+            BasicValueEnum::PointerValue(ptr) => {
+                builder.build_ptr_to_int(ptr, self.context.i64_type(), "addr_int")?
+            }
+            other => other.into_int_value(),
+        })
+    }
+
+    /// Call seppo function `function` with `args`, which go through
+    /// `int_to_value`, and give its result as an integer. `target` is the
+    /// address to call instead of the function itself, see `set_hot_slots`.
+    fn call_seppo(
+        &self,
+        builder: &Builder<'ctx>,
+        function: FunctionValue<'ctx>,
+        target: Option<PointerValue<'ctx>>,
+        args: &[IntValue<'ctx>],
+        name: &str,
+    ) -> Result<IntValue<'ctx>> {
+        let fn_type = function.get_type();
+        let param_types = fn_type.get_param_types();
+        let args = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| match param_types.get(i) {
+                Some(ty) => self.int_to_value(builder, *arg, *ty).map(Into::into),
+                None => Ok((*arg).into()),
+            })
+            .collect::<Result<Vec<BasicMetadataValueEnum>>>()?;
+        let call = match target {
+            Some(target) => builder.build_indirect_call(fn_type, target, &args, name)?,
+            None => builder.build_call(function, &args, name)?,
+        };
+        self.value_to_int(builder, call.try_as_basic_value().left().unwrap())
+    }
+
+    /// Return `value` from the current function, as the type it returns
+    fn build_seppo_return(&mut self, value: IntValue<'ctx>) -> Result<()> {
+        let function = self.current_function.unwrap();
+        if let Some(flag) = self.overflowed {
+            // The only branch on the data, taken when the result is wrong
+            let overflowed = self
+                .builder
+                .build_load(self.context.bool_type(), flag, "overflowed")?
+                .into_int_value();
+            let panic_bb = self.context.append_basic_block(function, "overflow_panic");
+            let return_bb = self.context.append_basic_block(function, "return");
+            self.builder
+                .build_conditional_branch(overflowed, panic_bb, return_bb)?;
+            self.builder.position_at_end(panic_bb);
+            self.gen_panic("attempt to do arithmetic with overflow")?;
+            self.builder.position_at_end(return_bb);
+        }
+        let ty = function.get_type().get_return_type().unwrap();
+        let value = self.int_to_value(&self.builder, value, ty)?;
+        self.builder.build_return(Some(&value))?;
+        Ok(())
+    }
+
+    /// The symbol of seppo function `name`. Functions are mangled so they
+    /// can't clash with C functions of the same name, like a seppo `abs`
+    /// and the one from libc, except for `seppo` and `export fn`s, which C
+    /// code and hosts call by name.
+    pub(crate) fn symbol(&self, name: &str) -> String {
+        if name == "seppo" || self.exported.contains(name) {
+            name.to_string()
+        } else {
+            mangle(name)
+        }
+    }
+
+    /// The symbol a function defined so far, seppo or C, was compiled to
+    pub(crate) fn defined_symbol(&self, name: &str) -> Option<String> {
+        self.functions
+            .get(name)
+            .map(|function| function.get_name().to_string_lossy().into_owned())
+    }
+
+    /// Define `name` as a call into the host: the arguments are stored in an
+    /// array and passed, with the address `host`, to the native function at
+    /// `trampoline`. Both addresses are baked into the code, so the module
+    /// can only be run in the process that compiled it.
+    pub(crate) fn define_host_function(
+        &mut self,
+        name: &str,
+        params: usize,
+        host: usize,
+        trampoline: usize,
+    ) -> Result<()> {
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let fn_type = i64_type.fn_type(&vec![i64_type.into(); params], false);
+        let function = self.module.add_function(name, fn_type, None);
+        let entry = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+
+        let array_type = i64_type.array_type(params as u32);
+        let args = self.builder.build_alloca(array_type, "args")?;
+        for (i, param) in function.get_param_iter().enumerate() {
+            let index = i64_type.const_int(i as u64, false);
+            let slot = unsafe {
+                self.builder.build_in_bounds_gep(
+                    array_type,
+                    args,
+                    &[i64_type.const_zero(), index],
+                    "arg",
+                )?
+            };
+            self.builder.build_store(slot, param)?;
+        }
+        let address = |address: usize| {
+            i64_type
+                .const_int(address as u64, false)
+                .const_to_pointer(ptr_type)
+        };
+        let trampoline_type = i64_type.fn_type(&[ptr_type.into(), ptr_type.into()], false);
+        let result = self.builder.build_indirect_call(
+            trampoline_type,
+            address(trampoline),
+            &[address(host).into(), args.into()],
+            "host",
+        )?;
+        self.builder
+            .build_return(Some(&result.try_as_basic_value().left().unwrap()))?;
+        self.functions.insert(name.to_string(), function);
+        Ok(())
+    }
+
+    /// Name, symbol and parameter count of every function calls can
+    /// reach, including those of ceppo blocks
+    pub(crate) fn function_signatures(&self) -> Vec<(String, String, usize)> {
+        self.functions
+            .iter()
+            .map(|(name, function)| {
+                let symbol = function.get_name().to_string_lossy().into_owned();
+                (name.clone(), symbol, function.count_params() as usize)
+            })
+            .collect()
+    }
+
+    /// The `-fsanitize=` argument for compiling and linking C, if any
+    /// sanitizers are enabled
+    fn sanitize_flag(&self) -> Option<String> {
+        if self.sanitizers.is_empty() {
+            return None;
+        }
+        let names: Vec<&str> = self.sanitizers.iter().map(|s| s.name()).collect();
+        Some(format!("-fsanitize={}", names.join(",")))
+    }
+
+    /// Arguments for the C compiler that make ceppo blocks match the
+    /// program's instrumentation
+    fn c_codegen_flags(&self) -> Vec<String> {
+        let mut flags: Vec<String> = self.sanitize_flag().into_iter().collect();
+        if self.frame_pointers || self.profiling {
+            flags.push("-fno-omit-frame-pointer".to_string());
+        }
+        if self.profiling || self.debug_info {
+            flags.push("-g".to_string());
+        }
+        if let Some(stack_protector) = self.stack_protector {
+            flags.push(format!("-fstack-protector-{}", stack_protector.name()));
         }
+        if self.stack_probes {
+            flags.push("-fstack-clash-protection".to_string());
+        }
+        match self.pie {
+            Some(true) => flags.push("-fPIE".to_string()),
+            Some(false) => flags.push("-fno-PIE".to_string()),
+            None => {}
+        }
+        flags
+    }
+
+    /// Arguments the linker needs for the runtimes and symbols these
+    /// settings rely on
+    pub fn link_flags(&self) -> Vec<String> {
+        let mut flags: Vec<String> = self.sanitize_flag().into_iter().collect();
+        if self.profiling && !self.target.is_wasm() {
+            flags.push("-rdynamic".to_string());
+        }
+        if self.module.get_function("pthread_create").is_some() {
+            flags.push("-pthread".to_string());
+        }
+        // glibc before 2.34 has dlopen in libdl
+        if self.module.get_function("dlopen").is_some() && !self.target.is_apple() {
+            flags.push("-ldl".to_string());
+        }
+        // Executables for Apple platforms are always position-independent
+        if !self.target.is_apple() {
+            match self.pie {
+                Some(true) => flags.push("-pie".to_string()),
+                Some(false) => flags.push("-no-pie".to_string()),
+                None => {}
+            }
+        }
+        if let Some(size) = self.stack_size {
+            if self.target.is_apple() {
+                flags.push(format!("-Wl,-stack_size,{:#x}", size));
+            } else if self.target.is_wasm() {
+                flags.push("-z".to_string());
+                flags.push(format!("stack-size={}", size));
+            }
+        }
+        if self.target.is_wasm() && !self.target.is_wasi() {
+            let mut exported: Vec<&String> = self.exported.iter().collect();
+            exported.sort();
+            flags.extend(exported.iter().map(|name| format!("--export={}", name)));
+        }
+        flags
+    }
+
+    /// File and line of each coverage counter, by counter index
+    pub fn coverage_locations(&self) -> Vec<(String, usize)> {
+        self.coverage_counters
+            .iter()
+            .map(|(file, line, _)| (file.clone(), *line))
+            .collect()
     }
 
     pub fn compile(&mut self, expr: &SeppoExpr) -> Result<()> {
+        if self.coverage && self.target.is_wasm() {
+            return Err(SeppoError::Codegen(
+                "Coverage is not supported on wasm targets".to_string(),
+            ));
+        }
+        if !self.sanitizers.is_empty() && self.target.is_wasm() {
+            return Err(SeppoError::Codegen(
+                "Sanitizers are not supported on wasm targets".to_string(),
+            ));
+        }
+        if (self.stack_protector.is_some() || self.stack_probes) && self.target.is_wasm() {
+            // Wasm code can't reach the return address to overwrite it
+            return Err(SeppoError::Codegen(
+                "Stack protectors and probes are not supported on wasm targets".to_string(),
+            ));
+        }
+        if let Some(pie) = self.pie {
+            self.check_pie(pie)?;
+        }
+        if self.stdout_buffering.is_some() && self.target.is_wasm() {
+            return Err(SeppoError::Codegen(
+                "--stdout-buffering is not supported on wasm targets, which write output unbuffered"
+                    .to_string(),
+            ));
+        }
+        if self.stack_size.is_some() && self.target.triple().contains("windows") {
+            return Err(SeppoError::Codegen(format!(
+                "--stack-size is not supported on {}, which has no POSIX threads",
+                self.target.triple()
+            )));
+        }
+        if !self.sanitizers.is_empty() && self.target.is_musl() {
+            // The sanitizer runtimes need glibc and dynamic linking
+            return Err(SeppoError::Codegen(
+                "Sanitizers are not supported on musl targets".to_string(),
+            ));
+        }
+        // Trees loaded from JSON haven't been through the parser's check
+        check_entry(expr)?;
+        self.export_functions(expr);
+        self.declare_types(expr);
+        self.declare_globals(expr)?;
+
         // Generate code for the expression first
-        self.gen_expr(expr)?;
+        let optimized = self.optimize_tree(expr);
+        self.gen_expr(optimized.as_ref().unwrap_or(expr))?;
+        let coverage_dump = if self.coverage {
+            Some(self.build_coverage_dump()?)
+        } else {
+            None
+        };
+
+        if self.target.is_wasm() && !self.target.is_wasi() {
+            // Bare wasm modules have no process entry, the host calls seppo itself
+            if self.module.get_function("seppo").is_none() {
+                return Err(SeppoError::Sema("No seppo function found".to_string()));
+            }
+            return self.finish();
+        }
+
+        if self.target.is_wasi() {
+            let seppo_fn = self.module.get_function("seppo");
+            if seppo_fn.is_some_and(|f| f.count_params() > 0) {
+                return Err(SeppoError::Codegen(
+                    "The seppo function can't take argc and argv on WASI, which has no main"
+                        .to_string(),
+                ));
+            }
+            self.build_wasi_start()?;
+            return self.finish();
+        }
 
         // Now create the main function that calls seppo
         let i32_type = self.context.i32_type();
-        let main_type = i32_type.fn_type(&[], false);
+        let ptr_type = self.context.ptr_type(0.into());
+        let main_type = i32_type.fn_type(&[i32_type.into(), ptr_type.into()], false);
         let main_fn = self.module.add_function("main", main_type, None);
         let entry = self.context.append_basic_block(main_fn, "entry");
         self.builder.position_at_end(entry);
 
+        if let Some(dump) = coverage_dump {
+            let atexit = self.libc_function("atexit", i32_type.fn_type(&[ptr_type.into()], false));
+            self.builder.build_call(
+                atexit,
+                &[dump.as_global_value().as_pointer_value().into()],
+                "atexit",
+            )?;
+        }
+
+        if let Some(buffering) = self.stdout_buffering {
+            let stdout = self.stdio_stream("stdout")?;
+            let setvbuf = self.libc_function(
+                "setvbuf",
+                i32_type.fn_type(
+                    &[
+                        ptr_type.into(),
+                        ptr_type.into(),
+                        i32_type.into(),
+                        self.context.i64_type().into(),
+                    ],
+                    false,
+                ),
+            );
+            self.builder.build_call(
+                setvbuf,
+                &[
+                    stdout.into(),
+                    ptr_type.const_null().into(),
+                    i32_type.const_int(buffering.mode(), false).into(),
+                    self.context.i64_type().const_zero().into(),
+                ],
+                "setvbuf",
+            )?;
+        }
+
         // Get the seppo function and call it
         if let Some(seppo_fn) = self.module.get_function("seppo") {
-            let seppo_result = self.builder.build_call(seppo_fn, &[], "seppo_call")?;
-            let result = self.builder.build_int_truncate(
-                seppo_result
-                    .try_as_basic_value()
-                    .left()
-                    .unwrap()
-                    .into_int_value(),
-                i32_type,
-                "result",
-            )?;
+            // seppo takes the leading arguments of main it declares
+            // parameters for, argc as an integer and argv as an address
+            let i64_type = self.context.i64_type();
+            let mut args = Vec::new();
+            if seppo_fn.count_params() > 0 {
+                let argc = main_fn.get_nth_param(0).unwrap().into_int_value();
+                args.push(self.builder.build_int_s_extend(argc, i64_type, "argc")?);
+            }
+            if seppo_fn.count_params() > 1 {
+                let argv = main_fn.get_nth_param(1).unwrap().into_pointer_value();
+                args.push(self.builder.build_ptr_to_int(argv, i64_type, "argv")?);
+            }
+            let seppo_result = if self.stack_size.is_some() && !self.target.is_apple() {
+                self.current_function = Some(main_fn);
+                self.call_seppo_on_thread(seppo_fn, &args)?
+            } else {
+                self.call_seppo(&self.builder, seppo_fn, None, &args, "seppo_call")?
+            };
+            let result = self
+                .builder
+                .build_int_truncate(seppo_result, i32_type, "result")?;
             self.builder.build_return(Some(&result))?;
         } else {
-            return Err(anyhow!("No seppo function found"));
+            return Err(SeppoError::Sema("No seppo function found".to_string()));
+        }
+
+        if self.sanitizes(Sanitizer::Address) {
+            self.instrument_address()?;
+        }
+        self.finish()
+    }
+
+    /// Whether the target can build the kind of executable `--pie` or
+    /// `--no-pie` asks for
+    fn check_pie(&self, pie: bool) -> Result<()> {
+        let flag = if pie { "--pie" } else { "--no-pie" };
+        let reason = if self.target.is_wasm() {
+            "wasm modules aren't loaded at an address"
+        } else if pie && self.target.is_musl() {
+            "musl builds are linked statically"
+        } else if !pie && self.target.is_apple() {
+            "Apple platforms require position-independent executables"
+        } else {
+            return Ok(());
+        };
+        Err(SeppoError::Codegen(format!(
+            "{} is not supported on {}, {}",
+            flag,
+            self.target.triple(),
+            reason
+        )))
+    }
+
+    /// Compile the functions of a program without adding a process entry
+    /// point, for modules that are run through the JIT
+    pub fn compile_library(&mut self, expr: &SeppoExpr) -> Result<()> {
+        self.export_functions(expr);
+        self.declare_types(expr);
+        self.declare_globals(expr)?;
+        let optimized = self.optimize_tree(expr);
+        self.gen_expr(optimized.as_ref().unwrap_or(expr))?;
+        if self.coverage {
+            self.build_coverage_hits()?;
+        }
+        self.finish()
+    }
+
+    /// Whether calls to small functions are replaced with their bodies, see
+    /// `inline_functions`. Not at -O0, and not when coverage counts the
+    /// statements of functions or hot reload swaps their bodies.
+    fn inlines(&self) -> bool {
+        self.opt_level != OptimizationLevel::None && !self.coverage && self.hot_slots.is_empty()
+    }
+
+    /// Whether allocations that don't escape are moved to the stack. Not at
+    /// -O0, and not on wasm, which has no `alloc`.
+    fn promotes_allocations(&self) -> bool {
+        self.heap_to_stack && self.opt_level != OptimizationLevel::None && !self.target.is_wasm()
+    }
+
+    /// `expr` rewritten by inlining and moving allocations to the stack, or
+    /// `None` if neither applies
+    fn optimize_tree(&self, expr: &SeppoExpr) -> Option<SeppoExpr> {
+        let inlined = self.inlines().then(|| inline_functions(expr));
+        if self.promotes_allocations() {
+            return Some(promote_allocations(
+                inlined.as_ref().unwrap_or(expr),
+                &self.target,
+            ));
+        }
+        inlined
+    }
+
+    /// Compile a program given as serialized AST instead of seppo source
+    #[cfg(feature = "serde")]
+    pub fn compile_ast_json(&mut self, json: &str) -> Result<()> {
+        let expr = SeppoExpr::from_json(json)?;
+        self.compile(&expr)
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.frame_pointers || self.profiling {
+            self.add_attribute_to_definitions(
+                self.context.create_string_attribute("frame-pointer", "all"),
+            );
+        }
+        if self.pie == Some(true) {
+            // Lets the backend access symbols of the executable directly
+            let level = self.context.i32_type().const_int(2, false);
+            self.module
+                .add_basic_value_flag("PIC Level", FlagBehavior::Warning, level);
+            self.module
+                .add_basic_value_flag("PIE Level", FlagBehavior::Warning, level);
+        }
+        if let Some(stack_protector) = self.stack_protector {
+            let kind = Attribute::get_named_enum_kind_id(stack_protector.attribute());
+            self.add_attribute_to_definitions(self.context.create_enum_attribute(kind, 0));
+        }
+        if self.stack_probes {
+            self.add_attribute_to_definitions(
+                self.context
+                    .create_string_attribute("probe-stack", "inline-asm"),
+            );
+        }
+        if self.profiling {
+            // 2 is asynchronous unwind tables, usable from any instruction
+            let uwtable = Attribute::get_named_enum_kind_id("uwtable");
+            self.add_attribute_to_definitions(self.context.create_enum_attribute(uwtable, 2));
+        }
+
+        if let Some(debug) = &self.debug {
+            debug.finalize();
         }
 
         // Print LLVM IR for debugging
-        println!("LLVM IR:\n{}", self.module.print_to_string().to_string());
+        if self.dump_ir {
+            println!("LLVM IR:\n{}", self.module.print_to_string().to_string());
+        }
 
         // Verify module
-        if self.module.verify().is_err() {
-            return Err(anyhow!("Module verification failed"));
+        if let Err(message) = self.module.verify() {
+            return Err(self.verification_error(message.to_string()));
         }
 
+        // Even unoptimized builds inline `#[always_inline]` functions, which
+        // only LLVM's inliner does
+        let always_inline = self
+            .function_attributes
+            .values()
+            .any(|kinds| kinds.contains(&"alwaysinline"));
+        if always_inline {
+            let target_machine = self.target_machine()?;
+            self.module
+                .run_passes(
+                    "always-inline",
+                    &target_machine,
+                    PassBuilderOptions::create(),
+                )
+                .map_err(|e| SeppoError::Codegen(format!("Failed to inline functions: {}", e)))?;
+        }
+        if let Some(pipeline) = self.options.passes.pipeline() {
+            let target_machine = self.target_machine()?;
+            self.module
+                .run_passes(pipeline, &target_machine, PassBuilderOptions::create())
+                .map_err(|e| SeppoError::Codegen(format!("Failed to optimize module: {}", e)))?;
+        }
+        // Only the unroller reads the hints, and the loops need rotating
+        // so their exit test is at the bottom before it can unroll them
+        if self.unroll_hints && self.opt_level != OptimizationLevel::None {
+            let target_machine = self.target_machine()?;
+            self.module
+                .run_passes(
+                    "function(sroa,instcombine,simplifycfg,loop(loop-rotate),loop-unroll<O2>,simplifycfg)",
+                    &target_machine,
+                    PassBuilderOptions::create(),
+                )
+                .map_err(|e| SeppoError::Codegen(format!("Failed to unroll loops: {}", e)))?;
+        }
         Ok(())
     }
 
+    /// Invalid IR is always a bug in codegen. Name the function LLVM's
+    /// verifier rejected and where it is in the source, and quote the IR
+    /// around the instructions the verifier complains about, so the error
+    /// can be reported as it is.
+    fn verification_error(&self, message: String) -> SeppoError {
+        let message = message.trim_end();
+        let broken = self
+            .module
+            .get_functions()
+            .find(|function| function.count_basic_blocks() > 0 && !function.verify(false));
+        let mut text = String::from("Module verification failed");
+        if let Some(function) = broken {
+            let symbol = function.get_name().to_string_lossy().into_owned();
+            match self.function_sources.get(&symbol) {
+                Some((name, Some((file, line)))) => {
+                    text.push_str(&format!(" in function {} at {}:{}", name, file, line))
+                }
+                Some((name, None)) => text.push_str(&format!(" in function {}", name)),
+                None => text.push_str(&format!(" in generated function {}", symbol)),
+            }
+        }
+        text.push_str(&format!(": {}\n", message));
+        if let Some(function) = broken {
+            let ir = function.print_to_string().to_string();
+            text.push_str(&format!("\n{}", ir_snippet(&ir, message)));
+        }
+        text.push_str(&format!(
+            "\nThis is a bug in the compiler, please file an issue at {} with the program that triggered it",
+            ice::ISSUES_URL
+        ));
+        SeppoError::Codegen(text)
+    }
+
     fn gen_expr(&mut self, expr: &SeppoExpr) -> Result<IntValue<'ctx>> {
         match expr {
             SeppoExpr::Number(n) => Ok(self.context.i64_type().const_int(*n as u64, false)),
+            SeppoExpr::Variable(name) if self.bool_variables.contains(name) => {
+                let value = self.gen_bool(expr)?;
+                Ok(self
+                    .builder
+                    .build_int_z_extend(value, self.context.i64_type(), "bool_ext")?)
+            }
             SeppoExpr::Variable(name) => {
                 if let Some(ptr) = self.variables.get(name) {
-                    let load = self
-                        .builder
-                        .build_load(self.context.i64_type(), *ptr, name)?;
+                    let ty = match self.wide_variables.get(name) {
+                        Some(ty) => *ty,
+                        None => self.context.i64_type(),
+                    };
+                    let load = self.builder.build_load(ty, *ptr, name)?;
                     Ok(load.into_int_value())
+                } else if name == builtins::NONE {
+                    Ok(self.option_type().const_zero())
                 } else {
-                    Err(anyhow!("Undefined variable: {}", name))
+                    Err(undefined(
+                        "variable",
+                        name,
+                        self.variables.keys().map(String::as_str),
+                    ))
                 }
             }
             SeppoExpr::Function(name, params, body) => {
+                ice::enter_function(name);
                 let i64_type = self.context.i64_type();
-                let param_types = vec![i64_type.into(); params.len()];
-                let fn_type = i64_type.fn_type(&param_types, false);
-                let function = self.module.add_function(name, fn_type, None);
+                let fn_type = self.function_type(name, params.len());
+                let symbol = match self.hot_generation {
+                    0 => self.symbol(name),
+                    generation => hot_symbol(&self.symbol(name), generation),
+                };
+                let function = self.module.add_function(&symbol, fn_type, None);
+                for kind in self.function_attributes.get(name).into_iter().flatten() {
+                    function.add_attribute(AttributeLoc::Function, self.enum_attribute(kind));
+                }
+                let location = first_location(body).map(|(file, line)| (file.to_string(), line));
+                self.function_sources
+                    .insert(symbol, (name.clone(), location));
 
                 // Store function for later use
                 self.functions.insert(name.clone(), function);
@@ -107,23 +1280,61 @@ impl<'ctx> CodeGen<'ctx> {
                 // Create entry block
                 let entry = self.context.append_basic_block(function, "entry");
                 self.builder.position_at_end(entry);
+                let scope = self.debug_function(function, name, body);
+                let prev_scope = std::mem::replace(&mut self.debug_scope, scope);
 
                 // Save current function
                 let prev_function = self.current_function;
                 self.current_function = Some(function);
+                let prev_branchless =
+                    std::mem::replace(&mut self.branchless, self.constant_time.contains(name));
+                let prev_overflowed = self.overflowed.take();
+                if self.branchless && (self.overflow_checks || self.sanitizes(Sanitizer::Undefined))
+                {
+                    let bool_type = self.context.bool_type();
+                    let flag = self.builder.build_alloca(bool_type, "overflowed")?;
+                    self.builder.build_store(flag, bool_type.const_zero())?;
+                    self.overflowed = Some(flag);
+                }
 
-                // Create new scope for variables
+                // Create new scope for variables, where sema keeps the
+                // names of globals free for them
                 let prev_vars = self.variables.clone();
                 self.variables.clear();
+                for (name, global) in &self.globals {
+                    self.variables
+                        .insert(name.clone(), global.as_pointer_value());
+                }
+                let mut bools = bool_variables(params, body);
+                bools.retain(|name| !self.globals.contains_key(name));
+                let prev_bools = std::mem::replace(&mut self.bool_variables, bools);
+                let prev_wide = std::mem::take(&mut self.wide_variables);
 
                 // Add parameters to variables
+                let mut param_slots = Vec::new();
                 for (i, param) in params.iter().enumerate() {
-                    let alloca = self.builder.build_alloca(i64_type, param)?;
-                    self.builder
-                        .build_store(alloca, function.get_nth_param(i as u32).unwrap())?;
+                    let value = function.get_nth_param(i as u32).unwrap();
+                    let value = self.value_to_int(&self.builder, value)?;
+                    let alloca = self.builder.build_alloca(value.get_type(), param)?;
+                    self.builder.build_store(alloca, value)?;
+                    if value.get_type() != i64_type {
+                        self.wide_variables.insert(param.clone(), value.get_type());
+                    }
                     self.variables.insert(param.clone(), alloca);
+                    param_slots.push(alloca);
                 }
 
+                // Self-recursive tail calls store the new arguments and jump
+                // back here instead of calling
+                let start = self.context.append_basic_block(function, "start");
+                self.builder.build_unconditional_branch(start)?;
+                self.builder.position_at_end(start);
+                let prev_tail_call = self.tail_call.replace(TailCall {
+                    function: name.clone(),
+                    params: param_slots,
+                    start,
+                });
+
                 // Generate body
                 let _result = self.gen_expr(body)?;
 
@@ -136,147 +1347,181 @@ impl<'ctx> CodeGen<'ctx> {
                     .is_some()
                 {
                     // Always return 0 by default from seppo
-                    let return_value = i64_type.const_int(0, false);
-                    self.builder.build_return(Some(&return_value))?;
+                    self.build_seppo_return(i64_type.const_int(0, false))?;
                 }
 
                 // Restore previous scope
                 self.variables = prev_vars;
+                self.bool_variables = prev_bools;
+                self.wide_variables = prev_wide;
                 self.current_function = prev_function;
+                self.branchless = prev_branchless;
+                self.overflowed = prev_overflowed;
+                self.tail_call = prev_tail_call;
+                self.debug_scope = prev_scope;
+                self.builder.unset_current_debug_location();
 
                 Ok(i64_type.const_int(0, false))
             }
             SeppoExpr::FunctionCall(name, args) => {
-                if let Some(&function) = self.functions.get(name) {
-                    let compiled_args: Vec<_> = args
+                if let Some(signature) = self.c_functions.get(name).cloned() {
+                    self.gen_c_call(&signature, args)
+                } else if let Some(&function) = self.functions.get(name) {
+                    let compiled_args = args
                         .iter()
                         .map(|arg| self.gen_expr(arg))
-                        .collect::<Result<Vec<_>>>()?
-                        .into_iter()
-                        .map(|val| val.into())
-                        .collect();
-
-                    let result = self.builder.build_call(
-                        self.module.get_function(name).unwrap_or(function),
-                        &compiled_args,
-                        "calltmp",
-                    )?;
-                    Ok(result.try_as_basic_value().left().unwrap().into_int_value())
+                        .collect::<Result<Vec<_>>>()?;
+
+                    let target = match self.hot_slots.get(name) {
+                        Some(&slot) => {
+                            // Load the current version from its slot
+                            let ptr_type = self.context.ptr_type(0.into());
+                            let slot = self
+                                .context
+                                .i64_type()
+                                .const_int(slot as u64, false)
+                                .const_to_pointer(ptr_type);
+                            let target = self
+                                .builder
+                                .build_load(ptr_type, slot, "hot_fn")?
+                                .into_pointer_value();
+                            Some(target)
+                        }
+                        None => None,
+                    };
+                    self.call_seppo(&self.builder, function, target, &compiled_args, "calltmp")
+                } else if builtins::is_thread_builtin(name) {
+                    self.gen_thread_builtin(name, args)
+                } else if builtins::is_atomic_builtin(name) {
+                    self.gen_atomic_builtin(name, args)
+                } else if name == STACK_ALLOC {
+                    self.gen_stack_alloc(args)
+                } else if builtins::is_memory_builtin(name) {
+                    self.gen_memory_builtin(name, args)
+                } else if builtins::is_dynamic_builtin(name) {
+                    self.gen_dynamic_builtin(name, args)
+                } else if builtins::is_io_builtin(name) {
+                    self.gen_io_builtin(name, args)
+                } else if builtins::is_option_builtin(name) {
+                    self.gen_option_builtin(name, args)
+                } else if builtins::is_vector_builtin(name) {
+                    self.gen_vector_builtin(name, args)
+                } else if builtins::is_builtin(name) {
+                    self.gen_builtin(name, args)
                 } else {
-                    Err(anyhow!("Undefined function: {}", name))
+                    let builtins = builtins::BUILTINS.iter().map(|(builtin, _)| *builtin);
+                    Err(undefined(
+                        "function",
+                        name,
+                        self.functions.keys().map(String::as_str).chain(builtins),
+                    ))
                 }
             }
             SeppoExpr::Return(value) => {
+                if let SeppoExpr::FunctionCall(name, args) = value.as_ref() {
+                    if self.is_self_call(name) {
+                        return self.gen_tail_call(name, args);
+                    }
+                }
                 let return_value = self.gen_expr(value)?;
+                if matches!(value.as_ref(), SeppoExpr::FunctionCall(name, _) if self.functions.contains_key(name))
+                {
+                    // Let LLVM turn calls of other functions into jumps too
+                    if let Some(call) = return_value
+                        .as_instruction()
+                        .and_then(|call| CallSiteValue::try_from(call).ok())
+                    {
+                        call.set_tail_call(true);
+                    }
+                }
                 if let Some(_) = self.current_function {
-                    self.builder.build_return(Some(&return_value))?;
+                    self.build_seppo_return(return_value)?;
                     // Return the value but don't generate more code after this
                     Ok(return_value)
                 } else {
-                    Err(anyhow!("Return statement outside of function"))
+                    Err(SeppoError::Sema(
+                        "Return statement outside of function".to_string(),
+                    ))
                 }
             }
+            SeppoExpr::Operation(op, ..) if is_logical(op) => {
+                let value = self.gen_bool(expr)?;
+                Ok(self
+                    .builder
+                    .build_int_z_extend(value, self.context.i64_type(), "bool_ext")?)
+            }
             SeppoExpr::Operation(op, left, right) => {
                 let lhs = self.gen_expr(left)?;
                 let rhs = self.gen_expr(right)?;
+                let vector_type = self.vector_type();
+                if lhs.get_type() == vector_type || rhs.get_type() == vector_type {
+                    return self.gen_vector_arithmetic(op, lhs, rhs);
+                }
+
+                let traps = op == "/" && self.division_overflow == Some(DivisionOverflow::Trap);
+                if self.sanitizes(Sanitizer::Undefined) || self.overflow_checks || traps {
+                    if let Some(value) = self.gen_checked_arithmetic(op, lhs, rhs)? {
+                        return Ok(value);
+                    }
+                }
 
                 match op.as_str() {
                     "+" => Ok(self.builder.build_int_add(lhs, rhs, "addtmp")?),
                     "-" => Ok(self.builder.build_int_sub(lhs, rhs, "subtmp")?),
                     "*" => Ok(self.builder.build_int_mul(lhs, rhs, "multmp")?),
-                    "/" => Ok(self.builder.build_int_signed_div(lhs, rhs, "divtmp")?),
-                    ">" => {
-                        let cmp = self.builder.build_int_compare(
-                            inkwell::IntPredicate::SGT,
-                            lhs,
-                            rhs,
-                            "cmptmp",
-                        )?;
-                        Ok(self.builder.build_int_z_extend(
-                            cmp,
-                            self.context.i64_type(),
-                            "bool_ext",
-                        )?)
-                    }
-                    "<" => {
-                        let cmp = self.builder.build_int_compare(
-                            inkwell::IntPredicate::SLT,
-                            lhs,
-                            rhs,
-                            "cmptmp",
-                        )?;
-                        Ok(self.builder.build_int_z_extend(
-                            cmp,
-                            self.context.i64_type(),
-                            "bool_ext",
-                        )?)
-                    }
-                    ">=" => {
-                        let cmp = self.builder.build_int_compare(
-                            inkwell::IntPredicate::SGE,
-                            lhs,
-                            rhs,
-                            "cmptmp",
-                        )?;
-                        Ok(self.builder.build_int_z_extend(
-                            cmp,
-                            self.context.i64_type(),
-                            "bool_ext",
-                        )?)
-                    }
-                    "<=" => {
-                        let cmp = self.builder.build_int_compare(
-                            inkwell::IntPredicate::SLE,
-                            lhs,
-                            rhs,
-                            "cmptmp",
-                        )?;
-                        Ok(self.builder.build_int_z_extend(
-                            cmp,
-                            self.context.i64_type(),
-                            "bool_ext",
-                        )?)
-                    }
-                    "==" => {
-                        let cmp = self.builder.build_int_compare(
-                            inkwell::IntPredicate::EQ,
-                            lhs,
-                            rhs,
-                            "cmptmp",
-                        )?;
-                        Ok(self.builder.build_int_z_extend(
-                            cmp,
-                            self.context.i64_type(),
-                            "bool_ext",
-                        )?)
-                    }
-                    "!=" => {
-                        let cmp = self.builder.build_int_compare(
-                            inkwell::IntPredicate::NE,
-                            lhs,
-                            rhs,
-                            "cmptmp",
-                        )?;
+                    "/" => self.gen_quotient(lhs, rhs),
+                    op if is_comparison(op) => {
+                        let cmp = self.gen_comparison(op, lhs, rhs)?;
                         Ok(self.builder.build_int_z_extend(
                             cmp,
                             self.context.i64_type(),
                             "bool_ext",
                         )?)
                     }
-                    op => {
-                        // Convert to anyhow error directly since we can't create a BuilderError
-                        Err(anyhow!("Unknown operator: {}", op))
-                    }
+                    op => Err(SeppoError::Codegen(format!("Unknown operator: {}", op))),
                 }
-                .map_err(|e| anyhow!(e.to_string()))
             }
-            SeppoExpr::Assignment(name, value) => {
+            // A `let` outside of functions is a global, see `declare_globals`
+            SeppoExpr::Let { .. } if self.current_function.is_none() => {
+                Ok(self.context.i64_type().const_zero())
+            }
+            SeppoExpr::Assignment(name, value) | SeppoExpr::Let { name, value, .. }
+                if self.bool_variables.contains(name) =>
+            {
+                let val = self.gen_bool(value)?;
+                let alloca = match self.variables.get(name) {
+                    Some(ptr) => *ptr,
+                    None => {
+                        let bool_type = self.context.bool_type();
+                        let alloca = match self.current_function {
+                            Some(_) => self.entry_alloca_of(bool_type.into(), name)?,
+                            None => self.builder.build_alloca(bool_type, name)?,
+                        };
+                        self.variables.insert(name.clone(), alloca);
+                        alloca
+                    }
+                };
+                self.builder.build_store(alloca, val)?;
+                Ok(self
+                    .builder
+                    .build_int_z_extend(val, self.context.i64_type(), "bool_ext")?)
+            }
+            SeppoExpr::Assignment(name, value) | SeppoExpr::Let { name, value, .. } => {
                 let val = self.gen_expr(value)?;
 
                 let alloca = if let Some(ptr) = self.variables.get(name) {
                     *ptr
                 } else {
-                    let alloca = self.builder.build_alloca(self.context.i64_type(), name)?;
+                    // The type checker keeps Options and vectors apart, so
+                    // the first value tells what the variable holds
+                    let ty = val.get_type();
+                    let alloca = match self.current_function {
+                        Some(_) => self.entry_alloca_of(ty.into(), name)?,
+                        None => self.builder.build_alloca(ty, name)?,
+                    };
+                    if ty != self.context.i64_type() {
+                        self.wide_variables.insert(name.clone(), ty);
+                    }
                     self.variables.insert(name.clone(), alloca);
                     alloca
                 };
@@ -286,51 +1531,82 @@ impl<'ctx> CodeGen<'ctx> {
             }
             SeppoExpr::Print(format, expr) => {
                 let value = self.gen_expr(expr)?;
-
-                let printf = self.module.get_function("printf").unwrap();
-
-                // Choose format based on the print type
-                let format_string = match format {
-                    PrintFormat::Hex => self
-                        .builder
-                        .build_global_string_ptr("0x%lx\n", "format_string")?,
-                    PrintFormat::Decimal => self
-                        .builder
-                        .build_global_string_ptr("%ld\n", "format_string")?,
-                };
-
-                self.builder.build_call(
-                    printf,
-                    &[format_string.as_pointer_value().into(), value.into()],
-                    "printf_call",
-                )?;
-
+                match format {
+                    PrintFormat::Decimal | PrintFormat::Hex | PrintFormat::Stderr => {
+                        self.gen_print_int(format, value)?
+                    }
+                    PrintFormat::Bool => self.gen_print_bool(value)?,
+                    PrintFormat::Char => {
+                        let print_char = self.print_char_function()?;
+                        self.builder
+                            .build_call(print_char, &[value.into()], "print_call")?;
+                    }
+                }
                 Ok(value)
             }
             SeppoExpr::Block(expressions) => {
                 let mut last_value = self.context.i64_type().const_int(0, false);
                 for expr in expressions {
+                    if let SeppoExpr::Location { file, line } = expr {
+                        if self.coverage {
+                            self.count_statement(file, *line)?;
+                        }
+                        self.location = Some((file.clone(), *line));
+                        self.set_debug_line(*line);
+                        ice::enter_location(file, *line);
+                        continue;
+                    }
+                    if expr.is_trivia() {
+                        continue;
+                    }
+                    if let SeppoExpr::Attribute { name, args } = expr {
+                        if name == UNROLL_ATTRIBUTE {
+                            self.unroll = args.first().and_then(|count| count.parse().ok());
+                        }
+                        continue;
+                    }
+                    ice::enter_statement(expr);
                     last_value = self.gen_expr(expr)?;
                     // Don't generate code after a return instruction
-                    if matches!(expr, SeppoExpr::Return(_)) {
+                    if matches!(expr, SeppoExpr::Return(_) | SeppoExpr::Become(..)) {
                         break;
                     }
                 }
                 Ok(last_value)
             }
             SeppoExpr::InlineC(code) => {
-                // Create a unique temporary directory
-                let timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos();
-                let pid = process::id();
-                let temp_dir =
-                    env::temp_dir().join(format!("seppolang_extern_{}_{}", pid, timestamp));
-                fs::create_dir_all(&temp_dir)?;
+                // An AST loaded from JSON skips the parser's check
+                if sandbox::no_extern() {
+                    return Err(SeppoError::Codegen(sandbox::CEPPO_DISABLED.to_string()));
+                }
+                // Compile the C file
+                let mut compiler = match &self.c_compiler {
+                    _ if self.lto => self.target.bitcode_compiler(),
+                    Some(c_compiler) => self.target.c_compiler_with(c_compiler),
+                    None => self.target.c_compiler(),
+                };
+                compiler.args(self.c_codegen_flags()).args(&self.c_flags);
+                let program = compiler.get_program().to_string_lossy().into_owned();
+                let flags: Vec<String> = compiler
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect();
 
+                let cache = match &self.ceppo_cache {
+                    Some(cache) if !includes_local_header(code) => Some(cache.clone()),
+                    _ => None,
+                };
+                let temp_dir = match &cache {
+                    Some(cache) => {
+                        let version = self.compiler_version(&program, &flags)?;
+                        let dir = cache.join(ceppo_hash(code, &program, &version, &flags));
+                        fs::create_dir_all(&dir)?;
+                        dir
+                    }
+                    None => self.temps.dir("seppolang_extern")?,
+                };
                 let c_file = temp_dir.join("inline.c");
-                let o_file = temp_dir.join("inline.o");
+                let o_file = temp_dir.join(if self.lto { "inline.bc" } else { "inline.o" });
 
                 // Write the C code to a file with proper headers
                 let c_code = format!(
@@ -342,110 +1618,114 @@ impl<'ctx> CodeGen<'ctx> {
                      {}\n",
                     code.trim()
                 );
-                std::fs::write(&c_file, c_code)?;
+                let cached = cache.is_some() && o_file.exists();
+                if !cached {
+                    std::fs::write(&c_file, c_code)?;
+                    compiler.arg("-o").arg(&o_file).arg(&c_file);
+                    let output = timing::time(Phase::Ceppo, || compiler.output())?;
 
-                // Compile the C file
-                let output = std::process::Command::new("cc")
-                    .arg("-c")
-                    .arg("-fPIC")
-                    .arg("-o")
-                    .arg(&o_file)
-                    .arg(&c_file)
-                    .output()?;
-
-                if !output.status.success() {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    return Err(anyhow!("Failed to compile C code: {}", stderr));
+                    if !output.status.success() {
+                        // Nothing is left for a later build to mistake for a result
+                        let _ = fs::remove_file(&o_file);
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        return Err(SeppoError::Codegen(format!(
+                            "Failed to compile C code: {}",
+                            stderr
+                        )));
+                    }
                 }
 
-                // Clean up C file
-                fs::remove_file(c_file)?;
-
                 // Store the object file path for later linking
                 let o_file_abs = fs::canonicalize(&o_file)?; // Get absolute path
-                self.c_object_files.push(o_file_abs);
-
-                println!("Added C object file: {:?}", o_file);
-
-                // Extract function declarations from the C code
-                let code = code.trim();
-
-                // Find function declarations by looking for opening parenthesis
-                let mut pos = 0;
-                while let Some(paren_pos) = code[pos..].find('(') {
-                    let start_pos = pos + paren_pos;
-
-                    // Look backwards for the function name and return type
-                    let before_paren = &code[..start_pos];
-                    if let Some(name_start) = before_paren.rfind(|c: char| c.is_whitespace()) {
-                        let func_name = before_paren[name_start + 1..].trim();
-
-                        // Find the closing parenthesis
-                        if let Some(params_end) = code[start_pos..].find(')') {
-                            let params = code[start_pos + 1..start_pos + params_end].trim();
-
-                            // Count parameters by counting commas + 1 (if not empty)
-                            let param_count = if params.is_empty() {
-                                0
-                            } else {
-                                params.split(',').count()
-                            };
-
-                            // Create function type - always use i64 for now
-                            let i64_type = self.context.i64_type();
-                            let param_types = vec![i64_type.into(); param_count];
-                            let fn_type = i64_type.fn_type(&param_types, false);
-
-                            // Declare the function with external linkage
-                            let function = self.module.add_function(
-                                func_name,
-                                fn_type,
-                                Some(inkwell::module::Linkage::External),
-                            );
-
-                            // Store in our functions map
-                            self.functions.insert(func_name.to_string(), function);
-                        }
-                    }
+                if self.lto {
+                    self.c_bitcode_files.push(o_file_abs.clone());
+                } else {
+                    self.c_object_files.push(o_file_abs.clone());
+                }
+
+                self.ceppo_builds.push(CeppoBuild {
+                    object: o_file_abs,
+                    compiler: program,
+                    flags,
+                });
 
-                    pos = start_pos + 1;
+                for signature in parse_signatures(code.trim(), &self.target) {
+                    self.declare_c_function(signature);
                 }
 
                 Ok(self.context.i64_type().const_int(0, false))
             }
+            SeppoExpr::Macro(name, ..) => {
+                Err(SeppoError::Sema(format!("Macro {} was not expanded", name)))
+            }
+            SeppoExpr::ConstFunction(name, ..) => Err(SeppoError::Sema(format!(
+                "const fn {} was not folded",
+                name
+            ))),
+            SeppoExpr::Comment { .. }
+            | SeppoExpr::BlankLine
+            | SeppoExpr::DocComment(_)
+            | SeppoExpr::Attribute { .. }
+            | SeppoExpr::Signature(_)
+            | SeppoExpr::Location { .. } => Ok(self.context.i64_type().const_int(0, false)),
             SeppoExpr::String(s) => {
-                // Create a global string constant
-                let str_ptr = self
-                    .builder
-                    .build_global_string_ptr(s, "str_const")?
-                    .as_pointer_value();
+                let str_ptr = self.string_constant(s)?;
 
                 // Convert pointer to i64 for passing to C functions
                 Ok(self
                     .builder
                     .build_ptr_to_int(str_ptr, self.context.i64_type(), "str_ptr")?)
             }
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => self.gen_for(variable, iterable, body),
+            SeppoExpr::In(value, range) => {
+                let inside = self.gen_in(value, range)?;
+                Ok(self
+                    .builder
+                    .build_int_z_extend(inside, self.context.i64_type(), "bool_ext")?)
+            }
+            SeppoExpr::Become(name, args) => {
+                if !self.is_self_call(name) {
+                    return Err(SeppoError::Sema(format!(
+                        "become can only call the function it is in, not {}",
+                        name
+                    )));
+                }
+                self.gen_tail_call(name, args)
+            }
+            SeppoExpr::Range { .. } => Err(SeppoError::Sema(RANGE_OUTSIDE_LOOP.to_string())),
             SeppoExpr::Conditional {
                 condition,
                 true_block,
                 false_block,
             } => {
                 // Get current function
-                let current_fn = self
-                    .current_function
-                    .ok_or_else(|| anyhow!("Conditional block outside of function"))?;
-
-                // Generate condition code
-                let cond_value = self.gen_expr(condition)?;
-
-                // Convert condition to boolean (i1)
-                let zero = self.context.i64_type().const_int(0, false);
-                let cond_bool = self.builder.build_int_compare(
-                    inkwell::IntPredicate::NE,
-                    cond_value,
-                    zero,
-                    "cond",
-                )?;
+                let current_fn = self.current_function.ok_or_else(|| {
+                    SeppoError::Sema("Conditional block outside of function".to_string())
+                })?;
+
+                let cond_bool = self.gen_bool(condition)?;
+                if self.branchless {
+                    // Sema only lets through a choice between two values
+                    // that can both be computed
+                    let then_val = self.gen_expr(true_block)?;
+                    let else_val = match false_block {
+                        Some(false_block) => self.gen_expr(false_block)?,
+                        None => self.context.i64_type().const_zero(),
+                    };
+                    if then_val.get_type() != else_val.get_type() {
+                        return Err(SeppoError::Codegen(
+                            "mismatched branch types in conditional".to_string(),
+                        ));
+                    }
+                    return Ok(self
+                        .builder
+                        .build_select(cond_bool, then_val, else_val, "select")?
+                        .into_int_value());
+                }
 
                 // Create basic blocks
                 let then_bb = self.context.append_basic_block(current_fn, "then");
@@ -456,166 +1736,2874 @@ impl<'ctx> CodeGen<'ctx> {
                 self.builder
                     .build_conditional_branch(cond_bool, then_bb, else_bb)?;
 
-                // Save current variables state
+                // Variables live in stack slots of the entry block, so a
+                // branch assigning one stores to the same slot and nothing
+                // needs merging. Only variables first assigned inside a
+                // branch are dropped again, they don't outlive it.
                 let entry_vars = self.variables.clone();
 
-                // Generate then block
                 self.builder.position_at_end(then_bb);
                 let then_val = self.gen_expr(true_block)?;
-                let then_block = self.builder.get_insert_block().unwrap();
-                let then_vars = self.variables.clone();
-                if !then_block.get_terminator().is_some() {
-                    self.builder.build_unconditional_branch(merge_bb)?;
-                }
-
-                // Restore entry variables for else block
+                let then_end = self.branch_to(merge_bb)?;
                 self.variables = entry_vars.clone();
 
-                // Generate else block
                 self.builder.position_at_end(else_bb);
-                let else_val = if let Some(false_block) = false_block {
-                    self.gen_expr(false_block)?
-                } else {
-                    self.context.i64_type().const_int(0, false)
+                let else_val = match false_block {
+                    Some(false_block) => self.gen_expr(false_block)?,
+                    None => self.context.i64_type().const_zero(),
                 };
-                let else_block = self.builder.get_insert_block().unwrap();
-                let else_vars = self.variables.clone();
-                if !else_block.get_terminator().is_some() {
-                    self.builder.build_unconditional_branch(merge_bb)?;
-                }
+                let else_end = self.branch_to(merge_bb)?;
+                self.variables = entry_vars;
 
-                // Generate merge block
+                // The value of the conditional is that of the branch taken,
+                // an Option or a vector when both branches give one. Branches
+                // ending in statements on values of different types, like
+                // assignments, leave it without one, which the type checker
+                // only lets through for conditionals not used as values.
                 self.builder.position_at_end(merge_bb);
-
-                // Create phi nodes for modified variables
-                let mut merged_vars = HashMap::new();
-                let all_vars: std::collections::HashSet<_> = then_vars
-                    .keys()
-                    .chain(else_vars.keys())
-                    .filter(|k| {
-                        then_vars.get(*k) != entry_vars.get(*k)
-                            || else_vars.get(*k) != entry_vars.get(*k)
-                    })
+                let reaching: Vec<_> = [(then_end, then_val), (else_end, else_val)]
+                    .into_iter()
+                    .filter(|(end, _)| end.is_some())
+                    .map(|(_, value)| value.get_type())
                     .collect();
+                let value_type = match reaching.as_slice() {
+                    [ty] => *ty,
+                    [then_type, else_type] if then_type == else_type => *then_type,
+                    [_, _] => return Ok(self.context.i64_type().const_zero()),
+                    _ => self.context.i64_type(),
+                };
+                let incoming: Vec<(&dyn BasicValue, BasicBlock)> = [
+                    then_end.map(|block| (&then_val as &dyn BasicValue, block)),
+                    else_end.map(|block| (&else_val as &dyn BasicValue, block)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+                if incoming.is_empty() {
+                    // Both branches return, nothing reaches the merge block
+                    return Ok(self.context.i64_type().const_zero());
+                }
+                let phi = self.builder.build_phi(value_type, "merge_val")?;
+                phi.add_incoming(&incoming);
+                Ok(phi.as_basic_value().into_int_value())
+            }
+        }
+    }
 
-                for var_name in all_vars {
-                    let var_type = self.context.i64_type();
-                    let phi = self
-                        .builder
-                        .build_phi(var_type, &format!("{}_phi", var_name))?;
-                    let mut incoming = Vec::new();
-
-                    // Load values from both branches if they exist and reach here
-                    if let Some(&then_var) = then_vars.get(var_name) {
-                        if then_block.get_terminator().unwrap().get_opcode()
-                            != inkwell::values::InstructionOpcode::Return
-                        {
-                            let then_val = self.builder.build_load(
-                                var_type,
-                                then_var,
-                                &format!("{}_then", var_name),
-                            )?;
-                            incoming.push((then_val, then_block));
-                        }
-                    }
+    /// Branch from the current block to `target` unless it already ends in
+    /// a return or a tail call. Returns the block when it branches.
+    fn branch_to(&self, target: BasicBlock<'ctx>) -> Result<Option<BasicBlock<'ctx>>> {
+        let block = self.builder.get_insert_block().unwrap();
+        if block.get_terminator().is_some() {
+            return Ok(None);
+        }
+        self.builder.build_unconditional_branch(target)?;
+        Ok(Some(block))
+    }
 
-                    if let Some(&else_var) = else_vars.get(var_name) {
-                        if else_block.get_terminator().unwrap().get_opcode()
-                            != inkwell::values::InstructionOpcode::Return
-                        {
-                            let else_val = self.builder.build_load(
-                                var_type,
-                                else_var,
-                                &format!("{}_else", var_name),
-                            )?;
-                            incoming.push((else_val, else_block));
-                        }
-                    }
+    /// Lower `for variable in iterable` into a counting loop: over the numbers
+    /// of a range, or over the indices of a string up to its terminating NUL
+    fn gen_for(
+        &mut self,
+        variable: &str,
+        iterable: &SeppoExpr,
+        body: &SeppoExpr,
+    ) -> Result<IntValue<'ctx>> {
+        let current_fn = self
+            .current_function
+            .ok_or_else(|| SeppoError::Sema("for loop outside of function".to_string()))?;
+        let unroll = self.unroll.take();
+        let i64_type = self.context.i64_type();
+        let i8_type = self.context.i8_type();
+        let ptr_type = self.context.ptr_type(0.into());
 
-                    if !incoming.is_empty() {
-                        let incoming_refs: Vec<_> = incoming
-                            .iter()
-                            .map(|(val, block)| (&*val as &dyn BasicValue, *block))
-                            .collect();
-                        phi.add_incoming(&incoming_refs);
-
-                        // Store phi result in the original variable
-                        if let Some(&var) = entry_vars.get(var_name) {
-                            self.builder.build_store(var, phi.as_basic_value())?;
-                            merged_vars.insert(var_name.clone(), var);
-                        }
-                    }
-                }
+        // First index, the bound and, for strings, the bytes to read
+        let (first, limit, inclusive, string) = match iterable {
+            SeppoExpr::Range {
+                start,
+                end,
+                inclusive,
+            } => (self.gen_expr(start)?, self.gen_expr(end)?, *inclusive, None),
+            other => {
+                let string = self.gen_expr(other)?;
+                let string = self.builder.build_int_to_ptr(string, ptr_type, "iter")?;
+                let strlen =
+                    self.libc_function("strlen", i64_type.fn_type(&[ptr_type.into()], false));
+                let length = self
+                    .builder
+                    .build_call(strlen, &[string.into()], "iter_len")?
+                    .try_as_basic_value()
+                    .left()
+                    .unwrap()
+                    .into_int_value();
+                (i64_type.const_zero(), length, false, Some(string))
+            }
+        };
 
-                // Update variables with merged values
-                self.variables = entry_vars;
-                self.variables.extend(merged_vars);
+        // Allocas go in the entry block so the loop doesn't grow the stack
+        let index = self.entry_alloca("index")?;
+        self.builder.build_store(index, first)?;
+        let entry_vars = self.variables.clone();
+        let element = self.entry_alloca(variable)?;
+        self.variables.insert(variable.to_string(), element);
+
+        let cond_bb = self.context.append_basic_block(current_fn, "for_cond");
+        let body_bb = self.context.append_basic_block(current_fn, "for_body");
+        let step_bb = self.context.append_basic_block(current_fn, "for_step");
+        let next_bb = self.context.append_basic_block(current_fn, "for_next");
+        let exit_bb = self.context.append_basic_block(current_fn, "for_exit");
+        self.builder.build_unconditional_branch(cond_bb)?;
+
+        self.builder.position_at_end(cond_bb);
+        let i = self
+            .builder
+            .build_load(i64_type, index, "i")?
+            .into_int_value();
+        let predicate = if inclusive {
+            IntPredicate::SLE
+        } else {
+            IntPredicate::SLT
+        };
+        let more = self
+            .builder
+            .build_int_compare(predicate, i, limit, "more")?;
+        self.builder
+            .build_conditional_branch(more, body_bb, exit_bb)?;
 
-                // Create phi node for the block result
-                let phi = self
+        self.builder.position_at_end(body_bb);
+        let value = match string {
+            Some(string) => {
+                let byte_ptr =
+                    unsafe { self.builder.build_gep(i8_type, string, &[i], "byte_ptr")? };
+                let byte = self
                     .builder
-                    .build_phi(self.context.i64_type(), "merge_val")?;
-                let mut incoming = Vec::new();
+                    .build_load(i8_type, byte_ptr, "byte")?
+                    .into_int_value();
+                self.builder.build_int_z_extend(byte, i64_type, variable)?
+            }
+            None => i,
+        };
+        self.builder.build_store(element, value)?;
+        self.gen_expr(body)?;
+        if self
+            .builder
+            .get_insert_block()
+            .unwrap()
+            .get_terminator()
+            .is_none()
+        {
+            self.builder.build_unconditional_branch(step_bb)?;
+        }
 
-                if then_block.get_terminator().unwrap().get_opcode()
-                    != inkwell::values::InstructionOpcode::Return
-                {
-                    incoming.push((then_val, then_block));
+        // An inclusive range stops at its end instead of incrementing past
+        // it, which would overflow for `..=` up to the largest number
+        self.builder.position_at_end(step_bb);
+        let i = self
+            .builder
+            .build_load(i64_type, index, "i")?
+            .into_int_value();
+        if inclusive {
+            let done = self
+                .builder
+                .build_int_compare(IntPredicate::EQ, i, limit, "done")?;
+            self.builder
+                .build_conditional_branch(done, exit_bb, next_bb)?;
+        } else {
+            self.builder.build_unconditional_branch(next_bb)?;
+        }
+
+        self.builder.position_at_end(next_bb);
+        let next = self
+            .builder
+            .build_int_add(i, i64_type.const_int(1, false), "next")?;
+        self.builder.build_store(index, next)?;
+        let back_edge = self.builder.build_unconditional_branch(cond_bb)?;
+        if let Some(count) = unroll {
+            let kind = self.context.get_kind_id("llvm.loop");
+            back_edge
+                .set_metadata(self.unroll_metadata(count), kind)
+                .map_err(|e| SeppoError::Codegen(e.to_string()))?;
+            self.unroll_hints = true;
+        }
+
+        // Variables first assigned in the body go out of scope with the loop
+        self.builder.position_at_end(exit_bb);
+        self.variables = entry_vars;
+        Ok(i64_type.const_zero())
+    }
+
+    /// The `!llvm.loop` node asking LLVM to unroll a loop `count` times. A
+    /// loop's node lists itself first, which takes a placeholder replaced
+    /// once the node exists.
+    fn unroll_metadata(&self, count: u32) -> MetadataValue<'ctx> {
+        let hint = self.context.metadata_node(&[
+            self.context
+                .metadata_string("llvm.loop.unroll.count")
+                .into(),
+            self.context
+                .i32_type()
+                .const_int(count as u64, false)
+                .into(),
+        ]);
+        let context = self.context.raw();
+        unsafe {
+            let placeholder = LLVMTemporaryMDNode(context, std::ptr::null_mut(), 0);
+            let mut operands = [placeholder, LLVMValueAsMetadata(hint.as_value_ref())];
+            let node = LLVMMDNodeInContext2(context, operands.as_mut_ptr(), operands.len());
+            LLVMMetadataReplaceAllUsesWith(placeholder, node);
+            MetadataValue::new(LLVMMetadataAsValue(context, node))
+        }
+    }
+
+    /// Declare a function of a ceppo block with its C types. Functions whose
+    /// types seppo cannot pass are only remembered, so calling them is an
+    /// error that names the type.
+    fn declare_c_function(&mut self, signature: CSignature) {
+        let supported =
+            signature.ret.is_supported() && signature.params.iter().all(CType::is_supported);
+        if supported && self.module.get_function(&signature.name).is_none() {
+            let params: Vec<_> = signature
+                .params
+                .iter()
+                .map(|param| self.c_basic_type(param).into())
+                .collect();
+            let fn_type = match signature.ret {
+                CType::Void => self
+                    .context
+                    .void_type()
+                    .fn_type(&params, signature.variadic),
+                ref ret => self.c_basic_type(ret).fn_type(&params, signature.variadic),
+            };
+            let function =
+                self.module
+                    .add_function(&signature.name, fn_type, Some(Linkage::External));
+            // C callers extend narrow integers, and callees may rely on it
+            for (i, param) in signature.params.iter().enumerate() {
+                if let Some(kind) = extension_attribute(param) {
+                    function
+                        .add_attribute(AttributeLoc::Param(i as u32), self.enum_attribute(kind));
                 }
-                if else_block.get_terminator().unwrap().get_opcode()
-                    != inkwell::values::InstructionOpcode::Return
-                {
-                    incoming.push((else_val, else_block));
+            }
+            if let Some(kind) = extension_attribute(&signature.ret) {
+                function.add_attribute(AttributeLoc::Return, self.enum_attribute(kind));
+            }
+        }
+        if let Some(function) = self.module.get_function(&signature.name) {
+            self.functions.insert(signature.name.clone(), function);
+        }
+        self.c_functions.insert(signature.name.clone(), signature);
+    }
+
+    fn c_basic_type(&self, ty: &CType) -> BasicTypeEnum<'ctx> {
+        match ty {
+            CType::Int { bits, .. } => self.context.custom_width_int_type(*bits).into(),
+            CType::Pointer => self.context.ptr_type(0.into()).into(),
+            CType::Void | CType::Unsupported(_) => unreachable!("only declared when supported"),
+        }
+    }
+
+    fn enum_attribute(&self, name: &str) -> Attribute {
+        self.context
+            .create_enum_attribute(Attribute::get_named_enum_kind_id(name), 0)
+    }
+
+    /// Call a function of a ceppo block. String literals are passed as
+    /// pointers, other values are converted from seppo's i64 to the type
+    /// of each parameter, and the result back to i64.
+    fn gen_c_call(&mut self, signature: &CSignature, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        let name = &signature.name;
+        if let Some(CType::Unsupported(ty)) = std::iter::once(&signature.ret)
+            .chain(&signature.params)
+            .find(|ty| !ty.is_supported())
+        {
+            return Err(SeppoError::Sema(format!(
+                "C function {} uses the type {}, which seppo cannot pass or return",
+                name, ty
+            )));
+        }
+        let count_ok = if signature.variadic {
+            args.len() >= signature.params.len()
+        } else {
+            args.len() == signature.params.len()
+        };
+        if !count_ok {
+            return Err(SeppoError::Sema(format!(
+                "Function {} expects {}{} arguments, got {}",
+                name,
+                if signature.variadic { "at least " } else { "" },
+                signature.params.len(),
+                args.len()
+            )));
+        }
+
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let mut values = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            let value: BasicMetadataValueEnum = match (signature.params.get(i), arg) {
+                (Some(CType::Pointer) | None, SeppoExpr::String(text)) => {
+                    self.string_constant(text)?.into()
+                }
+                (Some(CType::Int { .. }), SeppoExpr::String(_)) => {
+                    return Err(SeppoError::Sema(format!(
+                        "Argument {} of {} is a string, but the C function takes an integer",
+                        i + 1,
+                        name
+                    )))
+                }
+                (Some(CType::Pointer), _) => {
+                    let value = self.gen_expr(arg)?;
+                    self.builder
+                        .build_int_to_ptr(value, ptr_type, "arg")?
+                        .into()
                 }
+                (Some(CType::Int { bits, .. }), _) if *bits < 64 => {
+                    let value = self.gen_expr(arg)?;
+                    let ty = self.context.custom_width_int_type(*bits);
+                    self.builder.build_int_truncate(value, ty, "arg")?.into()
+                }
+                _ => self.gen_expr(arg)?.into(),
+            };
+            values.push(value);
+        }
 
-                if !incoming.is_empty() {
-                    let incoming_refs: Vec<_> = incoming
-                        .iter()
-                        .map(|(val, block)| (&*val as &dyn BasicValue, *block))
-                        .collect();
-                    phi.add_incoming(&incoming_refs);
-                    Ok(phi.as_basic_value().into_int_value())
+        let function = self.functions[name];
+        let call = self.builder.build_call(function, &values, "calltmp")?;
+        Ok(match (&signature.ret, call.try_as_basic_value().left()) {
+            (CType::Int { bits, signed }, Some(value)) if *bits < 64 => {
+                let value = value.into_int_value();
+                if *signed {
+                    self.builder.build_int_s_extend(value, i64_type, "ret")?
                 } else {
-                    Ok(self.context.i64_type().const_int(0, false))
+                    self.builder.build_int_z_extend(value, i64_type, "ret")?
                 }
             }
+            (CType::Pointer, Some(value)) => {
+                self.builder
+                    .build_ptr_to_int(value.into_pointer_value(), i64_type, "ret")?
+            }
+            (_, Some(value)) => value.into_int_value(),
+            (_, None) => i64_type.const_zero(),
+        })
+    }
+
+    /// A pointer to `text` as a NUL-terminated constant, shared by every use
+    /// of the same text in the module. The bytes are stored as they are, so
+    /// any UTF-8 survives, but an embedded NUL would cut the string short
+    /// for the C functions it is passed to and is rejected.
+    fn string_constant(&mut self, text: &str) -> Result<PointerValue<'ctx>> {
+        if let Some(global) = self.strings.get(text) {
+            return Ok(global.as_pointer_value());
+        }
+        if text.contains('\0') {
+            return Err(SeppoError::Sema(format!(
+                "String literals cannot contain NUL characters: {:?}",
+                text
+            )));
         }
+        let value = self.context.const_string(text.as_bytes(), true);
+        let global = self.module.add_global(value.get_type(), None, ".str");
+        global.set_initializer(&value);
+        global.set_constant(true);
+        global.set_linkage(Linkage::Private);
+        global.set_unnamed_addr(true);
+        global.set_alignment(1);
+        self.strings.insert(text.to_string(), global);
+        Ok(global.as_pointer_value())
     }
 
-    pub fn get_module(&self) -> &Module<'ctx> {
-        &self.module
+    /// `value in start..end` as an `i1`
+    fn gen_in(&mut self, value: &SeppoExpr, range: &SeppoExpr) -> Result<IntValue<'ctx>> {
+        let SeppoExpr::Range {
+            start,
+            end,
+            inclusive,
+        } = range
+        else {
+            return Err(SeppoError::Sema(
+                "The right side of `in` must be a range".to_string(),
+            ));
+        };
+        let value = self.gen_expr(value)?;
+        let start = self.gen_expr(start)?;
+        let end = self.gen_expr(end)?;
+
+        let above = self
+            .builder
+            .build_int_compare(IntPredicate::SGE, value, start, "above")?;
+        let predicate = if *inclusive {
+            IntPredicate::SLE
+        } else {
+            IntPredicate::SLT
+        };
+        let below = self
+            .builder
+            .build_int_compare(predicate, value, end, "below")?;
+        Ok(self.builder.build_and(above, below, "in_range")?)
     }
 
-    pub fn write_object_file(&self, output: &Path) -> Result<()> {
-        // Get host target triple
-        let target_triple = TargetMachine::get_default_triple();
-        let cpu = TargetMachine::get_host_cpu_name().to_string();
-        let features = TargetMachine::get_host_cpu_features().to_string();
+    /// Generate a condition as an `i1`. Comparisons, `in`, `&&`, `||` and
+    /// bool variables produce one directly, any other value is true when
+    /// it isn't zero.
+    fn gen_bool(&mut self, expr: &SeppoExpr) -> Result<IntValue<'ctx>> {
+        match expr {
+            SeppoExpr::Operation(op, left, right) if is_comparison(op) => {
+                let lhs = self.gen_expr(left)?;
+                let rhs = self.gen_expr(right)?;
+                self.gen_comparison(op, lhs, rhs)
+            }
+            SeppoExpr::Operation(op, left, right) if is_logical(op) => {
+                self.gen_logical(op, left, right)
+            }
+            SeppoExpr::In(value, range) => self.gen_in(value, range),
+            SeppoExpr::FunctionCall(name, args)
+                if matches!(name.as_str(), "is_some" | "is_none")
+                    && !self.functions.contains_key(name)
+                    && !self.c_functions.contains_key(name) =>
+            {
+                self.gen_is_some(name, args)
+            }
+            SeppoExpr::Variable(name) if self.bool_variables.contains(name) => {
+                let ptr = self.variables.get(name).copied().ok_or_else(|| {
+                    undefined("variable", name, self.variables.keys().map(String::as_str))
+                })?;
+                let load = self
+                    .builder
+                    .build_load(self.context.bool_type(), ptr, name)?;
+                Ok(load.into_int_value())
+            }
+            _ => {
+                let value = self.gen_expr(expr)?;
+                let zero = self.context.i64_type().const_zero();
+                Ok(self
+                    .builder
+                    .build_int_compare(IntPredicate::NE, value, zero, "cond")?)
+            }
+        }
+    }
 
-        // Initialize target
-        let target = Target::from_triple(&target_triple)
-            .map_err(|e| anyhow!("Failed to get target: {}", e))?;
+    /// `left && right` or `left || right` as an `i1`. The right side is
+    /// only evaluated when the left one doesn't decide the result, except
+    /// in constant-time functions, which evaluate both rather than branch.
+    fn gen_logical(
+        &mut self,
+        op: &str,
+        left: &SeppoExpr,
+        right: &SeppoExpr,
+    ) -> Result<IntValue<'ctx>> {
+        let lhs = self.gen_bool(left)?;
+        if self.branchless {
+            let rhs = self.gen_bool(right)?;
+            return Ok(match op {
+                "&&" => self.builder.build_and(lhs, rhs, "and")?,
+                _ => self.builder.build_or(lhs, rhs, "or")?,
+            });
+        }
+        let current_fn = self
+            .current_function
+            .ok_or_else(|| SeppoError::Sema(format!("{} outside of function", op)))?;
+        let lhs_end = self.builder.get_insert_block().unwrap();
+        let rhs_bb = self.context.append_basic_block(current_fn, "rhs");
+        let merge_bb = self.context.append_basic_block(current_fn, "logic_merge");
+        let is_or = op == "||";
+        // `||` is decided by a true left side and `&&` by a false one
+        if is_or {
+            self.builder
+                .build_conditional_branch(lhs, merge_bb, rhs_bb)?;
+        } else {
+            self.builder
+                .build_conditional_branch(lhs, rhs_bb, merge_bb)?;
+        }
 
-        // Create target machine
-        let target_machine = target
-            .create_target_machine(
-                &target_triple,
-                &cpu,
-                &features,
-                inkwell::OptimizationLevel::Default,
-                RelocMode::Default,
-                CodeModel::Default,
-            )
-            .ok_or_else(|| anyhow!("Failed to create target machine"))?;
+        self.builder.position_at_end(rhs_bb);
+        let rhs = self.gen_bool(right)?;
+        let rhs_end = self.builder.get_insert_block().unwrap();
+        self.builder.build_unconditional_branch(merge_bb)?;
 
-        // Write object file
-        target_machine
-            .write_to_file(&self.module, FileType::Object, output)
-            .map_err(|e| anyhow!("Failed to write object file: {}", e))
+        self.builder.position_at_end(merge_bb);
+        let bool_type = self.context.bool_type();
+        let phi = self.builder.build_phi(bool_type, "logic")?;
+        let decided = bool_type.const_int(is_or as u64, false);
+        phi.add_incoming(&[
+            (&decided as &dyn BasicValue, lhs_end),
+            (&rhs as &dyn BasicValue, rhs_end),
+        ]);
+        Ok(phi.as_basic_value().into_int_value())
     }
 
-    #[allow(dead_code)]
-    pub fn c_object_files(&self) -> &[std::path::PathBuf] {
-        &self.c_object_files
+    fn gen_comparison(
+        &mut self,
+        op: &str,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let predicate = match op {
+            ">" => IntPredicate::SGT,
+            "<" => IntPredicate::SLT,
+            ">=" => IntPredicate::SGE,
+            "<=" => IntPredicate::SLE,
+            "==" => IntPredicate::EQ,
+            "!=" => IntPredicate::NE,
+            op => return Err(SeppoError::Codegen(format!("Unknown operator: {}", op))),
+        };
+        Ok(self
+            .builder
+            .build_int_compare(predicate, lhs, rhs, "cmptmp")?)
+    }
+
+    /// Add one to a new coverage counter for the statement at `file:line`
+    fn count_statement(&mut self, file: &str, line: usize) -> Result<()> {
+        let i64_type = self.context.i64_type();
+        let name = format!("seppo.coverage.{}", self.coverage_counters.len());
+        let counter = self.module.add_global(i64_type, None, &name);
+        counter.set_initializer(&i64_type.const_zero());
+        counter.set_linkage(Linkage::Internal);
+
+        let ptr = counter.as_pointer_value();
+        let hits = self
+            .builder
+            .build_load(i64_type, ptr, "hits")?
+            .into_int_value();
+        let hits = self
+            .builder
+            .build_int_add(hits, i64_type.const_int(1, false), "hits")?;
+        self.builder.build_store(ptr, hits)?;
+        self.coverage_counters
+            .push((file.to_string(), line, counter));
+        Ok(())
+    }
+
+    /// The function registered with atexit that appends one
+    /// `hits<TAB>line<TAB>file` record per source line to the coverage file.
+    /// A line with several statements counts as often as the one run most.
+    fn build_coverage_dump(&mut self) -> Result<FunctionValue<'ctx>> {
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let getenv = self.libc_function("getenv", ptr_type.fn_type(&[ptr_type.into()], false));
+        let fopen = self.libc_function(
+            "fopen",
+            ptr_type.fn_type(&[ptr_type.into(), ptr_type.into()], false),
+        );
+        let fprintf = self.libc_function(
+            "fprintf",
+            i32_type.fn_type(&[ptr_type.into(), ptr_type.into()], true),
+        );
+        let fclose = self.libc_function("fclose", i32_type.fn_type(&[ptr_type.into()], false));
+
+        let function = self.module.add_function(
+            "seppo.coverage_dump",
+            self.context.void_type().fn_type(&[], false),
+            Some(Linkage::Internal),
+        );
+        let entry = self.context.append_basic_block(function, "entry");
+        let write_bb = self.context.append_basic_block(function, "write");
+        let done_bb = self.context.append_basic_block(function, "done");
+
+        self.builder.position_at_end(entry);
+        let variable = self
+            .builder
+            .build_global_string_ptr(COVERAGE_FILE_VAR, "coverage_env")?;
+        let path = self
+            .builder
+            .build_call(getenv, &[variable.as_pointer_value().into()], "path")?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value();
+        let default_path = self
+            .builder
+            .build_global_string_ptr(DEFAULT_COVERAGE_FILE, "coverage_default")?;
+        let unset = self.builder.build_is_null(path, "unset")?;
+        let path =
+            self.builder
+                .build_select(unset, default_path.as_pointer_value(), path, "path")?;
+        let append = self.builder.build_global_string_ptr("a", "append")?;
+        let file = self
+            .builder
+            .build_call(
+                fopen,
+                &[path.into(), append.as_pointer_value().into()],
+                "file",
+            )?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value();
+        let failed = self.builder.build_is_null(file, "failed")?;
+        self.builder
+            .build_conditional_branch(failed, done_bb, write_bb)?;
+
+        self.builder.position_at_end(write_bb);
+        let format = self
+            .builder
+            .build_global_string_ptr("%lld\t%lld\t%s\n", "coverage_format")?;
+        let mut lines: Vec<((String, usize), Vec<GlobalValue<'ctx>>)> = Vec::new();
+        for (source, line, counter) in &self.coverage_counters {
+            let key = (source.clone(), *line);
+            match lines.iter_mut().find(|(location, _)| *location == key) {
+                Some((_, counters)) => counters.push(*counter),
+                None => lines.push((key, vec![*counter])),
+            }
+        }
+        for ((source, line), counters) in lines {
+            let mut hits = None;
+            for counter in counters {
+                let value = self
+                    .builder
+                    .build_load(i64_type, counter.as_pointer_value(), "hits")?
+                    .into_int_value();
+                hits = Some(match hits {
+                    None => value,
+                    Some(max) => {
+                        let more = self.builder.build_int_compare(
+                            IntPredicate::UGT,
+                            value,
+                            max,
+                            "more",
+                        )?;
+                        self.builder
+                            .build_select(more, value, max, "max")?
+                            .into_int_value()
+                    }
+                });
+            }
+            let source = self.builder.build_global_string_ptr(&source, "source")?;
+            self.builder.build_call(
+                fprintf,
+                &[
+                    file.into(),
+                    format.as_pointer_value().into(),
+                    hits.unwrap().into(),
+                    i64_type.const_int(line as u64, false).into(),
+                    source.as_pointer_value().into(),
+                ],
+                "record",
+            )?;
+        }
+        self.builder.build_call(fclose, &[file.into()], "close")?;
+        self.builder.build_unconditional_branch(done_bb)?;
+
+        self.builder.position_at_end(done_bb);
+        self.builder.build_return(None)?;
+        Ok(function)
+    }
+
+    /// `seppo.coverage_hits(i)`, the value of coverage counter `i` or 0 for
+    /// an index out of range
+    fn build_coverage_hits(&mut self) -> Result<()> {
+        let i64_type = self.context.i64_type();
+        let function = self.module.add_function(
+            "seppo.coverage_hits",
+            i64_type.fn_type(&[i64_type.into()], false),
+            None,
+        );
+        let entry = self.context.append_basic_block(function, "entry");
+        let missing = self.context.append_basic_block(function, "missing");
+        let mut cases = Vec::new();
+        for (i, (_, _, counter)) in self.coverage_counters.iter().enumerate() {
+            let block = self.context.append_basic_block(function, "counter");
+            self.builder.position_at_end(block);
+            let hits = self
+                .builder
+                .build_load(i64_type, counter.as_pointer_value(), "hits")?;
+            self.builder.build_return(Some(&hits))?;
+            cases.push((i64_type.const_int(i as u64, false), block));
+        }
+
+        self.builder.position_at_end(missing);
+        self.builder.build_return(Some(&i64_type.const_zero()))?;
+        self.builder.position_at_end(entry);
+        let index = function.get_nth_param(0).unwrap().into_int_value();
+        self.builder.build_switch(index, missing, &cases)?;
+        Ok(())
+    }
+
+    /// `+`, `-`, `*` and `/` that report overflow and division by zero to the
+    /// UBSan runtime, then carry on like the unchecked operation, or with
+    /// only overflow checks enabled stop the program. In constant_time
+    /// functions overflow only sets `overflowed`. Returns None for other
+    /// operators.
+    fn gen_checked_arithmetic(
+        &mut self,
+        op: &str,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<Option<IntValue<'ctx>>> {
+        let i64_type = self.context.i64_type();
+        let (handler, failed, value) = match op {
+            "+" | "-" | "*" => {
+                let (intrinsic, handler) = match op {
+                    "+" => ("llvm.sadd.with.overflow", "__ubsan_handle_add_overflow"),
+                    "-" => ("llvm.ssub.with.overflow", "__ubsan_handle_sub_overflow"),
+                    _ => ("llvm.smul.with.overflow", "__ubsan_handle_mul_overflow"),
+                };
+                let intrinsic = Intrinsic::find(intrinsic)
+                    .and_then(|i| i.get_declaration(&self.module, &[i64_type.into()]))
+                    .ok_or_else(|| {
+                        SeppoError::Codegen(format!("Missing intrinsic {}", intrinsic))
+                    })?;
+                let result = self
+                    .builder
+                    .build_call(intrinsic, &[lhs.into(), rhs.into()], "checked")?
+                    .try_as_basic_value()
+                    .left()
+                    .unwrap()
+                    .into_struct_value();
+                let value = self
+                    .builder
+                    .build_extract_value(result, 0, "value")?
+                    .into_int_value();
+                let failed = self
+                    .builder
+                    .build_extract_value(result, 1, "overflow")?
+                    .into_int_value();
+                (handler, failed, Some(value))
+            }
+            "/" => {
+                let zero = self.builder.build_int_compare(
+                    IntPredicate::EQ,
+                    rhs,
+                    i64_type.const_zero(),
+                    "div_zero",
+                )?;
+                let min = self.builder.build_int_compare(
+                    IntPredicate::EQ,
+                    lhs,
+                    i64_type.const_int(i64::MIN as u64, false),
+                    "lhs_min",
+                )?;
+                let minus_one = self.builder.build_int_compare(
+                    IntPredicate::EQ,
+                    rhs,
+                    i64_type.const_all_ones(),
+                    "rhs_minus_one",
+                )?;
+                let overflow = self.builder.build_and(min, minus_one, "div_overflow")?;
+                let failed = if self.division_overflow == Some(DivisionOverflow::Wrap) {
+                    zero
+                } else {
+                    self.builder.build_or(zero, overflow, "div_failed")?
+                };
+                ("__ubsan_handle_divrem_overflow", failed, None)
+            }
+            _ => return Ok(None),
+        };
+
+        if let (Some(flag), Some(value)) = (self.overflowed, value) {
+            let bool_type = self.context.bool_type();
+            let seen = self
+                .builder
+                .build_load(bool_type, flag, "overflowed")?
+                .into_int_value();
+            let seen = self.builder.build_or(seen, failed, "overflowed")?;
+            self.builder.build_store(flag, seen)?;
+            return Ok(Some(value));
+        }
+
+        let function = self
+            .current_function
+            .ok_or_else(|| SeppoError::Sema("Arithmetic outside of function".to_string()))?;
+        if !self.sanitizes(Sanitizer::Undefined) {
+            let panic_bb = self.context.append_basic_block(function, "overflow_panic");
+            let cont_bb = self.context.append_basic_block(function, "overflow_cont");
+            self.builder
+                .build_conditional_branch(failed, panic_bb, cont_bb)?;
+            self.builder.position_at_end(panic_bb);
+            self.gen_overflow_panic(op)?;
+            self.builder.position_at_end(cont_bb);
+            return match value {
+                Some(value) => Ok(Some(value)),
+                None => Ok(Some(self.gen_quotient(lhs, rhs)?)),
+            };
+        }
+
+        let report_bb = self.context.append_basic_block(function, "ubsan_report");
+        let cont_bb = self.context.append_basic_block(function, "ubsan_cont");
+        self.builder
+            .build_conditional_branch(failed, report_bb, cont_bb)?;
+
+        self.builder.position_at_end(report_bb);
+        let data = self.ubsan_overflow_data()?;
+        let ptr_type = self.context.ptr_type(0.into());
+        let handler = self.libc_function(
+            handler,
+            self.context
+                .void_type()
+                .fn_type(&[ptr_type.into(), i64_type.into(), i64_type.into()], false),
+        );
+        self.builder.build_call(
+            handler,
+            &[data.into(), lhs.into(), rhs.into()],
+            "ubsan_report",
+        )?;
+        self.builder.build_unconditional_branch(cont_bb)?;
+
+        self.builder.position_at_end(cont_bb);
+        match value {
+            Some(value) => Ok(Some(value)),
+            None => Ok(Some(self.gen_quotient(lhs, rhs)?)),
+        }
+    }
+
+    /// `lhs / rhs`, rounded and wrapped as set by `set_division`. Division
+    /// by zero is left to the checks.
+    fn gen_quotient(&self, lhs: IntValue<'ctx>, rhs: IntValue<'ctx>) -> Result<IntValue<'ctx>> {
+        let i64_type = self.context.i64_type();
+        let quotient = if self.division_overflow == Some(DivisionOverflow::Wrap) {
+            // sdiv of i64::MIN by -1 is undefined, so divide by 1 and negate
+            let minus_one = self.builder.build_int_compare(
+                IntPredicate::EQ,
+                rhs,
+                i64_type.const_all_ones(),
+                "rhs_minus_one",
+            )?;
+            let divisor = self
+                .builder
+                .build_select(minus_one, i64_type.const_int(1, false), rhs, "divisor")?
+                .into_int_value();
+            let quotient = self.builder.build_int_signed_div(lhs, divisor, "divtmp")?;
+            let negated = self
+                .builder
+                .build_int_sub(i64_type.const_zero(), quotient, "negtmp")?;
+            self.builder
+                .build_select(minus_one, negated, quotient, "divtmp")?
+                .into_int_value()
+        } else {
+            self.builder.build_int_signed_div(lhs, rhs, "divtmp")?
+        };
+        if self.division_rounding == DivisionRounding::Truncate {
+            return Ok(quotient);
+        }
+        // Truncating rounded up when there is a remainder with a sign other
+        // than the divisor's
+        let product = self.builder.build_int_mul(quotient, rhs, "product")?;
+        let remainder = self.builder.build_int_sub(lhs, product, "remainder")?;
+        let inexact = self.builder.build_int_compare(
+            IntPredicate::NE,
+            remainder,
+            i64_type.const_zero(),
+            "inexact",
+        )?;
+        let signs = self.builder.build_xor(remainder, rhs, "signs")?;
+        let opposite = self.builder.build_int_compare(
+            IntPredicate::SLT,
+            signs,
+            i64_type.const_zero(),
+            "opposite",
+        )?;
+        let rounded_up = self.builder.build_and(inexact, opposite, "rounded_up")?;
+        let adjust = self
+            .builder
+            .build_int_z_extend(rounded_up, i64_type, "adjust")?;
+        Ok(self.builder.build_int_sub(quotient, adjust, "floortmp")?)
+    }
+
+    /// Print where an overflow check failed to standard error and trap
+    fn gen_overflow_panic(&mut self, op: &str) -> Result<()> {
+        let what = match op {
+            "+" => "attempt to add with overflow",
+            "-" => "attempt to subtract with overflow",
+            "*" => "attempt to multiply with overflow",
+            _ => "attempt to divide by zero or with overflow",
+        };
+        self.gen_panic(what)
+    }
+
+    /// Print `what`, with the location of the current statement, to
+    /// standard error and trap
+    fn gen_panic(&mut self, what: &str) -> Result<()> {
+        // Bare wasm has nowhere to write to, the trap alone stops it
+        if !self.target.is_wasm() {
+            // The trap skips exit, so whatever was printed must be written first
+            self.gen_flush()?;
+            let message = match &self.location {
+                Some((file, line)) => format!("{}:{}: {}\n", file, line, what),
+                None => format!("{}\n", what),
+            };
+            let i32_type = self.context.i32_type();
+            let i64_type = self.context.i64_type();
+            let ptr_type = self.context.ptr_type(0.into());
+            let write = self.libc_function(
+                "write",
+                i64_type.fn_type(&[i32_type.into(), ptr_type.into(), i64_type.into()], false),
+            );
+            let text = self.string_constant(&message)?;
+            self.builder.build_call(
+                write,
+                &[
+                    i32_type.const_int(2, false).into(),
+                    text.into(),
+                    i64_type.const_int(message.len() as u64, false).into(),
+                ],
+                "write_call",
+            )?;
+        }
+        let trap = Intrinsic::find("llvm.trap")
+            .and_then(|trap| trap.get_declaration(&self.module, &[]))
+            .ok_or_else(|| SeppoError::Codegen("Missing intrinsic llvm.trap".to_string()))?;
+        self.builder.build_call(trap, &[], "trap")?;
+        self.builder.build_unreachable()?;
+        Ok(())
+    }
+
+    /// Describe a function in the debug info when it is enabled, starting
+    /// its line table at its first statement
+    fn debug_function(
+        &mut self,
+        function: FunctionValue<'ctx>,
+        name: &str,
+        body: &SeppoExpr,
+    ) -> Option<DISubprogram<'ctx>> {
+        if !self.debug_info {
+            return None;
+        }
+        let (file, line) = first_location(body)?;
+        let optimized = self.opt_level != OptimizationLevel::None;
+        let debug = self
+            .debug
+            .get_or_insert_with(|| DebugInfo::new(self.context, &self.module, file, optimized));
+        let scope = debug.function(function, name, file, line);
+        self.builder
+            .set_current_debug_location(debug.location(self.context, line, scope));
+        Some(scope)
+    }
+
+    /// Attribute the instructions that follow to `line`
+    fn set_debug_line(&self, line: usize) {
+        if let (Some(debug), Some(scope)) = (&self.debug, self.debug_scope) {
+            self.builder
+                .set_current_debug_location(debug.location(self.context, line, scope));
+        }
+    }
+
+    /// UBSan's `OverflowData`: the source location of the current statement
+    /// and a descriptor of the signed 64-bit type
+    fn ubsan_overflow_data(&mut self) -> Result<PointerValue<'ctx>> {
+        let i16_type = self.context.i16_type();
+        let i32_type = self.context.i32_type();
+
+        let type_descriptor = match self.module.get_global("seppo.ubsan.i64") {
+            Some(global) => global,
+            None => {
+                // Kind 0 is an integer, info is log2 of the bit width shifted
+                // left once with the low bit set for signed
+                let name = self.context.const_string(b"'i64'", true);
+                let value = self.context.const_struct(
+                    &[
+                        i16_type.const_zero().into(),
+                        i16_type.const_int((6 << 1) | 1, false).into(),
+                        name.into(),
+                    ],
+                    false,
+                );
+                let global = self
+                    .module
+                    .add_global(value.get_type(), None, "seppo.ubsan.i64");
+                global.set_initializer(&value);
+                global.set_linkage(Linkage::Private);
+                global.set_constant(true);
+                global
+            }
+        };
+
+        let (file, line) = self
+            .location
+            .clone()
+            .unwrap_or_else(|| ("<unknown>".to_string(), 0));
+        let file = self.string_constant(&file)?;
+        let value = self.context.const_struct(
+            &[
+                file.into(),
+                i32_type.const_int(line as u64, false).into(),
+                i32_type.const_zero().into(),
+                type_descriptor.as_pointer_value().into(),
+            ],
+            false,
+        );
+        // The runtime writes to the location to report each site only once
+        let data = self.module.add_global(value.get_type(), None, "ubsan_data");
+        data.set_initializer(&value);
+        data.set_linkage(Linkage::Private);
+        Ok(data.as_pointer_value())
+    }
+
+    /// Mark every function defined in the module for AddressSanitizer and
+    /// run its instrumentation pass
+    fn instrument_address(&self) -> Result<()> {
+        let kind = Attribute::get_named_enum_kind_id("sanitize_address");
+        self.add_attribute_to_definitions(self.context.create_enum_attribute(kind, 0));
+
+        let target_machine = self.target_machine()?;
+        timing::time(Phase::Llvm, || {
+            self.module
+                .run_passes("asan", &target_machine, PassBuilderOptions::create())
+        })
+        .map_err(|e| SeppoError::Codegen(format!("Failed to run AddressSanitizer: {}", e)))
+    }
+
+    fn add_attribute_to_definitions(&self, attribute: Attribute) {
+        let mut function = self.module.get_first_function();
+        while let Some(f) = function {
+            if f.count_basic_blocks() > 0 {
+                f.add_attribute(AttributeLoc::Function, attribute);
+            }
+            function = f.get_next_function();
+        }
+    }
+
+    /// Whether a call to `name` calls the function being generated
+    fn is_self_call(&self, name: &str) -> bool {
+        self.tail_call
+            .as_ref()
+            .is_some_and(|tail_call| tail_call.function == name)
+    }
+
+    /// A self-recursive call in tail position, as a jump back to the start
+    /// of the function with the arguments stored in the parameters
+    fn gen_tail_call(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        let params = self.tail_call.as_ref().unwrap().params.clone();
+        if params.len() != args.len() {
+            return Err(SeppoError::Sema(format!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                params.len(),
+                args.len()
+            )));
+        }
+        // Evaluate every argument before overwriting any parameter
+        let values = args
+            .iter()
+            .map(|arg| self.gen_expr(arg))
+            .collect::<Result<Vec<_>>>()?;
+        for (param, value) in params.iter().zip(values) {
+            self.builder.build_store(*param, value)?;
+        }
+        let start = self.tail_call.as_ref().unwrap().start;
+        self.builder.build_unconditional_branch(start)?;
+        Ok(self.context.i64_type().const_zero())
+    }
+
+    /// `Some(v)` and the builtins taking an Option, see `option_type`
+    fn gen_option_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        let option_type = self.option_type();
+        let i64_type = self.context.i64_type();
+        if name == "Some" {
+            let value = self.gen_expr(&args[0])?;
+            let value = self
+                .builder
+                .build_int_z_extend(value, option_type, "some_value")?;
+            let tag = option_type.const_int_arbitrary_precision(&[0, 1]);
+            return Ok(self.builder.build_or(value, tag, "some")?);
+        }
+        if let "is_some" | "is_none" = name {
+            let value = self.gen_is_some(name, args)?;
+            return Ok(self
+                .builder
+                .build_int_z_extend(value, i64_type, "bool_ext")?);
+        }
+        let option = self.gen_option(name, &args[0])?;
+        let value = self.builder.build_int_truncate(option, i64_type, "value")?;
+        let is_some = self.option_tag(option)?;
+        if name == "unwrap_or" {
+            let default = self.gen_expr(&args[1])?;
+            return Ok(self
+                .builder
+                .build_select(is_some, value, default, "unwrap_or")?
+                .into_int_value());
+        }
+        let function = self.current_function.unwrap();
+        let none_bb = self.context.append_basic_block(function, "unwrap_none");
+        let some_bb = self.context.append_basic_block(function, "unwrap_some");
+        self.builder
+            .build_conditional_branch(is_some, some_bb, none_bb)?;
+        self.builder.position_at_end(none_bb);
+        self.gen_panic("called unwrap on None")?;
+        self.builder.position_at_end(some_bb);
+        Ok(value)
+    }
+
+    /// Whether the Option `args[0]` holds a value for `is_some`, or holds
+    /// none for `is_none`, as an `i1`
+    fn gen_is_some(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        let option = self.gen_option(name, &args[0])?;
+        let is_some = self.option_tag(option)?;
+        match name {
+            "is_none" => Ok(self.builder.build_not(is_some, "is_none")?),
+            _ => Ok(is_some),
+        }
+    }
+
+    /// `arg` of Option builtin `name`, which has to be an Option. Values
+    /// the type checker couldn't tell end up here.
+    fn gen_option(&mut self, name: &str, arg: &SeppoExpr) -> Result<IntValue<'ctx>> {
+        let option = self.gen_expr(arg)?;
+        if option.get_type() != self.option_type() {
+            return Err(SeppoError::Sema(format!(
+                "{} takes an Option, got an integer or a string",
+                name
+            )));
+        }
+        Ok(option)
+    }
+
+    /// Bit 64 of an Option, set when it holds a value
+    fn option_tag(&self, option: IntValue<'ctx>) -> Result<IntValue<'ctx>> {
+        let option_type = self.option_type();
+        let tag = self.builder.build_right_shift(
+            option,
+            option_type.const_int(64, false),
+            false,
+            "tag",
+        )?;
+        Ok(self
+            .builder
+            .build_int_truncate(tag, self.context.bool_type(), "is_some")?)
+    }
+
+    /// `flush()`, which on wasm has nothing to do as prints go straight to
+    /// fd_write
+    fn gen_io_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        if !self.target.is_wasm() {
+            self.gen_flush()?;
+        }
+        Ok(self.context.i64_type().const_zero())
+    }
+
+    /// `fflush(NULL)`, writing out every buffered stream
+    fn gen_flush(&self) -> Result<()> {
+        let ptr_type = self.context.ptr_type(0.into());
+        let fflush = self.libc_function(
+            "fflush",
+            self.context.i32_type().fn_type(&[ptr_type.into()], false),
+        );
+        self.builder
+            .build_call(fflush, &[ptr_type.const_null().into()], "flush")?;
+        Ok(())
+    }
+
+    /// `seppo x`, `0xseppo x` or `eseppo x`, through printf and fprintf or
+    /// on wasm the fd_write based helper
+    fn gen_print_int(&mut self, format: &PrintFormat, value: IntValue<'ctx>) -> Result<()> {
+        let hex = matches!(format, PrintFormat::Hex);
+        let stderr = matches!(format, PrintFormat::Stderr);
+        if self.target.is_wasm() {
+            let print_fn = self.module.get_function("seppo_print_i64").unwrap();
+            let fd = if stderr { STDERR_FD } else { STDOUT_FD };
+            self.builder.build_call(
+                print_fn,
+                &[
+                    value.into(),
+                    self.context.bool_type().const_int(hex as u64, false).into(),
+                    self.context.i32_type().const_int(fd, false).into(),
+                ],
+                "print_call",
+            )?;
+            return Ok(());
+        }
+
+        let format_string = if hex {
+            self.string_constant("0x%lx\n")?
+        } else {
+            self.string_constant("%ld\n")?
+        };
+        if stderr {
+            let stream = self.stdio_stream("stderr")?;
+            let fprintf = self.libc_function(
+                "fprintf",
+                self.context.i32_type().fn_type(
+                    &[
+                        self.context.ptr_type(0.into()).into(),
+                        self.context.ptr_type(0.into()).into(),
+                    ],
+                    true,
+                ),
+            );
+            self.builder.build_call(
+                fprintf,
+                &[stream.into(), format_string.into(), value.into()],
+                "fprintf_call",
+            )?;
+            return Ok(());
+        }
+        let printf = self.module.get_function("printf").unwrap();
+        self.builder
+            .build_call(printf, &[format_string.into(), value.into()], "printf_call")?;
+        Ok(())
+    }
+
+    /// libc's `FILE *` called `stream`, `stdout` or `stderr`, which Apple's
+    /// libc names `__stdoutp` and `__stderrp`
+    fn stdio_stream(&self, stream: &str) -> Result<PointerValue<'ctx>> {
+        let name = if self.target.is_apple() {
+            format!("__{}p", stream)
+        } else {
+            stream.to_string()
+        };
+        let name = name.as_str();
+        let ptr_type = self.context.ptr_type(0.into());
+        let global = self.module.get_global(name).unwrap_or_else(|| {
+            let global = self.module.add_global(ptr_type, None, name);
+            global.set_linkage(Linkage::External);
+            global
+        });
+        Ok(self
+            .builder
+            .build_load(ptr_type, global.as_pointer_value(), stream)?
+            .into_pointer_value())
+    }
+
+    /// `boolseppo x`: `false` for zero and `true` for anything else
+    fn gen_print_bool(&mut self, value: IntValue<'ctx>) -> Result<()> {
+        let is_true = self.builder.build_int_compare(
+            IntPredicate::NE,
+            value,
+            self.context.i64_type().const_zero(),
+            "is_true",
+        )?;
+        if self.target.is_wasm() {
+            let yes = self.string_constant("true\n")?;
+            let no = self.string_constant("false\n")?;
+            let text = self.builder.build_select(is_true, yes, no, "text")?;
+            let i32_type = self.context.i32_type();
+            let len = self.builder.build_select(
+                is_true,
+                i32_type.const_int(5, false),
+                i32_type.const_int(6, false),
+                "len",
+            )?;
+            return self.write_fd(
+                &self.builder,
+                i32_type.const_int(STDOUT_FD, false),
+                text.into_pointer_value(),
+                len.into_int_value(),
+            );
+        }
+
+        let yes = self.string_constant("true")?;
+        let no = self.string_constant("false")?;
+        let text = self.builder.build_select(is_true, yes, no, "text")?;
+        let format_string = self.string_constant("%s\n")?;
+        let printf = self.module.get_function("printf").unwrap();
+        self.builder
+            .build_call(printf, &[format_string.into(), text.into()], "printf_call")?;
+        Ok(())
+    }
+
+    /// `seppo_print_char(value)`, behind `charseppo x`: the UTF-8 encoding of
+    /// the code point and a newline, or U+FFFD for values that are negative,
+    /// past U+10FFFF or a surrogate half. Built on first use.
+    fn print_char_function(&mut self) -> Result<FunctionValue<'ctx>> {
+        const NAME: &str = "seppo_print_char";
+        if let Some(function) = self.module.get_function(NAME) {
+            return Ok(function);
+        }
+        let format_string = match self.target.is_wasm() {
+            true => None,
+            false => Some(self.string_constant("%s")?),
+        };
+
+        let i8_type = self.context.i8_type();
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let int = |value: u64| i64_type.const_int(value, false);
+        let fn_type = self.context.void_type().fn_type(&[i64_type.into()], false);
+        let function = self
+            .module
+            .add_function(NAME, fn_type, Some(Linkage::Internal));
+        // A builder of its own, so the current position stays as it is
+        let builder = self.context.create_builder();
+        builder.position_at_end(self.context.append_basic_block(function, "entry"));
+        let value = function.get_nth_param(0).unwrap().into_int_value();
+
+        // Unsigned, so negative values are too big as well
+        let too_big =
+            builder.build_int_compare(IntPredicate::UGT, value, int(0x10FFFF), "too_big")?;
+        let offset = builder.build_int_sub(value, int(0xD800), "offset")?;
+        let surrogate =
+            builder.build_int_compare(IntPredicate::ULT, offset, int(0x800), "surrogate")?;
+        let invalid = builder.build_or(too_big, surrogate, "invalid")?;
+        let code = builder
+            .build_select(invalid, int(0xFFFD), value, "code")?
+            .into_int_value();
+
+        // Bytes of the encoding and the marker bits of the first one
+        let mut len = int(4);
+        let mut marker = int(0xF0);
+        for (limit, bytes, bits) in [(0x10000, 3, 0xE0), (0x800, 2, 0xC0), (0x80, 1, 0)] {
+            let below = builder.build_int_compare(IntPredicate::ULT, code, int(limit), "below")?;
+            len = builder
+                .build_select(below, int(bytes), len, "len")?
+                .into_int_value();
+            marker = builder
+                .build_select(below, int(bits), marker, "marker")?
+                .into_int_value();
+        }
+
+        // The code point, newline and NUL, at most 6 bytes
+        let buf = builder.build_array_alloca(i8_type, int(6), "buf")?;
+        let store_byte = |index: IntValue<'ctx>, byte: IntValue<'ctx>| -> Result<()> {
+            let slot = unsafe { builder.build_in_bounds_gep(i8_type, buf, &[index], "slot")? };
+            let byte = builder.build_int_truncate(byte, i8_type, "byte")?;
+            builder.build_store(slot, byte)?;
+            Ok(())
+        };
+        // The first byte carries the bits above the 6 of each continuation byte
+        let continuations = builder.build_int_sub(len, int(1), "continuations")?;
+        let shift = builder.build_int_mul(continuations, int(6), "shift")?;
+        let high = builder.build_right_shift(code, shift, false, "high")?;
+        store_byte(int(0), builder.build_or(marker, high, "lead")?)?;
+        for i in 1..4 {
+            // Bytes past the encoding are overwritten by the newline and NUL
+            // or never read, so their shift only has to stay in range
+            let used = builder.build_int_compare(IntPredicate::ULT, int(i), len, "used")?;
+            let remaining = builder.build_int_sub(continuations, int(i), "remaining")?;
+            let bits = builder.build_int_mul(remaining, int(6), "bits")?;
+            let bits = builder
+                .build_select(used, bits, int(0), "bits")?
+                .into_int_value();
+            let part = builder.build_right_shift(code, bits, false, "part")?;
+            let part = builder.build_and(part, int(0x3F), "part")?;
+            store_byte(int(i), builder.build_or(part, int(0x80), "continuation")?)?;
+        }
+        store_byte(len, int(b'\n' as u64))?;
+        let end = builder.build_int_add(len, int(1), "end")?;
+        store_byte(end, int(0))?;
+
+        match format_string {
+            Some(format_string) => {
+                let printf = self.module.get_function("printf").unwrap();
+                builder.build_call(printf, &[format_string.into(), buf.into()], "printf_call")?;
+            }
+            None => {
+                let end = builder.build_int_truncate(end, i32_type, "len")?;
+                self.write_fd(&builder, i32_type.const_int(STDOUT_FD, false), buf, end)?;
+            }
+        }
+        builder.build_return(None)?;
+        Ok(function)
+    }
+
+    /// Write `len` bytes at `text` to the file descriptor `fd` with WASI's
+    /// fd_write
+    fn write_fd(
+        &self,
+        builder: &Builder<'ctx>,
+        fd: IntValue<'ctx>,
+        text: PointerValue<'ctx>,
+        len: IntValue<'ctx>,
+    ) -> Result<()> {
+        let i32_type = self.context.i32_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let iov_type = self
+            .context
+            .struct_type(&[ptr_type.into(), i32_type.into()], false);
+        let iov = builder.build_alloca(iov_type, "iov")?;
+        let iov_base = builder.build_struct_gep(iov_type, iov, 0, "iov_base")?;
+        builder.build_store(iov_base, text)?;
+        let iov_len = builder.build_struct_gep(iov_type, iov, 1, "iov_len")?;
+        builder.build_store(iov_len, len)?;
+        let written = builder.build_alloca(i32_type, "written")?;
+
+        let fd_write = self.module.get_function("fd_write").unwrap();
+        builder.build_call(
+            fd_write,
+            &[
+                fd.into(),
+                iov.into(),
+                i32_type.const_int(1, false).into(),
+                written.into(),
+            ],
+            "fd_write_call",
+        )?;
+        Ok(())
+    }
+
+    /// Stack slot at the start of the current function's entry block
+    fn entry_alloca(&self, name: &str) -> Result<PointerValue<'ctx>> {
+        self.entry_alloca_of(self.context.i64_type().into(), name)
+    }
+
+    /// Stack slot of type `ty`, like `entry_alloca`
+    fn entry_alloca_of(&self, ty: BasicTypeEnum<'ctx>, name: &str) -> Result<PointerValue<'ctx>> {
+        let function = self.current_function.unwrap();
+        let entry = function.get_first_basic_block().unwrap();
+        let builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(first) => builder.position_before(&first),
+            None => builder.position_at_end(entry),
+        }
+        Ok(builder.build_alloca(ty, name)?)
+    }
+
+    /// Inline code for a call to one of the string builtins, which work on
+    /// NUL-terminated strings through libc
+    fn gen_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        if self.target.is_wasm() {
+            return Err(SeppoError::Codegen(format!(
+                "Builtin {} needs libc and is not available on wasm",
+                name
+            )));
+        }
+
+        let i64_type = self.context.i64_type();
+        let i8_type = self.context.i8_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let args = args
+            .iter()
+            .map(|arg| self.gen_expr(arg))
+            .collect::<Result<Vec<_>>>()?;
+        let string = self.builder.build_int_to_ptr(args[0], ptr_type, "str")?;
+        let strlen = self.libc_function("strlen", i64_type.fn_type(&[ptr_type.into()], false));
+        let length = self
+            .builder
+            .build_call(strlen, &[string.into()], "len")?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        let zero = i64_type.const_zero();
+
+        match name {
+            "len" => Ok(length),
+            "char_at" => {
+                // Negative indices wrap to huge unsigned values and fail the check too
+                let index = args[1];
+                let in_range =
+                    self.builder
+                        .build_int_compare(IntPredicate::ULT, index, length, "in_range")?;
+                // Read the terminating NUL instead when out of range, so the
+                // load is always valid
+                let safe_index = self
+                    .builder
+                    .build_select(in_range, index, zero, "index")?
+                    .into_int_value();
+                let byte_ptr = unsafe {
+                    self.builder
+                        .build_gep(i8_type, string, &[safe_index], "byte_ptr")?
+                };
+                let byte = self
+                    .builder
+                    .build_load(i8_type, byte_ptr, "byte")?
+                    .into_int_value();
+                let byte = self.builder.build_int_z_extend(byte, i64_type, "char")?;
+                let result = self.builder.build_select(
+                    in_range,
+                    byte,
+                    i64_type.const_all_ones(),
+                    "char_at",
+                )?;
+                Ok(result.into_int_value())
+            }
+            "substr" => {
+                let start = self.clamp(args[1], length, "start")?;
+                let rest = self.builder.build_int_sub(length, start, "rest")?;
+                let count = self.clamp(args[2], rest, "count")?;
+
+                let malloc =
+                    self.libc_function("malloc", ptr_type.fn_type(&[i64_type.into()], false));
+                let memcpy = self.libc_function(
+                    "memcpy",
+                    ptr_type.fn_type(&[ptr_type.into(), ptr_type.into(), i64_type.into()], false),
+                );
+                let size =
+                    self.builder
+                        .build_int_add(count, i64_type.const_int(1, false), "size")?;
+                let buffer = self
+                    .builder
+                    .build_call(malloc, &[size.into()], "substr")?
+                    .try_as_basic_value()
+                    .left()
+                    .unwrap()
+                    .into_pointer_value();
+                let source = unsafe { self.builder.build_gep(i8_type, string, &[start], "from")? };
+                self.builder.build_call(
+                    memcpy,
+                    &[buffer.into(), source.into(), count.into()],
+                    "",
+                )?;
+                let end = unsafe { self.builder.build_gep(i8_type, buffer, &[count], "end")? };
+                self.builder.build_store(end, i8_type.const_zero())?;
+                Ok(self
+                    .builder
+                    .build_ptr_to_int(buffer, i64_type, "substr_ptr")?)
+            }
+            _ => unreachable!("not a builtin: {}", name),
+        }
+    }
+
+    /// The thread builtins, lowered to pthreads calls. Mutexes and
+    /// condition variables are allocated with malloc and never freed.
+    fn gen_thread_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        if self.target.is_wasm() || self.target.triple().contains("windows") {
+            return Err(SeppoError::Codegen(format!(
+                "Builtin {} needs POSIX threads, which {} doesn't have",
+                name,
+                self.target.triple()
+            )));
+        }
+        match name {
+            "spawn" => return self.gen_spawn(args),
+            "join" => return self.gen_join(args),
+            "sleep_ms" => return self.gen_sleep(args),
+            "yield" => {
+                let sched_yield =
+                    self.libc_function("sched_yield", self.context.i32_type().fn_type(&[], false));
+                self.builder.build_call(sched_yield, &[], "yield")?;
+                return Ok(self.context.i64_type().const_zero());
+            }
+            _ => {}
+        }
+
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        if let Some(init) = match name {
+            "mutex" => Some("pthread_mutex_init"),
+            "condvar" => Some("pthread_cond_init"),
+            _ => None,
+        } {
+            // Larger than either type on the targets seppo supports
+            const SYNC_OBJECT_SIZE: u64 = 64;
+            let size = i64_type.const_int(SYNC_OBJECT_SIZE, false);
+            let object = self.gen_malloc(size, "sync_object")?;
+            // With the default attributes
+            self.gen_pthread_call(
+                init,
+                &[object.into(), ptr_type.const_null().into()],
+                &format!("{} failed", name),
+            )?;
+            return Ok(self.builder.build_ptr_to_int(object, i64_type, name)?);
+        }
+
+        let function = match name {
+            "lock" => "pthread_mutex_lock",
+            "unlock" => "pthread_mutex_unlock",
+            "wait" => "pthread_cond_wait",
+            "notify" => "pthread_cond_broadcast",
+            _ => unreachable!("not a thread builtin: {}", name),
+        };
+        let mut objects = Vec::new();
+        for arg in args {
+            let value = self.gen_expr(arg)?;
+            objects.push(
+                self.builder
+                    .build_int_to_ptr(value, ptr_type, "sync")?
+                    .into(),
+            );
+        }
+        self.gen_pthread_call(function, &objects, &format!("{} failed", name))?;
+        Ok(i64_type.const_zero())
+    }
+
+    /// The atomic builtins, as LLVM atomic instructions with
+    /// `ATOMIC_ORDERING` on 8-byte aligned integers
+    fn gen_atomic_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        if self.target.is_wasm() {
+            return Err(SeppoError::Codegen(format!(
+                "Builtin {} needs libc and is not available on wasm",
+                name
+            )));
+        }
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let args = args
+            .iter()
+            .map(|arg| self.gen_expr(arg))
+            .collect::<Result<Vec<_>>>()?;
+        if name == "atomic" {
+            let cell = self.gen_malloc(i64_type.const_int(8, false), "atomic")?;
+            self.builder.build_store(cell, args[0])?;
+            return Ok(self.builder.build_ptr_to_int(cell, i64_type, "atomic")?);
+        }
+
+        let cell = self.builder.build_int_to_ptr(args[0], ptr_type, "cell")?;
+        match name {
+            "atomic_load" => {
+                let value = self.builder.build_load(i64_type, cell, "atomic_load")?;
+                let load = value.as_instruction_value().unwrap();
+                load.set_alignment(8)
+                    .and_then(|_| load.set_atomic_ordering(ATOMIC_ORDERING))
+                    .map_err(|e| SeppoError::Codegen(e.to_string()))?;
+                Ok(value.into_int_value())
+            }
+            "atomic_store" => {
+                let store = self.builder.build_store(cell, args[1])?;
+                store
+                    .set_alignment(8)
+                    .and_then(|_| store.set_atomic_ordering(ATOMIC_ORDERING))
+                    .map_err(|e| SeppoError::Codegen(e.to_string()))?;
+                Ok(i64_type.const_zero())
+            }
+            "atomic_add" => Ok(self.builder.build_atomicrmw(
+                AtomicRMWBinOp::Add,
+                cell,
+                args[1],
+                ATOMIC_ORDERING,
+            )?),
+            "cas" => {
+                let result = self.builder.build_cmpxchg(
+                    cell,
+                    args[1],
+                    args[2],
+                    ATOMIC_ORDERING,
+                    ATOMIC_ORDERING,
+                )?;
+                Ok(self
+                    .builder
+                    .build_extract_value(result, 0, "previous")?
+                    .into_int_value())
+            }
+            _ => unreachable!("not an atomic builtin: {}", name),
+        }
+    }
+
+    /// `alloc`, `realloc` and `free` through libc, and `load` and `store`
+    /// of 64-bit integers
+    fn gen_memory_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        if self.target.is_wasm() {
+            return Err(SeppoError::Codegen(format!(
+                "Builtin {} needs libc and is not available on wasm",
+                name
+            )));
+        }
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let args = args
+            .iter()
+            .map(|arg| self.gen_expr(arg))
+            .collect::<Result<Vec<_>>>()?;
+        if name == "alloc" {
+            let memory = self.gen_malloc(args[0], "alloc")?;
+            return Ok(self.builder.build_ptr_to_int(memory, i64_type, "alloc")?);
+        }
+
+        let pointer = self
+            .builder
+            .build_int_to_ptr(args[0], ptr_type, "pointer")?;
+        match name {
+            "realloc" => {
+                let realloc = self.libc_function(
+                    "realloc",
+                    ptr_type.fn_type(&[ptr_type.into(), i64_type.into()], false),
+                );
+                let memory = self
+                    .builder
+                    .build_call(realloc, &[pointer.into(), args[1].into()], "realloc")?
+                    .try_as_basic_value()
+                    .left()
+                    .unwrap()
+                    .into_pointer_value();
+                Ok(self.builder.build_ptr_to_int(memory, i64_type, "realloc")?)
+            }
+            "free" => {
+                let free = self.libc_function(
+                    "free",
+                    self.context.void_type().fn_type(&[ptr_type.into()], false),
+                );
+                self.builder.build_call(free, &[pointer.into()], "")?;
+                Ok(i64_type.const_zero())
+            }
+            "load" => Ok(self
+                .builder
+                .build_load(i64_type, pointer, "load")?
+                .into_int_value()),
+            "store" => {
+                self.builder.build_store(pointer, args[1])?;
+                Ok(i64_type.const_zero())
+            }
+            _ => unreachable!("not a memory builtin: {}", name),
+        }
+    }
+
+    /// `+`, `-` and `*` of two vectors as the LLVM instructions on
+    /// `<4 x i64>`, which wrap around without overflow checks
+    fn gen_vector_arithmetic(
+        &mut self,
+        op: &str,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        if lhs.get_type() != rhs.get_type() {
+            return Err(SeppoError::Sema(format!(
+                "Both sides of {} have to be i64x4, got an integer or a string",
+                op
+            )));
+        }
+        let lhs = self.lanes_of(lhs)?;
+        let rhs = self.lanes_of(rhs)?;
+        let result = match op {
+            "+" => self.builder.build_int_add(lhs, rhs, "vaddtmp")?,
+            "-" => self.builder.build_int_sub(lhs, rhs, "vsubtmp")?,
+            "*" => self.builder.build_int_mul(lhs, rhs, "vmultmp")?,
+            op => {
+                return Err(SeppoError::Sema(format!(
+                    "i64x4 supports +, - and *, not {}",
+                    op
+                )))
+            }
+        };
+        self.vector_of(result)
+    }
+
+    /// `i64x4`, `lane` and `shuffle` as the LLVM vector instructions, and
+    /// `load_i64x4` and `store_i64x4` of the 32 bytes at an address, which
+    /// like those from `alloc` only has to be 8-byte aligned
+    fn gen_vector_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        let i32_type = self.context.i32_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let index = |i: u32| i32_type.const_int(i as u64, false);
+        match name {
+            "i64x4" => {
+                let mut lanes = self.lanes_type().get_undef();
+                for (i, arg) in (0..).zip(args) {
+                    let value = self.gen_expr(arg)?;
+                    lanes = self
+                        .builder
+                        .build_insert_element(lanes, value, index(i), "lane")?;
+                }
+                self.vector_of(lanes)
+            }
+            "lane" => {
+                let [i] = builtins::lanes(name, args)?[..] else {
+                    unreachable!("lane takes one element number");
+                };
+                let lanes = self.gen_vector(name, &args[0])?;
+                Ok(self
+                    .builder
+                    .build_extract_element(lanes, index(i), "lane")?
+                    .into_int_value())
+            }
+            "shuffle" => {
+                let mask: Vec<_> = builtins::lanes(name, args)?
+                    .into_iter()
+                    .map(index)
+                    .collect();
+                let a = self.gen_vector(name, &args[0])?;
+                let b = self.gen_vector(name, &args[1])?;
+                let shuffled = self.builder.build_shuffle_vector(
+                    a,
+                    b,
+                    VectorType::const_vector(&mask),
+                    "shuffle",
+                )?;
+                self.vector_of(shuffled)
+            }
+            "load_i64x4" => {
+                let address = self.gen_expr(&args[0])?;
+                let pointer = self
+                    .builder
+                    .build_int_to_ptr(address, ptr_type, "pointer")?;
+                let value = self
+                    .builder
+                    .build_load(self.lanes_type(), pointer, "load_i64x4")?;
+                value
+                    .as_instruction_value()
+                    .unwrap()
+                    .set_alignment(8)
+                    .map_err(|e| SeppoError::Codegen(e.to_string()))?;
+                self.vector_of(value.into_vector_value())
+            }
+            "store_i64x4" => {
+                let address = self.gen_expr(&args[0])?;
+                let lanes = self.gen_vector(name, &args[1])?;
+                let pointer = self
+                    .builder
+                    .build_int_to_ptr(address, ptr_type, "pointer")?;
+                self.builder
+                    .build_store(pointer, lanes)?
+                    .set_alignment(8)
+                    .map_err(|e| SeppoError::Codegen(e.to_string()))?;
+                Ok(self.context.i64_type().const_zero())
+            }
+            _ => unreachable!("not a vector builtin: {}", name),
+        }
+    }
+
+    /// `arg` of vector builtin `name` as its lanes, which has to be a
+    /// vector. Values the type checker couldn't tell end up here.
+    fn gen_vector(&mut self, name: &str, arg: &SeppoExpr) -> Result<VectorValue<'ctx>> {
+        let vector = self.gen_expr(arg)?;
+        if vector.get_type() != self.vector_type() {
+            return Err(SeppoError::Sema(format!(
+                "{} takes an i64x4, got an integer or a string",
+                name
+            )));
+        }
+        self.lanes_of(vector)
+    }
+
+    fn lanes_of(&self, vector: IntValue<'ctx>) -> Result<VectorValue<'ctx>> {
+        Ok(self
+            .builder
+            .build_bit_cast(vector, self.lanes_type(), "lanes")?
+            .into_vector_value())
+    }
+
+    fn vector_of(&self, lanes: VectorValue<'ctx>) -> Result<IntValue<'ctx>> {
+        Ok(self
+            .builder
+            .build_bit_cast(lanes, self.vector_type(), "vector")?
+            .into_int_value())
+    }
+
+    /// An `alloc` moved to the stack by `promote_allocations`, in the entry
+    /// block so that one in a loop doesn't grow the frame each iteration
+    fn gen_stack_alloc(&mut self, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        let [SeppoExpr::Number(size)] = args else {
+            unreachable!("stack allocations have a constant size");
+        };
+        let array_type = self.context.i8_type().array_type(*size as u32);
+        let memory = self.entry_alloca_of(array_type.into(), "stack_alloc")?;
+        // As aligned as what malloc gives
+        memory
+            .as_instruction()
+            .unwrap()
+            .set_alignment(16)
+            .map_err(|e| SeppoError::Codegen(e.to_string()))?;
+        Ok(self
+            .builder
+            .build_ptr_to_int(memory, self.context.i64_type(), "stack_alloc")?)
+    }
+
+    /// `dlopen` and `dlsym` from libdl, and `call` as an indirect call
+    fn gen_dynamic_builtin(&mut self, name: &str, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        builtins::check_arity(name, args.len())?;
+        // A library can call anything in libc, like system
+        if sandbox::no_extern() {
+            return Err(SeppoError::Codegen(format!(
+                "{} is not allowed with --no-extern",
+                name
+            )));
+        }
+        if self.target.is_wasm() || self.target.triple().contains("windows") {
+            return Err(SeppoError::Codegen(format!(
+                "Builtin {} needs POSIX dynamic loading, which {} doesn't have",
+                name,
+                self.target.triple()
+            )));
+        }
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let args = args
+            .iter()
+            .map(|arg| self.gen_expr(arg))
+            .collect::<Result<Vec<_>>>()?;
+        let first = self.builder.build_int_to_ptr(args[0], ptr_type, "dl_arg")?;
+        let result = match name {
+            "dlopen" => {
+                let dlopen = self.libc_function(
+                    "dlopen",
+                    ptr_type.fn_type(&[ptr_type.into(), i32_type.into()], false),
+                );
+                // Resolve every symbol now, so missing ones fail here
+                const RTLD_NOW: u64 = 2;
+                self.builder.build_call(
+                    dlopen,
+                    &[first.into(), i32_type.const_int(RTLD_NOW, false).into()],
+                    "dlopen",
+                )?
+            }
+            "dlsym" => {
+                let dlsym = self.libc_function(
+                    "dlsym",
+                    ptr_type.fn_type(&[ptr_type.into(), ptr_type.into()], false),
+                );
+                let symbol = self.builder.build_int_to_ptr(args[1], ptr_type, "symbol")?;
+                self.builder
+                    .build_call(dlsym, &[first.into(), symbol.into()], "dlsym")?
+            }
+            "call" => {
+                let params = vec![i64_type.into(); args.len() - 1];
+                let fn_type = i64_type.fn_type(&params, false);
+                let call_args: Vec<BasicMetadataValueEnum> =
+                    args[1..].iter().map(|&arg| arg.into()).collect();
+                let result = self
+                    .builder
+                    .build_indirect_call(fn_type, first, &call_args, "call")?;
+                return Ok(result.try_as_basic_value().left().unwrap().into_int_value());
+            }
+            _ => unreachable!("not a dynamic loading builtin: {}", name),
+        };
+        let pointer = result
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value();
+        Ok(self.builder.build_ptr_to_int(pointer, i64_type, name)?)
+    }
+
+    /// `malloc(size)`
+    fn gen_malloc(&mut self, size: IntValue<'ctx>, name: &str) -> Result<PointerValue<'ctx>> {
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let malloc = self.libc_function("malloc", ptr_type.fn_type(&[i64_type.into()], false));
+        Ok(self
+            .builder
+            .build_call(malloc, &[size.into()], name)?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_pointer_value())
+    }
+
+    /// `sleep_ms(n)`: nanosleep for `n` milliseconds, or none if `n` is
+    /// negative, sleeping again for what's left when a signal interrupts
+    fn gen_sleep(&mut self, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let target_data = self.target_machine()?.get_target_data();
+        // `time_t` is 64 bits wide on the targets seppo supports, `long`
+        // is as wide as a pointer
+        let long_type = self.context.ptr_sized_int_type(&target_data, None);
+        let timespec = self
+            .context
+            .struct_type(&[i64_type.into(), long_type.into()], false);
+
+        let millis = self.gen_expr(&args[0])?;
+        let negative = self.builder.build_int_compare(
+            IntPredicate::SLT,
+            millis,
+            i64_type.const_zero(),
+            "negative",
+        )?;
+        let millis = self
+            .builder
+            .build_select(negative, i64_type.const_zero(), millis, "millis")?
+            .into_int_value();
+        let thousand = i64_type.const_int(1000, false);
+        let seconds = self
+            .builder
+            .build_int_unsigned_div(millis, thousand, "seconds")?;
+        let rest = self
+            .builder
+            .build_int_unsigned_rem(millis, thousand, "rest")?;
+        let nanos =
+            self.builder
+                .build_int_mul(rest, i64_type.const_int(1_000_000, false), "nanos")?;
+        let nanos = self
+            .builder
+            .build_int_truncate_or_bit_cast(nanos, long_type, "nanos")?;
+
+        let time = self.entry_alloca_of(timespec.into(), "timespec")?;
+        let seconds_field = self.builder.build_struct_gep(timespec, time, 0, "tv_sec")?;
+        self.builder.build_store(seconds_field, seconds)?;
+        let nanos_field = self
+            .builder
+            .build_struct_gep(timespec, time, 1, "tv_nsec")?;
+        self.builder.build_store(nanos_field, nanos)?;
+
+        let nanosleep = self.libc_function(
+            "nanosleep",
+            i32_type.fn_type(&[ptr_type.into(), ptr_type.into()], false),
+        );
+        let function = self.current_function.unwrap();
+        let sleep_bb = self.context.append_basic_block(function, "sleep");
+        let done_bb = self.context.append_basic_block(function, "slept");
+        self.builder.build_unconditional_branch(sleep_bb)?;
+        self.builder.position_at_end(sleep_bb);
+        // What's left is written back into the same timespec
+        let status = self
+            .builder
+            .build_call(nanosleep, &[time.into(), time.into()], "nanosleep")?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        let interrupted = self.builder.build_int_compare(
+            IntPredicate::NE,
+            status,
+            i32_type.const_zero(),
+            "interrupted",
+        )?;
+        self.builder
+            .build_conditional_branch(interrupted, sleep_bb, done_bb)?;
+        self.builder.position_at_end(done_bb);
+        Ok(i64_type.const_zero())
+    }
+
+    /// `spawn(f, arg)`: start a thread running `f(arg)` and give its
+    /// `pthread_t` as the handle
+    fn gen_spawn(&mut self, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        let name = builtins::spawned_function(args)?;
+        let Some(&function) = self.functions.get(name) else {
+            return Err(undefined(
+                "function",
+                name,
+                self.functions.keys().map(String::as_str),
+            ));
+        };
+        if function.count_params() != 1 {
+            return Err(SeppoError::Sema(format!(
+                "spawn runs functions taking one argument, but {} takes {}",
+                name,
+                function.count_params()
+            )));
+        }
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let arg = self.gen_expr(&args[1])?;
+        let arg = self.builder.build_int_to_ptr(arg, ptr_type, "thread_arg")?;
+        let start = self.thread_start(name, function)?;
+        let attributes = self.thread_attributes()?;
+        // Zeroed, so a pthread_t narrower than 64 bits reads back unchanged
+        let handle = self.entry_alloca("thread")?;
+        self.builder.build_store(handle, i64_type.const_zero())?;
+        self.gen_pthread_call(
+            "pthread_create",
+            &[
+                handle.into(),
+                attributes.into(),
+                start.as_global_value().as_pointer_value().into(),
+                arg.into(),
+            ],
+            "spawn: cannot create a thread",
+        )?;
+        Ok(self
+            .builder
+            .build_load(i64_type, handle, "handle")?
+            .into_int_value())
+    }
+
+    /// `join(handle)`: wait for the thread and give what its function
+    /// returned
+    fn gen_join(&mut self, args: &[SeppoExpr]) -> Result<IntValue<'ctx>> {
+        let i64_type = self.context.i64_type();
+        let target_data = self.target_machine()?.get_target_data();
+        let pthread_t = self.context.ptr_sized_int_type(&target_data, None);
+
+        let handle = self.gen_expr(&args[0])?;
+        let handle = self
+            .builder
+            .build_int_truncate_or_bit_cast(handle, pthread_t, "pthread")?;
+        let result = self.entry_alloca("thread_result")?;
+        self.builder.build_store(result, i64_type.const_zero())?;
+        self.gen_pthread_call(
+            "pthread_join",
+            &[handle.into(), result.into()],
+            "join: cannot join the thread",
+        )?;
+        Ok(self
+            .builder
+            .build_load(i64_type, result, "joined")?
+            .into_int_value())
+    }
+
+    /// Call a pthreads function, which returns 0 or an error number, and
+    /// panic with `what` if it fails
+    fn gen_pthread_call(
+        &mut self,
+        name: &str,
+        args: &[BasicValueEnum<'ctx>],
+        what: &str,
+    ) -> Result<()> {
+        let i32_type = self.context.i32_type();
+        let params: Vec<_> = args.iter().map(|arg| arg.get_type().into()).collect();
+        let function = self.libc_function(name, i32_type.fn_type(&params, false));
+        let args: Vec<BasicMetadataValueEnum> = args.iter().map(|&arg| arg.into()).collect();
+        let status = self
+            .builder
+            .build_call(function, &args, "status")?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+
+        let current = self.current_function.unwrap();
+        let failed = self.builder.build_int_compare(
+            IntPredicate::NE,
+            status,
+            i32_type.const_zero(),
+            "failed",
+        )?;
+        let fail_bb = self.context.append_basic_block(current, "pthread_failed");
+        let ok_bb = self.context.append_basic_block(current, "pthread_ok");
+        self.builder
+            .build_conditional_branch(failed, fail_bb, ok_bb)?;
+        self.builder.position_at_end(fail_bb);
+        self.gen_panic(what)?;
+        self.builder.position_at_end(ok_bb);
+        Ok(())
+    }
+
+    /// `void *seppo_thread.<name>(void *arg)`, the start routine pthreads
+    /// calls, which converts between pointers and seppo's integers around
+    /// a call of `function`
+    fn thread_start(
+        &self,
+        name: &str,
+        function: FunctionValue<'ctx>,
+    ) -> Result<FunctionValue<'ctx>> {
+        let symbol = format!("seppo_thread.{}", name);
+        if let Some(start) = self.module.get_function(&symbol) {
+            return Ok(start);
+        }
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let start = self.module.add_function(
+            &symbol,
+            ptr_type.fn_type(&[ptr_type.into()], false),
+            Some(Linkage::Internal),
+        );
+        // A builder of its own, so the current position and debug location
+        // stay as they are
+        let builder = self.context.create_builder();
+        builder.position_at_end(self.context.append_basic_block(start, "entry"));
+        let arg = start.get_nth_param(0).unwrap().into_pointer_value();
+        let arg = builder.build_ptr_to_int(arg, i64_type, "arg")?;
+        let result = self.call_seppo(&builder, function, None, &[arg], "result")?;
+        let result = builder.build_int_to_ptr(result, ptr_type, "result")?;
+        builder.build_return(Some(&result))?;
+        Ok(start)
+    }
+
+    /// Call seppo from `main` on a thread with `--stack-size` bytes of stack
+    /// and wait for it, for targets where the linker can't size the main
+    /// thread's stack
+    fn call_seppo_on_thread(
+        &mut self,
+        seppo_fn: FunctionValue<'ctx>,
+        args: &[IntValue<'ctx>],
+    ) -> Result<IntValue<'ctx>> {
+        let i64_type = self.context.i64_type();
+        let ptr_type = self.context.ptr_type(0.into());
+        let args_type = i64_type.array_type(2);
+
+        let start = self.module.add_function(
+            "seppo.main_thread",
+            ptr_type.fn_type(&[ptr_type.into()], false),
+            Some(Linkage::Internal),
+        );
+        let builder = self.context.create_builder();
+        builder.position_at_end(self.context.append_basic_block(start, "entry"));
+        let slots = start.get_nth_param(0).unwrap().into_pointer_value();
+        let mut values = Vec::new();
+        for i in 0..args.len() {
+            let slot = unsafe {
+                builder.build_in_bounds_gep(
+                    args_type,
+                    slots,
+                    &[i64_type.const_zero(), i64_type.const_int(i as u64, false)],
+                    "arg_slot",
+                )?
+            };
+            values.push(builder.build_load(i64_type, slot, "arg")?.into_int_value());
+        }
+        let result = self.call_seppo(&builder, seppo_fn, None, &values, "seppo_call")?;
+        let result = builder.build_int_to_ptr(result, ptr_type, "result")?;
+        builder.build_return(Some(&result))?;
+
+        // argc and argv, as many as seppo takes
+        let slots = self.entry_alloca_of(args_type.into(), "main_args")?;
+        let mut stored = args_type.const_zero();
+        for (i, &arg) in args.iter().enumerate() {
+            stored = self
+                .builder
+                .build_insert_value(stored, arg, i as u32, "main_arg")?
+                .into_array_value();
+        }
+        self.builder.build_store(slots, stored)?;
+        let attributes = self.thread_attributes()?;
+        let handle = self.entry_alloca("thread")?;
+        self.builder.build_store(handle, i64_type.const_zero())?;
+        self.gen_pthread_call(
+            "pthread_create",
+            &[
+                handle.into(),
+                attributes.into(),
+                start.as_global_value().as_pointer_value().into(),
+                slots.into(),
+            ],
+            "--stack-size: cannot create the main thread",
+        )?;
+
+        let target_data = self.target_machine()?.get_target_data();
+        let pthread_t = self.context.ptr_sized_int_type(&target_data, None);
+        let handle = self.builder.build_load(i64_type, handle, "handle")?;
+        let handle = self.builder.build_int_truncate_or_bit_cast(
+            handle.into_int_value(),
+            pthread_t,
+            "pthread",
+        )?;
+        let result = self.entry_alloca("thread_result")?;
+        self.builder.build_store(result, i64_type.const_zero())?;
+        self.gen_pthread_call(
+            "pthread_join",
+            &[handle.into(), result.into()],
+            "--stack-size: cannot join the main thread",
+        )?;
+        Ok(self
+            .builder
+            .build_load(i64_type, result, "seppo_result")?
+            .into_int_value())
+    }
+
+    /// Attributes for `pthread_create`: null for the defaults, or ones with
+    /// the `--stack-size` stack
+    fn thread_attributes(&mut self) -> Result<PointerValue<'ctx>> {
+        let ptr_type = self.context.ptr_type(0.into());
+        let Some(stack_size) = self.stack_size else {
+            return Ok(ptr_type.const_null());
+        };
+        let attributes_type = self.context.i64_type().array_type(PTHREAD_ATTR_SIZE / 8);
+        let attributes = self.entry_alloca_of(attributes_type.into(), "thread_attributes")?;
+        self.gen_pthread_call(
+            "pthread_attr_init",
+            &[attributes.into()],
+            "cannot create thread attributes",
+        )?;
+        let target_data = self.target_machine()?.get_target_data();
+        let size_t = self.context.ptr_sized_int_type(&target_data, None);
+        self.gen_pthread_call(
+            "pthread_attr_setstacksize",
+            &[
+                attributes.into(),
+                size_t.const_int(stack_size, false).into(),
+            ],
+            &format!("cannot give threads a stack of {} bytes", stack_size),
+        )?;
+        Ok(attributes)
+    }
+
+    /// `value` clamped to `0..=max`, for a nonnegative `max`
+    fn clamp(
+        &self,
+        value: IntValue<'ctx>,
+        max: IntValue<'ctx>,
+        name: &str,
+    ) -> Result<IntValue<'ctx>> {
+        let zero = self.context.i64_type().const_zero();
+        let negative =
+            self.builder
+                .build_int_compare(IntPredicate::SLT, value, zero, "negative")?;
+        let low = self
+            .builder
+            .build_select(negative, zero, value, name)?
+            .into_int_value();
+        let too_big = self
+            .builder
+            .build_int_compare(IntPredicate::SGT, low, max, "too_big")?;
+        Ok(self
+            .builder
+            .build_select(too_big, max, low, name)?
+            .into_int_value())
+    }
+
+    /// Declare a libc function on first use
+    fn libc_function(
+        &self,
+        name: &str,
+        fn_type: inkwell::types::FunctionType<'ctx>,
+    ) -> FunctionValue<'ctx> {
+        self.module
+            .get_function(name)
+            .unwrap_or_else(|| self.module.add_function(name, fn_type, None))
+    }
+
+    pub fn get_module(&self) -> &Module<'ctx> {
+        &self.module
+    }
+
+    /// Link the bitcode of ceppo blocks into the module and optimize the
+    /// whole program at once. Only the entry points stay visible outside
+    /// the module, so everything else can be inlined across seppo and C and
+    /// dropped when unused.
+    pub fn link_time_optimize(&mut self) -> Result<()> {
+        for path in std::mem::take(&mut self.c_bitcode_files) {
+            let module = Module::parse_bitcode_from_path(&path, self.context).map_err(|e| {
+                SeppoError::Codegen(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+            self.module.link_in_module(module).map_err(|e| {
+                SeppoError::Codegen(format!("Failed to link {}: {}", path.display(), e))
+            })?;
+        }
+
+        let mut function = self.module.get_first_function();
+        while let Some(f) = function {
+            let exported = match f.get_name().to_str() {
+                Ok("main" | "_start" | "seppo") => true,
+                Ok(name) => self.exported.contains(name),
+                Err(_) => false,
+            };
+            if f.count_basic_blocks() > 0 && !exported {
+                f.set_linkage(Linkage::Internal);
+            }
+            function = f.get_next_function();
+        }
+
+        let level = match self.opt_level {
+            OptimizationLevel::None => return Ok(()),
+            OptimizationLevel::Less => 1,
+            OptimizationLevel::Default => 2,
+            OptimizationLevel::Aggressive => 3,
+        };
+        let target_machine = self.target_machine()?;
+        self.module
+            .run_passes(
+                &format!("default<O{}>", level),
+                &target_machine,
+                PassBuilderOptions::create(),
+            )
+            .map_err(|e| SeppoError::Codegen(format!("Failed to optimize module: {}", e)))
+    }
+
+    pub fn write_object_file(&self, output: &Path) -> Result<()> {
+        let target_machine = self.target_machine()?;
+        self.module
+            .set_data_layout(&target_machine.get_target_data().get_data_layout());
+
+        // Write object file
+        target_machine
+            .write_to_file(&self.module, FileType::Object, output)
+            .map_err(|e| SeppoError::Codegen(format!("Failed to write object file: {}", e)))
+    }
+
+    fn target_machine(&self) -> Result<TargetMachine> {
+        let target_triple = TargetTriple::create(self.target.triple());
+        let (cpu, features) = self.target.cpu_and_features();
+
+        // Initialize target
+        let target = Target::from_triple(&target_triple)
+            .map_err(|e| SeppoError::Codegen(format!("Failed to get target: {}", e)))?;
+
+        // Create target machine
+        target
+            .create_target_machine(
+                &target_triple,
+                &cpu,
+                &features,
+                self.opt_level,
+                match self.pie {
+                    Some(true) => RelocMode::PIC,
+                    Some(false) => RelocMode::Static,
+                    None => RelocMode::Default,
+                },
+                CodeModel::Default,
+            )
+            .ok_or_else(|| SeppoError::Codegen("Failed to create target machine".to_string()))
+    }
+
+    /// Declare the WASI imports and the integer printing helper used instead of printf
+    fn declare_wasi_runtime(&mut self) {
+        let i32_type = self.context.i32_type();
+        let ptr_type = self.context.ptr_type(0.into());
+
+        let fd_write_type = i32_type.fn_type(
+            &[
+                i32_type.into(),
+                ptr_type.into(),
+                i32_type.into(),
+                ptr_type.into(),
+            ],
+            false,
+        );
+        let proc_exit_type = self.context.void_type().fn_type(&[i32_type.into()], false);
+
+        for (name, fn_type) in [("fd_write", fd_write_type), ("proc_exit", proc_exit_type)] {
+            let function = self
+                .module
+                .add_function(name, fn_type, Some(Linkage::External));
+            function.add_attribute(
+                AttributeLoc::Function,
+                self.context
+                    .create_string_attribute("wasm-import-module", "wasi_snapshot_preview1"),
+            );
+            function.add_attribute(
+                AttributeLoc::Function,
+                self.context
+                    .create_string_attribute("wasm-import-name", name),
+            );
+        }
+
+        self.build_wasi_print()
+            .expect("Failed to build wasm print helper");
+    }
+
+    /// Build `seppo_print_i64(value, hex, fd)`, formatting the value into a
+    /// stack buffer back to front and writing it to `fd` with fd_write.
+    fn build_wasi_print(&mut self) -> Result<()> {
+        const BUF_LEN: u64 = 24;
+
+        let i8_type = self.context.i8_type();
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let bool_type = self.context.bool_type();
+
+        let fn_type = self
+            .context
+            .void_type()
+            .fn_type(&[i64_type.into(), bool_type.into(), i32_type.into()], false);
+        let function =
+            self.module
+                .add_function("seppo_print_i64", fn_type, Some(Linkage::Internal));
+        let value = function.get_nth_param(0).unwrap().into_int_value();
+        let hex = function.get_nth_param(1).unwrap().into_int_value();
+        let fd = function.get_nth_param(2).unwrap().into_int_value();
+
+        let entry = self.context.append_basic_block(function, "entry");
+        let digit_bb = self.context.append_basic_block(function, "digit");
+        let done_bb = self.context.append_basic_block(function, "done");
+        let prefix_bb = self.context.append_basic_block(function, "prefix");
+        let sign_bb = self.context.append_basic_block(function, "sign");
+        let minus_bb = self.context.append_basic_block(function, "minus");
+        let write_bb = self.context.append_basic_block(function, "write");
+
+        let buf_type = i8_type.array_type(BUF_LEN as u32);
+
+        self.builder.position_at_end(entry);
+        let buf = self.builder.build_alloca(buf_type, "buf")?;
+        let pos = self.builder.build_alloca(i64_type, "pos")?;
+        let rest = self.builder.build_alloca(i64_type, "rest")?;
+
+        // Writes one byte just before the current position
+        let push_byte = |codegen: &Self, byte: IntValue<'ctx>| -> Result<()> {
+            let current = codegen
+                .builder
+                .build_load(i64_type, pos, "pos")?
+                .into_int_value();
+            let next =
+                codegen
+                    .builder
+                    .build_int_sub(current, i64_type.const_int(1, false), "pos_next")?;
+            codegen.builder.build_store(pos, next)?;
+            let slot = unsafe {
+                codegen
+                    .builder
+                    .build_in_bounds_gep(i8_type, buf, &[next], "slot")?
+            };
+            codegen.builder.build_store(slot, byte)?;
+            Ok(())
+        };
+
+        self.builder
+            .build_store(pos, i64_type.const_int(BUF_LEN, false))?;
+        push_byte(self, i8_type.const_int(b'\n' as u64, false))?;
+
+        // Hex prints the raw bits, decimal prints the magnitude and a sign
+        let is_negative = self.builder.build_int_compare(
+            IntPredicate::SLT,
+            value,
+            i64_type.const_zero(),
+            "is_neg",
+        )?;
+        let not_hex = self.builder.build_not(hex, "not_hex")?;
+        let negative = self.builder.build_and(is_negative, not_hex, "negative")?;
+        let negated = self.builder.build_int_neg(value, "negated")?;
+        let magnitude = self
+            .builder
+            .build_select(negative, negated, value, "magnitude")?
+            .into_int_value();
+        self.builder.build_store(rest, magnitude)?;
+        let base = self
+            .builder
+            .build_select(
+                hex,
+                i64_type.const_int(16, false),
+                i64_type.const_int(10, false),
+                "base",
+            )?
+            .into_int_value();
+        self.builder.build_unconditional_branch(digit_bb)?;
+
+        self.builder.position_at_end(digit_bb);
+        let current = self
+            .builder
+            .build_load(i64_type, rest, "rest")?
+            .into_int_value();
+        let digit = self
+            .builder
+            .build_int_unsigned_rem(current, base, "digit")?;
+        let is_decimal_digit = self.builder.build_int_compare(
+            IntPredicate::ULT,
+            digit,
+            i64_type.const_int(10, false),
+            "is_dec",
+        )?;
+        let decimal_char = self.builder.build_int_add(
+            digit,
+            i64_type.const_int(b'0' as u64, false),
+            "dec_char",
+        )?;
+        let hex_char = self.builder.build_int_add(
+            digit,
+            i64_type.const_int(b'a' as u64 - 10, false),
+            "hex_char",
+        )?;
+        let char_value = self
+            .builder
+            .build_select(is_decimal_digit, decimal_char, hex_char, "char")?
+            .into_int_value();
+        let byte = self
+            .builder
+            .build_int_truncate(char_value, i8_type, "byte")?;
+        push_byte(self, byte)?;
+        let remaining = self
+            .builder
+            .build_int_unsigned_div(current, base, "remaining")?;
+        self.builder.build_store(rest, remaining)?;
+        let more = self.builder.build_int_compare(
+            IntPredicate::NE,
+            remaining,
+            i64_type.const_zero(),
+            "more",
+        )?;
+        self.builder
+            .build_conditional_branch(more, digit_bb, done_bb)?;
+
+        self.builder.position_at_end(done_bb);
+        self.builder
+            .build_conditional_branch(hex, prefix_bb, sign_bb)?;
+
+        self.builder.position_at_end(prefix_bb);
+        push_byte(self, i8_type.const_int(b'x' as u64, false))?;
+        push_byte(self, i8_type.const_int(b'0' as u64, false))?;
+        self.builder.build_unconditional_branch(write_bb)?;
+
+        self.builder.position_at_end(sign_bb);
+        self.builder
+            .build_conditional_branch(negative, minus_bb, write_bb)?;
+
+        self.builder.position_at_end(minus_bb);
+        push_byte(self, i8_type.const_int(b'-' as u64, false))?;
+        self.builder.build_unconditional_branch(write_bb)?;
+
+        self.builder.position_at_end(write_bb);
+        let start = self
+            .builder
+            .build_load(i64_type, pos, "start")?
+            .into_int_value();
+        let text = unsafe {
+            self.builder
+                .build_in_bounds_gep(i8_type, buf, &[start], "text")?
+        };
+        let len = self
+            .builder
+            .build_int_sub(i64_type.const_int(BUF_LEN, false), start, "len")?;
+        let len = self.builder.build_int_truncate(len, i32_type, "len32")?;
+        self.write_fd(&self.builder, fd, text, len)?;
+        self.builder.build_return(None)?;
+
+        Ok(())
+    }
+
+    /// WASI programs start at `_start`, which reports seppo's result through proc_exit
+    fn build_wasi_start(&mut self) -> Result<()> {
+        let seppo_fn = self
+            .module
+            .get_function("seppo")
+            .ok_or_else(|| SeppoError::Sema("No seppo function found".to_string()))?;
+
+        let i32_type = self.context.i32_type();
+        let start_type = self.context.void_type().fn_type(&[], false);
+        let start_fn = self.module.add_function("_start", start_type, None);
+        let entry = self.context.append_basic_block(start_fn, "entry");
+        self.builder.position_at_end(entry);
+
+        let seppo_result = self.call_seppo(&self.builder, seppo_fn, None, &[], "seppo_call")?;
+        let result = self
+            .builder
+            .build_int_truncate(seppo_result, i32_type, "result")?;
+        let proc_exit = self.module.get_function("proc_exit").unwrap();
+        self.builder
+            .build_call(proc_exit, &[result.into()], "proc_exit_call")?;
+        self.builder.build_unreachable()?;
+
+        Ok(())
+    }
+
+    /// Add `__seppo_bench_<function>(iterations)`, which calls a zero-argument
+    /// function the given number of times and returns the elapsed wall time in
+    /// nanoseconds, read from the monotonic clock around the loop
+    pub fn add_bench_harness(&mut self, function: &str) -> Result<String> {
+        let bench_fn = self
+            .functions
+            .get(function)
+            .copied()
+            .filter(|f| f.count_params() == 0)
+            .ok_or_else(|| {
+                SeppoError::Sema(format!("No zero-argument function named {}", function))
+            })?;
+
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let timespec_type = self
+            .context
+            .struct_type(&[i64_type.into(), i64_type.into()], false);
+        let clock_gettime = self
+            .module
+            .get_function("clock_gettime")
+            .unwrap_or_else(|| {
+                let ptr_type = self.context.ptr_type(0.into());
+                let fn_type = i32_type.fn_type(&[i32_type.into(), ptr_type.into()], false);
+                self.module.add_function("clock_gettime", fn_type, None)
+            });
+        // CLOCK_MONOTONIC
+        let clock_id = i32_type.const_int(if cfg!(target_os = "macos") { 6 } else { 1 }, false);
+
+        let name = format!("__seppo_bench_{}", function);
+        let harness =
+            self.module
+                .add_function(&name, i64_type.fn_type(&[i64_type.into()], false), None);
+        let entry = self.context.append_basic_block(harness, "entry");
+        let cond_bb = self.context.append_basic_block(harness, "loop_cond");
+        let body_bb = self.context.append_basic_block(harness, "loop_body");
+        let done_bb = self.context.append_basic_block(harness, "done");
+
+        self.builder.position_at_end(entry);
+        let iterations = harness.get_nth_param(0).unwrap().into_int_value();
+        let start = self.builder.build_alloca(timespec_type, "start")?;
+        let end = self.builder.build_alloca(timespec_type, "end")?;
+        let counter = self.builder.build_alloca(i64_type, "i")?;
+        // Results are stored here so the calls can't be optimized out
+        let sink = self.builder.build_alloca(i64_type, "sink")?;
+        self.builder.build_store(counter, i64_type.const_zero())?;
+        self.builder.build_call(
+            clock_gettime,
+            &[clock_id.into(), start.into()],
+            "start_time",
+        )?;
+        self.builder.build_unconditional_branch(cond_bb)?;
+
+        self.builder.position_at_end(cond_bb);
+        let i = self
+            .builder
+            .build_load(i64_type, counter, "i")?
+            .into_int_value();
+        let more = self
+            .builder
+            .build_int_compare(IntPredicate::SLT, i, iterations, "more")?;
+        self.builder
+            .build_conditional_branch(more, body_bb, done_bb)?;
+
+        self.builder.position_at_end(body_bb);
+        let result = self.call_seppo(&self.builder, bench_fn, None, &[], "result")?;
+        self.builder
+            .build_store(sink, result)?
+            .set_volatile(true)
+            .map_err(|e| SeppoError::Codegen(e.to_string()))?;
+        let next = self
+            .builder
+            .build_int_add(i, i64_type.const_int(1, false), "next")?;
+        self.builder.build_store(counter, next)?;
+        self.builder.build_unconditional_branch(cond_bb)?;
+
+        self.builder.position_at_end(done_bb);
+        self.builder
+            .build_call(clock_gettime, &[clock_id.into(), end.into()], "end_time")?;
+        let nanos = |builder: &Builder<'ctx>, ts: PointerValue<'ctx>| -> Result<IntValue<'ctx>> {
+            let sec_ptr = builder.build_struct_gep(timespec_type, ts, 0, "sec_ptr")?;
+            let nsec_ptr = builder.build_struct_gep(timespec_type, ts, 1, "nsec_ptr")?;
+            let sec = builder
+                .build_load(i64_type, sec_ptr, "sec")?
+                .into_int_value();
+            let nsec = builder
+                .build_load(i64_type, nsec_ptr, "nsec")?
+                .into_int_value();
+            let sec_ns =
+                builder.build_int_mul(sec, i64_type.const_int(1_000_000_000, false), "sec_ns")?;
+            Ok(builder.build_int_add(sec_ns, nsec, "ns")?)
+        };
+        let start_ns = nanos(&self.builder, start)?;
+        let end_ns = nanos(&self.builder, end)?;
+        let elapsed = self.builder.build_int_sub(end_ns, start_ns, "elapsed")?;
+        self.builder.build_return(Some(&elapsed))?;
+
+        if !harness.verify(true) {
+            return Err(SeppoError::Codegen(format!(
+                "Benchmark harness for {} failed verification",
+                function
+            )));
+        }
+        Ok(name)
+    }
+
+    #[allow(dead_code)]
+    pub fn c_object_files(&self) -> &[std::path::PathBuf] {
+        &self.c_object_files
+    }
+}
+
+/// `signext` or `zeroext` for integers narrower than C's `int`
+fn extension_attribute(ty: &CType) -> Option<&'static str> {
+    match ty {
+        CType::Int { bits, signed: true } if *bits < 32 => Some("signext"),
+        CType::Int {
+            bits,
+            signed: false,
+        } if *bits < 32 => Some("zeroext"),
+        _ => None,
+    }
+}
+
+/// Prefix of the symbols of seppo functions that aren't exported. The dots
+/// keep them apart from every C identifier.
+const MANGLE_PREFIX: &str = "seppo.fn.";
+
+/// Prefix of the symbols of globals, dotted like `MANGLE_PREFIX`
+const GLOBAL_PREFIX: &str = "seppo.global.";
+
+fn mangle(name: &str) -> String {
+    format!("{}{}", MANGLE_PREFIX, name)
+}
+
+/// The seppo name of a function's symbol
+pub(crate) fn demangle(symbol: &str) -> &str {
+    symbol.strip_prefix(MANGLE_PREFIX).unwrap_or(symbol)
+}
+
+/// The variables of a function that are only ever assigned conditions,
+/// directly or through other such variables. Parameters and loop
+/// variables hold integers.
+fn bool_variables(params: &[String], body: &SeppoExpr) -> HashSet<String> {
+    let mut assignments = Vec::new();
+    let mut integers: HashSet<&str> = params.iter().map(String::as_str).collect();
+    collect_assignments(body, &mut assignments, &mut integers);
+    let mut bools: HashSet<&str> = assignments
+        .iter()
+        .map(|(name, _)| *name)
+        .filter(|name| !integers.contains(name))
+        .collect();
+    loop {
+        let before = bools.len();
+        for (name, value) in &assignments {
+            if !is_condition(value, &bools) {
+                bools.remove(name);
+            }
+        }
+        if bools.len() == before {
+            return bools.into_iter().map(str::to_string).collect();
+        }
+    }
+}
+
+fn is_condition(expr: &SeppoExpr, bools: &HashSet<&str>) -> bool {
+    match expr {
+        SeppoExpr::Operation(op, ..) => is_comparison(op) || is_logical(op),
+        SeppoExpr::In(..) => true,
+        SeppoExpr::Variable(name) => bools.contains(name.as_str()),
+        _ => false,
+    }
+}
+
+/// The assignments in `expr`, and the loop variables in `loops`
+fn collect_assignments<'a>(
+    expr: &'a SeppoExpr,
+    assignments: &mut Vec<(&'a str, &'a SeppoExpr)>,
+    loops: &mut HashSet<&'a str>,
+) {
+    match expr {
+        SeppoExpr::Block(statements) => {
+            for statement in statements {
+                collect_assignments(statement, assignments, loops);
+            }
+        }
+        SeppoExpr::Assignment(name, value) | SeppoExpr::Let { name, value, .. } => {
+            assignments.push((name.as_str(), value.as_ref()));
+            collect_assignments(value, assignments, loops);
+        }
+        SeppoExpr::Return(value) => collect_assignments(value, assignments, loops),
+        SeppoExpr::Conditional {
+            true_block,
+            false_block,
+            ..
+        } => {
+            collect_assignments(true_block, assignments, loops);
+            if let Some(false_block) = false_block {
+                collect_assignments(false_block, assignments, loops);
+            }
+        }
+        SeppoExpr::For { variable, body, .. } => {
+            loops.insert(variable.as_str());
+            collect_assignments(body, assignments, loops);
+        }
+        _ => {}
+    }
+}
+
+/// File and line of the first statement of a function body parsed with
+/// locations
+fn first_location(body: &SeppoExpr) -> Option<(&str, usize)> {
+    let SeppoExpr::Block(statements) = body else {
+        return None;
+    };
+    statements.iter().find_map(|statement| match statement {
+        SeppoExpr::Location { file, line } => Some((file.as_str(), *line)),
+        _ => None,
+    })
+}
+
+/// Ordering of the atomic builtins
+const ATOMIC_ORDERING: AtomicOrdering = AtomicOrdering::SequentiallyConsistent;
+
+/// `--stack-size` rounds up to a multiple of this, the largest page size
+/// of the targets seppo supports, as pthreads and the Apple linker want whole
+/// pages
+const STACK_SIZE_GRANULE: u64 = 16 * 1024;
+
+/// File descriptors of standard output and error, for fd_write on wasm
+const STDOUT_FD: u64 = 1;
+const STDERR_FD: u64 = 2;
+
+/// Bytes reserved for a `pthread_attr_t`, larger than it is on the targets
+/// seppo supports
+const PTHREAD_ATTR_SIZE: u32 = 64;
+
+/// Lines of IR shown before and after each line the verifier quotes
+const SNIPPET_CONTEXT: usize = 3;
+
+/// The lines of a function's IR that `message` quotes, marked with `>` and
+/// with some context, or the start of the function when it quotes none
+fn ir_snippet(ir: &str, message: &str) -> String {
+    let quoted: Vec<&str> = message
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let lines: Vec<&str> = ir.lines().collect();
+    // Blocks are quoted as `label %name` but printed as `name:`
+    let is_quoted = |line: &str| {
+        let line = line.trim();
+        quoted
+            .iter()
+            .any(|quoted| match quoted.strip_prefix("label %") {
+                Some(label) => line
+                    .strip_prefix(label)
+                    .is_some_and(|rest| rest.starts_with(':')),
+                None => line == *quoted,
+            })
+    };
+    let marked: Vec<bool> = lines.iter().map(|line| is_quoted(line)).collect();
+    if !marked.contains(&true) {
+        let mut snippet: Vec<String> = lines.iter().take(20).map(|l| format!("  {}", l)).collect();
+        if lines.len() > 20 {
+            snippet.push("  ...".to_string());
+        }
+        return snippet.join("\n") + "\n";
+    }
+    let mut snippet = String::new();
+    let mut last = None;
+    for (i, line) in lines.iter().enumerate() {
+        let near = (i.saturating_sub(SNIPPET_CONTEXT)..=i + SNIPPET_CONTEXT)
+            .any(|j| marked.get(j).copied().unwrap_or(false));
+        if !near {
+            continue;
+        }
+        if last.is_some_and(|last| last + 1 != i) {
+            snippet.push_str("  ...\n");
+        }
+        let marker = if marked[i] { ">" } else { " " };
+        snippet.push_str(&format!("{} {}\n", marker, line));
+        last = Some(i);
     }
+    snippet
 }
@@ -0,0 +1,207 @@
+use crate::codegen::{
+    CodeGen, CodeGenOptions, DivisionOverflow, DivisionRounding, StdoutBuffering,
+};
+use crate::driver::{Driver, Input};
+use crate::error::{Result, SeppoError};
+use crate::sandbox::check_spawn;
+use crate::target::TargetSpec;
+use crate::temps::TempArtifacts;
+use inkwell::OptimizationLevel;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Settings for an in-memory compilation
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    pub target: TargetSpec,
+    pub module_name: String,
+    pub opt_level: OptimizationLevel,
+    /// Middle-end passes, none by default
+    pub codegen: CodeGenOptions,
+    /// Leave the build directory in place when the program is dropped
+    pub keep_temps: bool,
+    /// See `CodeGen::set_stdout_buffering`
+    pub stdout_buffering: Option<StdoutBuffering>,
+    /// See `CodeGen::set_heap_to_stack`
+    pub heap_to_stack: bool,
+    /// See `CodeGen::set_division`
+    pub division_rounding: DivisionRounding,
+    pub division_overflow: Option<DivisionOverflow>,
+    /// Thread stack size in bytes, see `CodeGen::set_stack_size`
+    pub stack_size: Option<u64>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            target: TargetSpec::native(),
+            module_name: "main".to_string(),
+            opt_level: OptimizationLevel::Default,
+            codegen: CodeGenOptions::default(),
+            keep_temps: false,
+            stdout_buffering: None,
+            heap_to_stack: true,
+            division_rounding: DivisionRounding::default(),
+            division_overflow: None,
+            stack_size: None,
+        }
+    }
+}
+
+/// A linked executable living in its own temporary directory, removed on drop
+#[derive(Debug)]
+pub struct CompiledProgram {
+    _temps: TempArtifacts,
+    executable: PathBuf,
+}
+
+impl CompiledProgram {
+    pub fn executable(&self) -> &Path {
+        &self.executable
+    }
+
+    /// A command for running the program, for callers that want to pass
+    /// arguments or capture output themselves
+    pub fn command(&self) -> Command {
+        Command::new(&self.executable)
+    }
+
+    /// Run the program to completion and return its exit code
+    pub fn run(&self) -> Result<i64> {
+        let output = self
+            .command()
+            .output()
+            .map_err(|e| SeppoError::Runtime(format!("Failed to execute binary: {}", e)))?;
+
+        let exit_code = output
+            .status
+            .code()
+            .ok_or_else(|| SeppoError::Runtime("Process terminated by signal".to_string()))?;
+
+        Ok(exit_code as i64)
+    }
+}
+
+/// Compile and link seppo source into an executable
+pub fn compile_str(source: &str, options: &CompileOptions) -> Result<CompiledProgram> {
+    compile_input(
+        Input::Source {
+            name: options.module_name.clone(),
+            text: source.to_string(),
+        },
+        options,
+    )
+}
+
+/// Compile any input, e.g. a file, into an executable in a temporary directory
+pub fn compile_input(input: Input, options: &CompileOptions) -> Result<CompiledProgram> {
+    let mut driver = Driver::new(vec![input]);
+    driver.target = options.target.clone();
+    driver.opt_level = options.opt_level;
+    driver.codegen = options.codegen.clone();
+    driver.keep_temps = options.keep_temps;
+    driver.stdout_buffering = options.stdout_buffering;
+    driver.heap_to_stack = options.heap_to_stack;
+    driver.division_rounding = options.division_rounding;
+    driver.division_overflow = options.division_overflow;
+    driver.stack_size = options.stack_size;
+    driver.dump_ir = false;
+    compile_driver(driver, &options.module_name)
+}
+
+/// Build the executable `driver` describes in a temporary directory,
+/// named `name`, with every other setting as it is
+pub fn compile_driver(mut driver: Driver, name: &str) -> Result<CompiledProgram> {
+    let temps = TempArtifacts::new(driver.keep_temps);
+    let dir = temps.dir("seppolang_build")?;
+    driver.output = Some(dir.join(name));
+
+    let executable = driver.run()?;
+    Ok(CompiledProgram {
+        _temps: temps,
+        executable,
+    })
+}
+
+/// Compile seppo source for the host, run it and return its exit code
+pub fn run_str(source: &str) -> Result<i64> {
+    compile_str(source, &CompileOptions::default())?.run()
+}
+
+/// Link an object file and the ceppo objects of `codegen` into an executable
+pub fn link(
+    obj_file: &Path,
+    output: &Path,
+    codegen: &CodeGen,
+    extra_args: &[String],
+) -> Result<()> {
+    let mut link_command = codegen.target().linker();
+    link_command.arg("-o").arg(output).arg(obj_file);
+
+    // Add any C object files from ceppo blocks
+    for c_obj in codegen.c_object_files() {
+        link_command.arg(c_obj);
+    }
+    link_command.args(codegen.link_flags());
+    link_command.args(extra_args);
+    check_spawn(&link_command, "Linking")?;
+
+    let output = link_command.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(SeppoError::Link(format!(
+            "Linking failed: {}\nLink command was: {:?}",
+            stderr, link_command
+        )));
+    }
+
+    Ok(())
+}
+
+/// Run the steps that follow a successful link on `executable`: `strip`
+/// when `strip` is set, then each of `commands` in order. A command may
+/// carry arguments, e.g. `codesign -s -`, and gets the executable's path
+/// as its last one.
+pub fn post_link(
+    executable: &Path,
+    target: &TargetSpec,
+    strip: bool,
+    commands: &[String],
+) -> Result<()> {
+    if strip {
+        let mut command = target.strip();
+        command.arg(executable);
+        run_post_link(command, "Stripping")?;
+    }
+    for hook in commands {
+        let mut words = hook.split_whitespace();
+        let Some(program) = words.next() else {
+            continue;
+        };
+        let mut command = Command::new(program);
+        command.args(words).arg(executable);
+        run_post_link(command, "Post-link command")?;
+    }
+    Ok(())
+}
+
+fn run_post_link(mut command: Command, purpose: &str) -> Result<()> {
+    check_spawn(&command, purpose)?;
+    let output = command.output().map_err(|e| {
+        SeppoError::Link(format!(
+            "Failed to run `{}`: {}",
+            command.get_program().to_string_lossy(),
+            e
+        ))
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(SeppoError::Link(format!(
+            "{} failed: {}\nCommand was: {:?}",
+            purpose,
+            stderr.trim(),
+            command
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use crate::builtins;
+use crate::codegen::{DivisionOverflow, DivisionRounding};
+use crate::error::{Result, SeppoError};
+use crate::interp::{Interpreter, Value};
+use crate::types::{globals, SeppoExpr};
+use std::collections::HashSet;
+
+/// Evaluate calls to `const fn`s whose arguments are integer literals and
+/// replace them with the result, then turn the `const fn`s into ordinary
+/// functions so calls with runtime arguments still work.
+///
+/// A `const fn` may only call other `const fn`s and builtins and can't print
+/// or use globals, so evaluating it at compile time has no effects the
+/// program could observe.
+/// Calls whose result depends on how the program is built to divide, see
+/// `CodeGen::set_division`, are left to run with the program.
+pub(crate) fn fold_constants(program: SeppoExpr) -> Result<SeppoExpr> {
+    let globals: HashSet<String> = globals(&program)?
+        .into_iter()
+        .map(|global| global.name)
+        .collect();
+    let SeppoExpr::Block(items) = program else {
+        return Ok(program);
+    };
+
+    let const_fns: HashSet<String> = items
+        .iter()
+        .filter_map(|item| match item {
+            SeppoExpr::ConstFunction(name, ..) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    if const_fns.is_empty() {
+        return Ok(SeppoExpr::Block(items));
+    }
+
+    let mut definitions = Vec::new();
+    for item in &items {
+        if let SeppoExpr::ConstFunction(name, params, body) = item {
+            check_const_body(name, body, &const_fns, &globals)?;
+            definitions.push(SeppoExpr::Function(
+                name.clone(),
+                params.clone(),
+                body.clone(),
+            ));
+        }
+    }
+    let definitions = SeppoExpr::Block(definitions);
+    let mut interpreter = Interpreter::new();
+    interpreter.load(&definitions)?;
+    let mut other_division =
+        Interpreter::new().with_division(DivisionRounding::Floor, Some(DivisionOverflow::Trap));
+    other_division.load(&definitions)?;
+
+    let mut folder = Folder {
+        const_fns,
+        interpreter,
+        other_division,
+    };
+    let items = items
+        .into_iter()
+        .map(|item| match item {
+            SeppoExpr::Function(name, params, body)
+            | SeppoExpr::ConstFunction(name, params, body) => Ok(SeppoExpr::Function(
+                name,
+                params,
+                Box::new(folder.fold(*body)?),
+            )),
+            other => Ok(other),
+        })
+        .collect::<Result<_>>()?;
+    Ok(SeppoExpr::Block(items))
+}
+
+/// Reject anything in a `const fn` that can't run at compile time
+fn check_const_body(
+    function: &str,
+    expr: &SeppoExpr,
+    const_fns: &HashSet<String>,
+    globals: &HashSet<String>,
+) -> Result<()> {
+    let check = |expr: &SeppoExpr| check_const_body(function, expr, const_fns, globals);
+    match expr {
+        SeppoExpr::Variable(name) | SeppoExpr::Assignment(name, _) if globals.contains(name) => {
+            Err(SeppoError::Sema(format!(
+                "const fn {} can't use global {}",
+                function, name
+            )))
+        }
+        SeppoExpr::Print(..) => Err(SeppoError::Sema(format!(
+            "const fn {} can't print",
+            function
+        ))),
+        SeppoExpr::FunctionCall(name, args) | SeppoExpr::Become(name, args) => {
+            if !const_fns.contains(name) && !builtins::is_builtin(name) {
+                return Err(SeppoError::Sema(format!(
+                    "const fn {} calls {}, which is not a const fn",
+                    function, name
+                )));
+            }
+            args.iter().try_for_each(check)
+        }
+        SeppoExpr::Block(statements) => statements.iter().try_for_each(check),
+        SeppoExpr::Operation(_, left, right)
+        | SeppoExpr::In(left, right)
+        | SeppoExpr::Range {
+            start: left,
+            end: right,
+            ..
+        } => {
+            check(left)?;
+            check(right)
+        }
+        SeppoExpr::Assignment(_, value)
+        | SeppoExpr::Let { value, .. }
+        | SeppoExpr::Return(value) => check(value),
+        SeppoExpr::Conditional {
+            condition,
+            true_block,
+            false_block,
+        } => {
+            check(condition)?;
+            check(true_block)?;
+            false_block.as_deref().map_or(Ok(()), check)
+        }
+        SeppoExpr::For { iterable, body, .. } => {
+            check(iterable)?;
+            check(body)
+        }
+        _ => Ok(()),
+    }
+}
+
+struct Folder {
+    const_fns: HashSet<String>,
+    interpreter: Interpreter,
+    /// Divides the other way on every count, to find results that depend
+    /// on it
+    other_division: Interpreter,
+}
+
+impl Folder {
+    fn fold(&mut self, expr: SeppoExpr) -> Result<SeppoExpr> {
+        Ok(match expr {
+            SeppoExpr::FunctionCall(name, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| self.fold(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                let constants: Option<Vec<i64>> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        SeppoExpr::Number(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                let value = match constants {
+                    Some(constants) if self.const_fns.contains(&name) => {
+                        self.evaluate(&name, &constants)?
+                    }
+                    _ => None,
+                };
+                match value {
+                    Some(value) => SeppoExpr::Number(value),
+                    None => SeppoExpr::FunctionCall(name, args),
+                }
+            }
+            SeppoExpr::Block(statements) => SeppoExpr::Block(
+                statements
+                    .into_iter()
+                    .map(|statement| self.fold(statement))
+                    .collect::<Result<_>>()?,
+            ),
+            SeppoExpr::Operation(op, left, right) => SeppoExpr::Operation(
+                op,
+                Box::new(self.fold(*left)?),
+                Box::new(self.fold(*right)?),
+            ),
+            SeppoExpr::Range {
+                start,
+                end,
+                inclusive,
+            } => SeppoExpr::Range {
+                start: Box::new(self.fold(*start)?),
+                end: Box::new(self.fold(*end)?),
+                inclusive,
+            },
+            SeppoExpr::In(value, range) => {
+                SeppoExpr::In(Box::new(self.fold(*value)?), Box::new(self.fold(*range)?))
+            }
+            SeppoExpr::Assignment(name, value) => {
+                SeppoExpr::Assignment(name, Box::new(self.fold(*value)?))
+            }
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } => SeppoExpr::Let {
+                name,
+                mutable,
+                value: Box::new(self.fold(*value)?),
+            },
+            SeppoExpr::Print(format, value) => {
+                SeppoExpr::Print(format, Box::new(self.fold(*value)?))
+            }
+            SeppoExpr::Return(value) => SeppoExpr::Return(Box::new(self.fold(*value)?)),
+            SeppoExpr::Become(name, args) => SeppoExpr::Become(
+                name,
+                args.into_iter()
+                    .map(|arg| self.fold(arg))
+                    .collect::<Result<_>>()?,
+            ),
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => SeppoExpr::Conditional {
+                condition: Box::new(self.fold(*condition)?),
+                true_block: Box::new(self.fold(*true_block)?),
+                false_block: false_block
+                    .map(|block| self.fold(*block).map(Box::new))
+                    .transpose()?,
+            },
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => SeppoExpr::For {
+                variable,
+                iterable: Box::new(self.fold(*iterable)?),
+                body: Box::new(self.fold(*body)?),
+            },
+            other => other,
+        })
+    }
+
+    /// The result of calling `name`, or `None` if it depends on how the
+    /// program divides
+    fn evaluate(&mut self, name: &str, args: &[i64]) -> Result<Option<i64>> {
+        let values: Vec<Value> = args.iter().map(|n| Value::Int(*n)).collect();
+        let value = self
+            .interpreter
+            .call(name, values.clone())
+            .and_then(|value| value.as_int())
+            .map_err(|e| {
+                let args: Vec<String> = args.iter().map(i64::to_string).collect();
+                SeppoError::Sema(format!(
+                    "Failed to evaluate {}({}) at compile time: {}",
+                    name,
+                    args.join(", "),
+                    e
+                ))
+            })?;
+        let other = self
+            .other_division
+            .call(name, values)
+            .and_then(|value| value.as_int());
+        Ok(other.ok().filter(|other| *other == value))
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::{Result, SeppoError};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Environment variable naming the file instrumented executables append
+/// their counts to
+pub const COVERAGE_FILE_VAR: &str = "SEPPO_COVERAGE_FILE";
+
+/// Default coverage file, relative to the working directory of the program
+pub const DEFAULT_COVERAGE_FILE: &str = "seppo.cov";
+
+/// How often each instrumented source line ran, by file and line number.
+/// Lines without statements are not tracked at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub files: BTreeMap<String, BTreeMap<usize, u64>>,
+}
+
+impl Coverage {
+    /// Add `hits` executions of `file:line`, counting the line as
+    /// instrumented even when `hits` is zero
+    pub fn record(&mut self, file: &str, line: usize, hits: u64) {
+        *self
+            .files
+            .entry(file.to_string())
+            .or_default()
+            .entry(line)
+            .or_default() += hits;
+    }
+
+    /// Read the `hits<TAB>line<TAB>file` records written by instrumented
+    /// executables. A file holding several runs sums them.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut coverage = Self::default();
+        for (i, record) in text.lines().enumerate() {
+            if record.trim().is_empty() {
+                continue;
+            }
+            let mut fields = record.splitn(3, '\t');
+            let parsed = match (fields.next(), fields.next(), fields.next()) {
+                (Some(hits), Some(line), Some(file)) => {
+                    hits.parse().ok().zip(line.parse().ok()).map(|r| (r, file))
+                }
+                _ => None,
+            };
+            let Some(((hits, line), file)) = parsed else {
+                return Err(SeppoError::Config(format!(
+                    "Invalid coverage record on line {}: {}",
+                    i + 1,
+                    record
+                )));
+            };
+            coverage.record(file, line, hits);
+        }
+        Ok(coverage)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            SeppoError::Io(std::io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", path.display(), e),
+            ))
+        })?;
+        Self::parse(&text).map_err(|e| SeppoError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Add the counts of another run
+    pub fn merge(&mut self, other: &Coverage) {
+        for (file, lines) in &other.files {
+            for (line, hits) in lines {
+                self.record(file, *line, *hits);
+            }
+        }
+    }
+
+    /// Lines that ran at least once and lines instrumented in `file`
+    pub fn lines_covered(&self, file: &str) -> (usize, usize) {
+        match self.files.get(file) {
+            Some(lines) => (
+                lines.values().filter(|hits| **hits > 0).count(),
+                lines.len(),
+            ),
+            None => (0, 0),
+        }
+    }
+
+    /// One `file: covered/total lines (percent)` line per file and a total
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        let (mut covered, mut total) = (0, 0);
+        for file in self.files.keys() {
+            let (file_covered, file_total) = self.lines_covered(file);
+            covered += file_covered;
+            total += file_total;
+            let _ = writeln!(out, "{}: {}", file, format_ratio(file_covered, file_total));
+        }
+        let _ = writeln!(out, "TOTAL: {}", format_ratio(covered, total));
+        out
+    }
+
+    /// `source` with the count of each line in front of it, leaving the
+    /// count empty for lines without statements
+    pub fn annotate(&self, file: &str, source: &str) -> String {
+        let empty = BTreeMap::new();
+        let lines = self.files.get(file).unwrap_or(&empty);
+        let width = source.lines().count().to_string().len();
+        let mut out = String::new();
+        for (i, text) in source.lines().enumerate() {
+            let hits = lines
+                .get(&(i + 1))
+                .map(|hits| hits.to_string())
+                .unwrap_or_default();
+            let _ = writeln!(out, "{:>7} | {:>width$} | {}", hits, i + 1, text);
+        }
+        out
+    }
+}
+
+fn format_ratio(covered: usize, total: usize) -> String {
+    let percent = if total == 0 {
+        100.0
+    } else {
+        covered as f64 * 100.0 / total as f64
+    };
+    format!("{}/{} lines ({:.1}%)", covered, total, percent)
+}
@@ -0,0 +1,157 @@
+use inkwell::context::Context;
+use inkwell::debug_info::{
+    AsDIScope, DIFile, DIFlags, DIFlagsConstants, DILocation, DISubprogram, DISubroutineType,
+    DWARFEmissionKind, DWARFSourceLanguage, DebugInfoBuilder,
+};
+use inkwell::module::{FlagBehavior, Module};
+use inkwell::values::FunctionValue;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// `DW_ATE_signed`, the encoding of seppo's 64-bit integers
+const DW_ATE_SIGNED: u32 = 0x05;
+
+/// DWARF line tables for a module: a subprogram for each function, so
+/// debuggers can set breakpoints on seppo lines and name the frames of a
+/// backtrace
+pub(crate) struct DebugInfo<'ctx> {
+    builder: DebugInfoBuilder<'ctx>,
+    files: HashMap<String, DIFile<'ctx>>,
+    /// `fn(i64, ...) -> i64` by parameter count
+    signatures: HashMap<usize, DISubroutineType<'ctx>>,
+    optimized: bool,
+}
+
+impl<'ctx> DebugInfo<'ctx> {
+    /// Start the debug info of `module`, with `file` as its main source
+    pub(crate) fn new(
+        context: &'ctx Context,
+        module: &Module<'ctx>,
+        file: &str,
+        optimized: bool,
+    ) -> Self {
+        let (name, directory) = split(file);
+        let (builder, _) = module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            name,
+            directory,
+            concat!("seppolang ", env!("CARGO_PKG_VERSION")),
+            optimized,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+        let i32_type = context.i32_type();
+        module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            i32_type.const_int(inkwell::debug_info::debug_metadata_version() as u64, false),
+        );
+        module.add_basic_value_flag(
+            "Dwarf Version",
+            FlagBehavior::Warning,
+            i32_type.const_int(4, false),
+        );
+        Self {
+            builder,
+            files: HashMap::new(),
+            signatures: HashMap::new(),
+            optimized,
+        }
+    }
+
+    /// Describe `function`, the seppo function `name` defined at `line` of
+    /// `file`
+    pub(crate) fn function(
+        &mut self,
+        function: FunctionValue<'ctx>,
+        name: &str,
+        file: &str,
+        line: usize,
+    ) -> DISubprogram<'ctx> {
+        let file = self.file(file);
+        let params = function.count_params() as usize;
+        let signature = self.signature(file, params);
+        // Debuggers show the name from the source and find the symbol by
+        // its mangled one
+        let symbol = function.get_name().to_string_lossy();
+        let linkage_name = (symbol != name).then_some(symbol.as_ref());
+        let subprogram = self.builder.create_function(
+            file.as_debug_info_scope(),
+            name,
+            linkage_name,
+            file,
+            line as u32,
+            signature,
+            false,
+            true,
+            line as u32,
+            DIFlags::PUBLIC,
+            self.optimized,
+        );
+        function.set_subprogram(subprogram);
+        subprogram
+    }
+
+    /// A location on `line` inside `scope`, for the statements that follow
+    pub(crate) fn location(
+        &self,
+        context: &'ctx Context,
+        line: usize,
+        scope: DISubprogram<'ctx>,
+    ) -> DILocation<'ctx> {
+        self.builder.create_debug_location(
+            context,
+            line as u32,
+            1,
+            scope.as_debug_info_scope(),
+            None,
+        )
+    }
+
+    /// Resolve everything described so far, before the module is verified
+    pub(crate) fn finalize(&self) {
+        self.builder.finalize();
+    }
+
+    fn file(&mut self, path: &str) -> DIFile<'ctx> {
+        let builder = &self.builder;
+        *self.files.entry(path.to_string()).or_insert_with(|| {
+            let (name, directory) = split(path);
+            builder.create_file(name, directory)
+        })
+    }
+
+    fn signature(&mut self, file: DIFile<'ctx>, params: usize) -> DISubroutineType<'ctx> {
+        let builder = &self.builder;
+        *self.signatures.entry(params).or_insert_with(|| {
+            let int = builder
+                .create_basic_type("int", 64, DW_ATE_SIGNED, DIFlags::PUBLIC)
+                .expect("the type has a name")
+                .as_type();
+            builder.create_subroutine_type(file, Some(int), &vec![int; params], DIFlags::PUBLIC)
+        })
+    }
+}
+
+/// File name and directory of a source path
+fn split(path: &str) -> (&str, &str) {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    let directory = path
+        .parent()
+        .and_then(|dir| dir.to_str())
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or(".");
+    (name, directory)
+}
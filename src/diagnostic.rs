@@ -0,0 +1,262 @@
+use crate::error::SeppoError;
+use crate::lint::{Level, LintDiagnostic};
+use crate::types::Span;
+use std::env;
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const GREEN: &str = "\x1b[1;32m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => GREEN,
+        }
+    }
+}
+
+/// Whether diagnostics are printed with ANSI colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Color when stderr is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Result<Self, SeppoError> {
+        match name {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            other => Err(SeppoError::Config(format!(
+                "Unknown --color value: {} (expected auto, always or never)",
+                other
+            ))),
+        }
+    }
+
+    /// Resolve `Auto` for output written to stderr
+    pub fn use_color(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::io::stderr().is_terminal()
+                    && env::var_os("NO_COLOR").is_none()
+                    && env::var("TERM").ok().is_none_or(|term| term != "dumb")
+            }
+        }
+    }
+}
+
+/// Where in the source a diagnostic points
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+}
+
+/// An error or warning ready to be shown to the user
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub location: Option<Location>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            location: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn from_error(error: &SeppoError) -> Self {
+        let mut diagnostic = Diagnostic::new(Severity::Error, error_message(error));
+        diagnostic.code = Some(error.code().to_string());
+        if let SeppoError::Parse {
+            span,
+            line,
+            column,
+            file,
+            help,
+            ..
+        } = error
+        {
+            diagnostic.help = help.clone();
+            diagnostic.location = Some(Location {
+                file: file.clone(),
+                line: *line,
+                column: *column,
+                span: *span,
+            });
+        }
+        diagnostic
+    }
+
+    pub fn from_lint(lint: &LintDiagnostic) -> Self {
+        let severity = match lint.level {
+            Level::Deny => Severity::Error,
+            _ => Severity::Warning,
+        };
+        let mut diagnostic = Diagnostic::new(severity, lint.message.clone());
+        diagnostic.code = Some(lint.lint.to_string());
+        diagnostic
+            .notes
+            .push(format!("in function `{}`", lint.function));
+        if lint.escalated {
+            diagnostic
+                .notes
+                .push("warnings are errors because of `--deny-warnings`".to_string());
+        }
+        diagnostic
+    }
+
+    /// Render in the rustc layout. With the source text the offending line is
+    /// shown with the span underlined.
+    pub fn render(&self, source: Option<&str>, color: bool) -> String {
+        let paint = |style: &'static str| if color { style } else { "" };
+        let reset = paint(RESET);
+        let mut out = String::new();
+
+        let code = self
+            .code
+            .as_ref()
+            .map(|code| format!("[{}]", code))
+            .unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{}{}{}{}: {}{}{}",
+            paint(self.severity.color()),
+            self.severity.label(),
+            code,
+            reset,
+            paint(BOLD),
+            self.message,
+            reset
+        );
+
+        let mut gutter_width = 1;
+        if let Some(location) = &self.location {
+            let line_number = location.line.to_string();
+            gutter_width = line_number.len();
+            let pad = " ".repeat(gutter_width);
+            let file = location
+                .file
+                .as_ref()
+                .map(|file| file.display().to_string())
+                .unwrap_or_else(|| "<input>".to_string());
+            let _ = writeln!(
+                out,
+                "{}{}-->{} {}:{}:{}",
+                pad,
+                paint(BLUE),
+                reset,
+                file,
+                location.line,
+                location.column
+            );
+
+            let source_line = source.and_then(|source| source.lines().nth(location.line - 1));
+            if let Some(source_line) = source_line {
+                let bar = format!("{}|{}", paint(BLUE), reset);
+                let _ = writeln!(out, "{} {}", pad, bar);
+                let _ = writeln!(
+                    out,
+                    "{}{}{} {} {}",
+                    paint(BLUE),
+                    line_number,
+                    reset,
+                    bar,
+                    source_line
+                );
+
+                // Underline the span, or a single column for zero-width spans,
+                // stopping at the end of the line
+                let start = location.column.saturating_sub(1);
+                let available = source_line.chars().count().saturating_sub(start).max(1);
+                let width = (location.span.end - location.span.start).clamp(1, available);
+                let _ = writeln!(
+                    out,
+                    "{} {} {}{}{}{}",
+                    pad,
+                    bar,
+                    " ".repeat(start),
+                    paint(self.severity.color()),
+                    "^".repeat(width),
+                    reset
+                );
+            }
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(
+                out,
+                "{} {}={} {}note{}: {}",
+                " ".repeat(gutter_width),
+                paint(BLUE),
+                reset,
+                paint(BOLD),
+                reset,
+                note
+            );
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(
+                out,
+                "{} {}={} {}help{}: {}",
+                " ".repeat(gutter_width),
+                paint(BLUE),
+                reset,
+                paint(BOLD),
+                reset,
+                help
+            );
+        }
+        out
+    }
+}
+
+/// The message without the location prefix of the error's Display form
+fn error_message(error: &SeppoError) -> String {
+    match error {
+        SeppoError::Parse { message, .. } => message.clone(),
+        other => other.to_string(),
+    }
+}
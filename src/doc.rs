@@ -0,0 +1,166 @@
+use crate::bench::BENCH_PREFIX;
+use crate::error::{Result, SeppoError};
+use crate::testing::TEST_PREFIX;
+use crate::types::{SeppoExpr, Signature};
+use std::fmt::Write as _;
+
+/// Output format of `seppoc doc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocFormat {
+    #[default]
+    Html,
+    Markdown,
+}
+
+impl DocFormat {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "html" => Ok(DocFormat::Html),
+            "markdown" | "md" => Ok(DocFormat::Markdown),
+            other => Err(SeppoError::Config(format!(
+                "Unknown doc format: {} (expected html or markdown)",
+                other
+            ))),
+        }
+    }
+}
+
+/// A documented function: its signature and the `##` lines above it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDoc {
+    pub name: String,
+    pub params: Vec<String>,
+    /// Types the function declares
+    pub types: Signature,
+    /// Doc comment lines joined with newlines, empty when undocumented
+    pub docs: String,
+}
+
+impl FunctionDoc {
+    pub fn signature(&self) -> String {
+        format!("fn {}{}", self.name, self.types.format(&self.params))
+    }
+}
+
+/// The functions of a program in source order with their doc comments.
+/// Test and benchmark functions are not part of the API and are left out.
+pub fn collect(program: &SeppoExpr) -> Vec<FunctionDoc> {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+
+    let mut docs = Vec::new();
+    let mut pending: Vec<&str> = Vec::new();
+    let mut types = Signature::default();
+    for item in items {
+        match item {
+            SeppoExpr::DocComment(line) => pending.push(line),
+            SeppoExpr::Comment { .. } | SeppoExpr::BlankLine | SeppoExpr::Attribute { .. } => {}
+            SeppoExpr::Signature(signature) => types = signature.clone(),
+            SeppoExpr::Function(name, params, _) => {
+                let types = std::mem::take(&mut types);
+                if !name.starts_with(TEST_PREFIX) && !name.starts_with(BENCH_PREFIX) {
+                    docs.push(FunctionDoc {
+                        name: name.clone(),
+                        params: params.clone(),
+                        types,
+                        docs: pending.join("\n"),
+                    });
+                }
+                pending.clear();
+            }
+            // Doc comments only attach to the function directly below them
+            _ => {
+                pending.clear();
+                types = Signature::default();
+            }
+        }
+    }
+    docs
+}
+
+pub fn render(title: &str, functions: &[FunctionDoc], format: DocFormat) -> String {
+    match format {
+        DocFormat::Html => render_html(title, functions),
+        DocFormat::Markdown => render_markdown(title, functions),
+    }
+}
+
+pub fn render_markdown(title: &str, functions: &[FunctionDoc]) -> String {
+    let mut out = format!("# {}\n", title);
+    for function in functions {
+        let _ = write!(
+            out,
+            "\n## `{}`\n\n```\n{}\n```\n",
+            function.name,
+            function.signature()
+        );
+        if !function.docs.is_empty() {
+            let _ = writeln!(out, "\n{}", function.docs);
+        }
+    }
+    out
+}
+
+pub fn render_html(title: &str, functions: &[FunctionDoc]) -> String {
+    let title = escape_html(title);
+    let mut out = String::new();
+    let _ = writeln!(out, "<!DOCTYPE html>");
+    let _ = writeln!(out, "<html>\n<head>\n<meta charset=\"utf-8\">");
+    let _ = writeln!(out, "<title>{}</title>", title);
+    let _ = writeln!(out, "</head>\n<body>\n<h1>{}</h1>", title);
+
+    if !functions.is_empty() {
+        let _ = writeln!(out, "<ul>");
+        for function in functions {
+            let name = escape_html(&function.name);
+            let _ = writeln!(
+                out,
+                "<li><a href=\"#fn.{0}\"><code>{0}</code></a></li>",
+                name
+            );
+        }
+        let _ = writeln!(out, "</ul>");
+    }
+
+    for function in functions {
+        let _ = writeln!(
+            out,
+            "<section id=\"fn.{}\">\n<h2><code>{}</code></h2>",
+            escape_html(&function.name),
+            escape_html(&function.signature())
+        );
+        // Blank doc lines separate paragraphs
+        for paragraph in function.docs.split("\n\n").filter(|p| !p.trim().is_empty()) {
+            let _ = writeln!(
+                out,
+                "<p>{}</p>",
+                inline_code(&escape_html(paragraph.trim()))
+            );
+        }
+        let _ = writeln!(out, "</section>");
+    }
+    let _ = writeln!(out, "</body>\n</html>");
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Turn `backticked` spans into `<code>` elements
+fn inline_code(text: &str) -> String {
+    let mut out = String::new();
+    for (i, part) in text.split('`').enumerate() {
+        if i % 2 == 1 {
+            let _ = write!(out, "<code>{}</code>", part);
+        } else {
+            out.push_str(part);
+        }
+    }
+    out
+}
@@ -0,0 +1,586 @@
+use crate::codegen::{
+    CodeGen, CodeGenOptions, DivisionOverflow, DivisionRounding, Sanitizer, StackProtector,
+    StdoutBuffering,
+};
+use crate::compile::{link, post_link};
+use crate::error::{Result, SeppoError};
+use crate::lexer::{render_tokens, tokenize};
+use crate::nesting::{with_max_nesting, DEFAULT_MAX_NESTING};
+use crate::output::{OutputPath, TargetDir};
+use crate::parser::{parse_file, parse_module, parse_with_locations};
+use crate::sandbox::with_no_extern;
+use crate::sema::{check_entry, select_entry, Definitions};
+use crate::target::TargetSpec;
+use crate::temps::TempArtifacts;
+use crate::timing::{self, Phase};
+use crate::types::SeppoExpr;
+use crate::universal::{write_fat_binary, UNIVERSAL_TRIPLES};
+use inkwell::context::Context;
+use inkwell::OptimizationLevel;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// A source file handed to the driver
+#[derive(Debug, Clone)]
+pub enum Input {
+    File(PathBuf),
+    Source {
+        name: String,
+        text: String,
+    },
+    /// Read the program from standard input, given as `-` on the command line
+    Stdin,
+}
+
+impl Input {
+    /// Interpret a command line argument, where `-` means standard input
+    pub fn from_arg(arg: &str) -> Self {
+        if arg == "-" {
+            Input::Stdin
+        } else {
+            Input::File(PathBuf::from(arg))
+        }
+    }
+
+    /// Name used for the LLVM module and default output path
+    pub fn name(&self) -> String {
+        match self {
+            Input::File(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "main".to_string()),
+            Input::Source { name, .. } => name.clone(),
+            Input::Stdin => "stdin".to_string(),
+        }
+    }
+
+    pub fn read(&self) -> Result<String> {
+        match self {
+            Input::File(path) => fs::read_to_string(path).map_err(|e| {
+                SeppoError::Io(io::Error::new(
+                    e.kind(),
+                    format!("Failed to read {}: {}", path.display(), e),
+                ))
+            }),
+            Input::Source { text, .. } => Ok(text.clone()),
+            Input::Stdin => io::read_to_string(io::stdin()).map_err(|e| {
+                SeppoError::Io(io::Error::new(
+                    e.kind(),
+                    format!("Failed to read standard input: {}", e),
+                ))
+            }),
+        }
+    }
+
+    /// Parse the input, loading `.json` files as serialized AST
+    pub fn parse(&self) -> Result<SeppoExpr> {
+        match self {
+            #[cfg(feature = "serde")]
+            Input::File(path) if path.extension().is_some_and(|ext| ext == "json") => {
+                SeppoExpr::from_json(&self.read()?)
+            }
+            Input::File(path) => parse_file(&self.read()?, path).map_err(|e| e.in_file(path)),
+            _ => parse_module(&self.read()?),
+        }
+    }
+
+    /// Parse the input with the statement locations coverage counts, naming
+    /// files by the path they were given as
+    pub fn parse_with_locations(&self) -> Result<SeppoExpr> {
+        match self {
+            Input::File(path) => parse_with_locations(&self.read()?, &path.display().to_string())
+                .map_err(|e| e.in_file(path)),
+            _ => parse_with_locations(&self.read()?, &self.name()),
+        }
+    }
+}
+
+/// What the driver stops at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    Executable,
+    Object,
+    LlvmIr,
+    /// The parsed program as JSON, requires the `serde` feature
+    AstJson,
+    /// The tokens of each input, see `lexer::render_tokens`. Inputs are
+    /// only lexed, not parsed.
+    Tokens,
+}
+
+impl OutputKind {
+    /// Extension of the artifact built for `target`, empty for Unix
+    /// executables
+    pub fn extension(self, target: &TargetSpec) -> &'static str {
+        match self {
+            OutputKind::Executable => target.exe_extension(),
+            OutputKind::Object => "o",
+            OutputKind::LlvmIr => "ll",
+            OutputKind::AstJson => "json",
+            OutputKind::Tokens => "tokens",
+        }
+    }
+}
+
+/// Extra artifacts written next to the main output
+#[derive(Debug, Clone, Default)]
+pub struct EmitOptions {
+    pub llvm_ir: bool,
+}
+
+/// A bundle of settings for a kind of build, applied with
+/// `Driver::apply_profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// `-O0`, debug info and overflow checks, for ceppo blocks too
+    Debug,
+    /// `-O3` for the program and its ceppo blocks, stripped, and no IR
+    /// printed or written
+    Release,
+}
+
+impl Profile {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "debug" => Some(Profile::Debug),
+            "release" => Some(Profile::Release),
+            _ => None,
+        }
+    }
+
+    /// The name `from_name` takes, which is also the directory of the
+    /// profile's builds, see `TargetDir`
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Debug => "debug",
+            Profile::Release => "release",
+        }
+    }
+
+    pub fn opt_level(self) -> OptimizationLevel {
+        match self {
+            Profile::Debug => OptimizationLevel::None,
+            Profile::Release => OptimizationLevel::Aggressive,
+        }
+    }
+}
+
+/// One compiler invocation: parse all inputs into a single module, generate
+/// code for the target and produce the requested output.
+#[derive(Debug, Clone)]
+pub struct Driver {
+    pub inputs: Vec<Input>,
+    pub output: Option<PathBuf>,
+    pub output_kind: OutputKind,
+    pub opt_level: OptimizationLevel,
+    pub target: TargetSpec,
+    pub linker_args: Vec<String>,
+    /// C compiler for ceppo blocks instead of `$CC`
+    pub c_compiler: Option<String>,
+    /// Extra C compiler arguments for ceppo blocks
+    pub c_flags: Vec<String>,
+    /// Optimize the program together with its ceppo blocks as one module
+    pub lto: bool,
+    /// Count how often each source line runs, see `CodeGen::set_coverage`
+    pub coverage: bool,
+    pub sanitizers: Vec<Sanitizer>,
+    pub frame_pointers: bool,
+    /// Stack canaries, see `CodeGen::set_stack_protector`
+    pub stack_protector: Option<StackProtector>,
+    /// See `CodeGen::set_stack_probes`
+    pub stack_probes: bool,
+    /// Position-independent executable, see `CodeGen::set_pie`
+    pub pie: Option<bool>,
+    /// See `CodeGen::set_stdout_buffering`
+    pub stdout_buffering: Option<StdoutBuffering>,
+    /// See `CodeGen::set_heap_to_stack`
+    pub heap_to_stack: bool,
+    /// How `/` rounds and overflows, see `CodeGen::set_division`
+    pub division_rounding: DivisionRounding,
+    pub division_overflow: Option<DivisionOverflow>,
+    /// Thread stack size in bytes, see `CodeGen::set_stack_size`
+    pub stack_size: Option<u64>,
+    /// Build for profilers, see `CodeGen::set_profiling`
+    pub profiling: bool,
+    /// DWARF line tables, see `CodeGen::set_debug_info`
+    pub debug_info: bool,
+    /// Stop on arithmetic overflow, see `CodeGen::set_overflow_checks`
+    pub overflow_checks: bool,
+    /// Print the LLVM IR while compiling
+    pub dump_ir: bool,
+    /// Deepest nesting of blocks and calls accepted in the inputs
+    pub max_nesting: usize,
+    /// Strip symbols from the executable after linking
+    pub strip: bool,
+    /// Commands run on the executable after linking and stripping, e.g.
+    /// `codesign -s -`, each getting the executable's path as its last
+    /// argument
+    pub post_link: Vec<String>,
+    /// Build a macOS universal binary with an arm64 and an x86_64 slice
+    pub universal: bool,
+    /// Reject ceppo blocks and refuse to run the C compiler or linker, see
+    /// `sandbox::with_no_extern`
+    pub no_extern: bool,
+    /// Leave the objects of ceppo blocks and the program in place instead
+    /// of removing them after linking, printing where they are
+    pub keep_temps: bool,
+    /// Build the entry point `<bin>_seppo` instead of `seppo`, for files
+    /// holding several programs
+    pub bin: Option<String>,
+    /// Put the artifacts in `target/seppo/<profile>/` of this directory
+    /// unless `output` says where, see `TargetDir`
+    pub target_dir: Option<PathBuf>,
+    /// The profile last applied with `apply_profile`, which picks the
+    /// directory in `target_dir`
+    pub profile: Option<Profile>,
+    /// Name of the package being built, which names its artifacts instead
+    /// of the first input
+    pub package: Option<String>,
+    /// Passes run on the generated module, see `CodeGen::set_options`
+    pub codegen: CodeGenOptions,
+    pub emit: EmitOptions,
+}
+
+impl Driver {
+    pub fn new(inputs: Vec<Input>) -> Self {
+        Self {
+            inputs,
+            output: None,
+            output_kind: OutputKind::Executable,
+            opt_level: OptimizationLevel::Default,
+            target: TargetSpec::native(),
+            linker_args: Vec::new(),
+            c_compiler: None,
+            c_flags: Vec::new(),
+            lto: false,
+            coverage: false,
+            sanitizers: Vec::new(),
+            frame_pointers: false,
+            stack_protector: None,
+            stack_probes: false,
+            pie: None,
+            stdout_buffering: None,
+            heap_to_stack: true,
+            division_rounding: DivisionRounding::default(),
+            division_overflow: None,
+            stack_size: None,
+            profiling: false,
+            debug_info: false,
+            overflow_checks: false,
+            dump_ir: true,
+            max_nesting: DEFAULT_MAX_NESTING,
+            strip: false,
+            post_link: Vec::new(),
+            universal: false,
+            no_extern: false,
+            keep_temps: false,
+            bin: None,
+            target_dir: None,
+            profile: None,
+            package: None,
+            codegen: CodeGenOptions::default(),
+            emit: EmitOptions::default(),
+        }
+    }
+
+    /// Switch to the settings of `profile`. Settings changed afterwards,
+    /// e.g. from command line flags, win over it; its C flags come before
+    /// any others.
+    pub fn apply_profile(&mut self, profile: Profile) {
+        self.profile = Some(profile);
+        self.opt_level = profile.opt_level();
+        let c_flag = match profile {
+            Profile::Debug => {
+                self.debug_info = true;
+                self.overflow_checks = true;
+                "-O0"
+            }
+            Profile::Release => {
+                self.strip = true;
+                self.dump_ir = false;
+                self.emit.llvm_ir = false;
+                "-O3"
+            }
+        };
+        self.c_flags.insert(0, c_flag.to_string());
+    }
+
+    /// Convenience for callers that only have a path
+    pub fn for_file(path: &Path) -> Self {
+        Self::new(vec![Input::File(path.to_path_buf())])
+    }
+
+    /// Path of the main artifact, without a platform extension
+    pub fn output_stem(&self) -> Result<PathBuf> {
+        Ok(self.output_path()?.stem().to_path_buf())
+    }
+
+    /// Where the artifacts go: named after `output` when set, which may
+    /// already carry the extension of `output_kind`, or else after
+    /// `package`, `bin` or the first input, in the profile's directory of
+    /// `target_dir` if there is one
+    pub fn output_path(&self) -> Result<OutputPath> {
+        if let Some(output) = &self.output {
+            let extension = self.output_kind.extension(&self.target);
+            return Ok(OutputPath::for_artifact(output, extension));
+        }
+        let Some(target_dir) = self.target_dir.as_ref().map(TargetDir::new) else {
+            return self.default_output_path();
+        };
+        let default = self.default_output_path()?;
+        let name = default.stem().file_name().unwrap_or_default();
+        Ok(OutputPath::new(
+            target_dir.profile_dir(self.profile).join(name),
+        ))
+    }
+
+    /// The artifacts next to the first input, named after the package,
+    /// `bin` or the input
+    fn default_output_path(&self) -> Result<OutputPath> {
+        match (self.inputs.first(), &self.package) {
+            (Some(Input::File(path)), Some(package)) => {
+                return Ok(OutputPath::new(path.with_file_name(package)))
+            }
+            (_, Some(package)) => return Ok(OutputPath::new(package)),
+            _ => {}
+        }
+        match (self.inputs.first(), &self.bin) {
+            (Some(Input::File(path)), Some(bin)) => {
+                return Ok(OutputPath::new(path.with_file_name(bin)))
+            }
+            (_, Some(bin)) => return Ok(OutputPath::new(bin)),
+            _ => {}
+        }
+        match self.inputs.first() {
+            Some(Input::File(path)) => Ok(OutputPath::for_input(path)),
+            Some(input @ Input::Source { .. }) => Ok(OutputPath::new(input.name())),
+            // There is no file name to derive from, so use the one compile_str uses
+            Some(Input::Stdin) => Ok(OutputPath::new("main")),
+            None => Err(SeppoError::Sema("No input files".to_string())),
+        }
+    }
+
+    /// Parse every input and merge them into one program
+    pub fn parse(&self) -> Result<SeppoExpr> {
+        self.sandboxed(|| with_max_nesting(self.max_nesting, || self.parse_inputs()))
+    }
+
+    fn sandboxed<T>(&self, f: impl FnOnce() -> T) -> T {
+        if self.no_extern {
+            with_no_extern(f)
+        } else {
+            f()
+        }
+    }
+
+    fn parse_inputs(&self) -> Result<SeppoExpr> {
+        let mut items = Vec::new();
+        let mut functions = Definitions::default();
+        for input in &self.inputs {
+            // Source lines for coverage counters, sanitizer reports and
+            // internal compiler errors, left out of the AST users see
+            let module = if self.output_kind != OutputKind::AstJson {
+                input.parse_with_locations()?
+            } else {
+                input.parse()?
+            };
+            let module_items = match module {
+                SeppoExpr::Block(module_items) => module_items,
+                other => vec![other],
+            };
+            // The parser reports duplicates within a module, with their lines
+            for item in &module_items {
+                if let SeppoExpr::Function(name, ..) | SeppoExpr::ConstFunction(name, ..) = item {
+                    let what = format!("Function {}", name);
+                    functions.define(&what, name, input.name())?;
+                }
+            }
+            items.extend(module_items);
+        }
+
+        let program = match &self.bin {
+            Some(bin) => select_entry(SeppoExpr::Block(items), bin)?,
+            None => SeppoExpr::Block(items),
+        };
+        check_entry(&program)?;
+        Ok(program)
+    }
+
+    /// Run the whole pipeline, returning the path of the produced artifact
+    pub fn run(&self) -> Result<PathBuf> {
+        // Dropped last, after the code generators that use it
+        let temps = Rc::new(TempArtifacts::new(self.keep_temps));
+        self.sandboxed(|| self.run_pipeline(&temps))
+    }
+
+    fn run_pipeline(&self, temps: &Rc<TempArtifacts>) -> Result<PathBuf> {
+        if self.output_kind == OutputKind::Tokens {
+            return self.write_tokens(&self.output_path()?.tokens());
+        }
+        let expr = self.parse()?;
+        let output = self.output_path()?;
+        if self.output.is_none() && self.target_dir.is_some() {
+            if let Some(dir) = output.stem().parent() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+
+        if self.output_kind == OutputKind::AstJson {
+            return write_ast_json(&expr, &output.ast_json());
+        }
+        let artifact = if self.universal {
+            self.build_universal(&expr, &output, temps)?
+        } else {
+            self.build(&expr, &output, &self.target, temps)?
+        };
+        if self.output_kind == OutputKind::Executable {
+            timing::time(Phase::Link, || {
+                post_link(&artifact, &self.target, self.strip, &self.post_link)
+            })?;
+        }
+        Ok(artifact)
+    }
+
+    /// Lex the inputs, without parsing them, into one file of tokens
+    fn write_tokens(&self, path: &Path) -> Result<PathBuf> {
+        let mut out = String::new();
+        for input in &self.inputs {
+            out.push_str(&render_tokens(&input.name(), &tokenize(&input.read()?)));
+        }
+        fs::write(path, out)?;
+        Ok(path.to_path_buf())
+    }
+
+    /// Build every slice of a universal binary and merge them
+    fn build_universal(
+        &self,
+        expr: &SeppoExpr,
+        output: &OutputPath,
+        temps: &Rc<TempArtifacts>,
+    ) -> Result<PathBuf> {
+        // The slices have their own targets, a native one is only a default
+        if !self.target.is_apple() && !self.target.is_native() {
+            return Err(SeppoError::Config(format!(
+                "--universal builds for macOS, not {}",
+                self.target.triple()
+            )));
+        }
+        if self.output_kind == OutputKind::LlvmIr {
+            return Err(SeppoError::Config(
+                "--universal produces an executable or an object, not LLVM IR".to_string(),
+            ));
+        }
+
+        let mut slices = Vec::new();
+        for triple in UNIVERSAL_TRIPLES {
+            let target = TargetSpec::from_triple(triple)?;
+            let arch = triple.split('-').next().unwrap_or(triple);
+            let stem = output.with_suffix(&format!("-{}", arch));
+            let slice = self.build(expr, &stem, &target, temps)?;
+            temps.file(&slice);
+            slices.push(slice);
+        }
+
+        let merged = if self.output_kind == OutputKind::Object {
+            output.object()
+        } else {
+            output.executable(&self.target)
+        };
+        timing::time(Phase::Link, || write_fat_binary(&slices, &merged))?;
+        Ok(merged)
+    }
+
+    /// Generate code for `expr` on `target` and produce the requested
+    /// output next to `output`
+    fn build(
+        &self,
+        expr: &SeppoExpr,
+        output: &OutputPath,
+        target: &TargetSpec,
+        temps: &Rc<TempArtifacts>,
+    ) -> Result<PathBuf> {
+        let module_name = self
+            .inputs
+            .first()
+            .map(Input::name)
+            .unwrap_or_else(|| "main".to_string());
+
+        // Initialize LLVM
+        target.initialize()?;
+
+        // Generate code
+        let context = Context::create();
+        let mut codegen = CodeGen::with_target(&context, &module_name, target.clone());
+        codegen.set_opt_level(self.opt_level);
+        codegen.set_options(self.codegen.clone());
+        codegen.set_c_compiler(self.c_compiler.clone());
+        codegen.set_c_flags(self.c_flags.clone());
+        codegen.set_lto(self.lto);
+        codegen.set_coverage(self.coverage);
+        codegen.set_sanitizers(self.sanitizers.clone());
+        codegen.set_frame_pointers(self.frame_pointers);
+        codegen.set_stack_protector(self.stack_protector);
+        codegen.set_stack_probes(self.stack_probes);
+        codegen.set_pie(self.pie);
+        codegen.set_stdout_buffering(self.stdout_buffering);
+        codegen.set_heap_to_stack(self.heap_to_stack);
+        codegen.set_division(self.division_rounding, self.division_overflow);
+        codegen.set_stack_size(self.stack_size);
+        codegen.set_profiling(self.profiling);
+        codegen.set_debug_info(self.debug_info);
+        codegen.set_overflow_checks(self.overflow_checks);
+        codegen.set_dump_ir(self.dump_ir);
+        codegen.set_temps(temps.clone());
+        if let Some(target_dir) = &self.target_dir {
+            codegen.set_ceppo_cache(Some(TargetDir::new(target_dir).ceppo_cache(self.profile)));
+        }
+        timing::time(Phase::Codegen, || codegen.compile(expr))?;
+        if self.lto {
+            timing::time(Phase::Llvm, || codegen.link_time_optimize())?;
+        }
+
+        let ir_file = output.llvm_ir();
+        if self.emit.llvm_ir || self.output_kind == OutputKind::LlvmIr {
+            codegen.get_module().print_to_file(&ir_file).map_err(|e| {
+                SeppoError::Codegen(format!("Failed to write LLVM IR: {}", e.to_string()))
+            })?;
+        }
+        if self.output_kind == OutputKind::LlvmIr {
+            return Ok(ir_file);
+        }
+
+        // Generate object file
+        let obj_file = output.object();
+        timing::time(Phase::Llvm, || codegen.write_object_file(&obj_file))?;
+        if self.output_kind == OutputKind::Object {
+            return Ok(obj_file);
+        }
+        // The target directory keeps it for the next build to look at
+        if self.target_dir.is_none() {
+            temps.file(&obj_file);
+        }
+
+        // Link the object file
+        let output_exe = output.executable(target);
+        timing::time(Phase::Link, || {
+            link(&obj_file, &output_exe, &codegen, &self.linker_args)
+        })?;
+
+        Ok(output_exe)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn write_ast_json(expr: &SeppoExpr, path: &Path) -> Result<PathBuf> {
+    fs::write(path, expr.to_json()?)?;
+    Ok(path.to_path_buf())
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_ast_json(_expr: &SeppoExpr, _path: &Path) -> Result<PathBuf> {
+    Err(SeppoError::Codegen(
+        "AST JSON output requires seppolang to be built with the `serde` feature".to_string(),
+    ))
+}
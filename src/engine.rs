@@ -0,0 +1,139 @@
+use crate::error::{Result, SeppoError};
+use crate::interp::Interpreter;
+use crate::jit::Jit;
+use crate::parser::parse_seppo;
+use crate::types::SeppoExpr;
+use inkwell::context::Context;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Seppo as a scripting layer: native functions registered by the Rust host
+/// that seppo code can call like its own, when run through the interpreter
+/// or the JIT, e.g. `engine.register_fn("emit", |x: i64| x * 2)` followed by
+/// `engine.eval(source)`
+#[derive(Default)]
+pub struct Engine {
+    host: HashMap<String, Arc<HostFn>>,
+}
+
+/// A native function seppo code can call, taking and returning integers
+pub struct HostFn {
+    params: usize,
+    callback: Box<Callback>,
+}
+
+type Callback = dyn Fn(&[i64]) -> i64 + Send + Sync;
+
+impl HostFn {
+    pub(crate) fn params(&self) -> usize {
+        self.params
+    }
+
+    /// Call the function, with exactly `params` arguments
+    pub(crate) fn call(&self, args: &[i64]) -> i64 {
+        (self.callback)(args)
+    }
+}
+
+/// Closures that can be registered with `Engine::register_fn`: any
+/// `Fn(i64, ...) -> i64` with up to four parameters. `Args` only tells the
+/// arities apart.
+pub trait HostFunction<Args> {
+    fn into_host_fn(self) -> HostFn;
+}
+
+macro_rules! impl_host_function {
+    (@int $arg:ident) => { i64 };
+    ($params:literal $(, $arg:ident)*) => {
+        impl<F> HostFunction<[i64; $params]> for F
+        where
+            F: Fn($(impl_host_function!(@int $arg)),*) -> i64 + Send + Sync + 'static,
+        {
+            fn into_host_fn(self) -> HostFn {
+                HostFn {
+                    params: $params,
+                    callback: Box::new(move |args| {
+                        let &[$($arg),*] = args else {
+                            unreachable!("argument count is checked before calling")
+                        };
+                        self($($arg),*)
+                    }),
+                }
+            }
+        }
+    };
+}
+
+impl_host_function!(0);
+impl_host_function!(1, a);
+impl_host_function!(2, a, b);
+impl_host_function!(3, a, b, c);
+impl_host_function!(4, a, b, c, d);
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make `function` callable from seppo code as `name`, replacing any
+    /// function registered under that name before. Host functions may run
+    /// on threads started by `Jit::spawn`, so they must be `Send + Sync`.
+    pub fn register_fn<Args>(
+        &mut self,
+        name: &str,
+        function: impl HostFunction<Args>,
+    ) -> &mut Self {
+        self.host
+            .insert(name.to_string(), Arc::new(function.into_host_fn()));
+        self
+    }
+
+    /// Parse `source` and run its `seppo` function with the interpreter
+    pub fn eval(&self, source: &str) -> Result<i64> {
+        let program = parse_seppo(source)?;
+        self.interpreter(&program)?.run(&program)
+    }
+
+    /// An interpreter with the host functions registered, for `program`
+    pub fn interpreter(&self, program: &SeppoExpr) -> Result<Interpreter> {
+        self.check_conflicts(program)?;
+        let mut interpreter = Interpreter::new();
+        for (name, function) in &self.host {
+            interpreter.register_host_fn(name, function.clone());
+        }
+        Ok(interpreter)
+    }
+
+    /// Compile `program` in memory with the host functions linked in
+    pub fn jit<'ctx>(&self, context: &'ctx Context, program: &SeppoExpr) -> Result<Jit<'ctx>> {
+        self.check_conflicts(program)?;
+        Jit::with_host(context, program, &self.host)
+    }
+
+    fn check_conflicts(&self, program: &SeppoExpr) -> Result<()> {
+        let SeppoExpr::Block(items) = program else {
+            return Ok(());
+        };
+        for item in items {
+            if let SeppoExpr::Function(name, ..) = item {
+                if self.host.contains_key(name) {
+                    return Err(SeppoError::Sema(format!(
+                        "Function {} is defined by the program and registered by the host",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Called by the code generated for host functions, with the `HostFn` and
+/// the arguments stored in an array
+pub(crate) extern "C" fn call_host(function: *const HostFn, args: *const i64) -> i64 {
+    // The JIT keeps the function alive, and the generated code stores
+    // exactly `params` arguments
+    let function = unsafe { &*function };
+    let args = unsafe { std::slice::from_raw_parts(args, function.params) };
+    function.call(args)
+}
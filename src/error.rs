@@ -0,0 +1,106 @@
+use crate::types::Span;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Errors reported by the seppolang library, grouped by compilation phase
+#[derive(Debug, Error)]
+pub enum SeppoError {
+    #[error("Parse error at {line}:{column}: {message}")]
+    Parse {
+        message: String,
+        span: Span,
+        line: usize,
+        column: usize,
+        /// Source file the error is in, when parsing from a file
+        file: Option<PathBuf>,
+        /// How the mistake is usually fixed
+        help: Option<String>,
+    },
+    /// The program parsed but is not valid, e.g. an undefined variable
+    #[error("{0}")]
+    Sema(String),
+    /// LLVM or the ceppo C compiler failed
+    #[error("{0}")]
+    Codegen(String),
+    #[error("{0}")]
+    Link(String),
+    /// Failures while running a program, in the interpreter or as a binary
+    #[error("{0}")]
+    Runtime(String),
+    /// Invalid command line or configuration settings
+    #[error("{0}")]
+    Config(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, SeppoError>;
+
+impl SeppoError {
+    /// Stable code shown in diagnostics, one per phase
+    pub fn code(&self) -> &'static str {
+        match self {
+            SeppoError::Parse { .. } => "E0001",
+            SeppoError::Sema(_) => "E0002",
+            SeppoError::Codegen(_) => "E0003",
+            SeppoError::Link(_) => "E0004",
+            SeppoError::Runtime(_) => "E0005",
+            SeppoError::Config(_) => "E0006",
+            SeppoError::Io(_) => "E0007",
+        }
+    }
+
+    /// Record which file a parse error came from
+    pub fn in_file(mut self, path: &Path) -> Self {
+        if let SeppoError::Parse { file, .. } = &mut self {
+            *file = Some(path.to_path_buf());
+        }
+        self
+    }
+
+    /// Parse error covering a pest span
+    pub(crate) fn parse_at(span: &pest::Span, message: impl Into<String>) -> Self {
+        let (line, column) = span.start_pos().line_col();
+        SeppoError::Parse {
+            message: message.into(),
+            span: Span {
+                start: span.start(),
+                end: span.end(),
+            },
+            line,
+            column,
+            file: None,
+            help: None,
+        }
+    }
+}
+
+impl<R: pest::RuleType> From<pest::error::Error<R>> for SeppoError {
+    fn from(e: pest::error::Error<R>) -> Self {
+        let span = match e.location {
+            pest::error::InputLocation::Pos(pos) => Span {
+                start: pos,
+                end: pos,
+            },
+            pest::error::InputLocation::Span((start, end)) => Span { start, end },
+        };
+        let (line, column) = match e.line_col {
+            pest::error::LineColLocation::Pos(pos) => pos,
+            pest::error::LineColLocation::Span(start, _) => start,
+        };
+        SeppoError::Parse {
+            message: e.variant.message().into_owned(),
+            span,
+            line,
+            column,
+            file: None,
+            help: None,
+        }
+    }
+}
+
+impl From<inkwell::builder::BuilderError> for SeppoError {
+    fn from(e: inkwell::builder::BuilderError) -> Self {
+        SeppoError::Codegen(e.to_string())
+    }
+}
@@ -0,0 +1,336 @@
+use crate::cdecl::parse_signatures;
+use crate::target::TargetSpec;
+use crate::types::SeppoExpr;
+use std::collections::{HashMap, HashSet};
+
+/// Name of the call `promote_allocations` puts in place of `alloc(n)`,
+/// which no seppo identifier can spell
+pub(crate) const STACK_ALLOC: &str = "alloc.stack";
+
+/// Allocations of at most this many bytes are moved to the stack
+const SIZE_LIMIT: i64 = 4096;
+
+/// The builtins whose calls the analysis understands
+const MEMORY_BUILTINS: &[&str] = &["alloc", "load", "store", "free"];
+
+/// Move allocations that can't outlive their function from the heap to
+/// its stack frame. `p = alloc(n)` is moved if `n` is a constant of at
+/// most `SIZE_LIMIT` bytes and `p` is only assigned once and only used as
+/// the address of `load` and `store`, possibly with an offset, or freed.
+/// Variables holding `p` plus an offset are followed the same way, like
+/// `next = node + 8`. Anything else, like returning `p`, passing it to a
+/// function or comparing it, lets the address escape.
+///
+/// `free` of a moved allocation does nothing. As variables are resolved in
+/// source order, an allocation in a loop can share its memory across
+/// iterations: no address from an earlier one is still in use.
+///
+/// Nothing is moved if the program or one of its ceppo blocks, whose
+/// types are those of `target`, defines a function named like one of
+/// `MEMORY_BUILTINS`, which is called instead of the builtin and could do
+/// anything with the address.
+pub(crate) fn promote_allocations(program: &SeppoExpr, target: &TargetSpec) -> SeppoExpr {
+    let SeppoExpr::Block(items) = program else {
+        return program.clone();
+    };
+    let redefined = items.iter().any(|item| match item {
+        SeppoExpr::Function(name, ..) | SeppoExpr::ConstFunction(name, ..) => {
+            MEMORY_BUILTINS.contains(&name.as_str())
+        }
+        SeppoExpr::InlineC(code) => parse_signatures(code, target)
+            .iter()
+            .any(|signature| MEMORY_BUILTINS.contains(&signature.name.as_str())),
+        _ => false,
+    });
+    if redefined {
+        return program.clone();
+    }
+    SeppoExpr::Block(
+        items
+            .iter()
+            .map(|item| match item {
+                SeppoExpr::Function(name, params, body) => {
+                    let stack = stack_allocations(params, body);
+                    if stack.is_empty() {
+                        item.clone()
+                    } else {
+                        SeppoExpr::Function(
+                            name.clone(),
+                            params.clone(),
+                            Box::new(rewrite(body, &stack)),
+                        )
+                    }
+                }
+                other => other.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// The variables of a function body bound to allocations that don't escape
+fn stack_allocations(params: &[String], body: &SeppoExpr) -> HashSet<String> {
+    let mut bindings: HashMap<&str, Vec<&SeppoExpr>> = HashMap::new();
+    collect_bindings(body, &mut bindings);
+    // Variables assigned once, to a constant sized allocation or to an
+    // address within one, by the allocation they point into
+    let single: HashMap<&str, &SeppoExpr> = bindings
+        .iter()
+        .filter(|(name, values)| values.len() == 1 && !params.iter().any(|p| p == *name))
+        .map(|(name, values)| (*name, values[0]))
+        .collect();
+    let mut roots: HashMap<&str, &str> = single
+        .iter()
+        .filter(|(_, value)| allocation_size(value).is_some_and(|n| (1..=SIZE_LIMIT).contains(&n)))
+        .map(|(name, _)| (*name, *name))
+        .collect();
+    loop {
+        let derived: Vec<(&str, &str)> = single
+            .iter()
+            .filter(|(name, _)| !roots.contains_key(*name))
+            .filter_map(|(name, value)| {
+                let base = address_base(value)?;
+                Some((*name, *roots.get(base)?))
+            })
+            .collect();
+        if derived.is_empty() {
+            break;
+        }
+        roots.extend(derived);
+    }
+
+    let mut stack = HashSet::new();
+    for root in roots.values().collect::<HashSet<_>>() {
+        let group: HashSet<&str> = roots
+            .iter()
+            .filter(|(_, r)| *r == root)
+            .map(|(name, _)| *name)
+            .collect();
+        if !escapes(body, root, &group) {
+            stack.insert(root.to_string());
+        }
+    }
+    stack
+}
+
+/// Every value assigned to each variable, by `let`, assignment or as the
+/// variable of a `for` loop
+fn collect_bindings<'a>(expr: &'a SeppoExpr, bindings: &mut HashMap<&'a str, Vec<&'a SeppoExpr>>) {
+    match expr {
+        SeppoExpr::Let { name, value, .. } | SeppoExpr::Assignment(name, value) => {
+            bindings.entry(name).or_default().push(value);
+        }
+        SeppoExpr::For {
+            variable, iterable, ..
+        } => bindings.entry(variable).or_default().push(iterable),
+        _ => {}
+    }
+    for child in children(expr) {
+        collect_bindings(child, bindings);
+    }
+}
+
+/// `n` of `alloc(n)`, if it is a constant
+fn allocation_size(expr: &SeppoExpr) -> Option<i64> {
+    match expr {
+        SeppoExpr::FunctionCall(name, args) if name == "alloc" && args.len() == 1 => {
+            constant(&args[0])
+        }
+        _ => None,
+    }
+}
+
+/// Value of arithmetic on integer literals, like `sizeof(i64) * 2` once
+/// `sizeof` is resolved
+fn constant(expr: &SeppoExpr) -> Option<i64> {
+    match expr {
+        SeppoExpr::Number(n) => Some(*n),
+        SeppoExpr::Operation(op, left, right) => {
+            let (left, right) = (constant(left)?, constant(right)?);
+            match op.as_str() {
+                "+" => left.checked_add(right),
+                "-" => left.checked_sub(right),
+                "*" => left.checked_mul(right),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The variable an address is an offset from: `p`, `p + k`, `k + p` or
+/// `p - k`
+fn address_base(expr: &SeppoExpr) -> Option<&str> {
+    match expr {
+        SeppoExpr::Variable(name) => Some(name),
+        SeppoExpr::Operation(op, left, right) if op == "+" || op == "-" => {
+            match (left.as_ref(), right.as_ref()) {
+                (SeppoExpr::Variable(name), _) => Some(name),
+                (_, SeppoExpr::Variable(name)) if op == "+" => Some(name),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether `expr` uses a variable of `group`, the allocation `root` and
+/// the addresses derived from it, other than as an address
+fn escapes(expr: &SeppoExpr, root: &str, group: &HashSet<&str>) -> bool {
+    match expr {
+        SeppoExpr::Variable(name) => group.contains(name.as_str()),
+        SeppoExpr::FunctionCall(name, args) if name == "load" || name == "store" => {
+            args.first()
+                .is_some_and(|address| address_escapes(address, root, group))
+                || args.iter().skip(1).any(|arg| escapes(arg, root, group))
+        }
+        SeppoExpr::FunctionCall(name, args)
+            if name == "free"
+                && matches!(args.as_slice(), [SeppoExpr::Variable(v)] if v == root) =>
+        {
+            false
+        }
+        SeppoExpr::Let { name, value, .. } | SeppoExpr::Assignment(name, value)
+            if name != root && group.contains(name.as_str()) =>
+        {
+            address_escapes(value, root, group)
+        }
+        _ => children(expr)
+            .into_iter()
+            .any(|child| escapes(child, root, group)),
+    }
+}
+
+/// Like `escapes`, for an expression used as an address
+fn address_escapes(expr: &SeppoExpr, root: &str, group: &HashSet<&str>) -> bool {
+    match expr {
+        SeppoExpr::Variable(_) => false,
+        SeppoExpr::Operation(op, left, right) if op == "+" || op == "-" => {
+            match (left.as_ref(), right.as_ref()) {
+                (SeppoExpr::Variable(_), offset) => escapes(offset, root, group),
+                (offset, SeppoExpr::Variable(_)) if op == "+" => escapes(offset, root, group),
+                _ => escapes(expr, root, group),
+            }
+        }
+        _ => escapes(expr, root, group),
+    }
+}
+
+/// Replace the allocations of the variables in `stack` and their `free`s
+fn rewrite(expr: &SeppoExpr, stack: &HashSet<String>) -> SeppoExpr {
+    match expr {
+        SeppoExpr::Let {
+            name,
+            mutable,
+            value,
+        } if stack.contains(name) => SeppoExpr::Let {
+            name: name.clone(),
+            mutable: *mutable,
+            value: Box::new(stack_alloc(value)),
+        },
+        SeppoExpr::Assignment(name, value) if stack.contains(name) => {
+            SeppoExpr::Assignment(name.clone(), Box::new(stack_alloc(value)))
+        }
+        SeppoExpr::FunctionCall(name, args)
+            if name == "free"
+                && matches!(args.as_slice(), [SeppoExpr::Variable(v)] if stack.contains(v)) =>
+        {
+            SeppoExpr::Number(0)
+        }
+        SeppoExpr::Let {
+            name,
+            mutable,
+            value,
+        } => SeppoExpr::Let {
+            name: name.clone(),
+            mutable: *mutable,
+            value: Box::new(rewrite(value, stack)),
+        },
+        SeppoExpr::Assignment(name, value) => {
+            SeppoExpr::Assignment(name.clone(), Box::new(rewrite(value, stack)))
+        }
+        SeppoExpr::FunctionCall(name, args) => SeppoExpr::FunctionCall(
+            name.clone(),
+            args.iter().map(|arg| rewrite(arg, stack)).collect(),
+        ),
+        SeppoExpr::Become(name, args) => SeppoExpr::Become(
+            name.clone(),
+            args.iter().map(|arg| rewrite(arg, stack)).collect(),
+        ),
+        SeppoExpr::Block(statements) => {
+            SeppoExpr::Block(statements.iter().map(|s| rewrite(s, stack)).collect())
+        }
+        SeppoExpr::Operation(op, left, right) => SeppoExpr::Operation(
+            op.clone(),
+            Box::new(rewrite(left, stack)),
+            Box::new(rewrite(right, stack)),
+        ),
+        SeppoExpr::Conditional {
+            condition,
+            true_block,
+            false_block,
+        } => SeppoExpr::Conditional {
+            condition: Box::new(rewrite(condition, stack)),
+            true_block: Box::new(rewrite(true_block, stack)),
+            false_block: false_block
+                .as_ref()
+                .map(|block| Box::new(rewrite(block, stack))),
+        },
+        SeppoExpr::For {
+            variable,
+            iterable,
+            body,
+        } => SeppoExpr::For {
+            variable: variable.clone(),
+            iterable: Box::new(rewrite(iterable, stack)),
+            body: Box::new(rewrite(body, stack)),
+        },
+        SeppoExpr::In(value, range) => SeppoExpr::In(
+            Box::new(rewrite(value, stack)),
+            Box::new(rewrite(range, stack)),
+        ),
+        SeppoExpr::Return(value) => SeppoExpr::Return(Box::new(rewrite(value, stack))),
+        SeppoExpr::Print(format, value) => {
+            SeppoExpr::Print(format.clone(), Box::new(rewrite(value, stack)))
+        }
+        other => other.clone(),
+    }
+}
+
+fn stack_alloc(value: &SeppoExpr) -> SeppoExpr {
+    let size = allocation_size(value).expect("stack allocations have a constant size");
+    SeppoExpr::FunctionCall(STACK_ALLOC.to_string(), vec![SeppoExpr::Number(size)])
+}
+
+/// The expressions directly inside `expr`
+fn children(expr: &SeppoExpr) -> Vec<&SeppoExpr> {
+    match expr {
+        SeppoExpr::Operation(_, left, right)
+        | SeppoExpr::In(left, right)
+        | SeppoExpr::Range {
+            start: left,
+            end: right,
+            ..
+        } => vec![left.as_ref(), right.as_ref()],
+        SeppoExpr::Assignment(_, value)
+        | SeppoExpr::Let { value, .. }
+        | SeppoExpr::Print(_, value)
+        | SeppoExpr::Return(value) => vec![value.as_ref()],
+        SeppoExpr::Block(items)
+        | SeppoExpr::FunctionCall(_, items)
+        | SeppoExpr::Become(_, items) => items.iter().collect(),
+        SeppoExpr::Function(_, _, body)
+        | SeppoExpr::ConstFunction(_, _, body)
+        | SeppoExpr::Macro(_, _, body) => vec![body.as_ref()],
+        SeppoExpr::Conditional {
+            condition,
+            true_block,
+            false_block,
+        } => {
+            let mut children = vec![condition.as_ref(), true_block.as_ref()];
+            children.extend(false_block.as_deref());
+            children
+        }
+        SeppoExpr::For { iterable, body, .. } => vec![iterable.as_ref(), body.as_ref()],
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,398 @@
+use crate::error::{Result, SeppoError};
+use crate::parser::parse_with_comments;
+use crate::types::*;
+
+const INDENT: &str = "    ";
+
+/// Reprint seppo source in canonical style: four space indentation, one
+/// statement per line, `perkele` on the line after the closing brace and a
+/// single blank line between top-level items, except between globals
+/// declared one after another. Comments are kept.
+pub fn format_source(source: &str) -> Result<String> {
+    let program = parse_with_comments(source)?;
+    format_program(&program)
+}
+
+/// Print a program parsed with `parse_with_comments`. Fails for trees the
+/// parser can't produce, like a block where an expression goes, rather than
+/// printing source that wouldn't parse back.
+pub fn format_program(program: &SeppoExpr) -> Result<String> {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+
+    let mut printer = Printer::default();
+    let mut previous: Option<&SeppoExpr> = None;
+    for item in items {
+        match item {
+            // Top-level spacing is fixed, so source blank lines are dropped
+            SeppoExpr::BlankLine => continue,
+            SeppoExpr::Comment { text, trailing } if *trailing && previous.is_some() => {
+                printer.append_to_line(text);
+            }
+            _ => {
+                // Comments directly above an item stay attached to it
+                let after_comment = matches!(
+                    previous,
+                    Some(
+                        SeppoExpr::Comment { .. }
+                            | SeppoExpr::DocComment(_)
+                            | SeppoExpr::Attribute { .. }
+                            | SeppoExpr::Signature(_)
+                    )
+                );
+                let global = |item: &SeppoExpr| match item {
+                    SeppoExpr::Let { .. } => true,
+                    SeppoExpr::Attribute { name, .. } => name == THREADLOCAL_ATTRIBUTE,
+                    _ => false,
+                };
+                let after_global = previous.is_some_and(global) && global(item);
+                if previous.is_some() && !after_comment && !after_global {
+                    printer.out.push('\n');
+                }
+                printer.item(item)?;
+            }
+        }
+        previous = Some(item);
+    }
+    Ok(printer.out)
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    depth: usize,
+    /// Keywords like `export ` the next function or global was declared
+    /// with
+    markers: String,
+    /// Types the next function declares
+    signature: Signature,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Put a trailing comment at the end of the line just printed
+    fn append_to_line(&mut self, text: &str) {
+        if self.out.ends_with('\n') {
+            self.out.pop();
+        }
+        self.out.push(' ');
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn item(&mut self, item: &SeppoExpr) -> Result<()> {
+        match item {
+            SeppoExpr::Function(name, params, body) => {
+                let header = self.function_header("fn", name, params);
+                self.block(&header, body)?;
+            }
+            SeppoExpr::ConstFunction(name, params, body) => {
+                let header = self.function_header("const fn", name, params);
+                self.block(&header, body)?;
+            }
+            SeppoExpr::Attribute { name, .. }
+                if FUNCTION_MARKERS.contains(&name.as_str()) || name == THREADLOCAL_ATTRIBUTE =>
+            {
+                self.markers.push_str(name);
+                self.markers.push(' ');
+            }
+            SeppoExpr::Let { .. } => {
+                let global = format!("{}{}", self.markers(), expr(item)?);
+                self.line(&global);
+            }
+            SeppoExpr::Signature(signature) => self.signature = signature.clone(),
+            SeppoExpr::Macro(name, params, body) => {
+                let header = format!("macro {}({})", name, params.join(", "));
+                self.block(&header, body)?;
+            }
+            SeppoExpr::InlineC(code) => {
+                self.line("ceppo {");
+                for line in reindent_c(code) {
+                    if line.is_empty() {
+                        self.out.push('\n');
+                    } else {
+                        self.line(&format!("{}{}", INDENT, line));
+                    }
+                }
+                self.line("}");
+            }
+            other => self.statement(other)?,
+        }
+        Ok(())
+    }
+
+    /// The keywords before `fn` of the function being printed
+    fn markers(&mut self) -> String {
+        std::mem::take(&mut self.markers)
+    }
+
+    /// `keyword name(params)` with the markers and types of the function
+    fn function_header(&mut self, keyword: &str, name: &str, params: &[String]) -> String {
+        let signature = std::mem::take(&mut self.signature);
+        format!(
+            "{}{} {}{}",
+            self.markers(),
+            keyword,
+            name,
+            signature.format(params)
+        )
+    }
+
+    /// Print `header {`, the statements of `body` and the closing brace
+    fn block(&mut self, header: &str, body: &SeppoExpr) -> Result<()> {
+        let statements = match body {
+            SeppoExpr::Block(statements) => statements.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        if statements.is_empty() {
+            self.line(&format!("{} {{}}", header));
+            return Ok(());
+        }
+
+        self.line(&format!("{} {{", header));
+        self.depth += 1;
+        for (i, statement) in statements.iter().enumerate() {
+            match statement {
+                // Blank lines at the edges of a block are noise
+                SeppoExpr::BlankLine if i == 0 || i + 1 == statements.len() => {}
+                SeppoExpr::BlankLine => self.out.push('\n'),
+                SeppoExpr::Comment { text, trailing } if *trailing && i > 0 => {
+                    self.append_to_line(text);
+                }
+                other => self.statement(other)?,
+            }
+        }
+        self.depth -= 1;
+        self.line("}");
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &SeppoExpr) -> Result<()> {
+        match statement {
+            SeppoExpr::Conditional { .. } => self.conditional("", statement)?,
+            SeppoExpr::Assignment(name, value) if is_conditional(value) => {
+                self.conditional(&format!("{} = ", name), value)?
+            }
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } if is_conditional(value) => {
+                let keyword = if *mutable { "let mut" } else { "let" };
+                self.conditional(&format!("{} {} = ", keyword, name), value)?
+            }
+            SeppoExpr::Return(value) if is_conditional(value) => {
+                self.conditional("return ", value)?
+            }
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => self.block(&format!("for {} in {}", variable, expr(iterable)?), body)?,
+            SeppoExpr::Comment { text, .. } => self.line(text),
+            SeppoExpr::DocComment(text) if text.is_empty() => self.line("##"),
+            SeppoExpr::DocComment(text) => self.line(&format!("## {}", text)),
+            SeppoExpr::Attribute { name, args } if args.is_empty() => {
+                self.line(&format!("#[{}]", name))
+            }
+            SeppoExpr::Attribute { name, args } => {
+                self.line(&format!("#[{}({})]", name, args.join(", ")))
+            }
+            SeppoExpr::BlankLine => self.out.push('\n'),
+            SeppoExpr::Location { .. } => {}
+            other => self.line(&expr(other)?),
+        }
+        Ok(())
+    }
+
+    /// Print a conditional with `prefix`, like `x = `, before `seppo`
+    fn conditional(&mut self, prefix: &str, conditional: &SeppoExpr) -> Result<()> {
+        let SeppoExpr::Conditional {
+            condition: c,
+            true_block,
+            false_block,
+        } = conditional
+        else {
+            return Err(not_an_expression(conditional));
+        };
+        self.block(&format!("{}seppo {}", prefix, condition(c)?), true_block)?;
+        if let Some(false_block) = false_block {
+            self.block("perkele", false_block)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_conditional(expr: &SeppoExpr) -> bool {
+    matches!(expr, SeppoExpr::Conditional { .. })
+}
+
+/// One line describing a statement, with the bodies of conditionals and
+/// loops left out
+pub(crate) fn statement_summary(statement: &SeppoExpr) -> String {
+    let summary = match statement {
+        SeppoExpr::Conditional { condition: c, .. } => {
+            condition(c).map(|c| format!("seppo {} {{ ... }}", c))
+        }
+        SeppoExpr::For {
+            variable, iterable, ..
+        } => expr(iterable).map(|iterable| format!("for {} in {} {{ ... }}", variable, iterable)),
+        SeppoExpr::InlineC(_) => Ok("ceppo { ... }".to_string()),
+        other => expr(other),
+    };
+    summary.unwrap_or_else(|_| "...".to_string())
+}
+
+/// Single-line form of a statement or expression
+fn expr(e: &SeppoExpr) -> Result<String> {
+    Ok(match e {
+        SeppoExpr::Number(n) if *n < 0 => return Err(not_an_expression(e)),
+        SeppoExpr::Number(n) => n.to_string(),
+        // There are no escapes, so a quote would end the literal
+        SeppoExpr::String(s) if s.contains('"') => return Err(not_an_expression(e)),
+        SeppoExpr::String(s) => format!("\"{}\"", s),
+        SeppoExpr::Variable(name) => name.clone(),
+        // Conditions are only written where the grammar reads one
+        SeppoExpr::Operation(op, ..) if is_comparison(op) || is_logical(op) => {
+            return Err(not_an_expression(e))
+        }
+        SeppoExpr::In(..) => return Err(not_an_expression(e)),
+        SeppoExpr::Operation(op, left, right) => {
+            format!("{} {} {}", operand(left)?, op, operand(right)?)
+        }
+        SeppoExpr::Assignment(name, v) => format!("{} = {}", name, value(v)?),
+        SeppoExpr::Let {
+            name,
+            mutable,
+            value: v,
+        } => format!(
+            "let {}{} = {}",
+            if *mutable { "mut " } else { "" },
+            name,
+            value(v)?
+        ),
+        SeppoExpr::Print(PrintFormat::Decimal, value) => format!("seppo {}", expr(value)?),
+        SeppoExpr::Print(PrintFormat::Hex, value) => format!("0xseppo {}", expr(value)?),
+        SeppoExpr::Print(PrintFormat::Bool, value) => format!("boolseppo {}", expr(value)?),
+        SeppoExpr::Print(PrintFormat::Char, value) => format!("charseppo {}", expr(value)?),
+        SeppoExpr::Print(PrintFormat::Stderr, value) => format!("eseppo {}", expr(value)?),
+        SeppoExpr::FunctionCall(name, args) => format!("{}({})", name, exprs(args)?),
+        SeppoExpr::Return(v) => format!("return {}", value(v)?),
+        SeppoExpr::Become(name, args) => format!("become {}({})", name, exprs(args)?),
+        SeppoExpr::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let op = if *inclusive { "..=" } else { ".." };
+            format!("{}{}{}", expr(start)?, op, expr(end)?)
+        }
+        // The printer writes these over several lines or as items of their
+        // own, never inside an expression
+        SeppoExpr::Block(_)
+        | SeppoExpr::Function(..)
+        | SeppoExpr::ConstFunction(..)
+        | SeppoExpr::Macro(..)
+        | SeppoExpr::InlineC(_)
+        | SeppoExpr::Conditional { .. }
+        | SeppoExpr::For { .. }
+        | SeppoExpr::Comment { .. }
+        | SeppoExpr::BlankLine
+        | SeppoExpr::Location { .. }
+        | SeppoExpr::DocComment(_)
+        | SeppoExpr::Attribute { .. }
+        | SeppoExpr::Signature(_) => return Err(not_an_expression(e)),
+    })
+}
+
+/// The value of an assignment or return, which may be a condition
+fn value(e: &SeppoExpr) -> Result<String> {
+    match e {
+        SeppoExpr::Operation(op, ..) if is_comparison(op) || is_logical(op) => condition(e),
+        SeppoExpr::In(..) => condition(e),
+        other => expr(other),
+    }
+}
+
+/// A condition, where `&&` binds tighter than `||` and both group to the
+/// left, as the parser reads them
+fn condition(e: &SeppoExpr) -> Result<String> {
+    match e {
+        SeppoExpr::Operation(op, left, right) if op == "||" => {
+            Ok(format!("{} || {}", condition(left)?, conjunction(right)?))
+        }
+        other => conjunction(other),
+    }
+}
+
+fn conjunction(e: &SeppoExpr) -> Result<String> {
+    match e {
+        SeppoExpr::Operation(op, left, right) if op == "&&" => {
+            Ok(format!("{} && {}", conjunction(left)?, comparison(right)?))
+        }
+        other => comparison(other),
+    }
+}
+
+fn comparison(e: &SeppoExpr) -> Result<String> {
+    match e {
+        SeppoExpr::Operation(op, left, right) if is_comparison(op) => {
+            Ok(format!("{} {} {}", expr(left)?, op, expr(right)?))
+        }
+        SeppoExpr::In(value, range) => Ok(format!("{} in {}", expr(value)?, expr(range)?)),
+        SeppoExpr::Variable(_) | SeppoExpr::FunctionCall(..) => expr(e),
+        other => Err(not_an_expression(other)),
+    }
+}
+
+/// An operand of arithmetic, which the grammar limits to numbers and
+/// variables
+fn operand(e: &SeppoExpr) -> Result<String> {
+    match e {
+        SeppoExpr::Number(_) | SeppoExpr::Variable(_) => expr(e),
+        other => Err(not_an_expression(other)),
+    }
+}
+
+fn exprs(args: &[SeppoExpr]) -> Result<String> {
+    let args = args.iter().map(expr).collect::<Result<Vec<_>>>()?;
+    Ok(args.join(", "))
+}
+
+fn not_an_expression(e: &SeppoExpr) -> SeppoError {
+    SeppoError::Sema(format!("Can't format {:?} as seppo source", e))
+}
+
+/// Lines of a ceppo body with their common indentation removed. The parser
+/// trims the body, so the first line has already lost its indentation.
+fn reindent_c(code: &str) -> Vec<String> {
+    let lines: Vec<&str> = code.lines().map(str::trim_end).collect();
+    let common = lines
+        .iter()
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                line[common..].to_string()
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,305 @@
+use crate::fmt::format_program;
+use crate::types::*;
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+const MAX_FUNCTIONS: usize = 4;
+const MAX_PARAMS: usize = 3;
+const MAX_STATEMENTS: usize = 5;
+/// How deep conditionals and loops nest
+const MAX_BLOCK_DEPTH: usize = 2;
+/// How deep calls nest inside one expression
+const MAX_CALL_DEPTH: usize = 2;
+const MAX_ITERATIONS: i64 = 3;
+
+/// A random program and its source, see `arbitrary_program`
+#[derive(Debug, Clone)]
+pub struct ArbitraryProgram {
+    pub program: SeppoExpr,
+    /// `program` in canonical style, which parses back to `program`
+    pub source: String,
+}
+
+impl<'a> Arbitrary<'a> for ArbitraryProgram {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        arbitrary_program(u)
+    }
+}
+
+/// Generate a valid program from fuzzer input, for checking that the
+/// parser, the interpreter and codegen agree with each other. Programs
+/// define a few functions and `seppo`, pass the semantic checks and always
+/// finish: functions only call the ones defined before them, loops run over
+/// short constant ranges and nothing is divided by zero. Arithmetic may
+/// overflow and wraps around.
+pub fn arbitrary_program(u: &mut Unstructured) -> Result<ArbitraryProgram> {
+    let mut generator = Generator {
+        u,
+        functions: Vec::new(),
+        scopes: Vec::new(),
+        next_variable: 0,
+        block_depth: 0,
+        call_depth: 0,
+    };
+    let mut items = Vec::new();
+    let count = generator.u.int_in_range(0..=MAX_FUNCTIONS - 1)?;
+    for i in 0..count {
+        let params = generator.u.int_in_range(0..=MAX_PARAMS)?;
+        items.push(generator.function(format!("f{}", i), params)?);
+    }
+    items.push(generator.function("seppo".to_string(), 0)?);
+
+    let program = SeppoExpr::Block(items);
+    let source = format_program(&program).expect("generated programs are valid source");
+    Ok(ArbitraryProgram { program, source })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Binding {
+    Mutable,
+    /// Variables declared without `mut` and loop variables, which cannot be
+    /// assigned
+    Fixed,
+}
+
+struct Generator<'u, 'a> {
+    u: &'u mut Unstructured<'a>,
+    /// Name and parameter count of the functions defined so far
+    functions: Vec<(String, usize)>,
+    /// Variables of the function being generated, innermost block last
+    scopes: Vec<Vec<(String, Binding)>>,
+    next_variable: usize,
+    block_depth: usize,
+    call_depth: usize,
+}
+
+impl Generator<'_, '_> {
+    fn function(&mut self, name: String, params: usize) -> Result<SeppoExpr> {
+        let params: Vec<String> = (0..params).map(|i| format!("p{}", i)).collect();
+        self.scopes = vec![params
+            .iter()
+            .map(|param| (param.clone(), Binding::Mutable))
+            .collect()];
+        self.next_variable = 0;
+
+        let mut body = self.statements()?;
+        body.push(SeppoExpr::Return(Box::new(self.expression()?)));
+        self.functions.push((name.clone(), params.len()));
+        Ok(SeppoExpr::Function(
+            name,
+            params,
+            Box::new(SeppoExpr::Block(body)),
+        ))
+    }
+
+    fn statements(&mut self) -> Result<Vec<SeppoExpr>> {
+        let count = self.u.int_in_range(0..=MAX_STATEMENTS)?;
+        (0..count).map(|_| self.statement()).collect()
+    }
+
+    /// Statements of a conditional branch or loop body, in a scope of
+    /// their own with `binding` declared in it. With `value` the last
+    /// statement is an expression, for conditionals used as values.
+    fn block(&mut self, binding: Option<String>, value: bool) -> Result<SeppoExpr> {
+        self.scopes.push(
+            binding
+                .into_iter()
+                .map(|name| (name, Binding::Fixed))
+                .collect(),
+        );
+        self.block_depth += 1;
+        // Empty bodies are fine, but rarely interesting
+        let mut statements = vec![self.statement()?];
+        statements.extend(self.statements()?);
+        if value {
+            statements.push(self.expression()?);
+        }
+        self.block_depth -= 1;
+        self.scopes.pop();
+        Ok(SeppoExpr::Block(statements))
+    }
+
+    fn statement(&mut self) -> Result<SeppoExpr> {
+        let kinds = if self.block_depth < MAX_BLOCK_DEPTH {
+            7
+        } else {
+            5
+        };
+        Ok(match self.u.choose_index(kinds)? {
+            0 => {
+                let value = if self.block_depth < MAX_BLOCK_DEPTH && self.u.ratio(1, 4)? {
+                    SeppoExpr::Conditional {
+                        condition: Box::new(self.condition()?),
+                        true_block: Box::new(self.block(None, true)?),
+                        false_block: Some(Box::new(self.block(None, true)?)),
+                    }
+                } else {
+                    self.expression()?
+                };
+                let name = self.fresh_variable(Binding::Fixed);
+                SeppoExpr::Assignment(name, Box::new(value))
+            }
+            1 => {
+                let value = self.expression()?;
+                let mutable = self.u.arbitrary()?;
+                let binding = if mutable {
+                    Binding::Mutable
+                } else {
+                    Binding::Fixed
+                };
+                SeppoExpr::Let {
+                    name: self.fresh_variable(binding),
+                    mutable,
+                    value: Box::new(value),
+                }
+            }
+            2 => {
+                let format = self
+                    .u
+                    .choose(&[
+                        PrintFormat::Decimal,
+                        PrintFormat::Hex,
+                        PrintFormat::Bool,
+                        PrintFormat::Char,
+                    ])?
+                    .clone();
+                SeppoExpr::Print(format, Box::new(self.expression()?))
+            }
+            3 if !self.functions.is_empty() => self.call()?,
+            3 | 4 => {
+                let value = self.expression()?;
+                let assignable = self.variables(Some(Binding::Mutable));
+                let name = if assignable.is_empty() {
+                    self.fresh_variable(Binding::Fixed)
+                } else {
+                    self.u.choose(&assignable)?.clone()
+                };
+                SeppoExpr::Assignment(name, Box::new(value))
+            }
+            5 => {
+                let condition = self.condition()?;
+                let true_block = self.block(None, false)?;
+                let false_block = if self.u.arbitrary()? {
+                    Some(Box::new(self.block(None, false)?))
+                } else {
+                    None
+                };
+                SeppoExpr::Conditional {
+                    condition: Box::new(condition),
+                    true_block: Box::new(true_block),
+                    false_block,
+                }
+            }
+            _ => {
+                let start = self.u.int_in_range(0..=MAX_ITERATIONS)?;
+                let length = self.u.int_in_range(0..=MAX_ITERATIONS)?;
+                let inclusive = length > 0 && self.u.arbitrary()?;
+                let end = if inclusive {
+                    start + length - 1
+                } else {
+                    start + length
+                };
+                // Numbered like other variables, so names never repeat
+                let variable = format!("v{}", self.next_variable);
+                self.next_variable += 1;
+                SeppoExpr::For {
+                    iterable: Box::new(SeppoExpr::Range {
+                        start: Box::new(SeppoExpr::Number(start)),
+                        end: Box::new(SeppoExpr::Number(end)),
+                        inclusive,
+                    }),
+                    body: Box::new(self.block(Some(variable.clone()), false)?),
+                    variable,
+                }
+            }
+        })
+    }
+
+    fn condition(&mut self) -> Result<SeppoExpr> {
+        if self.u.ratio(1, 4)? {
+            let value = self.expression()?;
+            let range = SeppoExpr::Range {
+                start: Box::new(self.atom()?),
+                end: Box::new(self.atom()?),
+                inclusive: self.u.arbitrary()?,
+            };
+            return Ok(SeppoExpr::In(Box::new(value), Box::new(range)));
+        }
+        let op = self.u.choose(&[">", "<", "==", "!="])?;
+        Ok(SeppoExpr::Operation(
+            op.to_string(),
+            Box::new(self.expression()?),
+            Box::new(self.expression()?),
+        ))
+    }
+
+    fn expression(&mut self) -> Result<SeppoExpr> {
+        let kinds = if !self.functions.is_empty() && self.call_depth < MAX_CALL_DEPTH {
+            3
+        } else {
+            2
+        };
+        match self.u.choose_index(kinds)? {
+            0 => self.atom(),
+            1 => {
+                let op = *self.u.choose(&["+", "-", "*", "/"])?;
+                let left = self.atom()?;
+                let right = if op == "/" {
+                    SeppoExpr::Number(self.u.int_in_range(1..=9)?)
+                } else {
+                    self.atom()?
+                };
+                Ok(SeppoExpr::Operation(
+                    op.to_string(),
+                    Box::new(left),
+                    Box::new(right),
+                ))
+            }
+            _ => self.call(),
+        }
+    }
+
+    /// A number or a variable, the operands the grammar allows in
+    /// operations
+    fn atom(&mut self) -> Result<SeppoExpr> {
+        let variables = self.variables(None);
+        if !variables.is_empty() && self.u.arbitrary()? {
+            return Ok(SeppoExpr::Variable(self.u.choose(&variables)?.clone()));
+        }
+        let number = if self.u.ratio(1, 8)? {
+            self.u.int_in_range(0..=i64::MAX)?
+        } else {
+            self.u.int_in_range(0..=100)?
+        };
+        Ok(SeppoExpr::Number(number))
+    }
+
+    fn call(&mut self) -> Result<SeppoExpr> {
+        let (name, params) = self.u.choose(&self.functions)?.clone();
+        self.call_depth += 1;
+        let args = (0..params)
+            .map(|_| self.expression())
+            .collect::<Result<Vec<_>>>();
+        self.call_depth -= 1;
+        Ok(SeppoExpr::FunctionCall(name, args?))
+    }
+
+    /// Variables in scope, only those bound as `binding` if given
+    fn variables(&self, binding: Option<Binding>) -> Vec<String> {
+        self.scopes
+            .iter()
+            .flatten()
+            .filter(|(_, bound)| binding.is_none_or(|binding| *bound == binding))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    fn fresh_variable(&mut self, binding: Binding) -> String {
+        let name = format!("v{}", self.next_variable);
+        self.next_variable += 1;
+        self.scopes
+            .last_mut()
+            .expect("functions have a scope")
+            .push((name.clone(), binding));
+        name
+    }
+}
@@ -0,0 +1,568 @@
+use pest_meta::ast::{Expr, Rule, RuleType};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write as _;
+
+/// The grammar the parser is generated from
+const PEST_GRAMMAR: &str = include_str!("seppo.pest");
+
+/// Output format of `seppoc grammar`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrammarFormat {
+    /// A TextMate grammar, as JSON, for VS Code, Sublime Text and others
+    TmLanguage,
+    /// The `grammar.js` of a tree-sitter parser
+    TreeSitter,
+}
+
+/// An editor grammar for seppo, generated from the pest grammar the
+/// compiler itself parses with, so that highlighting follows changes to
+/// the language
+pub fn render(format: GrammarFormat) -> String {
+    let rules = pest_rules();
+    match format {
+        GrammarFormat::TmLanguage => render_tmlanguage(&rules),
+        GrammarFormat::TreeSitter => render_tree_sitter(&rules),
+    }
+}
+
+fn pest_rules() -> Vec<Rule> {
+    let pairs = pest_meta::parser::parse(pest_meta::parser::Rule::grammar_rules, PEST_GRAMMAR)
+        .expect("the parser is built from this grammar");
+    pest_meta::parser::consume_rules(pairs).expect("the parser is built from this grammar")
+}
+
+/// Every string literal of `expr`, lookaheads included
+fn literals<'a>(expr: &'a Expr, out: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Str(s) | Expr::Insens(s) => out.push(s),
+        Expr::Seq(a, b) | Expr::Choice(a, b) => {
+            literals(a, out);
+            literals(b, out);
+        }
+        Expr::PosPred(e)
+        | Expr::NegPred(e)
+        | Expr::Opt(e)
+        | Expr::Rep(e)
+        | Expr::RepOnce(e)
+        | Expr::RepExact(e, _)
+        | Expr::RepMin(e, _)
+        | Expr::RepMax(e, _)
+        | Expr::RepMinMax(e, _, _)
+        | Expr::Push(e) => literals(e, out),
+        _ => {}
+    }
+}
+
+fn is_atomic(rule: &Rule) -> bool {
+    matches!(rule.ty, RuleType::Atomic | RuleType::CompoundAtomic)
+}
+
+fn is_word(literal: &str) -> bool {
+    literal
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && literal.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+/// Keywords are the literals that look like words. Operators are the other
+/// literals between tokens, which leaves out whitespace, punctuation (the
+/// brackets, separators and the `:` and `->` of signatures) and the
+/// delimiters of comments, attributes and strings, which are atomic rules
+/// or start with `#`.
+fn keywords_and_operators(rules: &[Rule]) -> (BTreeSet<&str>, Vec<&str>) {
+    let mut keywords = BTreeSet::new();
+    let mut operators = BTreeSet::new();
+    for rule in rules {
+        let mut found = Vec::new();
+        literals(&rule.expr, &mut found);
+        for literal in found {
+            if is_word(literal) {
+                keywords.insert(literal);
+            } else if !is_atomic(rule)
+                && !IMPLICIT_RULES.contains(&rule.name.as_str())
+                && !literal.starts_with('#')
+                && !literal.chars().all(|c| "(){}[],;:".contains(c))
+                && literal != "->"
+            {
+                operators.insert(literal);
+            }
+        }
+    }
+    // Longest first, so that `..=` is not matched as `..` and `=`
+    let mut operators: Vec<&str> = operators.into_iter().collect();
+    operators.sort_by_key(|op| std::cmp::Reverse(op.len()));
+    (keywords, operators)
+}
+
+/// Matches `text` literally in an Oniguruma or JavaScript regex
+fn regex_escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}/-".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn render_tmlanguage(rules: &[Rule]) -> String {
+    let (keywords, operators) = keywords_and_operators(rules);
+    let keywords: Vec<String> = keywords.iter().map(|k| regex_escape(k)).collect();
+    let operators: Vec<String> = operators.iter().map(|op| regex_escape(op)).collect();
+    let keyword_pattern = json_string(&format!("\\b(?:{})\\b", keywords.join("|")));
+    let operator_pattern = json_string(&operators.join("|"));
+
+    // The structure is fixed; what the grammar decides is which words are
+    // keywords and which symbols are operators
+    format!(
+        r###"{{
+  "name": "Seppo",
+  "scopeName": "source.seppo",
+  "fileTypes": ["seppo"],
+  "patterns": [
+    {{ "include": "#doc-comment" }},
+    {{ "include": "#comment" }},
+    {{ "include": "#attribute" }},
+    {{ "include": "#ceppo" }},
+    {{ "include": "#string" }},
+    {{ "include": "#function" }},
+    {{ "include": "#keyword" }},
+    {{ "include": "#number" }},
+    {{ "include": "#operator" }}
+  ],
+  "repository": {{
+    "doc-comment": {{
+      "name": "comment.line.documentation.seppo",
+      "match": "##.*$"
+    }},
+    "comment": {{
+      "name": "comment.line.double-slash.seppo",
+      "match": "//.*$"
+    }},
+    "attribute": {{
+      "name": "meta.attribute.seppo",
+      "match": "#\\[[^\\]]*\\]"
+    }},
+    "ceppo": {{
+      "begin": "\\b(ceppo)\\s*(\\{{)",
+      "beginCaptures": {{
+        "1": {{ "name": "keyword.other.ceppo.seppo" }},
+        "2": {{ "name": "punctuation.section.block.begin.seppo" }}
+      }},
+      "end": "\\}}",
+      "endCaptures": {{
+        "0": {{ "name": "punctuation.section.block.end.seppo" }}
+      }},
+      "contentName": "source.c",
+      "patterns": [{{ "include": "#c-braces" }}, {{ "include": "source.c" }}]
+    }},
+    "c-braces": {{
+      "begin": "\\{{",
+      "end": "\\}}",
+      "patterns": [{{ "include": "#c-braces" }}, {{ "include": "source.c" }}]
+    }},
+    "string": {{
+      "name": "string.quoted.double.seppo",
+      "begin": "\"",
+      "end": "\""
+    }},
+    "function": {{
+      "match": "\\b(fn|macro)\\s+([A-Za-z][A-Za-z0-9_]*)",
+      "captures": {{
+        "1": {{ "name": "keyword.other.seppo" }},
+        "2": {{ "name": "entity.name.function.seppo" }}
+      }}
+    }},
+    "keyword": {{
+      "name": "keyword.control.seppo",
+      "match": {keyword_pattern}
+    }},
+    "number": {{
+      "name": "constant.numeric.seppo",
+      "match": "\\b[0-9]+"
+    }},
+    "operator": {{
+      "name": "keyword.operator.seppo",
+      "match": {operator_pattern}
+    }}
+  }}
+}}
+"###
+    )
+}
+
+/// A tree-sitter rule expression
+#[derive(Debug, Clone)]
+enum Node {
+    /// A string, quoted for JavaScript
+    Literal(String),
+    /// A regex literal, slashes included
+    Pattern(String),
+    Ref(String),
+    Seq(Vec<Node>),
+    Choice(Vec<Node>),
+    Optional(Box<Node>),
+    Repeat(Box<Node>),
+    Repeat1(Box<Node>),
+    Token(Box<Node>),
+}
+
+impl Node {
+    fn seq(mut nodes: Vec<Node>) -> Option<Node> {
+        match nodes.len() {
+            0 => None,
+            1 => nodes.pop(),
+            _ => Some(Node::Seq(nodes)),
+        }
+    }
+
+    fn nullable(&self, nullable_rules: &HashSet<String>) -> bool {
+        match self {
+            Node::Literal(_) | Node::Pattern(_) => false,
+            Node::Ref(name) => nullable_rules.contains(name),
+            Node::Seq(nodes) => nodes.iter().all(|n| n.nullable(nullable_rules)),
+            Node::Choice(nodes) => nodes.iter().any(|n| n.nullable(nullable_rules)),
+            Node::Optional(_) | Node::Repeat(_) => true,
+            Node::Repeat1(node) | Node::Token(node) => node.nullable(nullable_rules),
+        }
+    }
+
+    fn has_refs(&self) -> bool {
+        match self {
+            Node::Literal(_) | Node::Pattern(_) => false,
+            Node::Ref(_) => true,
+            Node::Seq(nodes) | Node::Choice(nodes) => nodes.iter().any(Node::has_refs),
+            Node::Optional(node) | Node::Repeat(node) | Node::Repeat1(node) | Node::Token(node) => {
+                node.has_refs()
+            }
+        }
+    }
+
+    /// Wrap references to `rules` in `optional()`
+    fn make_optional(self, rules: &HashSet<String>) -> Node {
+        let map = |nodes: Vec<Node>| nodes.into_iter().map(|n| n.make_optional(rules)).collect();
+        match self {
+            Node::Ref(name) if rules.contains(&name) => Node::Optional(Box::new(Node::Ref(name))),
+            Node::Seq(nodes) => Node::Seq(map(nodes)),
+            Node::Choice(nodes) => Node::Choice(map(nodes)),
+            Node::Optional(node) => Node::Optional(Box::new(node.make_optional(rules))),
+            Node::Repeat(node) => Node::Repeat(Box::new(node.make_optional(rules))),
+            Node::Repeat1(node) => Node::Repeat1(Box::new(node.make_optional(rules))),
+            Node::Token(node) => Node::Token(Box::new(node.make_optional(rules))),
+            leaf => leaf,
+        }
+    }
+
+    /// On one line if it fits in `width`, else with an argument per line
+    fn render(&self, indent: usize, width: usize) -> String {
+        let (function, args): (&str, Vec<&Node>) = match self {
+            Node::Literal(text) | Node::Pattern(text) => return text.clone(),
+            Node::Ref(name) => return format!("$.{}", name),
+            Node::Seq(nodes) => ("seq", nodes.iter().collect()),
+            Node::Choice(nodes) => ("choice", nodes.iter().collect()),
+            Node::Optional(node) => ("optional", vec![node]),
+            Node::Repeat(node) => ("repeat", vec![node]),
+            Node::Repeat1(node) => ("repeat1", vec![node]),
+            Node::Token(node) => ("token", vec![node]),
+        };
+        let inline: Vec<String> = args.iter().map(|arg| arg.render(0, usize::MAX)).collect();
+        let inline = format!("{}({})", function, inline.join(", "));
+        if indent + inline.len() <= width {
+            return inline;
+        }
+        let pad = " ".repeat(indent + 2);
+        let mut out = format!("{}(\n", function);
+        for arg in args {
+            let _ = writeln!(out, "{}{},", pad, arg.render(indent + 2, width));
+        }
+        out.push_str(&" ".repeat(indent));
+        out.push(')');
+        out
+    }
+}
+
+/// Rules tree-sitter gets some other way: whitespace and comments are
+/// `extras`, and the start and end of input are implied
+const IMPLICIT_RULES: &[&str] = &["WHITESPACE", "SOI", "EOI"];
+
+/// The name of a pest rule in tree-sitter, where hidden rules start with _
+fn tree_sitter_name(rule: &str, silent: &HashSet<&str>) -> String {
+    if rule == "COMMENT" {
+        "comment".to_string()
+    } else if silent.contains(rule) {
+        format!("_{}", rule)
+    } else {
+        rule.to_string()
+    }
+}
+
+fn js_string(text: &str) -> String {
+    let mut out = String::from("'");
+    for c in text.chars() {
+        match c {
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+/// The characters `expr` matches if it only matches single characters
+fn single_chars(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Str(s) if s.chars().count() == 1 => Some(s.clone()),
+        Expr::Choice(a, b) => Some(single_chars(a)? + &single_chars(b)?),
+        _ => None,
+    }
+}
+
+struct TreeSitter<'a> {
+    silent: HashSet<&'a str>,
+}
+
+impl TreeSitter<'_> {
+    /// `expr` in tree-sitter, or None where it matches only the empty
+    /// string. tree-sitter has no lookahead: `!c ~ ANY` becomes a negated
+    /// character class and other lookaheads are left out, which makes the
+    /// grammar accept a little more than the compiler does.
+    fn node(&self, expr: &Expr) -> Option<Node> {
+        let repeat = |expr: &Expr, min: u32, max: Option<u32>| {
+            let node = self.node(expr)?;
+            let mut nodes = vec![node.clone(); min as usize];
+            match max {
+                None => nodes.push(Node::Repeat(Box::new(node))),
+                Some(max) => {
+                    let optional = Node::Optional(Box::new(node));
+                    nodes.extend(vec![optional; max.saturating_sub(min) as usize]);
+                }
+            }
+            Node::seq(nodes)
+        };
+        match expr {
+            Expr::Str(s) => Some(Node::Literal(js_string(s))),
+            Expr::Insens(s) => {
+                let pattern: String = s
+                    .chars()
+                    .map(|c| {
+                        if c.is_ascii_alphabetic() {
+                            format!("[{}{}]", c.to_ascii_lowercase(), c.to_ascii_uppercase())
+                        } else {
+                            regex_escape(&c.to_string())
+                        }
+                    })
+                    .collect();
+                Some(Node::Pattern(format!("/{}/", pattern)))
+            }
+            Expr::Range(start, end) => Some(Node::Pattern(format!(
+                "/[{}-{}]/",
+                regex_escape(start),
+                regex_escape(end)
+            ))),
+            Expr::Ident(name) => {
+                let pattern = match name.as_str() {
+                    "ANY" => "/[\\s\\S]/",
+                    "ASCII_DIGIT" => "/[0-9]/",
+                    "ASCII_ALPHA" => "/[a-zA-Z]/",
+                    "ASCII_ALPHANUMERIC" => "/[a-zA-Z0-9]/",
+                    "XID_START" => "/\\p{XID_Start}/",
+                    "XID_CONTINUE" => "/\\p{XID_Continue}/",
+                    "NEWLINE" => "/\\r?\\n/",
+                    name if IMPLICIT_RULES.contains(&name) || name == "COMMENT" => return None,
+                    name => return Some(Node::Ref(tree_sitter_name(name, &self.silent))),
+                };
+                Some(Node::Pattern(pattern.to_string()))
+            }
+            Expr::Seq(..) => {
+                let mut parts = Vec::new();
+                flatten_seq(expr, &mut parts);
+                let mut nodes = Vec::new();
+                let mut i = 0;
+                while i < parts.len() {
+                    if let (Expr::NegPred(excluded), Some(Expr::Ident(any))) =
+                        (parts[i], parts.get(i + 1))
+                    {
+                        if let Some(chars) = single_chars(excluded).filter(|_| any == "ANY") {
+                            let chars: String = chars
+                                .chars()
+                                .map(|c| match c {
+                                    '\n' => "\\n".to_string(),
+                                    c => regex_escape(&c.to_string()),
+                                })
+                                .collect();
+                            nodes.push(Node::Pattern(format!("/[^{}]/", chars)));
+                            i += 2;
+                            continue;
+                        }
+                    }
+                    nodes.extend(self.node(parts[i]));
+                    i += 1;
+                }
+                Node::seq(nodes)
+            }
+            Expr::Choice(..) => {
+                let mut parts = Vec::new();
+                flatten_choice(expr, &mut parts);
+                let nodes: Vec<Option<Node>> = parts.into_iter().map(|e| self.node(e)).collect();
+                let empty = nodes.iter().any(Option::is_none);
+                let mut nodes: Vec<Node> = nodes.into_iter().flatten().collect();
+                let node = match nodes.len() {
+                    0 => return None,
+                    1 => nodes.pop().unwrap(),
+                    _ => Node::Choice(nodes),
+                };
+                Some(if empty {
+                    Node::Optional(Box::new(node))
+                } else {
+                    node
+                })
+            }
+            Expr::Opt(e) => Some(Node::Optional(Box::new(self.node(e)?))),
+            // Any number of line breaks is what `extras` skips anyway
+            Expr::Rep(e) if matches!(&**e, Expr::Ident(name) if name == "NEWLINE") => None,
+            Expr::Rep(e) => Some(Node::Repeat(Box::new(self.node(e)?))),
+            Expr::RepOnce(e) => Some(Node::Repeat1(Box::new(self.node(e)?))),
+            Expr::RepExact(e, n) => repeat(e, *n, Some(*n)),
+            Expr::RepMin(e, n) => repeat(e, *n, None),
+            Expr::RepMax(e, n) => repeat(e, 0, Some(*n)),
+            Expr::RepMinMax(e, min, max) => repeat(e, *min, Some(*max)),
+            Expr::Push(e) => self.node(e),
+            // Lookaheads and the stack
+            _ => None,
+        }
+    }
+}
+
+fn flatten_seq<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Seq(a, b) => {
+            flatten_seq(a, out);
+            flatten_seq(b, out);
+        }
+        e => out.push(e),
+    }
+}
+
+fn flatten_choice<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Choice(a, b) => {
+            flatten_choice(a, out);
+            flatten_choice(b, out);
+        }
+        e => out.push(e),
+    }
+}
+
+fn render_tree_sitter(rules: &[Rule]) -> String {
+    let generator = TreeSitter {
+        silent: rules
+            .iter()
+            .filter(|rule| rule.ty == RuleType::Silent)
+            .map(|rule| rule.name.as_str())
+            .collect(),
+    };
+    let mut translated: Vec<(String, Node)> = Vec::new();
+    for rule in rules {
+        if IMPLICIT_RULES.contains(&rule.name.as_str()) {
+            continue;
+        }
+        let Some(mut node) = generator.node(&rule.expr) else {
+            continue;
+        };
+        // Tokens cannot refer to other rules
+        if is_atomic(rule) && !node.has_refs() {
+            node = Node::Token(Box::new(node));
+        }
+        translated.push((tree_sitter_name(&rule.name, &generator.silent), node));
+    }
+    // The first rule is where tree-sitter starts: the one matching the
+    // whole input
+    let start = rules
+        .iter()
+        .find(|rule| refers_to(&rule.expr, "SOI"))
+        .map(|rule| tree_sitter_name(&rule.name, &generator.silent));
+    if let Some(index) = translated
+        .iter()
+        .position(|(name, _)| Some(name) == start.as_ref())
+    {
+        let rule = translated.remove(index);
+        translated.insert(0, rule);
+    }
+
+    // Only the start rule may match the empty string. Other rules that do
+    // match one or more times, and where they are used they're optional.
+    let mut nullable = HashSet::new();
+    loop {
+        let before = nullable.len();
+        for (name, node) in translated.iter().skip(1) {
+            if node.nullable(&nullable) {
+                nullable.insert(name.clone());
+            }
+        }
+        if nullable.len() == before {
+            break;
+        }
+    }
+    for (name, node) in translated.iter_mut() {
+        let mut body = node.clone().make_optional(&nullable);
+        if nullable.contains(name) {
+            body = match body {
+                Node::Repeat(inner) => Node::Repeat1(inner),
+                Node::Optional(inner) => *inner,
+                other => other,
+            };
+        }
+        *node = body;
+    }
+
+    let mut out = String::new();
+    out.push_str("// Generated by `seppoc grammar --format tree-sitter` from the pest grammar\n");
+    out.push_str("// of the compiler. Regenerate it instead of editing it.\n\n");
+    out.push_str("module.exports = grammar({\n");
+    out.push_str("  name: 'seppo',\n\n");
+    out.push_str("  extras: $ => [/\\s/, $.comment],\n\n");
+    out.push_str("  word: $ => $.identifier,\n\n");
+    out.push_str("  rules: {\n");
+    for (name, node) in &translated {
+        let _ = writeln!(out, "    {}: $ => {},", name, node.render(4, 100));
+    }
+    out.push_str("  },\n");
+    out.push_str("});\n");
+    out
+}
+
+/// Does `expr` refer to the rule `name` outside lookaheads
+fn refers_to(expr: &Expr, name: &str) -> bool {
+    match expr {
+        Expr::Ident(ident) => ident == name,
+        Expr::Seq(a, b) | Expr::Choice(a, b) => refers_to(a, name) || refers_to(b, name),
+        Expr::Opt(e)
+        | Expr::Rep(e)
+        | Expr::RepOnce(e)
+        | Expr::RepExact(e, _)
+        | Expr::RepMin(e, _)
+        | Expr::RepMax(e, _)
+        | Expr::RepMinMax(e, _, _)
+        | Expr::Push(e) => refers_to(e, name),
+        _ => false,
+    }
+}
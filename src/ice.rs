@@ -0,0 +1,155 @@
+use crate::diagnostic::{ColorChoice, Diagnostic, Location, Severity};
+use crate::fmt::statement_summary;
+use crate::timing::Phase;
+use crate::types::{SeppoExpr, Span};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::fs;
+use std::panic::{self, PanicHookInfo};
+use std::path::PathBuf;
+
+pub(crate) const ISSUES_URL: &str = "https://github.com/thevilledev/seppolang/issues";
+
+/// What the compiler was working on, for the report of an internal error
+#[derive(Default)]
+struct Context {
+    phase: Option<Phase>,
+    function: Option<String>,
+    statement: Option<String>,
+    location: Option<(String, usize)>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+fn update(f: impl FnOnce(&mut Context)) {
+    // A panic while the context is borrowed must not panic again in the hook
+    CONTEXT.with(|context| {
+        if let Ok(mut context) = context.try_borrow_mut() {
+            f(&mut context);
+        }
+    });
+}
+
+/// Enter `phase`, returning the phase to restore afterwards. Starting or
+/// leaving a phase outside any other forgets what was being compiled.
+pub(crate) fn enter_phase(phase: Option<Phase>) -> Option<Phase> {
+    let mut previous = None;
+    update(|context| {
+        previous = context.phase;
+        if previous.is_none() || phase.is_none() {
+            *context = Context::default();
+        }
+        context.phase = phase;
+    });
+    previous
+}
+
+pub(crate) fn enter_function(name: &str) {
+    update(|context| {
+        context.function = Some(name.to_string());
+        context.statement = None;
+    });
+}
+
+pub(crate) fn enter_statement(statement: &SeppoExpr) {
+    update(|context| context.statement = Some(statement_summary(statement)));
+}
+
+pub(crate) fn enter_location(file: &str, line: usize) {
+    update(|context| context.location = Some((file.to_string(), line)));
+}
+
+/// The internal compiler error diagnostic for a panic with `message`,
+/// describing what the compiler was doing on this thread
+pub fn diagnostic(message: &str) -> Diagnostic {
+    let mut diagnostic = Diagnostic::new(
+        Severity::Error,
+        format!("internal compiler error: {}", message),
+    );
+    CONTEXT.with(|context| {
+        let Ok(context) = context.try_borrow() else {
+            return;
+        };
+        if let Some((file, line)) = &context.location {
+            diagnostic.location = Some(Location {
+                file: Some(PathBuf::from(file)),
+                line: *line,
+                column: 1,
+                span: Span::default(),
+            });
+        }
+        let mut doing = Vec::new();
+        if let Some(statement) = &context.statement {
+            doing.push(format!("while compiling `{}`", statement));
+        }
+        if let Some(function) = &context.function {
+            doing.push(format!("in function `{}`", function));
+        }
+        if let Some(phase) = context.phase {
+            doing.push(format!("during {}", phase.name()));
+        }
+        if !doing.is_empty() {
+            diagnostic.notes.push(doing.join(" "));
+        }
+    });
+    diagnostic.notes.push(format!(
+        "seppolang {} crashed, this is a bug in the compiler",
+        env!("CARGO_PKG_VERSION")
+    ));
+    diagnostic.notes.push(format!(
+        "please file an issue at {} with the program that triggered it",
+        ISSUES_URL
+    ));
+    diagnostic
+}
+
+/// Report panics as internal compiler errors instead of a Rust backtrace,
+/// which is still printed when `RUST_BACKTRACE` is set
+pub fn install_hook(color: ColorChoice) {
+    panic::set_hook(Box::new(move |info| {
+        let mut diagnostic = diagnostic(&panic_message(info));
+        if let Some(location) = info.location() {
+            diagnostic.notes.push(format!(
+                "panicked at {}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            ));
+        }
+
+        let source = diagnostic.location.as_mut().and_then(|location| {
+            let source = fs::read_to_string(location.file.as_ref()?).ok()?;
+            // Underline the whole statement
+            let text = source.lines().nth(location.line.checked_sub(1)?)?;
+            let indent = text.len() - text.trim_start().len();
+            location.column = indent + 1;
+            location.span = Span {
+                start: 0,
+                end: text.trim().len(),
+            };
+            Some(source)
+        });
+        eprint!(
+            "{}",
+            diagnostic.render(source.as_deref(), color.use_color())
+        );
+
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            eprintln!("\nbacktrace:\n{}", backtrace);
+        } else {
+            eprintln!("\nnote: run with `RUST_BACKTRACE=1` for a backtrace");
+        }
+    }));
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
@@ -0,0 +1,106 @@
+use crate::error::{Result, SeppoError};
+use crate::sandbox::no_extern;
+use crate::types::SeppoExpr;
+use std::fs;
+use std::path::Path;
+
+const INCLUDE_STR: &str = "include_str";
+
+/// Replace `include_str("path")` with the contents of the file as a string
+/// literal, so fixtures are part of the program. Paths are relative to
+/// `dir`, the directory of the source file, or the working directory for
+/// source that wasn't read from one. A program defining a function of the
+/// same name calls it instead.
+pub(crate) fn resolve_includes(program: SeppoExpr, dir: Option<&Path>) -> Result<SeppoExpr> {
+    let SeppoExpr::Block(items) = &program else {
+        return Ok(program);
+    };
+    let defined = items
+        .iter()
+        .any(|item| matches!(item, SeppoExpr::Function(name, ..) if name == INCLUDE_STR));
+    if defined {
+        return Ok(program);
+    }
+    include(program, dir.unwrap_or(Path::new("")))
+}
+
+fn include(expr: SeppoExpr, dir: &Path) -> Result<SeppoExpr> {
+    let all = |exprs: Vec<SeppoExpr>| -> Result<Vec<SeppoExpr>> {
+        exprs.into_iter().map(|expr| include(expr, dir)).collect()
+    };
+    let boxed = |expr: Box<SeppoExpr>| include(*expr, dir).map(Box::new);
+    Ok(match expr {
+        SeppoExpr::FunctionCall(name, args) if name == INCLUDE_STR => read(&args, dir)?,
+        SeppoExpr::FunctionCall(name, args) => SeppoExpr::FunctionCall(name, all(args)?),
+        SeppoExpr::Become(name, args) => SeppoExpr::Become(name, all(args)?),
+        SeppoExpr::Block(statements) => SeppoExpr::Block(all(statements)?),
+        SeppoExpr::Function(name, params, body) => SeppoExpr::Function(name, params, boxed(body)?),
+        SeppoExpr::ConstFunction(name, params, body) => {
+            SeppoExpr::ConstFunction(name, params, boxed(body)?)
+        }
+        SeppoExpr::Operation(op, left, right) => {
+            SeppoExpr::Operation(op, boxed(left)?, boxed(right)?)
+        }
+        SeppoExpr::Range {
+            start,
+            end,
+            inclusive,
+        } => SeppoExpr::Range {
+            start: boxed(start)?,
+            end: boxed(end)?,
+            inclusive,
+        },
+        SeppoExpr::In(value, range) => SeppoExpr::In(boxed(value)?, boxed(range)?),
+        SeppoExpr::Assignment(name, value) => SeppoExpr::Assignment(name, boxed(value)?),
+        SeppoExpr::Let {
+            name,
+            mutable,
+            value,
+        } => SeppoExpr::Let {
+            name,
+            mutable,
+            value: boxed(value)?,
+        },
+        SeppoExpr::Print(format, value) => SeppoExpr::Print(format, boxed(value)?),
+        SeppoExpr::Return(value) => SeppoExpr::Return(boxed(value)?),
+        SeppoExpr::Conditional {
+            condition,
+            true_block,
+            false_block,
+        } => SeppoExpr::Conditional {
+            condition: boxed(condition)?,
+            true_block: boxed(true_block)?,
+            false_block: false_block.map(boxed).transpose()?,
+        },
+        SeppoExpr::For {
+            variable,
+            iterable,
+            body,
+        } => SeppoExpr::For {
+            variable,
+            iterable: boxed(iterable)?,
+            body: boxed(body)?,
+        },
+        other => other,
+    })
+}
+
+/// The contents of the file `include_str` names
+fn read(args: &[SeppoExpr], dir: &Path) -> Result<SeppoExpr> {
+    let [SeppoExpr::String(path)] = args else {
+        return Err(SeppoError::Sema(
+            "include_str takes the path of a file as a string literal".to_string(),
+        ));
+    };
+    // Untrusted programs could read any file of the machine compiling them
+    if no_extern() {
+        return Err(SeppoError::Sema(
+            "include_str is not allowed with --no-extern".to_string(),
+        ));
+    }
+    let file = dir.join(path);
+    let text = fs::read_to_string(&file).map_err(|e| {
+        SeppoError::Sema(format!("include_str can't read {}: {}", file.display(), e))
+    })?;
+    Ok(SeppoExpr::String(text))
+}
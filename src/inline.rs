@@ -0,0 +1,289 @@
+use crate::error::{Result, SeppoError};
+use crate::types::{marked_functions, SeppoExpr, INLINE_ATTRIBUTE, NOINLINE_ATTRIBUTE};
+use std::collections::HashMap;
+
+/// Functions whose returned expression has at most this many nodes are
+/// inlined even without `inline fn`. `add(x, y * 2)` has five.
+const SIZE_LIMIT: usize = 5;
+
+/// Check that every `inline fn` can be inlined: its body must be a single
+/// `return` of an expression of its parameters that doesn't call the
+/// function itself.
+pub(crate) fn check_inline_functions(program: &SeppoExpr) -> Result<()> {
+    let SeppoExpr::Block(items) = program else {
+        return Ok(());
+    };
+    let marked = marked_functions(program, INLINE_ATTRIBUTE);
+    for item in items {
+        let SeppoExpr::Function(name, params, body) = item else {
+            continue;
+        };
+        if !marked.contains(name) {
+            continue;
+        }
+        let Some(value) = returned_expression(body).filter(|value| is_inlinable(value, params))
+        else {
+            return Err(SeppoError::Sema(format!(
+                "inline fn {} can't be inlined, its body must be a single return of an expression of its parameters",
+                name
+            )));
+        };
+        if calls(value, name) {
+            return Err(SeppoError::Sema(format!(
+                "inline fn {} can't be inlined, it calls itself",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Replace calls to small functions with the expression they return, so
+/// optimized builds don't pay for a call to a one-line helper. A function
+/// is small if it is declared `inline fn` or returns an expression of at
+/// most `SIZE_LIMIT` nodes. The functions stay defined for other callers,
+/// like ceppo blocks and `spawn`. Functions named in ceppo blocks aren't
+/// inlined, as the C declarations may take their place, and neither are
+/// `#[noinline]` ones.
+///
+/// Arguments are substituted for the parameters, so a call is only inlined
+/// if evaluating its arguments has no effects, and arguments of parameters
+/// used other than once must be literals or variables, so nothing is
+/// computed more or fewer times than the call would have.
+pub(crate) fn inline_functions(program: &SeppoExpr) -> SeppoExpr {
+    let SeppoExpr::Block(items) = program else {
+        return program.clone();
+    };
+    let marked = marked_functions(program, INLINE_ATTRIBUTE);
+    let noinline = marked_functions(program, NOINLINE_ATTRIBUTE);
+    let c_code: Vec<&str> = items
+        .iter()
+        .filter_map(|item| match item {
+            SeppoExpr::InlineC(code) => Some(code.as_str()),
+            _ => None,
+        })
+        .collect();
+    let candidates = items
+        .iter()
+        .filter_map(|item| match item {
+            SeppoExpr::Function(name, ..)
+                if noinline.contains(name)
+                    || c_code.iter().any(|code| code.contains(name.as_str())) =>
+            {
+                None
+            }
+            SeppoExpr::Function(name, params, body) => {
+                let value = returned_expression(body)?;
+                let small = marked.contains(name) || size(value) <= SIZE_LIMIT;
+                (small && is_inlinable(value, params))
+                    .then(|| (name.clone(), (params.clone(), value.clone())))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut inliner = Inliner {
+        candidates,
+        stack: Vec::new(),
+    };
+    SeppoExpr::Block(items.iter().map(|item| inliner.rewrite(item)).collect())
+}
+
+struct Inliner {
+    /// Parameters and returned expression of each function that is inlined
+    candidates: HashMap<String, (Vec<String>, SeppoExpr)>,
+    /// Functions being inlined, which aren't expanded again inside their
+    /// own bodies
+    stack: Vec<String>,
+}
+
+impl Inliner {
+    fn rewrite(&mut self, expr: &SeppoExpr) -> SeppoExpr {
+        match expr {
+            SeppoExpr::FunctionCall(name, args) => {
+                let args: Vec<SeppoExpr> = args.iter().map(|arg| self.rewrite(arg)).collect();
+                match self.expand(name, &args) {
+                    Some(inlined) => inlined,
+                    None => SeppoExpr::FunctionCall(name.clone(), args),
+                }
+            }
+            SeppoExpr::Become(name, args) => {
+                SeppoExpr::Become(name.clone(), args.iter().map(|a| self.rewrite(a)).collect())
+            }
+            SeppoExpr::Function(name, params, body) => {
+                SeppoExpr::Function(name.clone(), params.clone(), Box::new(self.rewrite(body)))
+            }
+            SeppoExpr::Block(statements) => {
+                SeppoExpr::Block(statements.iter().map(|s| self.rewrite(s)).collect())
+            }
+            SeppoExpr::Operation(op, left, right) => SeppoExpr::Operation(
+                op.clone(),
+                Box::new(self.rewrite(left)),
+                Box::new(self.rewrite(right)),
+            ),
+            SeppoExpr::Assignment(name, value) => {
+                SeppoExpr::Assignment(name.clone(), Box::new(self.rewrite(value)))
+            }
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } => SeppoExpr::Let {
+                name: name.clone(),
+                mutable: *mutable,
+                value: Box::new(self.rewrite(value)),
+            },
+            SeppoExpr::Print(format, value) => {
+                SeppoExpr::Print(format.clone(), Box::new(self.rewrite(value)))
+            }
+            SeppoExpr::Return(value) => SeppoExpr::Return(Box::new(self.rewrite(value))),
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => SeppoExpr::Conditional {
+                condition: Box::new(self.rewrite(condition)),
+                true_block: Box::new(self.rewrite(true_block)),
+                false_block: false_block
+                    .as_ref()
+                    .map(|block| Box::new(self.rewrite(block))),
+            },
+            SeppoExpr::Range {
+                start,
+                end,
+                inclusive,
+            } => SeppoExpr::Range {
+                start: Box::new(self.rewrite(start)),
+                end: Box::new(self.rewrite(end)),
+                inclusive: *inclusive,
+            },
+            SeppoExpr::In(value, range) => {
+                SeppoExpr::In(Box::new(self.rewrite(value)), Box::new(self.rewrite(range)))
+            }
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => SeppoExpr::For {
+                variable: variable.clone(),
+                iterable: Box::new(self.rewrite(iterable)),
+                body: Box::new(self.rewrite(body)),
+            },
+            other => other.clone(),
+        }
+    }
+
+    /// The body of `name` with `args` for its parameters, if the call can
+    /// be inlined
+    fn expand(&mut self, name: &str, args: &[SeppoExpr]) -> Option<SeppoExpr> {
+        if self.stack.iter().any(|active| active == name) {
+            return None;
+        }
+        let (params, value) = self.candidates.get(name)?;
+        if params.len() != args.len() || !args.iter().all(is_pure) {
+            return None;
+        }
+        for (param, arg) in params.iter().zip(args) {
+            if uses(value, param) != 1 && !is_atom(arg) {
+                return None;
+            }
+        }
+        let bindings: HashMap<&str, &SeppoExpr> =
+            params.iter().map(String::as_str).zip(args).collect();
+        let substituted = substitute(value, &bindings);
+        self.stack.push(name.to_string());
+        let inlined = self.rewrite(&substituted);
+        self.stack.pop();
+        Some(inlined)
+    }
+}
+
+/// The expression of a body that is a single `return`, ignoring the
+/// locations kept for coverage and debug info
+fn returned_expression(body: &SeppoExpr) -> Option<&SeppoExpr> {
+    let mut statements = match body {
+        SeppoExpr::Block(statements) => statements.as_slice(),
+        other => std::slice::from_ref(other),
+    }
+    .iter()
+    .filter(|s| !matches!(s, SeppoExpr::Location { .. }));
+    match (statements.next(), statements.next()) {
+        (Some(SeppoExpr::Return(value)), None) => Some(value),
+        _ => None,
+    }
+}
+
+/// Whether `expr` is built only from numbers, the parameters, arithmetic
+/// and calls, so it means the same in the caller
+fn is_inlinable(expr: &SeppoExpr, params: &[String]) -> bool {
+    match expr {
+        SeppoExpr::Number(_) => true,
+        SeppoExpr::Variable(name) => params.contains(name),
+        SeppoExpr::Operation(_, left, right) => {
+            is_inlinable(left, params) && is_inlinable(right, params)
+        }
+        SeppoExpr::FunctionCall(_, args) => args.iter().all(|arg| is_inlinable(arg, params)),
+        _ => false,
+    }
+}
+
+/// Whether evaluating `expr` can have effects, which calls may
+fn is_pure(expr: &SeppoExpr) -> bool {
+    match expr {
+        SeppoExpr::Number(_) | SeppoExpr::String(_) | SeppoExpr::Variable(_) => true,
+        SeppoExpr::Operation(_, left, right) => is_pure(left) && is_pure(right),
+        _ => false,
+    }
+}
+
+fn is_atom(expr: &SeppoExpr) -> bool {
+    matches!(
+        expr,
+        SeppoExpr::Number(_) | SeppoExpr::String(_) | SeppoExpr::Variable(_)
+    )
+}
+
+fn size(expr: &SeppoExpr) -> usize {
+    match expr {
+        SeppoExpr::Operation(_, left, right) => 1 + size(left) + size(right),
+        SeppoExpr::FunctionCall(_, args) => 1 + args.iter().map(size).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+/// How many times an expression accepted by `is_inlinable` reads `param`
+fn uses(expr: &SeppoExpr, param: &str) -> usize {
+    match expr {
+        SeppoExpr::Variable(name) => usize::from(name == param),
+        SeppoExpr::Operation(_, left, right) => uses(left, param) + uses(right, param),
+        SeppoExpr::FunctionCall(_, args) => args.iter().map(|arg| uses(arg, param)).sum(),
+        _ => 0,
+    }
+}
+
+fn calls(expr: &SeppoExpr, function: &str) -> bool {
+    match expr {
+        SeppoExpr::Operation(_, left, right) => calls(left, function) || calls(right, function),
+        SeppoExpr::FunctionCall(name, args) => {
+            name == function || args.iter().any(|arg| calls(arg, function))
+        }
+        _ => false,
+    }
+}
+
+fn substitute(expr: &SeppoExpr, bindings: &HashMap<&str, &SeppoExpr>) -> SeppoExpr {
+    match expr {
+        SeppoExpr::Variable(name) => bindings
+            .get(name.as_str())
+            .map_or_else(|| expr.clone(), |arg| (*arg).clone()),
+        SeppoExpr::Operation(op, left, right) => SeppoExpr::Operation(
+            op.clone(),
+            Box::new(substitute(left, bindings)),
+            Box::new(substitute(right, bindings)),
+        ),
+        SeppoExpr::FunctionCall(name, args) => SeppoExpr::FunctionCall(
+            name.clone(),
+            args.iter().map(|arg| substitute(arg, bindings)).collect(),
+        ),
+        other => other.clone(),
+    }
+}
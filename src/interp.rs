@@ -0,0 +1,955 @@
+use crate::builtins;
+use crate::codegen::{DivisionOverflow, DivisionRounding};
+use crate::engine::HostFn;
+use crate::error::{Result, SeppoError};
+use crate::sema::{check_entry, undefined};
+use crate::types::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Recursion limit for seppo calls, so runaway recursion fails cleanly
+/// instead of overflowing the interpreter's own stack
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// Evaluate a parsed program by calling its `seppo` function, returning the
+/// value the compiled binary would exit with
+pub fn eval(program: &SeppoExpr) -> Result<i64> {
+    Interpreter::new().run(program)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Str(Rc<str>),
+    /// `Some(v)` or `None`
+    Option(Option<Rc<Value>>),
+    /// An `i64x4`
+    Vector([i64; 4]),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Result<i64> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            Value::Str(s) => Err(SeppoError::Runtime(format!(
+                "Expected a number, found string {:?}",
+                s
+            ))),
+            Value::Option(_) => Err(SeppoError::Runtime(
+                "Expected a number, found an Option, unwrap it first".to_string(),
+            )),
+            Value::Vector(_) => Err(SeppoError::Runtime(
+                "Expected a number, found an i64x4, take an element with lane".to_string(),
+            )),
+        }
+    }
+}
+
+/// As the REPL shows values: strings quoted, Options as written
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Option(Some(value)) => write!(f, "Some({})", value),
+            Value::Option(None) => write!(f, "{}", builtins::NONE),
+            Value::Vector([a, b, c, d]) => write!(f, "i64x4({}, {}, {}, {})", a, b, c, d),
+        }
+    }
+}
+
+/// How evaluation of a statement finished
+enum Flow {
+    Next(Value),
+    Return(Value),
+    /// `become`, calling the current function again with new arguments
+    TailCall(String, Vec<Value>),
+}
+
+#[derive(Clone)]
+struct Function {
+    params: Vec<String>,
+    body: SeppoExpr,
+}
+
+pub struct Interpreter {
+    functions: HashMap<String, Function>,
+    /// Native functions registered through `Engine`
+    host: HashMap<String, Arc<HostFn>>,
+    variables: HashMap<String, Value>,
+    /// Values of the globals of the loaded program
+    globals: HashMap<String, Value>,
+    /// The `threadlocal` globals, by the value a thread starts them at
+    thread_locals: HashMap<String, Value>,
+    output: Box<dyn Write>,
+    /// Where `eseppo` prints go
+    errors: Box<dyn Write>,
+    depth: usize,
+    /// Results of spawned functions by handle, taken by `join`
+    threads: Vec<Option<Value>>,
+    /// Whether each mutex, by handle, is locked
+    mutexes: Vec<bool>,
+    condvars: i64,
+    /// What `alloc` and `atomic` allocate
+    heap: Heap,
+    /// How `/` rounds and overflows, see `with_division`
+    division_rounding: DivisionRounding,
+    division_overflow: Option<DivisionOverflow>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// Create an interpreter that writes `seppo` prints to the given writer
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self {
+            functions: HashMap::new(),
+            host: HashMap::new(),
+            variables: HashMap::new(),
+            globals: HashMap::new(),
+            thread_locals: HashMap::new(),
+            output,
+            errors: Box::new(io::stderr()),
+            depth: 0,
+            threads: Vec::new(),
+            mutexes: Vec::new(),
+            condvars: 0,
+            heap: Heap::default(),
+            division_rounding: DivisionRounding::default(),
+            division_overflow: None,
+        }
+    }
+
+    /// Divide like a program built with `CodeGen::set_division`. Without
+    /// `DivisionOverflow::Trap`, `i64::MIN / -1` wraps around.
+    pub fn with_division(
+        mut self,
+        rounding: DivisionRounding,
+        overflow: Option<DivisionOverflow>,
+    ) -> Self {
+        self.division_rounding = rounding;
+        self.division_overflow = overflow;
+        self
+    }
+
+    /// Write `eseppo` prints to the given writer instead of standard error
+    pub fn with_error_output(mut self, errors: Box<dyn Write>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Register the functions and globals of a program without running
+    /// anything
+    pub fn load(&mut self, program: &SeppoExpr) -> Result<()> {
+        match program {
+            SeppoExpr::Block(items) => {
+                for global in globals(program)? {
+                    let value = Value::Int(global.value);
+                    if global.threadlocal {
+                        self.thread_locals
+                            .insert(global.name.clone(), value.clone());
+                    }
+                    self.globals.insert(global.name, value);
+                }
+                for item in items {
+                    self.load(item)?;
+                }
+                Ok(())
+            }
+            SeppoExpr::Function(name, params, body) => {
+                self.functions.insert(
+                    name.clone(),
+                    Function {
+                        params: params.clone(),
+                        body: (**body).clone(),
+                    },
+                );
+                Ok(())
+            }
+            SeppoExpr::InlineC(_) => Err(SeppoError::Sema(
+                "ceppo blocks are not supported by the interpreter".to_string(),
+            )),
+            // Loaded with the block they're in
+            SeppoExpr::Let { .. } => Ok(()),
+            SeppoExpr::Comment { .. }
+            | SeppoExpr::BlankLine
+            | SeppoExpr::DocComment(_)
+            | SeppoExpr::Attribute { .. }
+            | SeppoExpr::Signature(_)
+            | SeppoExpr::Location { .. } => Ok(()),
+            other => Err(SeppoError::Sema(format!(
+                "Unexpected top-level item: {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// Load a program and call its `seppo` function
+    pub fn run(&mut self, program: &SeppoExpr) -> Result<i64> {
+        check_entry(program)?;
+        self.load(program)?;
+        if !self.functions["seppo"].params.is_empty() {
+            return Err(SeppoError::Runtime(
+                "seppo(argc, argv) needs compiled code, the interpreter runs seppo without arguments"
+                    .to_string(),
+            ));
+        }
+        let result = self.call("seppo", Vec::new())?.as_int()?;
+        self.output.flush()?;
+        self.errors.flush()?;
+        Ok(result)
+    }
+
+    pub(crate) fn register_host_fn(&mut self, name: &str, function: Arc<HostFn>) {
+        self.host.insert(name.to_string(), function);
+    }
+
+    /// Evaluate a statement in the top-level scope, where the variables it
+    /// assigns stay defined for the statements that follow, as in a REPL
+    pub fn eval_statement(&mut self, statement: &SeppoExpr) -> Result<Value> {
+        match self.eval_flow(statement)? {
+            Flow::Next(value) | Flow::Return(value) => Ok(value),
+            Flow::TailCall(name, _) => Err(SeppoError::Sema(format!(
+                "become can only be used inside a function, not to call {}",
+                name
+            ))),
+        }
+    }
+
+    /// The variables of the top-level scope, sorted by name
+    pub fn variables(&self) -> Vec<(&str, &Value)> {
+        let mut variables: Vec<_> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        variables.sort_by_key(|(name, _)| *name);
+        variables
+    }
+
+    /// The names of the loaded functions, sorted
+    pub fn function_names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.functions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// `spawn(f, arg)` without threads: `f` runs to completion right away
+    /// and `join` gives its result. `threadlocal` globals start over for
+    /// `f`, and what it sets them to stays with it.
+    fn spawn(&mut self, args: &[SeppoExpr]) -> Result<Value> {
+        let function = builtins::spawned_function(args)?;
+        let arg = self.eval_expr(&args[1])?;
+        let mut spawner = self.thread_locals.clone();
+        for (name, value) in &mut spawner {
+            std::mem::swap(value, self.globals.get_mut(name).unwrap());
+        }
+        let result = self.call(function, vec![arg]);
+        self.globals.extend(spawner);
+        self.threads.push(Some(result?));
+        Ok(Value::Int(self.threads.len() as i64 - 1))
+    }
+
+    /// The thread builtins other than `spawn`, without threads. Nothing
+    /// else runs while a function waits, so locking a locked mutex and
+    /// waiting on a condition variable fail instead of waiting forever.
+    fn call_thread_builtin(&mut self, name: &str, args: &[Value]) -> Result<Value> {
+        builtins::check_arity(name, args.len())?;
+        match name {
+            "spawn" => Err(SeppoError::Sema(builtins::SPAWN_NEEDS_FUNCTION.to_string())),
+            "join" => {
+                let handle = args[0].as_int()?;
+                usize::try_from(handle)
+                    .ok()
+                    .and_then(|index| self.threads.get_mut(index)?.take())
+                    .ok_or_else(|| {
+                        SeppoError::Runtime(format!("No thread to join with handle {}", handle))
+                    })
+            }
+            "mutex" => {
+                self.mutexes.push(false);
+                Ok(Value::Int(self.mutexes.len() as i64 - 1))
+            }
+            "lock" | "unlock" => {
+                let handle = args[0].as_int()?;
+                let lock = name == "lock";
+                let locked = usize::try_from(handle)
+                    .ok()
+                    .and_then(|index| self.mutexes.get_mut(index))
+                    .ok_or_else(|| {
+                        SeppoError::Runtime(format!("{}: {} is not a mutex", name, handle))
+                    })?;
+                if *locked == lock {
+                    let state = if lock {
+                        "already locked, which without threads would wait forever"
+                    } else {
+                        "not locked"
+                    };
+                    return Err(SeppoError::Runtime(format!(
+                        "{}: mutex {} is {}",
+                        name, handle, state
+                    )));
+                }
+                *locked = lock;
+                Ok(Value::Int(0))
+            }
+            "condvar" => {
+                self.condvars += 1;
+                Ok(Value::Int(self.condvars - 1))
+            }
+            "wait" => Err(SeppoError::Runtime(
+                "wait: without threads nothing can notify, so this would wait forever".to_string(),
+            )),
+            "notify" => Ok(Value::Int(0)),
+            "sleep_ms" => {
+                let millis = args[0].as_int()?.max(0) as u64;
+                std::thread::sleep(std::time::Duration::from_millis(millis));
+                Ok(Value::Int(0))
+            }
+            "yield" => {
+                std::thread::yield_now();
+                Ok(Value::Int(0))
+            }
+            _ => unreachable!("not a thread builtin: {}", name),
+        }
+    }
+
+    /// `lhs / rhs`, rounded and wrapped as set by `with_division`
+    fn divide(&self, lhs: i64, rhs: i64) -> Result<i64> {
+        if rhs == 0 {
+            return Err(SeppoError::Runtime("Division by zero".to_string()));
+        }
+        if lhs == i64::MIN && rhs == -1 && self.division_overflow == Some(DivisionOverflow::Trap) {
+            return Err(SeppoError::Runtime("Division overflow".to_string()));
+        }
+        let quotient = lhs.wrapping_div(rhs);
+        let remainder = lhs.wrapping_rem(rhs);
+        if self.division_rounding == DivisionRounding::Floor
+            && remainder != 0
+            && (remainder < 0) != (rhs < 0)
+        {
+            return Ok(quotient - 1);
+        }
+        Ok(quotient)
+    }
+
+    /// The atomic and memory builtins, on the interpreter's own heap.
+    /// Addresses from C don't exist here.
+    fn call_memory_builtin(&mut self, name: &str, args: &[Value]) -> Result<Value> {
+        builtins::check_arity(name, args.len())?;
+        let args = args.iter().map(Value::as_int).collect::<Result<Vec<_>>>()?;
+        let heap = &mut self.heap;
+        let result = match name {
+            "alloc" => heap.alloc(args[0]),
+            "realloc" => heap.realloc(args[0], args[1])?,
+            "free" => {
+                heap.free(args[0])?;
+                0
+            }
+            "load" | "atomic_load" => heap.load(args[0], name)?,
+            "store" | "atomic_store" => {
+                heap.store(args[0], args[1], name)?;
+                0
+            }
+            "atomic" => {
+                let cell = heap.alloc(8);
+                heap.store(cell, args[0], name)?;
+                cell
+            }
+            "atomic_add" => {
+                let previous = heap.load(args[0], name)?;
+                heap.store(args[0], previous.wrapping_add(args[1]), name)?;
+                previous
+            }
+            "cas" => {
+                let previous = heap.load(args[0], name)?;
+                if previous == args[1] {
+                    heap.store(args[0], args[2], name)?;
+                }
+                previous
+            }
+            _ => unreachable!("not a memory builtin: {}", name),
+        };
+        Ok(Value::Int(result))
+    }
+
+    /// The vector builtins, loading and storing on the interpreter's heap
+    /// like the memory builtins
+    fn call_vector_builtin(&mut self, name: &str, args: &[Value]) -> Result<Value> {
+        builtins::check_arity(name, args.len())?;
+        let vector = |value: &Value| match value {
+            Value::Vector(lanes) => Ok(*lanes),
+            _ => Err(SeppoError::Runtime(format!("{} expects an i64x4", name))),
+        };
+        match name {
+            "i64x4" => {
+                let mut lanes = [0; 4];
+                for (lane, arg) in lanes.iter_mut().zip(args) {
+                    *lane = arg.as_int()?;
+                }
+                Ok(Value::Vector(lanes))
+            }
+            "lane" => {
+                let lanes = vector(&args[0])?;
+                let [i] = element_numbers(name, &args[1..])?[..] else {
+                    unreachable!("lane takes one element number")
+                };
+                Ok(Value::Int(lanes[i]))
+            }
+            "shuffle" => {
+                let (a, b) = (vector(&args[0])?, vector(&args[1])?);
+                let both: Vec<i64> = a.iter().chain(&b).copied().collect();
+                let mut lanes = [0; 4];
+                for (lane, i) in lanes.iter_mut().zip(element_numbers(name, &args[2..])?) {
+                    *lane = both[i];
+                }
+                Ok(Value::Vector(lanes))
+            }
+            "load_i64x4" => {
+                let address = args[0].as_int()?;
+                let mut lanes = [0; 4];
+                for (offset, lane) in (0..).step_by(8).zip(lanes.iter_mut()) {
+                    *lane = self.heap.load(address.wrapping_add(offset), name)?;
+                }
+                Ok(Value::Vector(lanes))
+            }
+            "store_i64x4" => {
+                let address = args[0].as_int()?;
+                for (offset, lane) in (0..).step_by(8).zip(vector(&args[1])?) {
+                    self.heap.store(address.wrapping_add(offset), lane, name)?;
+                }
+                Ok(Value::Int(0))
+            }
+            _ => unreachable!("not a vector builtin: {}", name),
+        }
+    }
+
+    /// Call a loaded function with already evaluated arguments
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let Some(function) = self.functions.get(name).cloned() else {
+            if let Some(host) = self.host.get(name) {
+                return call_host(name, host, &args);
+            }
+            match name {
+                _ if builtins::is_dynamic_builtin(name) => {
+                    return Err(SeppoError::Runtime(format!(
+                        "{} needs compiled code, the interpreter cannot load libraries",
+                        name
+                    )))
+                }
+                _ if builtins::is_thread_builtin(name) => {
+                    return self.call_thread_builtin(name, &args)
+                }
+                _ if builtins::is_atomic_builtin(name) || builtins::is_memory_builtin(name) => {
+                    return self.call_memory_builtin(name, &args)
+                }
+                _ if builtins::is_option_builtin(name) => return call_option_builtin(name, args),
+                _ if builtins::is_vector_builtin(name) => {
+                    return self.call_vector_builtin(name, &args)
+                }
+                _ if builtins::is_io_builtin(name) => {
+                    builtins::check_arity(name, args.len())?;
+                    self.output.flush()?;
+                    self.errors.flush()?;
+                    return Ok(Value::Int(0));
+                }
+                _ if builtins::is_builtin(name) => return call_builtin(name, &args),
+                _ => {}
+            }
+            let builtins = builtins::BUILTINS.iter().map(|(builtin, _)| *builtin);
+            let defined = self.functions.keys().chain(self.host.keys());
+            return Err(undefined(
+                "function",
+                name,
+                defined.map(String::as_str).chain(builtins),
+            ));
+        };
+
+        if function.params.len() != args.len() {
+            return Err(SeppoError::Sema(format!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                function.params.len(),
+                args.len()
+            )));
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(SeppoError::Runtime(format!(
+                "Call depth limit exceeded in {}",
+                name
+            )));
+        }
+
+        // Each function gets a fresh variable scope, like in codegen
+        let caller_vars = std::mem::take(&mut self.variables);
+        self.depth += 1;
+        let mut args = args;
+        let result = loop {
+            self.variables = function.params.iter().cloned().zip(args).collect();
+            match self.eval_flow(&function.body) {
+                // A tail call reruns the body without using up call depth
+                Ok(Flow::TailCall(callee, next_args)) if callee == name => {
+                    if next_args.len() != function.params.len() {
+                        break Err(SeppoError::Sema(format!(
+                            "Function {} expects {} arguments, got {}",
+                            name,
+                            function.params.len(),
+                            next_args.len()
+                        )));
+                    }
+                    args = next_args;
+                }
+                Ok(Flow::TailCall(callee, _)) => {
+                    break Err(SeppoError::Sema(format!(
+                        "become can only call the function it is in, not {}",
+                        callee
+                    )))
+                }
+                Ok(Flow::Return(value)) => break Ok(value),
+                // Functions without an explicit return yield 0
+                Ok(Flow::Next(_)) => break Ok(Value::Int(0)),
+                Err(e) => break Err(e),
+            }
+        };
+        self.depth -= 1;
+        self.variables = caller_vars;
+        result
+    }
+
+    fn eval_flow(&mut self, expr: &SeppoExpr) -> Result<Flow> {
+        match expr {
+            SeppoExpr::Return(value) => Ok(Flow::Return(self.eval_expr(value)?)),
+            SeppoExpr::Become(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval_expr(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Flow::TailCall(name.clone(), args))
+            }
+            SeppoExpr::Block(statements) => {
+                let mut last_value = Value::Int(0);
+                for statement in statements.iter().filter(|stmt| !stmt.is_trivia()) {
+                    match self.eval_flow(statement)? {
+                        Flow::Next(value) => last_value = value,
+                        flow => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Next(last_value))
+            }
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => {
+                let taken = self.eval_expr(condition)?.as_int()? != 0;
+
+                // Variables first assigned inside a branch don't outlive it
+                let entry_names: Vec<String> = self.variables.keys().cloned().collect();
+                let flow = if taken {
+                    self.eval_flow(true_block)?
+                } else if let Some(false_block) = false_block {
+                    self.eval_flow(false_block)?
+                } else {
+                    Flow::Next(Value::Int(0))
+                };
+                self.variables.retain(|name, _| entry_names.contains(name));
+
+                Ok(flow)
+            }
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                let elements: Vec<i64> = match iterable.as_ref() {
+                    SeppoExpr::Range {
+                        start,
+                        end,
+                        inclusive,
+                    } => {
+                        let start = self.eval_expr(start)?.as_int()?;
+                        let end = self.eval_expr(end)?.as_int()?;
+                        if *inclusive {
+                            (start..=end).collect()
+                        } else {
+                            (start..end).collect()
+                        }
+                    }
+                    other => match self.eval_expr(other)? {
+                        Value::Str(string) => string.bytes().map(i64::from).collect(),
+                        Value::Int(_) | Value::Option(_) | Value::Vector(_) => {
+                            return Err(SeppoError::Runtime(
+                                "for loops can only iterate over strings and ranges".to_string(),
+                            ))
+                        }
+                    },
+                };
+
+                // The loop variable and variables first assigned in the body
+                // don't outlive the loop
+                let entry_names: Vec<String> = self.variables.keys().cloned().collect();
+                let mut flow = Flow::Next(Value::Int(0));
+                for element in elements {
+                    self.variables.insert(variable.clone(), Value::Int(element));
+                    match self.eval_flow(body)? {
+                        Flow::Next(_) => {}
+                        exit => {
+                            flow = exit;
+                            break;
+                        }
+                    }
+                }
+                self.variables.retain(|name, _| entry_names.contains(name));
+
+                Ok(flow)
+            }
+            other => Ok(Flow::Next(self.eval_expr(other)?)),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &SeppoExpr) -> Result<Value> {
+        match expr {
+            SeppoExpr::Number(n) => Ok(Value::Int(*n)),
+            SeppoExpr::String(s) => Ok(Value::Str(Rc::from(s.as_str()))),
+            SeppoExpr::Variable(name)
+                if name == builtins::NONE && !self.variables.contains_key(name) =>
+            {
+                Ok(Value::Option(None))
+            }
+            SeppoExpr::Variable(name) => self
+                .variables
+                .get(name)
+                .or_else(|| self.globals.get(name))
+                .cloned()
+                .ok_or_else(|| {
+                    let defined = self.variables.keys().chain(self.globals.keys());
+                    undefined("variable", name, defined.map(String::as_str))
+                }),
+            SeppoExpr::Assignment(name, value) | SeppoExpr::Let { name, value, .. } => {
+                let value = self.eval_expr(value)?;
+                match self.globals.get_mut(name) {
+                    Some(global) if !self.variables.contains_key(name) => *global = value.clone(),
+                    _ => {
+                        self.variables.insert(name.clone(), value.clone());
+                    }
+                }
+                Ok(value)
+            }
+            SeppoExpr::Operation(op, left, right) if is_logical(op) => {
+                let lhs = self.eval_expr(left)?.as_int()? != 0;
+                // The right side only runs when the left one doesn't decide
+                let result = if lhs == (op == "||") {
+                    lhs
+                } else {
+                    self.eval_expr(right)?.as_int()? != 0
+                };
+                Ok(Value::Int(result as i64))
+            }
+            SeppoExpr::Operation(op, left, right) => {
+                let (lhs, rhs) = match (self.eval_expr(left)?, self.eval_expr(right)?) {
+                    (Value::Vector(lhs), Value::Vector(rhs)) => {
+                        return vector_arithmetic(op, lhs, rhs)
+                    }
+                    (lhs, rhs) => (lhs.as_int()?, rhs.as_int()?),
+                };
+                let result = match op.as_str() {
+                    "+" => lhs.wrapping_add(rhs),
+                    "-" => lhs.wrapping_sub(rhs),
+                    "*" => lhs.wrapping_mul(rhs),
+                    "/" => self.divide(lhs, rhs)?,
+                    ">" => (lhs > rhs) as i64,
+                    "<" => (lhs < rhs) as i64,
+                    ">=" => (lhs >= rhs) as i64,
+                    "<=" => (lhs <= rhs) as i64,
+                    "==" => (lhs == rhs) as i64,
+                    "!=" => (lhs != rhs) as i64,
+                    op => return Err(SeppoError::Sema(format!("Unknown operator: {}", op))),
+                };
+                Ok(Value::Int(result))
+            }
+            SeppoExpr::Print(format, value) => {
+                let value = self.eval_expr(value)?;
+                let n = value.as_int()?;
+                match format {
+                    PrintFormat::Decimal => writeln!(self.output, "{}", n)?,
+                    PrintFormat::Hex => writeln!(self.output, "0x{:x}", n)?,
+                    PrintFormat::Bool => writeln!(self.output, "{}", n != 0)?,
+                    PrintFormat::Char => {
+                        let c = u32::try_from(n)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .unwrap_or(char::REPLACEMENT_CHARACTER);
+                        writeln!(self.output, "{}", c)?
+                    }
+                    PrintFormat::Stderr => writeln!(self.errors, "{}", n)?,
+                }
+                Ok(value)
+            }
+            SeppoExpr::In(value, range) => {
+                let SeppoExpr::Range {
+                    start,
+                    end,
+                    inclusive,
+                } = range.as_ref()
+                else {
+                    return Err(SeppoError::Sema(
+                        "The right side of `in` must be a range".to_string(),
+                    ));
+                };
+                let value = self.eval_expr(value)?.as_int()?;
+                let start = self.eval_expr(start)?.as_int()?;
+                let end = self.eval_expr(end)?.as_int()?;
+                let inside = value >= start && (value < end || *inclusive && value == end);
+                Ok(Value::Int(inside as i64))
+            }
+            SeppoExpr::Range { .. } => Err(SeppoError::Sema(RANGE_OUTSIDE_LOOP.to_string())),
+            SeppoExpr::FunctionCall(name, args)
+                if name == "spawn"
+                    && !self.functions.contains_key(name)
+                    && !self.host.contains_key(name) =>
+            {
+                self.spawn(args)
+            }
+            SeppoExpr::FunctionCall(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval_expr(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.call(name, args)
+            }
+            SeppoExpr::Block(_)
+            | SeppoExpr::Conditional { .. }
+            | SeppoExpr::For { .. }
+            | SeppoExpr::Return(_)
+            | SeppoExpr::Become(..) => match self.eval_flow(expr)? {
+                Flow::Next(value) => Ok(value),
+                Flow::Return(_) | Flow::TailCall(..) => Err(SeppoError::Sema(
+                    "Return statement inside an expression".to_string(),
+                )),
+            },
+            SeppoExpr::Function(..) => Err(SeppoError::Sema(
+                "Nested function definitions are not supported".to_string(),
+            )),
+            SeppoExpr::Macro(name, ..) => {
+                Err(SeppoError::Sema(format!("Macro {} was not expanded", name)))
+            }
+            SeppoExpr::ConstFunction(name, ..) => Err(SeppoError::Sema(format!(
+                "const fn {} was not folded",
+                name
+            ))),
+            SeppoExpr::InlineC(_) => Err(SeppoError::Sema(
+                "ceppo blocks are not supported by the interpreter".to_string(),
+            )),
+            SeppoExpr::Comment { .. }
+            | SeppoExpr::BlankLine
+            | SeppoExpr::DocComment(_)
+            | SeppoExpr::Attribute { .. }
+            | SeppoExpr::Signature(_)
+            | SeppoExpr::Location { .. } => Ok(Value::Int(0)),
+        }
+    }
+}
+
+fn call_host(name: &str, function: &HostFn, args: &[Value]) -> Result<Value> {
+    if function.params() != args.len() {
+        return Err(SeppoError::Sema(format!(
+            "Function {} expects {} arguments, got {}",
+            name,
+            function.params(),
+            args.len()
+        )));
+    }
+    let args = args.iter().map(Value::as_int).collect::<Result<Vec<_>>>()?;
+    Ok(Value::Int(function.call(&args)))
+}
+
+/// `Some` and the builtins taking an Option
+fn call_option_builtin(name: &str, mut args: Vec<Value>) -> Result<Value> {
+    builtins::check_arity(name, args.len())?;
+    if name == "Some" {
+        return Ok(Value::Option(Some(Rc::new(args.remove(0)))));
+    }
+    let Value::Option(option) = args.remove(0) else {
+        return Err(SeppoError::Runtime(format!("{} expects an Option", name)));
+    };
+    match name {
+        "unwrap" => option
+            .map(|value| value.as_ref().clone())
+            .ok_or_else(|| SeppoError::Runtime("called unwrap on None".to_string())),
+        "unwrap_or" => Ok(option.map_or_else(|| args.remove(0), |value| value.as_ref().clone())),
+        "is_some" => Ok(Value::Int(option.is_some() as i64)),
+        "is_none" => Ok(Value::Int(option.is_none() as i64)),
+        _ => unreachable!("not an Option builtin: {}", name),
+    }
+}
+
+/// Element-wise `+`, `-` and `*` of two vectors, wrapping like integers
+fn vector_arithmetic(op: &str, lhs: [i64; 4], rhs: [i64; 4]) -> Result<Value> {
+    let apply: fn(i64, i64) -> i64 = match op {
+        "+" => i64::wrapping_add,
+        "-" => i64::wrapping_sub,
+        "*" => i64::wrapping_mul,
+        op => {
+            return Err(SeppoError::Runtime(format!(
+                "i64x4 supports +, - and *, not {}",
+                op
+            )))
+        }
+    };
+    let mut lanes = [0; 4];
+    for (lane, (a, b)) in lanes.iter_mut().zip(lhs.into_iter().zip(rhs)) {
+        *lane = apply(a, b);
+    }
+    Ok(Value::Vector(lanes))
+}
+
+/// The element numbers `lane` and `shuffle` take, as the compiled code
+/// requires them to be: in range
+fn element_numbers(name: &str, args: &[Value]) -> Result<Vec<usize>> {
+    let lanes = builtins::LANES as usize;
+    let limit = if name == "lane" { lanes } else { lanes * 2 };
+    args.iter()
+        .map(|arg| {
+            let n = arg.as_int()?;
+            usize::try_from(n)
+                .ok()
+                .filter(|n| *n < limit)
+                .ok_or_else(|| {
+                    SeppoError::Runtime(format!(
+                        "{} takes element numbers from 0 to {}, got {}",
+                        name,
+                        limit - 1,
+                        n
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// The string builtins, working on bytes like the compiled versions
+fn call_builtin(name: &str, args: &[Value]) -> Result<Value> {
+    builtins::check_arity(name, args.len())?;
+    let Value::Str(string) = &args[0] else {
+        return Err(SeppoError::Runtime(format!(
+            "{} expects a string as its first argument",
+            name
+        )));
+    };
+    let bytes = string.as_bytes();
+    let clamp = |value: i64, max: usize| (value.max(0) as usize).min(max);
+
+    match name {
+        "len" => Ok(Value::Int(bytes.len() as i64)),
+        "char_at" => {
+            let index = args[1].as_int()?;
+            let byte = usize::try_from(index)
+                .ok()
+                .and_then(|index| bytes.get(index))
+                .map_or(-1, |byte| *byte as i64);
+            Ok(Value::Int(byte))
+        }
+        "substr" => {
+            let start = clamp(args[1].as_int()?, bytes.len());
+            let count = clamp(args[2].as_int()?, bytes.len() - start);
+            let slice = String::from_utf8_lossy(&bytes[start..start + count]);
+            Ok(Value::Str(Rc::from(slice.as_ref())))
+        }
+        _ => unreachable!("not a builtin: {}", name),
+    }
+}
+
+/// Memory for `alloc` and `atomic` at made-up addresses, which are never
+/// 0 and leave gaps between allocations, so that going past the end of
+/// one is an error rather than a read of the next
+#[derive(Debug, Default)]
+struct Heap {
+    /// Allocations by start address
+    blocks: BTreeMap<i64, Vec<u8>>,
+    next: i64,
+}
+
+impl Heap {
+    const START: i64 = 0x10000;
+    /// Larger allocations fail like malloc does, giving 0
+    const MAX_ALLOC: i64 = 1 << 30;
+    const GAP: i64 = 16;
+
+    fn alloc(&mut self, size: i64) -> i64 {
+        if !(0..=Self::MAX_ALLOC).contains(&size) {
+            return 0;
+        }
+        let start = self.next.max(Self::START);
+        self.next = start + (size + Self::GAP - 1) / Self::GAP * Self::GAP + Self::GAP;
+        self.blocks.insert(start, vec![0; size as usize]);
+        start
+    }
+
+    fn free(&mut self, address: i64) -> Result<()> {
+        if address != 0 && self.blocks.remove(&address).is_none() {
+            return Err(SeppoError::Runtime(format!(
+                "free: {:#x} is not the start of an allocation",
+                address
+            )));
+        }
+        Ok(())
+    }
+
+    fn realloc(&mut self, address: i64, size: i64) -> Result<i64> {
+        if address == 0 {
+            return Ok(self.alloc(size));
+        }
+        let Some(old) = self.blocks.get(&address).cloned() else {
+            return Err(SeppoError::Runtime(format!(
+                "realloc: {:#x} is not the start of an allocation",
+                address
+            )));
+        };
+        let new = self.alloc(size);
+        if new != 0 {
+            let block = self.blocks.get_mut(&new).unwrap();
+            let kept = old.len().min(block.len());
+            block[..kept].copy_from_slice(&old[..kept]);
+            self.blocks.remove(&address);
+        }
+        Ok(new)
+    }
+
+    /// The 8 bytes at `address`, which must be inside one allocation
+    fn word(&mut self, address: i64, builtin: &str) -> Result<&mut [u8]> {
+        self.blocks
+            .range_mut(..=address)
+            .next_back()
+            .and_then(|(start, block)| {
+                let offset = usize::try_from(address - start).ok()?;
+                block.get_mut(offset..offset.checked_add(8)?)
+            })
+            .ok_or_else(|| {
+                SeppoError::Runtime(format!(
+                    "{}: {:#x} is not inside an allocation",
+                    builtin, address
+                ))
+            })
+    }
+
+    fn load(&mut self, address: i64, builtin: &str) -> Result<i64> {
+        let word = self.word(address, builtin)?;
+        Ok(i64::from_le_bytes(word.try_into().unwrap()))
+    }
+
+    fn store(&mut self, address: i64, value: i64, builtin: &str) -> Result<()> {
+        self.word(address, builtin)?
+            .copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,663 @@
+use crate::builtins::{is_builtin, is_option_builtin, is_vector_builtin, lanes, NONE};
+use crate::error::{Result, SeppoError};
+use crate::types::{
+    declared_signatures, globals, is_comparison, is_logical, SeppoExpr, SeppoType, Signature,
+};
+use std::collections::{HashMap, HashSet};
+
+/// What `typeof` gives for integers
+const TYPE_INT: i64 = 0;
+/// What `typeof` gives for strings
+const TYPE_STRING: i64 = 1;
+/// What `typeof` gives for the result of a condition
+const TYPE_BOOL: i64 = 2;
+/// What `typeof` gives for `Some(v)` and `None`
+const TYPE_OPTION: i64 = 3;
+/// What `typeof` gives for an `i64x4`
+const TYPE_VECTOR: i64 = 4;
+
+/// The sizes `sizeof` knows, in bytes. Strings and pointers are addresses
+/// and integers are 64-bit, like in C on the targets we build for.
+const TYPE_SIZES: &[(&str, i64)] = &[
+    ("i8", 1),
+    ("u8", 1),
+    ("i16", 2),
+    ("u16", 2),
+    ("i32", 4),
+    ("u32", 4),
+    ("i64", 8),
+    ("u64", 8),
+    ("int", 8),
+    ("ptr", 8),
+    ("string", 8),
+    ("i64x4", 32),
+];
+
+/// Replace `sizeof(type)` with the size of the type and `typeof(x)` with
+/// one of the `TYPE_` constants, all known at compile time, so neither
+/// reaches the backends.
+///
+/// The type of a variable is that of the values assigned to it before the
+/// `typeof`. Parameters and the results of function calls can hold either,
+/// unless the function's signature declares their types, so asking for
+/// their type is an error, as is asking for a variable that has held both.
+/// A program defining a function of the same name calls it instead.
+///
+/// The same types check signatures: the arguments of calls, the values
+/// assigned to parameters and those a function returns must have the
+/// declared type when it is known, and a function returning a string
+/// can't end without a return, which would give 0.
+///
+/// Conditions are bools: a variable holding one stays a bool, the only
+/// kind a bare variable may have where a condition is expected, and may
+/// not be given a value of another kind.
+///
+/// Options and vectors are kept apart the same way, and have to be
+/// unwrapped or taken apart with `lane` where other values are used: only
+/// variables, returns, parameters declared with their type and their own
+/// builtins take them as they are, and vectors the arithmetic between two
+/// of them. A conditional used as a value gives one when both of its
+/// branches do. Globals start as integers.
+pub(crate) fn resolve_introspection(program: SeppoExpr) -> Result<SeppoExpr> {
+    let signatures = declared_signatures(&program);
+    let globals: HashMap<String, Kind> = globals(&program)?
+        .into_iter()
+        .map(|global| (global.name, Kind::Int))
+        .collect();
+    let SeppoExpr::Block(items) = program else {
+        return Ok(program);
+    };
+    let defines = |builtin: &str| {
+        items
+            .iter()
+            .any(|item| matches!(item, SeppoExpr::Function(name, ..) if name == builtin))
+    };
+    let mut resolver = Resolver {
+        sizeof: !defines("sizeof"),
+        typeof_: !defines("typeof"),
+        defined: items
+            .iter()
+            .filter_map(|item| match item {
+                SeppoExpr::Function(name, ..) => Some(name.clone()),
+                _ => None,
+            })
+            .collect(),
+        kinds: HashMap::new(),
+        signatures,
+        function: String::new(),
+        declared: HashMap::new(),
+    };
+    let items = items
+        .into_iter()
+        .map(|item| match item {
+            SeppoExpr::Function(name, params, body) => {
+                let signature = resolver.signature(&name);
+                resolver.declared = params
+                    .iter()
+                    .zip(&signature.params)
+                    .filter_map(|(param, ty)| Some((param.clone(), (*ty)?)))
+                    .collect();
+                resolver.kinds = globals.clone();
+                resolver.kinds.extend(params.iter().map(|param| {
+                    let kind = resolver.declared.get(param).map_or(Kind::Unknown, Kind::of);
+                    (param.clone(), kind)
+                }));
+                resolver.function = name.clone();
+                let body = resolver.resolve(*body)?;
+                // Neither a string nor an Option can be 0
+                if let Some(ty @ (SeppoType::String | SeppoType::Option)) = signature.returns {
+                    if !always_returns(&body) {
+                        return Err(SeppoError::Sema(format!(
+                            "Function {} should return {}, but can end without a return, which gives 0",
+                            name,
+                            Kind::of(&ty).describe()
+                        )));
+                    }
+                }
+                Ok(SeppoExpr::Function(name, params, Box::new(body)))
+            }
+            other => Ok(other),
+        })
+        .collect::<Result<_>>()?;
+    Ok(SeppoExpr::Block(items))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Str,
+    /// The result of a condition
+    Bool,
+    /// `Some(v)` or `None`
+    Option,
+    /// An `i64x4`
+    Vector,
+    /// Either, depending on what happens at runtime
+    Unknown,
+}
+
+impl Kind {
+    fn of(ty: &SeppoType) -> Kind {
+        match ty {
+            SeppoType::Int => Kind::Int,
+            SeppoType::String => Kind::Str,
+            SeppoType::Option => Kind::Option,
+            SeppoType::I64x4 => Kind::Vector,
+        }
+    }
+
+    /// The type values of this kind are declared with, for those the
+    /// backends pass differently from integers, so it has to be known
+    fn declared(self) -> Option<SeppoType> {
+        match self {
+            Kind::Option => Some(SeppoType::Option),
+            Kind::Vector => Some(SeppoType::I64x4),
+            _ => None,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::Int => "an integer",
+            Kind::Str => "a string",
+            Kind::Bool => "a bool",
+            Kind::Option => "an Option",
+            Kind::Vector => "an i64x4",
+            Kind::Unknown => "an integer or a string",
+        }
+    }
+}
+
+struct Resolver {
+    /// Whether `sizeof` and `typeof` are the builtins rather than functions
+    /// of the program
+    sizeof: bool,
+    typeof_: bool,
+    /// The functions of the program, which take the place of builtins of
+    /// the same name
+    defined: HashSet<String>,
+    /// What the variables of the current function hold so far
+    kinds: HashMap<String, Kind>,
+    /// Of the functions that declare types
+    signatures: HashMap<String, Signature>,
+    /// The function being resolved and the types of its parameters that
+    /// have one
+    function: String,
+    declared: HashMap<String, SeppoType>,
+}
+
+impl Resolver {
+    fn resolve(&mut self, expr: SeppoExpr) -> Result<SeppoExpr> {
+        Ok(match expr {
+            SeppoExpr::FunctionCall(name, args) if name == "sizeof" && self.sizeof => {
+                SeppoExpr::Number(size_of(&args)?)
+            }
+            SeppoExpr::FunctionCall(name, args) if name == "typeof" && self.typeof_ => {
+                SeppoExpr::Number(self.type_of(&args)?)
+            }
+            SeppoExpr::FunctionCall(name, args) => {
+                let args = self.arguments(&name, args)?;
+                SeppoExpr::FunctionCall(name, args)
+            }
+            SeppoExpr::Become(name, args) => {
+                let args = self.arguments(&name, args)?;
+                SeppoExpr::Become(name, args)
+            }
+            SeppoExpr::Block(statements) => SeppoExpr::Block(self.all(statements)?),
+            SeppoExpr::Operation(op, left, right) if is_logical(&op) => {
+                SeppoExpr::Operation(op, self.condition(*left)?, self.condition(*right)?)
+            }
+            SeppoExpr::Operation(op, left, right) => {
+                let (left, right) = self.operands(&op, *left, *right)?;
+                SeppoExpr::Operation(op, left, right)
+            }
+            SeppoExpr::Range {
+                start,
+                end,
+                inclusive,
+            } => SeppoExpr::Range {
+                start: self.value(*start)?,
+                end: self.value(*end)?,
+                inclusive,
+            },
+            SeppoExpr::In(value, range) => {
+                SeppoExpr::In(self.value(*value)?, Box::new(self.resolve(*range)?))
+            }
+            SeppoExpr::Assignment(name, value) => {
+                let value = self.assign(&name, *value)?;
+                SeppoExpr::Assignment(name, value)
+            }
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } => {
+                let value = self.assign(&name, *value)?;
+                SeppoExpr::Let {
+                    name,
+                    mutable,
+                    value,
+                }
+            }
+            SeppoExpr::Print(format, value) => SeppoExpr::Print(format, self.value(*value)?),
+            SeppoExpr::Return(value) => SeppoExpr::Return(self.returned(*value)?),
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => SeppoExpr::Conditional {
+                condition: self.condition(*condition)?,
+                true_block: Box::new(self.resolve(*true_block)?),
+                false_block: false_block
+                    .map(|block| self.resolve(*block).map(Box::new))
+                    .transpose()?,
+            },
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                let iterable = self.value(*iterable)?;
+                // Both bytes of strings and numbers of ranges are integers
+                self.kinds.insert(variable.clone(), Kind::Int);
+                SeppoExpr::For {
+                    variable,
+                    iterable,
+                    body: Box::new(self.resolve(*body)?),
+                }
+            }
+            other => other,
+        })
+    }
+
+    fn all(&mut self, exprs: Vec<SeppoExpr>) -> Result<Vec<SeppoExpr>> {
+        exprs.into_iter().map(|expr| self.resolve(expr)).collect()
+    }
+
+    // Checks are done outside `resolve`, which recurses once per level of
+    // nesting and so should keep its stack frame small
+
+    /// Resolve the value assigned to `name` and record its kind
+    fn assign(&mut self, name: &str, value: SeppoExpr) -> Result<Box<SeppoExpr>> {
+        if name == NONE && !self.kinds.contains_key(name) {
+            return Err(SeppoError::Sema(
+                "None is the empty Option and can't be assigned".to_string(),
+            ));
+        }
+        let value = self.resolve(value)?;
+        self.chosen(&value)?;
+        if let Some(&ty) = self.declared.get(name) {
+            let owner = || format!("Parameter {} of {} should hold", name, self.function);
+            self.check(&value, ty, owner)?;
+        }
+        let kind = self.kind_of(&value);
+        let merged = match self.kinds.get(name) {
+            // Bools, Options and vectors are kept apart from the values
+            // that may be either
+            Some(&previous) if previous != kind && (kept_apart(previous) || kept_apart(kind)) => {
+                return Err(SeppoError::Sema(format!(
+                    "Variable {} holds {}, it can't be assigned {}",
+                    name,
+                    previous.describe(),
+                    kind.describe()
+                )));
+            }
+            Some(previous) if *previous != kind => Kind::Unknown,
+            _ => kind,
+        };
+        self.kinds.insert(name.to_string(), merged);
+        Ok(Box::new(value))
+    }
+
+    /// Resolve a value used as an integer or string, which can't be an
+    /// Option or a vector
+    fn value(&mut self, value: SeppoExpr) -> Result<Box<SeppoExpr>> {
+        let value = self.resolve(value)?;
+        self.scalar(&value)?;
+        Ok(Box::new(value))
+    }
+
+    /// Resolve the operands of arithmetic or a comparison, either two
+    /// vectors added, subtracted or multiplied element by element or two
+    /// values used as integers
+    fn operands(
+        &mut self,
+        op: &str,
+        left: SeppoExpr,
+        right: SeppoExpr,
+    ) -> Result<(Box<SeppoExpr>, Box<SeppoExpr>)> {
+        let left = self.resolve(left)?;
+        let right = self.resolve(right)?;
+        let kinds = (self.kind_of(&left), self.kind_of(&right));
+        match kinds {
+            (Kind::Vector, Kind::Vector) if matches!(op, "+" | "-" | "*") => {}
+            (Kind::Vector, Kind::Vector) => {
+                return Err(SeppoError::Sema(format!(
+                    "i64x4 supports +, - and *, not {}",
+                    op
+                )))
+            }
+            (Kind::Vector, other) | (other, Kind::Vector) => {
+                return Err(SeppoError::Sema(format!(
+                    "Both sides of {} have to be i64x4, got {}",
+                    op,
+                    other.describe()
+                )))
+            }
+            _ => {
+                self.scalar(&left)?;
+                self.scalar(&right)?;
+            }
+        }
+        Ok((Box::new(left), Box::new(right)))
+    }
+
+    /// An error when `value` is an Option, which has to be unwrapped
+    /// before use, or a vector, whose elements have to be taken out
+    fn scalar(&self, value: &SeppoExpr) -> Result<()> {
+        let how = match self.kind_of(value) {
+            Kind::Option => "an Option, unwrap it before use with unwrap or unwrap_or",
+            Kind::Vector => "an i64x4, take its elements with lane, e.g. lane(v, 0)",
+            _ => return Ok(()),
+        };
+        let what = match value {
+            SeppoExpr::Variable(name) => name.clone(),
+            SeppoExpr::FunctionCall(name, _) => format!("The result of {}", name),
+            _ => "The value".to_string(),
+        };
+        Err(SeppoError::Sema(format!("{} is {}", what, how)))
+    }
+
+    /// An error when `value` is a conditional, or has one as the value of
+    /// a branch, giving an Option or a vector from only one of its
+    /// branches, as the other has nothing of that type to give
+    fn chosen(&self, value: &SeppoExpr) -> Result<()> {
+        let SeppoExpr::Conditional {
+            true_block,
+            false_block,
+            ..
+        } = value
+        else {
+            return Ok(());
+        };
+        let then_value = block_value(true_block);
+        let then_kind = self.kind_of(then_value);
+        self.chosen(then_value)?;
+        let else_kind = match false_block {
+            Some(block) => {
+                self.chosen(block_value(block))?;
+                Some(self.kind_of(block_value(block)))
+            }
+            None => None,
+        };
+        let declared = |kind: Kind| kind.declared().is_some();
+        match else_kind {
+            Some(else_kind)
+                if else_kind == then_kind || !(declared(then_kind) || declared(else_kind)) =>
+            {
+                Ok(())
+            }
+            Some(else_kind) => Err(SeppoError::Sema(format!(
+                "The branches of a conditional give {} and {}, both have to give the same",
+                then_kind.describe(),
+                else_kind.describe()
+            ))),
+            None if declared(then_kind) => Err(SeppoError::Sema(format!(
+                "A conditional giving {} needs an else branch giving one too",
+                then_kind.describe()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve a condition, or an operand of `&&` or `||`, where a bare
+    /// variable has to hold a bool and a bare call has to give one
+    fn condition(&mut self, condition: SeppoExpr) -> Result<Box<SeppoExpr>> {
+        let condition = self.resolve(condition)?;
+        self.scalar(&condition)?;
+        let kind = self.kind_of(&condition);
+        let written = match &condition {
+            SeppoExpr::Variable(name) => name.clone(),
+            SeppoExpr::FunctionCall(name, _) => format!("{}(...)", name),
+            _ => return Ok(Box::new(condition)),
+        };
+        if kind != Kind::Bool {
+            return Err(SeppoError::Sema(format!(
+                "Condition {} is {}, not a bool, compare it instead, e.g. {} != 0",
+                written,
+                kind.describe(),
+                written
+            )));
+        }
+        Ok(Box::new(condition))
+    }
+
+    /// The types declared by function `name`, none for most
+    fn signature(&self, name: &str) -> Signature {
+        self.signatures.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Whether a call of `name` is of a builtin
+    fn calls_builtin(&self, name: &str) -> bool {
+        is_builtin(name) && !self.defined.contains(name)
+    }
+
+    /// The kind argument `i` of builtin `name` has to be, for those taking
+    /// an Option or a vector
+    fn builtin_takes(&self, name: &str, i: usize) -> Option<Kind> {
+        if !self.calls_builtin(name) {
+            return None;
+        }
+        match (name, i) {
+            ("Some", _) => None,
+            (_, 0) if is_option_builtin(name) => Some(Kind::Option),
+            ("lane", 0) | ("shuffle", 0 | 1) | ("store_i64x4", 1) => Some(Kind::Vector),
+            _ => None,
+        }
+    }
+
+    /// The arguments of a call of `name`, resolved and checked against its
+    /// signature. Only parameters declared `option` or `i64x4` and the
+    /// builtins for them take Options and vectors.
+    fn arguments(&mut self, name: &str, args: Vec<SeppoExpr>) -> Result<Vec<SeppoExpr>> {
+        let args = self.all(args)?;
+        if matches!(name, "lane" | "shuffle") && self.calls_builtin(name) {
+            lanes(name, &args)?;
+        }
+        let declared = self.signatures.get(name).map(|s| s.params.as_slice());
+        for (i, arg) in args.iter().enumerate() {
+            match declared.and_then(|params| params.get(i).copied().flatten()) {
+                Some(ty) => self.check(arg, ty, || {
+                    format!("Argument {} of {} should be", i + 1, name)
+                })?,
+                None => match self.builtin_takes(name, i) {
+                    Some(takes) => {
+                        // Values that may be one are checked when they run
+                        let kind = self.kind_of(arg);
+                        if kind != takes && kind != Kind::Unknown {
+                            return Err(SeppoError::Sema(format!(
+                                "{} takes {}, got {}",
+                                name,
+                                takes.describe(),
+                                kind.describe()
+                            )));
+                        }
+                    }
+                    None => self.scalar(arg)?,
+                },
+            }
+        }
+        Ok(args)
+    }
+
+    /// The value of a `return`, resolved and checked against the signature
+    fn returned(&mut self, value: SeppoExpr) -> Result<Box<SeppoExpr>> {
+        let value = self.resolve(value)?;
+        self.chosen(&value)?;
+        match self.signature(&self.function).returns {
+            Some(ty) => self.check(&value, ty, || {
+                format!("Function {} should return", self.function)
+            })?,
+            // Options and vectors are returned differently, so callers
+            // have to know
+            None => {
+                let kind = self.kind_of(&value);
+                if let Some(ty) = kind.declared() {
+                    return Err(SeppoError::Sema(format!(
+                        "Function {} returns {}, which it has to declare with -> {}",
+                        self.function,
+                        kind.describe(),
+                        ty.name()
+                    )));
+                }
+            }
+        }
+        Ok(Box::new(value))
+    }
+
+    /// An error starting with `what`, e.g. "Argument 1 of f should be",
+    /// when `value` isn't of type `ty`. Values that may be either pass.
+    fn check(&self, value: &SeppoExpr, ty: SeppoType, what: impl Fn() -> String) -> Result<()> {
+        let kind = self.kind_of(value);
+        // Bools are passed and returned as the integers 0 and 1
+        let bool_as_int = kind == Kind::Bool && ty == SeppoType::Int;
+        // while Options and vectors are passed differently, so have to be
+        // known
+        let may_be = kind == Kind::Unknown && Kind::of(&ty).declared().is_none();
+        if may_be || kind == Kind::of(&ty) || bool_as_int {
+            return Ok(());
+        }
+        Err(SeppoError::Sema(format!(
+            "{} {}, got {}",
+            what(),
+            Kind::of(&ty).describe(),
+            kind.describe()
+        )))
+    }
+
+    fn kind_of(&self, expr: &SeppoExpr) -> Kind {
+        match expr {
+            SeppoExpr::String(_) => Kind::Str,
+            SeppoExpr::Operation(op, ..) if is_comparison(op) || is_logical(op) => Kind::Bool,
+            SeppoExpr::In(..) => Kind::Bool,
+            SeppoExpr::Operation(_, left, _) if self.kind_of(left) == Kind::Vector => Kind::Vector,
+            SeppoExpr::Variable(name) => match self.kinds.get(name) {
+                Some(kind) => *kind,
+                None if name == NONE => Kind::Option,
+                None => Kind::Unknown,
+            },
+            SeppoExpr::FunctionCall(name, _)
+                if self.calls_builtin(name)
+                    && (is_option_builtin(name) || is_vector_builtin(name)) =>
+            {
+                match name.as_str() {
+                    "Some" => Kind::Option,
+                    "is_some" | "is_none" => Kind::Bool,
+                    "i64x4" | "shuffle" | "load_i64x4" => Kind::Vector,
+                    "lane" | "store_i64x4" => Kind::Int,
+                    // What the Option holds could be either
+                    _ => Kind::Unknown,
+                }
+            }
+            SeppoExpr::FunctionCall(name, _) => match self.signatures.get(name) {
+                Some(Signature {
+                    returns: Some(ty), ..
+                }) => Kind::of(ty),
+                _ if name == "substr" => Kind::Str,
+                // Other builtins give integers, but functions may return either
+                _ if crate::builtins::is_builtin(name) => Kind::Int,
+                _ => Kind::Unknown,
+            },
+            SeppoExpr::Conditional {
+                true_block,
+                false_block: Some(false_block),
+                ..
+            } => {
+                let kind = self.kind_of(block_value(true_block));
+                if kind == self.kind_of(block_value(false_block)) {
+                    kind
+                } else {
+                    Kind::Unknown
+                }
+            }
+            _ => Kind::Int,
+        }
+    }
+
+    fn type_of(&self, args: &[SeppoExpr]) -> Result<i64> {
+        let [value] = args else {
+            return Err(SeppoError::Sema(format!(
+                "typeof expects 1 argument, got {}",
+                args.len()
+            )));
+        };
+        match self.kind_of(value) {
+            Kind::Int => Ok(TYPE_INT),
+            Kind::Str => Ok(TYPE_STRING),
+            Kind::Bool => Ok(TYPE_BOOL),
+            Kind::Option => Ok(TYPE_OPTION),
+            Kind::Vector => Ok(TYPE_VECTOR),
+            Kind::Unknown => {
+                let what = match value {
+                    SeppoExpr::Variable(name) => name.clone(),
+                    _ => "its argument".to_string(),
+                };
+                Err(SeppoError::Sema(format!(
+                    "typeof can't tell the type of {} at compile time, it may be an integer or a string",
+                    what
+                )))
+            }
+        }
+    }
+}
+
+fn size_of(args: &[SeppoExpr]) -> Result<i64> {
+    let [SeppoExpr::Variable(name)] = args else {
+        return Err(SeppoError::Sema(
+            "sizeof expects the name of a type, such as sizeof(i64)".to_string(),
+        ));
+    };
+    TYPE_SIZES
+        .iter()
+        .find(|(ty, _)| ty == name)
+        .map(|(_, size)| *size)
+        .ok_or_else(|| {
+            let known: Vec<&str> = TYPE_SIZES.iter().map(|(ty, _)| *ty).collect();
+            SeppoError::Sema(format!(
+                "sizeof: unknown type {}, expected one of {}",
+                name,
+                known.join(", ")
+            ))
+        })
+}
+
+/// Whether values of `kind` can't be mixed with others in a variable
+fn kept_apart(kind: Kind) -> bool {
+    kind == Kind::Bool || kind.declared().is_some()
+}
+
+/// Whether every way through `body` ends in a `return` or a `become`
+fn always_returns(body: &SeppoExpr) -> bool {
+    match body {
+        SeppoExpr::Block(statements) => statements
+            .iter()
+            .rev()
+            .find(|statement| !statement.is_trivia())
+            .is_some_and(always_returns),
+        SeppoExpr::Return(_) | SeppoExpr::Become(..) => true,
+        SeppoExpr::Conditional {
+            true_block,
+            false_block: Some(false_block),
+            ..
+        } => always_returns(true_block) && always_returns(false_block),
+        _ => false,
+    }
+}
+
+/// The value of a block used as an expression, its last statement
+fn block_value(block: &SeppoExpr) -> &SeppoExpr {
+    match block {
+        SeppoExpr::Block(statements) => statements.last().unwrap_or(block),
+        other => other,
+    }
+}
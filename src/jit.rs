@@ -0,0 +1,427 @@
+use crate::codegen::{demangle, CodeGen};
+use crate::coverage::Coverage;
+use crate::engine::{call_host, HostFn};
+use crate::error::{Result, SeppoError};
+use crate::sandbox::check_spawn;
+use crate::sema::undefined;
+use crate::target::TargetSpec;
+use crate::temps::TempArtifacts;
+use crate::types::{SeppoExpr, THREADLOCAL_ATTRIBUTE};
+use inkwell::context::Context;
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::OptimizationLevel;
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// A program compiled in memory for the host, whose functions can be called
+/// directly without linking an executable
+pub struct Jit<'ctx> {
+    codegen: CodeGen<'ctx>,
+    engine: ExecutionEngine<'ctx>,
+    hot: Option<HotReload<'ctx>>,
+    /// Host functions the compiled code calls, kept alive by the JIT
+    host: Vec<Arc<HostFn>>,
+}
+
+/// What `Jit::reload` keeps track of
+struct HotReload<'ctx> {
+    context: &'ctx Context,
+    /// The pointer each seppo function is called through, boxed so the
+    /// compiled code can hold on to its address
+    slots: HashMap<String, Box<AtomicUsize>>,
+    /// Each function as it was last compiled
+    functions: HashMap<String, SeppoExpr>,
+    ceppo: Vec<String>,
+    /// The globals, declared by the first module
+    globals: Vec<SeppoExpr>,
+    /// Modules added by earlier reloads, still running in the engine
+    reloads: Vec<CodeGen<'ctx>>,
+}
+
+/// Symbol of a function compiled by the `generation`th reload, apart from
+/// the versions already in the engine
+pub(crate) fn hot_symbol(name: &str, generation: usize) -> String {
+    format!("{}.hot{}", name, generation)
+}
+
+impl<'ctx> Jit<'ctx> {
+    pub fn new(context: &'ctx Context, program: &SeppoExpr) -> Result<Self> {
+        let codegen = Self::codegen(context, program)?;
+        Self::from_codegen(codegen)
+    }
+
+    /// Compile `program` with coverage counters, which `coverage` reads back.
+    /// The program should come from `parse_with_locations`.
+    pub fn with_coverage(context: &'ctx Context, program: &SeppoExpr) -> Result<Self> {
+        let codegen = Self::build(context, program, true)?;
+        Self::from_codegen(codegen)
+    }
+
+    /// Compile `program` with calls to the `host` functions going to them,
+    /// see `Engine`
+    pub(crate) fn with_host(
+        context: &'ctx Context,
+        program: &SeppoExpr,
+        host: &HashMap<String, Arc<HostFn>>,
+    ) -> Result<Self> {
+        let target = TargetSpec::native();
+        target.initialize()?;
+        let mut codegen = CodeGen::with_target(context, "jit", target);
+        let trampoline = call_host as extern "C" fn(*const HostFn, *const i64) -> i64;
+        for (name, function) in host {
+            codegen.define_host_function(
+                name,
+                function.params(),
+                Arc::as_ptr(function) as usize,
+                trampoline as usize,
+            )?;
+        }
+        codegen.compile_library(program)?;
+
+        let mut jit = Self::from_codegen(codegen)?;
+        jit.host = host.values().cloned().collect();
+        Ok(jit)
+    }
+
+    /// Code generator for the host with `program` compiled in, for callers
+    /// that add their own functions before handing it to `from_codegen`
+    pub fn codegen(context: &'ctx Context, program: &SeppoExpr) -> Result<CodeGen<'ctx>> {
+        Self::build(context, program, false)
+    }
+
+    fn build(context: &'ctx Context, program: &SeppoExpr, coverage: bool) -> Result<CodeGen<'ctx>> {
+        let target = TargetSpec::native();
+        target.initialize()?;
+
+        let mut codegen = CodeGen::with_target(context, "jit", target);
+        codegen.set_coverage(coverage);
+        codegen.compile_library(program)?;
+        Ok(codegen)
+    }
+
+    /// Start executing a module. No code can be added to it afterwards.
+    pub fn from_codegen(codegen: CodeGen<'ctx>) -> Result<Self> {
+        load_c_objects(&codegen)?;
+
+        let engine = codegen
+            .get_module()
+            .create_jit_execution_engine(OptimizationLevel::Default)
+            .map_err(|e| SeppoError::Codegen(format!("Failed to create JIT: {}", e)))?;
+
+        Ok(Self {
+            codegen,
+            engine,
+            hot: None,
+            host: Vec::new(),
+        })
+    }
+
+    /// Compile `program` so that `reload` can swap in new versions of its
+    /// functions while it runs. Seppo functions call each other through a
+    /// table of pointers, which costs a load per call.
+    pub fn hot(context: &'ctx Context, program: &SeppoExpr) -> Result<Self> {
+        let (functions, ceppo, globals) = top_level(program);
+        let slots: HashMap<String, Box<AtomicUsize>> = functions
+            .keys()
+            .map(|name| (name.clone(), Box::new(AtomicUsize::new(0))))
+            .collect();
+
+        let target = TargetSpec::native();
+        target.initialize()?;
+        let mut codegen = CodeGen::with_target(context, "jit", target);
+        codegen.set_hot_slots(slot_addresses(&slots), 0);
+        codegen.compile_library(program)?;
+
+        let mut jit = Self::from_codegen(codegen)?;
+        for (name, slot) in &slots {
+            slot.store(jit.address(name)?, Ordering::SeqCst);
+        }
+        jit.hot = Some(HotReload {
+            context,
+            slots,
+            functions,
+            ceppo,
+            globals,
+            reloads: Vec::new(),
+        });
+        Ok(jit)
+    }
+
+    /// Recompile the functions of `program` that differ from the running
+    /// ones and switch every later call over to them, keeping the state of
+    /// ceppo blocks and the loops that are running. Returns the names of
+    /// the functions that were compiled, sorted.
+    ///
+    /// Functions missing from `program` keep running. Function parameters,
+    /// globals and ceppo blocks cannot change without starting over.
+    pub fn reload(&mut self, program: &SeppoExpr) -> Result<Vec<String>> {
+        let Some(hot) = &mut self.hot else {
+            return Err(SeppoError::Codegen(
+                "Only a program compiled with Jit::hot can be reloaded".to_string(),
+            ));
+        };
+        let (functions, ceppo, globals) = top_level(program);
+        if ceppo != hot.ceppo {
+            return Err(SeppoError::Codegen(
+                "ceppo blocks cannot be reloaded, restart the program to change them".to_string(),
+            ));
+        }
+        if globals != hot.globals {
+            return Err(SeppoError::Codegen(
+                "Globals cannot be reloaded, restart the program to change them".to_string(),
+            ));
+        }
+        let mut changed: Vec<String> = functions
+            .iter()
+            .filter(|(name, function)| hot.functions.get(*name) != Some(*function))
+            .map(|(name, _)| name.clone())
+            .collect();
+        changed.sort();
+        for name in &changed {
+            if let Some(old) = hot.functions.get(name) {
+                if param_count(old) != param_count(&functions[name]) {
+                    return Err(SeppoError::Sema(format!(
+                        "Reloading cannot change the parameters of {}, restart the program to change them",
+                        name
+                    )));
+                }
+            }
+        }
+        if changed.is_empty() {
+            return Ok(changed);
+        }
+
+        for name in &changed {
+            hot.slots
+                .entry(name.clone())
+                .or_insert_with(|| Box::new(AtomicUsize::new(0)));
+        }
+        let generation = hot.reloads.len() + 1;
+        let mut codegen = CodeGen::with_target(
+            hot.context,
+            &format!("jit.hot{}", generation),
+            TargetSpec::native(),
+        );
+        codegen.set_hot_slots(slot_addresses(&hot.slots), generation);
+        codegen.export_functions(program);
+        codegen.declare_types(program);
+        codegen.declare_globals(program)?;
+        // Everything else is already in the engine
+        for (name, symbol, params) in self.codegen.function_signatures() {
+            codegen.declare_function(&name, &symbol, params);
+        }
+        for (name, function) in &functions {
+            if !changed.contains(name) {
+                let symbol = codegen.symbol(name);
+                codegen.declare_function(name, &symbol, param_count(function));
+            }
+        }
+        let items = changed.iter().map(|name| functions[name].clone()).collect();
+        codegen.compile_library(&SeppoExpr::Block(items))?;
+
+        self.engine
+            .add_module(codegen.get_module())
+            .map_err(|_| SeppoError::Codegen("Failed to add reloaded module".to_string()))?;
+        for name in &changed {
+            let symbol = hot_symbol(&codegen.symbol(name), generation);
+            let address = self.engine.get_function_address(&symbol).map_err(|e| {
+                SeppoError::Codegen(format!("JIT lookup of {} failed: {}", name, e))
+            })?;
+            hot.slots[name].store(address, Ordering::SeqCst);
+        }
+        hot.functions.extend(functions);
+        hot.reloads.push(codegen);
+        Ok(changed)
+    }
+
+    /// Line counts of everything run so far, empty unless the program was
+    /// compiled by `with_coverage`
+    pub fn coverage(&self) -> Result<Coverage> {
+        let mut lines: Vec<((String, usize), u64)> = Vec::new();
+        for (i, location) in self.codegen.coverage_locations().into_iter().enumerate() {
+            let hits = self.call("seppo.coverage_hits", &[i as i64])? as u64;
+            // Statements sharing a line count as often as the one run most
+            match lines.iter_mut().find(|(seen, _)| *seen == location) {
+                Some((_, max)) => *max = (*max).max(hits),
+                None => lines.push((location, hits)),
+            }
+        }
+
+        let mut coverage = Coverage::default();
+        for ((file, line), hits) in lines {
+            coverage.record(&file, line, hits);
+        }
+        Ok(coverage)
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.codegen
+            .get_module()
+            .get_function(&self.symbol(name))
+            .is_some()
+            || self
+                .hot
+                .as_ref()
+                .is_some_and(|hot| hot.functions.contains_key(name))
+    }
+
+    /// Call a function with up to four arguments and return its result
+    pub fn call(&self, name: &str, args: &[i64]) -> Result<i64> {
+        let (params, address) = self.lookup(name)?;
+        if params != args.len() {
+            return Err(SeppoError::Sema(format!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                params,
+                args.len()
+            )));
+        }
+
+        // Every seppo function takes and returns i64, and the argument count
+        // was checked against the definition above
+        unsafe {
+            use std::mem::transmute;
+            Ok(match *args {
+                [] => transmute::<usize, extern "C" fn() -> i64>(address)(),
+                [a] => transmute::<usize, extern "C" fn(i64) -> i64>(address)(a),
+                [a, b] => transmute::<usize, extern "C" fn(i64, i64) -> i64>(address)(a, b),
+                [a, b, c] => {
+                    transmute::<usize, extern "C" fn(i64, i64, i64) -> i64>(address)(a, b, c)
+                }
+                [a, b, c, d] => transmute::<usize, extern "C" fn(i64, i64, i64, i64) -> i64>(
+                    address,
+                )(a, b, c, d),
+                _ => {
+                    return Err(SeppoError::Sema(format!(
+                        "Calling {} with more than four arguments is not supported",
+                        name
+                    )))
+                }
+            })
+        }
+    }
+
+    /// Run a function without parameters on a thread of its own, e.g. the
+    /// main loop of a program that is reloaded from another thread
+    ///
+    /// # Safety
+    ///
+    /// The JIT must outlive the thread, since the thread runs its code.
+    pub unsafe fn spawn(&self, name: &str) -> Result<JoinHandle<i64>> {
+        let (params, address) = self.lookup(name)?;
+        if params != 0 {
+            return Err(SeppoError::Sema(format!(
+                "Function {} expects {} arguments, got 0",
+                name, params
+            )));
+        }
+        let function = std::mem::transmute::<usize, extern "C" fn() -> i64>(address);
+        Ok(thread::spawn(move || function()))
+    }
+
+    /// Parameter count and current address of a function
+    fn lookup(&self, name: &str) -> Result<(usize, usize)> {
+        if let Some(hot) = &self.hot {
+            if let (Some(function), Some(slot)) = (hot.functions.get(name), hot.slots.get(name)) {
+                return Ok((param_count(function), slot.load(Ordering::SeqCst)));
+            }
+        }
+        let module = self.codegen.get_module();
+        let function = module.get_function(&self.symbol(name)).ok_or_else(|| {
+            let defined: Vec<String> = module
+                .get_functions()
+                .filter(|function| function.count_basic_blocks() > 0)
+                .map(|function| function.get_name().to_string_lossy().into_owned())
+                .map(|symbol| demangle(&symbol).to_string())
+                .collect();
+            undefined("function", name, defined.iter().map(String::as_str))
+        })?;
+        Ok((function.count_params() as usize, self.address(name)?))
+    }
+
+    fn address(&self, name: &str) -> Result<usize> {
+        self.engine
+            .get_function_address(&self.symbol(name))
+            .map_err(|e| SeppoError::Codegen(format!("JIT lookup of {} failed: {}", name, e)))
+    }
+
+    /// The symbol of a function of the program, or `name` itself for
+    /// helpers codegen adds, like benchmark harnesses
+    fn symbol(&self, name: &str) -> String {
+        self.codegen
+            .defined_symbol(name)
+            .unwrap_or_else(|| name.to_string())
+    }
+}
+
+/// The functions of a program by name, its ceppo blocks and its globals
+/// with their `threadlocal` markers
+fn top_level(program: &SeppoExpr) -> (HashMap<String, SeppoExpr>, Vec<String>, Vec<SeppoExpr>) {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    let mut functions = HashMap::new();
+    let mut ceppo = Vec::new();
+    let mut globals = Vec::new();
+    for item in items {
+        match item {
+            SeppoExpr::Function(name, ..) => {
+                functions.insert(name.clone(), item.clone());
+            }
+            SeppoExpr::InlineC(code) => ceppo.push(code.clone()),
+            SeppoExpr::Let { .. } => globals.push(item.clone()),
+            SeppoExpr::Attribute { name, .. } if name == THREADLOCAL_ATTRIBUTE => {
+                globals.push(item.clone())
+            }
+            _ => {}
+        }
+    }
+    (functions, ceppo, globals)
+}
+
+fn param_count(function: &SeppoExpr) -> usize {
+    match function {
+        SeppoExpr::Function(_, params, _) => params.len(),
+        _ => 0,
+    }
+}
+
+fn slot_addresses(slots: &HashMap<String, Box<AtomicUsize>>) -> HashMap<String, usize> {
+    slots
+        .iter()
+        .map(|(name, slot)| (name.clone(), slot.as_ref() as *const AtomicUsize as usize))
+        .collect()
+}
+
+/// Make the functions of ceppo blocks visible to the JIT by linking their
+/// objects into a shared library and loading it into the process
+fn load_c_objects(codegen: &CodeGen) -> Result<()> {
+    if codegen.c_object_files().is_empty() {
+        return Ok(());
+    }
+
+    let mut command = Command::new("cc");
+    check_spawn(&command, "Loading ceppo blocks")?;
+    // The library stays mapped after its file is gone
+    let temps = TempArtifacts::default();
+    let library = temps.dir("seppolang_jit")?.join("libceppo.so");
+    let output = command
+        .arg("-shared")
+        .arg("-o")
+        .arg(&library)
+        .args(codegen.c_object_files())
+        .output()?;
+    if !output.status.success() {
+        return Err(SeppoError::Link(format!(
+            "Failed to build ceppo library: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    inkwell::support::load_library_permanently(&library)
+        .map_err(|e| SeppoError::Link(format!("Failed to load ceppo library: {}", e)))
+}
@@ -0,0 +1,250 @@
+use crate::types::Span;
+
+/// What a token is, as far as highlighting cares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// `fn`, `seppo`, `perkele`, `for`, `let` and the other reserved words
+    Keyword,
+    Identifier,
+    Number,
+    /// A string literal, quotes included
+    String,
+    /// Arithmetic, comparisons, `=` and range operators
+    Operator,
+    /// Brackets, commas and the `:` and `->` of signatures
+    Punctuation,
+    /// `// ...`
+    Comment,
+    /// `## ...`
+    DocComment,
+    /// `#[allow(...)]` as a whole
+    Attribute,
+    /// The body of a ceppo block, between its braces
+    CCode,
+    /// A character that starts no token, which the parser will reject
+    Unknown,
+}
+
+impl TokenKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "keyword",
+            TokenKind::Identifier => "identifier",
+            TokenKind::Number => "number",
+            TokenKind::String => "string",
+            TokenKind::Operator => "operator",
+            TokenKind::Punctuation => "punctuation",
+            TokenKind::Comment => "comment",
+            TokenKind::DocComment => "doc-comment",
+            TokenKind::Attribute => "attribute",
+            TokenKind::CCode => "c-code",
+            TokenKind::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+    /// 1-based line and column, in characters, of the first character
+    pub line: usize,
+    pub column: usize,
+}
+
+const KEYWORDS: &[&str] = &[
+    "fn",
+    "const",
+    "macro",
+    "ceppo",
+    "seppo",
+    "0xseppo",
+    "boolseppo",
+    "charseppo",
+    "eseppo",
+    "perkele",
+    "for",
+    "in",
+    "let",
+    "mut",
+    "return",
+    "become",
+    "export",
+    "inline",
+    "threadlocal",
+];
+
+/// Whether `word` is one of the reserved words, which can't name variables
+pub(crate) fn is_keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word)
+}
+
+/// Letters of any script, digits and `_`, what names are made of
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// `source` as tokens, without parsing it. Whitespace is skipped and
+/// anything else becomes a token, so source with syntax errors lexes too
+/// and the spans cover every character that isn't whitespace. Meant for
+/// syntax highlighters and other tools that don't need the syntax tree.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer {
+        source,
+        pos: 0,
+        line: 1,
+        column: 1,
+        tokens: Vec::new(),
+    };
+    lexer.run();
+    lexer.tokens
+}
+
+/// One token per line as `file:line:column kind "text"`, the output of
+/// `--emit tokens`
+pub fn render_tokens(file: &str, tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| {
+            format!(
+                "{}:{}:{} {} {:?}\n",
+                file,
+                token.line,
+                token.column,
+                token.kind.name(),
+                token.text
+            )
+        })
+        .collect()
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+    line: usize,
+    column: usize,
+    tokens: Vec<Token>,
+}
+
+impl Lexer<'_> {
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    fn run(&mut self) {
+        while let Some(c) = self.rest().chars().next() {
+            let rest = self.rest();
+            let (kind, len) = if c.is_whitespace() {
+                self.advance(c.len_utf8());
+                continue;
+            } else if rest.starts_with("//") {
+                (TokenKind::Comment, line_length(rest))
+            } else if rest.starts_with("##") {
+                (TokenKind::DocComment, line_length(rest))
+            } else if rest.starts_with("#[") {
+                let len = rest.find(']').map_or(line_length(rest), |end| end + 1);
+                (TokenKind::Attribute, len)
+            } else if c == '"' {
+                let len = rest[1..].find('"').map_or(rest.len(), |end| end + 2);
+                (TokenKind::String, len)
+            } else if is_word_char(c) {
+                let len = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+                let word = &rest[..len];
+                if is_keyword(word) {
+                    (TokenKind::Keyword, len)
+                } else if c.is_ascii_digit() {
+                    // Digits up to the first letter, like the grammar's numbers
+                    let digits = word.find(|c: char| !c.is_ascii_digit()).unwrap_or(len);
+                    (TokenKind::Number, digits)
+                } else if !c.is_alphabetic() {
+                    (TokenKind::Unknown, c.len_utf8())
+                } else {
+                    (TokenKind::Identifier, len)
+                }
+            } else if let Some(op) = ["..=", "..", "==", "!=", ">=", "<=", "&&", "||"]
+                .iter()
+                .find(|op| rest.starts_with(*op))
+            {
+                (TokenKind::Operator, op.len())
+            } else if rest.starts_with("->") {
+                (TokenKind::Punctuation, 2)
+            } else {
+                match c {
+                    '+' | '-' | '*' | '/' | '=' | '<' | '>' => (TokenKind::Operator, 1),
+                    '(' | ')' | '{' | '}' | '[' | ']' | ',' | ':' => (TokenKind::Punctuation, 1),
+                    _ => (TokenKind::Unknown, c.len_utf8()),
+                }
+            };
+            let is_ceppo = kind == TokenKind::Keyword && &rest[..len] == "ceppo";
+            self.push(kind, len);
+            if is_ceppo {
+                self.ceppo_body();
+            }
+        }
+    }
+
+    /// After `ceppo`, the braces of the block and the C code between them
+    /// as a single token, which nested braces don't end
+    fn ceppo_body(&mut self) {
+        let rest = self.rest();
+        let padding = rest.len() - rest.trim_start().len();
+        if !rest[padding..].starts_with('{') {
+            return;
+        }
+        self.advance(padding);
+        self.push(TokenKind::Punctuation, 1);
+
+        let mut depth = 0;
+        let mut len = self.rest().len();
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    len = i;
+                    break;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        if len > 0 {
+            self.push(TokenKind::CCode, len);
+        }
+        if self.rest().starts_with('}') {
+            self.push(TokenKind::Punctuation, 1);
+        }
+    }
+
+    fn push(&mut self, kind: TokenKind, len: usize) {
+        let start = self.pos;
+        let (line, column) = (self.line, self.column);
+        self.advance(len);
+        self.tokens.push(Token {
+            kind,
+            text: self.source[start..self.pos].to_string(),
+            span: Span {
+                start,
+                end: self.pos,
+            },
+            line,
+            column,
+        });
+    }
+
+    fn advance(&mut self, len: usize) {
+        for c in self.source[self.pos..self.pos + len].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        self.pos += len;
+    }
+}
+
+fn line_length(text: &str) -> usize {
+    text.find('\n').unwrap_or(text.len())
+}
@@ -1,7 +1,68 @@
+pub mod bench;
+mod builtins;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod cdecl;
 mod codegen;
+mod compile;
+mod consteval;
+pub mod coverage;
+mod debuginfo;
+pub mod diagnostic;
+pub mod doc;
+mod driver;
+mod engine;
+mod error;
+mod escape;
+mod fmt;
+#[cfg(feature = "arbitrary")]
+mod generate;
+pub mod grammar;
+pub mod ice;
+mod include;
+mod inline;
+pub mod interp;
+mod introspect;
+mod jit;
+pub mod lexer;
+pub mod lint;
+mod macros;
+pub mod manifest;
+pub mod nesting;
+mod output;
 mod parser;
+#[cfg(feature = "python")]
+pub mod python;
+mod recover;
+pub mod repl;
+pub mod sandbox;
+mod sema;
+mod syntax_error;
+mod target;
+mod temps;
+pub mod testing;
+pub mod timing;
 mod types;
+mod universal;
 
-pub use codegen::CodeGen;
-pub use parser::parse_seppo;
-pub use types::SeppoExpr;
+pub use codegen::{
+    CeppoBuild, CodeGen, CodeGenOptions, DivisionOverflow, DivisionRounding, PassPreset, Sanitizer,
+    StackProtector, StdoutBuffering,
+};
+pub use compile::{
+    compile_driver, compile_input, compile_str, link, post_link, run_str, CompileOptions,
+    CompiledProgram,
+};
+pub use driver::{Driver, EmitOptions, Input, OutputKind, Profile};
+pub use engine::{Engine, HostFn, HostFunction};
+pub use error::{Result, SeppoError};
+pub use fmt::{format_program, format_source};
+pub use inkwell::OptimizationLevel;
+pub use jit::Jit;
+pub use output::{OutputPath, TargetDir};
+pub use parser::{
+    parse_file, parse_module, parse_seppo, parse_with_comments, parse_with_locations,
+};
+pub use recover::{parse_lenient, Recovered};
+pub use target::TargetSpec;
+pub use types::{SeppoExpr, Span};
@@ -0,0 +1,516 @@
+use crate::bench::BENCH_PREFIX;
+use crate::error::{Result, SeppoError};
+use crate::sema::ENTRY_SUFFIX;
+use crate::testing::TEST_PREFIX;
+use crate::types::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// How a lint finding is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            other => Err(SeppoError::Config(format!(
+                "Unknown lint level: {} (expected allow, warn or deny)",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Warn => write!(f, "warning"),
+            Level::Deny => write!(f, "error"),
+        }
+    }
+}
+
+/// A named lint rule and the level it runs at unless configured otherwise
+#[derive(Debug, Clone, Copy)]
+pub struct Lint {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default_level: Level,
+}
+
+pub const UNUSED_VARIABLE: Lint = Lint {
+    name: "unused-variable",
+    description: "a variable is assigned but never read",
+    default_level: Level::Warn,
+};
+
+/// Entry points are exempt: `seppo`, tests, benchmarks, and functions whose
+/// name appears in a ceppo block, which C code may call
+pub const UNUSED_FUNCTION: Lint = Lint {
+    name: "unused-function",
+    description: "a function is defined but never called",
+    default_level: Level::Warn,
+};
+
+pub const SHADOWED_VARIABLE: Lint = Lint {
+    name: "shadowed-variable",
+    description: "an assignment hides a parameter or a function of the same name",
+    default_level: Level::Warn,
+};
+
+pub const CONSTANT_CONDITION: Lint = Lint {
+    name: "constant-condition",
+    description: "a conditional compares two literals, so only one branch can run",
+    default_level: Level::Warn,
+};
+
+pub const MAGIC_NUMBER: Lint = Lint {
+    name: "magic-number",
+    description: "a numeric literal other than 0 or 1 is used without naming it first",
+    default_level: Level::Allow,
+};
+
+/// Every lint seppoc knows about
+pub const LINTS: &[Lint] = &[
+    UNUSED_VARIABLE,
+    UNUSED_FUNCTION,
+    SHADOWED_VARIABLE,
+    CONSTANT_CONDITION,
+    MAGIC_NUMBER,
+];
+
+/// Per-lint levels, starting from each lint's default
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<&'static str, Level>,
+    /// Report lints at warn level as errors
+    deny_warnings: bool,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read levels from a file of `lint-name = "level"` lines. A `[lints]`
+    /// header and `#` comments are accepted, so the same lines can live in a
+    /// TOML file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut config = Self::new();
+        config.apply_str(&text)?;
+        Ok(config)
+    }
+
+    /// Apply settings in the `from_file` format on top of the current ones
+    pub fn apply_str(&mut self, text: &str) -> Result<()> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() || line == "[lints]" {
+                continue;
+            }
+            let (name, level) = line.split_once('=').ok_or_else(|| {
+                SeppoError::Config(format!("Line {}: expected `lint-name = \"level\"`", i + 1))
+            })?;
+            let level = level.trim().trim_matches('"');
+            self.set(name.trim(), Level::from_name(level)?)?;
+        }
+        Ok(())
+    }
+
+    /// Override the level of one lint, or of every lint with the name `all`.
+    /// `warnings` set to `deny` is the same as `set_deny_warnings(true)`.
+    pub fn set(&mut self, name: &str, level: Level) -> Result<()> {
+        if name == "warnings" {
+            if level == Level::Allow {
+                return Err(SeppoError::Config(
+                    "warnings can only be set to warn or deny".to_string(),
+                ));
+            }
+            self.deny_warnings = level == Level::Deny;
+            return Ok(());
+        }
+        if name == "all" {
+            for lint in LINTS {
+                self.levels.insert(lint.name, level);
+            }
+            return Ok(());
+        }
+        let lint = LINTS
+            .iter()
+            .find(|lint| lint.name == name)
+            .ok_or_else(|| SeppoError::Config(format!("Unknown lint: {}", name)))?;
+        self.levels.insert(lint.name, level);
+        Ok(())
+    }
+
+    /// Turn every warning into an error, for CI
+    pub fn set_deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    pub fn level(&self, lint: &Lint) -> Level {
+        self.levels
+            .get(lint.name)
+            .copied()
+            .unwrap_or(lint.default_level)
+    }
+}
+
+/// One finding of an enabled lint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintDiagnostic {
+    pub lint: &'static str,
+    pub level: Level,
+    /// Function the finding is in
+    pub function: String,
+    pub message: String,
+    /// A warning made an error by `--deny-warnings`
+    pub escalated: bool,
+}
+
+impl fmt::Display for LintDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {} (in fn {})",
+            self.level, self.lint, self.message, self.function
+        )
+    }
+}
+
+/// Run every lint that is not allowed over a parsed program. Lints named in
+/// an `#[allow(...)]` attribute are not reported for the function below it.
+pub fn lint(program: &SeppoExpr, config: &LintConfig) -> Vec<LintDiagnostic> {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    let function_names: HashSet<&str> = items
+        .iter()
+        .filter_map(|item| match item {
+            SeppoExpr::Function(name, ..) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let globals: HashSet<&str> = items
+        .iter()
+        .filter_map(|item| match item {
+            SeppoExpr::Let { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    // Calls from other functions, and anything C code could be calling
+    let exported = exported_functions(program);
+    let mut called: HashSet<&str> = exported.iter().map(String::as_str).collect();
+    for item in items {
+        match item {
+            SeppoExpr::Function(name, _, body) => collect_calls(body, name, &mut called),
+            SeppoExpr::InlineC(code) => called.extend(
+                code.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .filter(|word| !word.is_empty()),
+            ),
+            _ => {}
+        }
+    }
+
+    let mut linter = Linter {
+        config,
+        function_names,
+        globals,
+        called,
+        diagnostics: Vec::new(),
+        function: String::new(),
+        allowed: Vec::new(),
+    };
+    let mut pending = Vec::new();
+    for item in items {
+        match item {
+            SeppoExpr::Attribute { name, args } if name == "allow" => {
+                pending.extend(args.iter().map(String::as_str))
+            }
+            SeppoExpr::Function(name, params, body) => {
+                linter.allowed = std::mem::take(&mut pending);
+                linter.function(name, params, body);
+            }
+            SeppoExpr::Comment { .. }
+            | SeppoExpr::DocComment(_)
+            | SeppoExpr::BlankLine
+            | SeppoExpr::Attribute { .. }
+            | SeppoExpr::Signature(_) => {}
+            _ => pending.clear(),
+        }
+    }
+    linter.diagnostics
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    function_names: HashSet<&'a str>,
+    /// Assigned in one function and read in another, so never unused
+    globals: HashSet<&'a str>,
+    called: HashSet<&'a str>,
+    diagnostics: Vec<LintDiagnostic>,
+    function: String,
+    /// Lints allowed by attributes on the current function
+    allowed: Vec<&'a str>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, lint: &Lint, message: String) {
+        let level = self.config.level(lint);
+        if level == Level::Allow
+            || self
+                .allowed
+                .iter()
+                .any(|name| *name == lint.name || *name == "all")
+        {
+            return;
+        }
+        let escalated = level == Level::Warn && self.config.deny_warnings;
+        self.diagnostics.push(LintDiagnostic {
+            lint: lint.name,
+            level: if escalated { Level::Deny } else { level },
+            function: self.function.clone(),
+            message,
+            escalated,
+        });
+    }
+
+    fn function(&mut self, name: &str, params: &[String], body: &SeppoExpr) {
+        self.function = name.to_string();
+
+        let entry_point = name == "seppo"
+            || name.starts_with(TEST_PREFIX)
+            || name.starts_with(BENCH_PREFIX)
+            || name.ends_with(ENTRY_SUFFIX);
+        if !entry_point && !self.called.contains(name) {
+            self.report(
+                &UNUSED_FUNCTION,
+                format!("function `{}` is never called", name),
+            );
+        }
+
+        let mut assigned = Vec::new();
+        let mut read = HashSet::new();
+        collect_variables(body, &mut assigned, &mut read);
+
+        let mut reported = self.globals.clone();
+        for variable in &assigned {
+            if !reported.insert(variable.as_str()) {
+                continue;
+            }
+            if !read.contains(variable.as_str()) {
+                self.report(
+                    &UNUSED_VARIABLE,
+                    format!("variable `{}` is assigned but never read", variable),
+                );
+            }
+            if params.contains(variable) {
+                self.report(
+                    &SHADOWED_VARIABLE,
+                    format!("assignment to `{}` overwrites the parameter", variable),
+                );
+            } else if self.function_names.contains(variable.as_str()) {
+                self.report(
+                    &SHADOWED_VARIABLE,
+                    format!("variable `{}` has the same name as a function", variable),
+                );
+            }
+        }
+
+        self.statement(body);
+    }
+
+    fn statement(&mut self, expr: &SeppoExpr) {
+        match expr {
+            SeppoExpr::Block(statements) => {
+                for statement in statements {
+                    self.statement(statement);
+                }
+            }
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => {
+                if let SeppoExpr::Operation(op, left, right) = condition.as_ref() {
+                    if let (SeppoExpr::Number(l), SeppoExpr::Number(r)) =
+                        (left.as_ref(), right.as_ref())
+                    {
+                        self.report(
+                            &CONSTANT_CONDITION,
+                            format!("condition `{} {} {}` is always the same", l, op, r),
+                        );
+                    } else {
+                        self.magic_numbers(condition);
+                    }
+                }
+                self.statement(true_block);
+                if let Some(false_block) = false_block {
+                    self.statement(false_block);
+                }
+            }
+            SeppoExpr::For { iterable, body, .. } => {
+                self.magic_numbers(iterable);
+                self.statement(body);
+            }
+            // Assigning a literal to a variable is how a number gets a name
+            SeppoExpr::Assignment(_, value) | SeppoExpr::Let { value, .. }
+                if matches!(value.as_ref(), SeppoExpr::Number(_)) => {}
+            other => self.magic_numbers(other),
+        }
+    }
+
+    fn magic_numbers(&mut self, expr: &SeppoExpr) {
+        match expr {
+            SeppoExpr::Number(n) if *n != 0 && *n != 1 => {
+                self.report(
+                    &MAGIC_NUMBER,
+                    format!("magic number {}, consider assigning it to a variable", n),
+                );
+            }
+            SeppoExpr::Operation(_, left, right)
+            | SeppoExpr::In(left, right)
+            | SeppoExpr::Range {
+                start: left,
+                end: right,
+                ..
+            } => {
+                self.magic_numbers(left);
+                self.magic_numbers(right);
+            }
+            SeppoExpr::Assignment(_, value)
+            | SeppoExpr::Let { value, .. }
+            | SeppoExpr::Print(_, value)
+            | SeppoExpr::Return(value) => self.magic_numbers(value),
+            SeppoExpr::FunctionCall(_, args) | SeppoExpr::Become(_, args) => {
+                for arg in args {
+                    self.magic_numbers(arg);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Names assigned in `expr`, in order of first assignment, and names read
+fn collect_variables(expr: &SeppoExpr, assigned: &mut Vec<String>, read: &mut HashSet<String>) {
+    match expr {
+        SeppoExpr::Variable(name) => {
+            read.insert(name.clone());
+        }
+        SeppoExpr::Assignment(name, value) | SeppoExpr::Let { name, value, .. } => {
+            collect_variables(value, assigned, read);
+            assigned.push(name.clone());
+        }
+        SeppoExpr::Operation(_, left, right)
+        | SeppoExpr::In(left, right)
+        | SeppoExpr::Range {
+            start: left,
+            end: right,
+            ..
+        } => {
+            collect_variables(left, assigned, read);
+            collect_variables(right, assigned, read);
+        }
+        SeppoExpr::Print(_, value) | SeppoExpr::Return(value) => {
+            collect_variables(value, assigned, read)
+        }
+        SeppoExpr::Block(statements) => {
+            for statement in statements {
+                collect_variables(statement, assigned, read);
+            }
+        }
+        SeppoExpr::FunctionCall(_, args) | SeppoExpr::Become(_, args) => {
+            for arg in args {
+                collect_variables(arg, assigned, read);
+            }
+        }
+        SeppoExpr::Conditional {
+            condition,
+            true_block,
+            false_block,
+        } => {
+            collect_variables(condition, assigned, read);
+            collect_variables(true_block, assigned, read);
+            if let Some(false_block) = false_block {
+                collect_variables(false_block, assigned, read);
+            }
+        }
+        SeppoExpr::For {
+            variable,
+            iterable,
+            body,
+        } => {
+            collect_variables(iterable, assigned, read);
+            assigned.push(variable.clone());
+            collect_variables(body, assigned, read);
+        }
+        _ => {}
+    }
+}
+
+/// Names of the functions `expr` calls, other than `function` itself
+fn collect_calls<'a>(expr: &'a SeppoExpr, function: &str, called: &mut HashSet<&'a str>) {
+    match expr {
+        SeppoExpr::FunctionCall(name, args) | SeppoExpr::Become(name, args) => {
+            if name != function {
+                called.insert(name.as_str());
+            }
+            // `spawn(worker, arg)` calls `worker`
+            if let ("spawn", Some(SeppoExpr::Variable(spawned))) = (name.as_str(), args.first()) {
+                called.insert(spawned.as_str());
+            }
+            for arg in args {
+                collect_calls(arg, function, called);
+            }
+        }
+        SeppoExpr::Operation(_, left, right)
+        | SeppoExpr::In(left, right)
+        | SeppoExpr::Range {
+            start: left,
+            end: right,
+            ..
+        } => {
+            collect_calls(left, function, called);
+            collect_calls(right, function, called);
+        }
+        SeppoExpr::Assignment(_, value)
+        | SeppoExpr::Let { value, .. }
+        | SeppoExpr::Print(_, value)
+        | SeppoExpr::Return(value) => collect_calls(value, function, called),
+        SeppoExpr::Block(statements) => {
+            for statement in statements {
+                collect_calls(statement, function, called);
+            }
+        }
+        SeppoExpr::Conditional {
+            condition,
+            true_block,
+            false_block,
+        } => {
+            collect_calls(condition, function, called);
+            collect_calls(true_block, function, called);
+            if let Some(false_block) = false_block {
+                collect_calls(false_block, function, called);
+            }
+        }
+        SeppoExpr::For { iterable, body, .. } => {
+            collect_calls(iterable, function, called);
+            collect_calls(body, function, called);
+        }
+        _ => {}
+    }
+}
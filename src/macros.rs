@@ -0,0 +1,385 @@
+use crate::error::{Result, SeppoError};
+use crate::types::SeppoExpr;
+use std::collections::HashMap;
+
+/// How deep macros may expand into other macros before we assume the
+/// expansion never terminates
+const RECURSION_LIMIT: usize = 64;
+
+struct MacroDef {
+    params: Vec<String>,
+    body: Vec<SeppoExpr>,
+}
+
+/// Replace every macro invocation in `program` with the macro's body and
+/// drop the definitions.
+///
+/// Arguments are substituted for the parameters as written, so `twice(x + 1)`
+/// evaluates `x + 1` once per use. Variables the macro assigns are renamed
+/// per expansion and never clash with the caller's. An invocation used as a
+/// value must expand to a single expression.
+pub(crate) fn expand_macros(program: SeppoExpr) -> Result<SeppoExpr> {
+    let SeppoExpr::Block(items) = program else {
+        return Ok(program);
+    };
+
+    let mut macros = HashMap::new();
+    for item in &items {
+        if let SeppoExpr::Macro(name, params, body) = item {
+            let body = match body.as_ref() {
+                SeppoExpr::Block(statements) => statements.clone(),
+                other => vec![other.clone()],
+            };
+            let def = MacroDef {
+                params: params.clone(),
+                body,
+            };
+            if macros.insert(name.clone(), def).is_some() {
+                return Err(SeppoError::Sema(format!("Macro {} is defined twice", name)));
+            }
+        }
+    }
+    if macros.is_empty() {
+        return Ok(SeppoExpr::Block(items));
+    }
+    for item in &items {
+        if let SeppoExpr::Function(name, ..) = item {
+            if macros.contains_key(name) {
+                return Err(SeppoError::Sema(format!(
+                    "{} is defined both as a function and as a macro",
+                    name
+                )));
+            }
+        }
+    }
+
+    let mut expander = Expander {
+        macros,
+        expansions: 0,
+        stack: Vec::new(),
+    };
+    let mut expanded = Vec::new();
+    for item in items {
+        match item {
+            SeppoExpr::Macro(..) => {}
+            SeppoExpr::Function(name, params, body) => {
+                expander.stack = vec![format!("function `{}`", name)];
+                let body = expander.block(*body, 0)?;
+                expanded.push(SeppoExpr::Function(name, params, Box::new(body)));
+            }
+            other => expanded.push(other),
+        }
+    }
+    Ok(SeppoExpr::Block(expanded))
+}
+
+struct Expander {
+    macros: HashMap<String, MacroDef>,
+    /// Counter making the variables of each expansion unique
+    expansions: usize,
+    /// Where the current expansion comes from, outermost first
+    stack: Vec<String>,
+}
+
+impl Expander {
+    fn block(&mut self, block: SeppoExpr, depth: usize) -> Result<SeppoExpr> {
+        match block {
+            SeppoExpr::Block(statements) => {
+                let mut expanded = Vec::new();
+                for statement in statements {
+                    self.statement(statement, depth, &mut expanded)?;
+                }
+                Ok(SeppoExpr::Block(expanded))
+            }
+            other => self.expr(other, depth),
+        }
+    }
+
+    /// Expand one statement, splicing macro bodies into `out`
+    fn statement(
+        &mut self,
+        statement: SeppoExpr,
+        depth: usize,
+        out: &mut Vec<SeppoExpr>,
+    ) -> Result<()> {
+        match statement {
+            SeppoExpr::FunctionCall(name, args) if self.macros.contains_key(&name) => {
+                let body = self.instantiate(&name, args, depth)?;
+                for statement in body {
+                    self.statement(statement, depth + 1, out)?;
+                }
+                self.stack.pop();
+            }
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => out.push(SeppoExpr::Conditional {
+                condition: Box::new(self.expr(*condition, depth)?),
+                true_block: Box::new(self.block(*true_block, depth)?),
+                false_block: false_block
+                    .map(|block| self.block(*block, depth).map(Box::new))
+                    .transpose()?,
+            }),
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => out.push(SeppoExpr::For {
+                variable,
+                iterable: Box::new(self.expr(*iterable, depth)?),
+                body: Box::new(self.block(*body, depth)?),
+            }),
+            other => out.push(self.expr(other, depth)?),
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: SeppoExpr, depth: usize) -> Result<SeppoExpr> {
+        Ok(match expr {
+            SeppoExpr::FunctionCall(name, args) if self.macros.contains_key(&name) => {
+                let body = self.instantiate(&name, args, depth)?;
+                let mut values = body.into_iter().filter(|s| !s.is_trivia());
+                let value = match (values.next(), values.next()) {
+                    (Some(value), None) if is_value(&value) => value,
+                    _ => {
+                        return Err(self.error(format!(
+                            "Macro {} expands to statements and can't be used as a value",
+                            name
+                        )))
+                    }
+                };
+                let value = self.expr(value, depth + 1)?;
+                self.stack.pop();
+                value
+            }
+            SeppoExpr::FunctionCall(name, args) => SeppoExpr::FunctionCall(
+                name,
+                args.into_iter()
+                    .map(|arg| self.expr(arg, depth))
+                    .collect::<Result<_>>()?,
+            ),
+            SeppoExpr::Operation(op, left, right) => SeppoExpr::Operation(
+                op,
+                Box::new(self.expr(*left, depth)?),
+                Box::new(self.expr(*right, depth)?),
+            ),
+            SeppoExpr::Range {
+                start,
+                end,
+                inclusive,
+            } => SeppoExpr::Range {
+                start: Box::new(self.expr(*start, depth)?),
+                end: Box::new(self.expr(*end, depth)?),
+                inclusive,
+            },
+            SeppoExpr::In(value, range) => SeppoExpr::In(
+                Box::new(self.expr(*value, depth)?),
+                Box::new(self.expr(*range, depth)?),
+            ),
+            SeppoExpr::Assignment(name, value) => {
+                SeppoExpr::Assignment(name, Box::new(self.expr(*value, depth)?))
+            }
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } => SeppoExpr::Let {
+                name,
+                mutable,
+                value: Box::new(self.expr(*value, depth)?),
+            },
+            SeppoExpr::Print(format, value) => {
+                SeppoExpr::Print(format, Box::new(self.expr(*value, depth)?))
+            }
+            SeppoExpr::Return(value) => SeppoExpr::Return(Box::new(self.expr(*value, depth)?)),
+            SeppoExpr::Become(name, args) => SeppoExpr::Become(
+                name,
+                args.into_iter()
+                    .map(|arg| self.expr(arg, depth))
+                    .collect::<Result<_>>()?,
+            ),
+            block @ SeppoExpr::Block(_) => self.block(block, depth)?,
+            conditional @ (SeppoExpr::Conditional { .. } | SeppoExpr::For { .. }) => {
+                let mut out = Vec::new();
+                self.statement(conditional, depth, &mut out)?;
+                out.remove(0)
+            }
+            other => other,
+        })
+    }
+
+    /// The body of macro `name` with `args` substituted, not yet expanded
+    /// itself. Pushes the expansion onto the stack; the caller pops it once
+    /// the body has been expanded.
+    fn instantiate(
+        &mut self,
+        name: &str,
+        args: Vec<SeppoExpr>,
+        depth: usize,
+    ) -> Result<Vec<SeppoExpr>> {
+        if depth >= RECURSION_LIMIT {
+            return Err(self.error(format!(
+                "Recursion limit of {} reached while expanding macro {}",
+                RECURSION_LIMIT, name
+            )));
+        }
+        let def = &self.macros[name];
+        if def.params.len() != args.len() {
+            return Err(self.error(format!(
+                "Macro {} expects {} arguments, got {}",
+                name,
+                def.params.len(),
+                args.len()
+            )));
+        }
+
+        self.expansions += 1;
+        let scope = Scope {
+            bindings: def.params.iter().cloned().zip(args).collect(),
+            suffix: format!("{}.{}", name, self.expansions),
+        };
+        let body = def
+            .body
+            .iter()
+            .map(|statement| scope.substitute(statement))
+            .collect::<std::result::Result<Vec<_>, String>>();
+        self.stack.push(format!("macro `{}`", name));
+        body.map_err(|message| self.error(message))
+    }
+
+    /// A semantic error naming the chain of expansions that led to it
+    fn error(&self, message: String) -> SeppoError {
+        let mut context = self.stack.iter().rev();
+        let mut message = message;
+        if let Some(innermost) = context.next() {
+            message.push_str(&format!(" (in {}", innermost));
+            for outer in context {
+                message.push_str(&format!(", expanded from {}", outer));
+            }
+            message.push(')');
+        }
+        SeppoError::Sema(message)
+    }
+}
+
+/// Whether a statement produces a value and can stand in for an expression
+fn is_value(expr: &SeppoExpr) -> bool {
+    matches!(
+        expr,
+        SeppoExpr::Number(_)
+            | SeppoExpr::String(_)
+            | SeppoExpr::Variable(_)
+            | SeppoExpr::Operation(..)
+            | SeppoExpr::FunctionCall(..)
+    )
+}
+
+/// Parameter bindings of one expansion
+struct Scope {
+    bindings: HashMap<String, SeppoExpr>,
+    /// Appended to the macro's own variables. Contains a `.`, which source
+    /// identifiers can't, so the renamed variables never capture the caller's.
+    suffix: String,
+}
+
+impl Scope {
+    fn variable(&self, name: &str) -> String {
+        format!("{}.{}", name, self.suffix)
+    }
+
+    /// The variable an assignment to `name` in the macro body writes to
+    fn target(&self, name: &str) -> std::result::Result<String, String> {
+        match self.bindings.get(name) {
+            Some(SeppoExpr::Variable(var)) => Ok(var.clone()),
+            Some(_) => Err(format!(
+                "Macro parameter {} is assigned to, so its argument must be a variable",
+                name
+            )),
+            None => Ok(self.variable(name)),
+        }
+    }
+
+    fn substitute(&self, expr: &SeppoExpr) -> std::result::Result<SeppoExpr, String> {
+        let boxed = |expr: &SeppoExpr| self.substitute(expr).map(Box::new);
+        Ok(match expr {
+            SeppoExpr::Variable(name) => match self.bindings.get(name) {
+                Some(arg) => arg.clone(),
+                None => SeppoExpr::Variable(self.variable(name)),
+            },
+            SeppoExpr::Assignment(name, value) => {
+                SeppoExpr::Assignment(self.target(name)?, boxed(value)?)
+            }
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } => SeppoExpr::Let {
+                name: self.target(name)?,
+                mutable: *mutable,
+                value: boxed(value)?,
+            },
+            SeppoExpr::Operation(op, left, right) => {
+                SeppoExpr::Operation(op.clone(), boxed(left)?, boxed(right)?)
+            }
+            SeppoExpr::Range {
+                start,
+                end,
+                inclusive,
+            } => SeppoExpr::Range {
+                start: boxed(start)?,
+                end: boxed(end)?,
+                inclusive: *inclusive,
+            },
+            SeppoExpr::In(value, range) => SeppoExpr::In(boxed(value)?, boxed(range)?),
+            SeppoExpr::Print(format, value) => SeppoExpr::Print(format.clone(), boxed(value)?),
+            SeppoExpr::Return(value) => SeppoExpr::Return(boxed(value)?),
+            SeppoExpr::Become(name, args) => SeppoExpr::Become(
+                name.clone(),
+                args.iter()
+                    .map(|arg| self.substitute(arg))
+                    .collect::<std::result::Result<_, _>>()?,
+            ),
+            SeppoExpr::FunctionCall(name, args) => SeppoExpr::FunctionCall(
+                name.clone(),
+                args.iter()
+                    .map(|arg| self.substitute(arg))
+                    .collect::<std::result::Result<_, _>>()?,
+            ),
+            SeppoExpr::Block(statements) => SeppoExpr::Block(
+                statements
+                    .iter()
+                    .map(|statement| self.substitute(statement))
+                    .collect::<std::result::Result<_, _>>()?,
+            ),
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => SeppoExpr::Conditional {
+                condition: boxed(condition)?,
+                true_block: boxed(true_block)?,
+                false_block: false_block.as_deref().map(boxed).transpose()?,
+            },
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => SeppoExpr::For {
+                variable: match self.bindings.get(variable) {
+                    Some(SeppoExpr::Variable(var)) => var.clone(),
+                    Some(_) => {
+                        return Err(format!(
+                            "Macro parameter {} is used as a loop variable, so its argument must be a variable",
+                            variable
+                        ))
+                    }
+                    None => self.variable(variable),
+                },
+                iterable: boxed(iterable)?,
+                body: boxed(body)?,
+            },
+            other => other.clone(),
+        })
+    }
+}
@@ -1,106 +1,1142 @@
-mod codegen;
-mod parser;
-mod types;
-
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use inkwell::context::Context;
-use inkwell::targets::{InitializationConfig, Target};
-use std::env;
-use std::env::consts::EXE_SUFFIX;
-use std::path::Path;
+use notify::{EventKind, RecursiveMode, Watcher};
+use seppolang::bench::{self, BenchOptions};
+use seppolang::coverage::{Coverage, DEFAULT_COVERAGE_FILE};
+use seppolang::diagnostic::{ColorChoice, Diagnostic, Severity};
+use seppolang::doc::{self, DocFormat};
+use seppolang::grammar::{self, GrammarFormat};
+use seppolang::lint::{self, Level, LintConfig};
+use seppolang::manifest::{Manifest, MANIFEST_NAME};
+use seppolang::repl::Session;
+use seppolang::testing::{self, TestStatus};
+use seppolang::timing;
+use seppolang::{
+    compile_driver, DivisionOverflow, DivisionRounding, Driver, Input, Jit, OptimizationLevel,
+    OutputKind, Profile, Sanitizer, SeppoError, StackProtector, StdoutBuffering, TargetDir,
+    TargetSpec,
+};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::mpsc;
+use std::time::Duration;
 
-fn compile_file(input: &Path, output: &Path) -> Result<()> {
-    let content = std::fs::read_to_string(input)?;
-    println!("Compiling {} to {}", input.display(), output.display());
+/// Compiler for SeppoLang, a minimalist language where everything is seppo
+#[derive(Parser)]
+#[command(name = "seppoc", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-    // Parse the input
-    let expr = parser::parse_seppo(&content)?;
+    /// Without a subcommand, seppoc builds its inputs
+    #[command(flatten)]
+    build: BuildArgs,
 
-    // Initialize LLVM
-    Target::initialize_native(&InitializationConfig::default())
-        .map_err(|e| anyhow!("Failed to initialize LLVM: {}", e))?;
+    /// When to color diagnostics
+    #[arg(long, value_enum, global = true, default_value_t = ColorWhen::Auto)]
+    color: ColorWhen,
 
-    // Generate code
-    let context = Context::create();
-    let mut codegen = codegen::CodeGen::new(&context, input.file_name().unwrap().to_str().unwrap());
-    codegen.compile(&expr)?;
+    /// Reject ceppo blocks and never start the C compiler, linker or git,
+    /// for untrusted programs. Interpreting and `--emit obj|llvm-ir` still
+    /// work.
+    #[arg(long, global = true)]
+    no_extern: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Compile and link a program
+    Build(BuildArgs),
+    /// Compile a program to a temporary directory and run it
+    Run {
+        /// Source file, or - for standard input
+        input: String,
+        #[command(flatten)]
+        codegen: CodegenArgs,
+        /// Arguments passed to the program, after --
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Evaluate a program with the interpreter, without LLVM
+    Interpret {
+        /// Source file, or - for standard input
+        input: String,
+    },
+    /// Type in definitions and statements and evaluate them with the
+    /// interpreter. `:save FILE` and `:load FILE` store and restore the session.
+    Repl,
+    /// Run the testi_* functions of a file
+    Test {
+        input: PathBuf,
+        /// Only run tests whose name contains this
+        filter: Option<String>,
+        /// Print how many of the file's lines the tests ran
+        #[arg(long)]
+        coverage: bool,
+    },
+    /// Time the bench_* functions of a file
+    Bench {
+        input: PathBuf,
+        /// Only run benchmarks whose name contains this
+        filter: Option<String>,
+    },
+    /// Reformat files in the canonical style
+    Fmt {
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Don't write anything, exit with status 1 if a file is not formatted
+        #[arg(long)]
+        check: bool,
+    },
+    /// Summarize the line counts written by programs built with --coverage
+    Cov {
+        /// Coverage files, summed together
+        #[arg(default_value = DEFAULT_COVERAGE_FILE)]
+        files: Vec<PathBuf>,
+        /// Print every source file with the count of each line
+        #[arg(long)]
+        show: bool,
+    },
+    /// Check a file for likely mistakes
+    Lint(LintArgs),
+    /// Generate API documentation from ## doc comments
+    Doc {
+        /// Source files [default: the sources of the project's seppo.toml]
+        inputs: Vec<PathBuf>,
+        #[arg(long, value_enum, default_value_t = Format::Html)]
+        format: Format,
+        /// File to write, defaults to standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Manifest of the project to document
+        #[arg(long, value_name = "PATH")]
+        manifest_path: Option<PathBuf>,
+    },
+    /// Generate a syntax highlighting grammar for editors from the
+    /// compiler's own grammar
+    Grammar {
+        #[arg(long, value_enum)]
+        format: EditorGrammar,
+        /// File to write, defaults to standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Remove the builds in the target directory
+    Clean {
+        /// Manifest of the project to clean [default: seppo.toml in the
+        /// current directory or a parent]
+        #[arg(long, value_name = "PATH")]
+        manifest_path: Option<PathBuf>,
+    },
+    /// Rebuild a file whenever it changes
+    Watch {
+        input: PathBuf,
+        /// Run the program after each successful build
+        #[arg(long)]
+        run: bool,
+        /// Run the program in the JIT and swap in changed functions while it
+        /// keeps running
+        #[arg(long, conflicts_with = "run")]
+        hot: bool,
+        #[command(flatten)]
+        codegen: CodegenArgs,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// Source files, or - for standard input
+    inputs: Vec<String>,
+    /// Output path, getting the extension of --emit unless it already ends
+    /// in it [default: named after the package or the first input, in
+    /// target/seppo/<profile>/]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Directory for builds without -o [default: target next to seppo.toml,
+    /// or in the current directory]
+    #[arg(long, value_name = "DIR")]
+    target_dir: Option<PathBuf>,
+    /// What to produce
+    #[arg(long, value_enum, default_value_t = Emit::Exe)]
+    emit: Emit,
+    /// Compile to an object file without linking, like --emit obj, for
+    /// linking in another build system
+    #[arg(short = 'c', conflicts_with_all = ["emit", "link_args", "strip", "post_link_cmds"])]
+    compile_only: bool,
+    /// Extra argument for the linker, may be repeated
+    #[arg(long = "link-arg", value_name = "ARG")]
+    link_args: Vec<String>,
+    /// Strip symbols from the executable after linking [default: $STRIP or
+    /// strip]
+    #[arg(long)]
+    strip: bool,
+    /// Command to run on the executable after linking, e.g. "codesign -s
+    /// -", which gets its path as the last argument, may be repeated
+    #[arg(
+        long = "post-link-cmd",
+        value_name = "COMMAND",
+        allow_hyphen_values = true
+    )]
+    post_link_cmds: Vec<String>,
+    /// Manifest to build when no inputs are given [default: seppo.toml in
+    /// the current directory or a parent]
+    #[arg(long, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
+    /// Evaluate the program with the interpreter instead of compiling it
+    #[arg(long, hide = true)]
+    interpret: bool,
+    #[command(flatten)]
+    codegen: CodegenArgs,
+}
+
+#[derive(Args)]
+struct CodegenArgs {
+    /// Target triple, e.g. wasm32-wasi [default: native]
+    #[arg(long)]
+    target: Option<String>,
+    /// Settings for a debug build (-O0, debug info, overflow checks) or a
+    /// release build (-O3, stripped), which other flags override
+    #[arg(long, value_enum)]
+    profile: Option<ProfileArg>,
+    /// Optimization level [default: 2]
+    #[arg(short = 'O', value_parser = ["0", "1", "2", "3"])]
+    opt_level: Option<String>,
+    /// Optimize the program and its ceppo blocks together, requires clang
+    #[arg(long)]
+    lto: bool,
+    /// Count how often each line runs, appending the counts to seppo.cov or
+    /// $SEPPO_COVERAGE_FILE when the program exits
+    #[arg(long)]
+    coverage: bool,
+    /// Instrument the program and its ceppo blocks with sanitizers
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "SANITIZERS")]
+    sanitize: Vec<SanitizerArg>,
+    /// Keep frame pointers so profilers can walk the stack
+    #[arg(long)]
+    frame_pointers: bool,
+    /// Check stack canaries before returning from functions with arrays or
+    /// address-taken locals, or from all functions
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "strong"
+    )]
+    stack_protector: Option<StackProtectorArg>,
+    /// Probe each page of large stack frames so they can't skip the guard
+    /// page
+    #[arg(long)]
+    stack_probes: bool,
+    /// Build a position-independent executable [default: as the system C
+    /// compiler does]
+    #[arg(long, overrides_with = "no_pie")]
+    pie: bool,
+    /// Build an executable loaded at a fixed address
+    #[arg(long, overrides_with = "pie")]
+    no_pie: bool,
+    /// Buffer stdout by line, in full or not at all [default: by line on a
+    /// terminal, in full otherwise]
+    #[arg(long, value_enum, value_name = "MODE")]
+    stdout_buffering: Option<StdoutBufferingArg>,
+    /// Keep every alloc on the heap, even those an optimized build would
+    /// move to the stack
+    #[arg(long)]
+    no_heap_to_stack: bool,
+    /// Round quotients toward zero or toward negative infinity [default:
+    /// trunc]
+    #[arg(long, value_enum, value_name = "MODE")]
+    division_rounding: Option<DivisionRoundingArg>,
+    /// Stop the program or wrap around when i64::MIN is divided by -1
+    /// [default: unchecked unless overflow checks are on]
+    #[arg(long, value_enum, value_name = "MODE")]
+    division_overflow: Option<DivisionOverflowArg>,
+    /// Stack size in bytes for the main thread and spawned threads, for
+    /// programs that recurse deeply
+    #[arg(long, value_name = "BYTES")]
+    stack_size: Option<u64>,
+    /// Build for perf and Instruments: frame pointers, unwind tables and
+    /// exported symbol names
+    #[arg(long)]
+    profiling: bool,
+    /// Print how long each compiler phase took, as a table or JSON
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "table"
+    )]
+    time_passes: Option<TimePassesFormat>,
+    /// C compiler for ceppo blocks, may include arguments [default: $CC or
+    /// cc]
+    #[arg(long, value_name = "COMPILER")]
+    cc: Option<String>,
+    /// Flags for the C compiler building ceppo blocks, e.g. "-O2
+    /// -march=native", may be repeated
+    #[arg(long, value_name = "FLAGS", allow_hyphen_values = true)]
+    cflags: Vec<String>,
+    /// Deepest nesting of blocks and calls to accept [default: 64]
+    #[arg(long, value_name = "DEPTH")]
+    max_nesting: Option<usize>,
+    /// Build a macOS universal binary for arm64 and x86_64
+    #[arg(long)]
+    universal: bool,
+    /// Keep the intermediate C sources and objects, printing where they are
+    #[arg(long)]
+    keep_temps: bool,
+    /// Build the entry point `fn NAME_seppo` of a file with several
+    /// programs instead of `seppo`
+    #[arg(long, value_name = "NAME")]
+    bin: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProfileArg {
+    Debug,
+    Release,
+}
+
+impl From<ProfileArg> for Profile {
+    fn from(profile: ProfileArg) -> Self {
+        match profile {
+            ProfileArg::Debug => Profile::Debug,
+            ProfileArg::Release => Profile::Release,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TimePassesFormat {
+    Table,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StackProtectorArg {
+    Strong,
+    All,
+}
+
+impl From<StackProtectorArg> for StackProtector {
+    fn from(stack_protector: StackProtectorArg) -> Self {
+        match stack_protector {
+            StackProtectorArg::Strong => StackProtector::Strong,
+            StackProtectorArg::All => StackProtector::All,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StdoutBufferingArg {
+    None,
+    Line,
+    Full,
+}
+
+impl From<StdoutBufferingArg> for StdoutBuffering {
+    fn from(buffering: StdoutBufferingArg) -> Self {
+        match buffering {
+            StdoutBufferingArg::None => StdoutBuffering::Unbuffered,
+            StdoutBufferingArg::Line => StdoutBuffering::Line,
+            StdoutBufferingArg::Full => StdoutBuffering::Full,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DivisionRoundingArg {
+    Trunc,
+    Floor,
+}
+
+impl From<DivisionRoundingArg> for DivisionRounding {
+    fn from(rounding: DivisionRoundingArg) -> Self {
+        match rounding {
+            DivisionRoundingArg::Trunc => DivisionRounding::Truncate,
+            DivisionRoundingArg::Floor => DivisionRounding::Floor,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DivisionOverflowArg {
+    Trap,
+    Wrap,
+}
+
+impl From<DivisionOverflowArg> for DivisionOverflow {
+    fn from(overflow: DivisionOverflowArg) -> Self {
+        match overflow {
+            DivisionOverflowArg::Trap => DivisionOverflow::Trap,
+            DivisionOverflowArg::Wrap => DivisionOverflow::Wrap,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SanitizerArg {
+    Address,
+    Undefined,
+}
+
+impl From<SanitizerArg> for Sanitizer {
+    fn from(sanitizer: SanitizerArg) -> Self {
+        match sanitizer {
+            SanitizerArg::Address => Sanitizer::Address,
+            SanitizerArg::Undefined => Sanitizer::Undefined,
+        }
+    }
+}
+
+impl CodegenArgs {
+    fn target(&self) -> Result<TargetSpec> {
+        match &self.target {
+            Some(triple) => Ok(TargetSpec::from_triple(triple)?),
+            None => Ok(TargetSpec::native()),
+        }
+    }
+
+    fn opt_level(&self) -> OptimizationLevel {
+        match self.opt_level.as_deref() {
+            Some("0") => OptimizationLevel::None,
+            Some("1") => OptimizationLevel::Less,
+            Some("3") => OptimizationLevel::Aggressive,
+            _ => OptimizationLevel::Default,
+        }
+    }
+
+    /// Override the driver's settings with the flags that were given, so
+    /// they win over a manifest
+    fn apply(&self, driver: &mut Driver) -> Result<()> {
+        if let Some(profile) = self.profile {
+            driver.apply_profile(profile.into());
+        }
+        if self.target.is_some() {
+            driver.target = self.target()?;
+        }
+        if self.opt_level.is_some() {
+            driver.opt_level = self.opt_level();
+        }
+        driver.lto |= self.lto;
+        driver.coverage |= self.coverage;
+        driver.frame_pointers |= self.frame_pointers;
+        if let Some(stack_protector) = self.stack_protector {
+            driver.stack_protector = Some(stack_protector.into());
+        }
+        driver.stack_probes |= self.stack_probes;
+        if self.pie || self.no_pie {
+            driver.pie = Some(self.pie);
+        }
+        if let Some(buffering) = self.stdout_buffering {
+            driver.stdout_buffering = Some(buffering.into());
+        }
+        if self.no_heap_to_stack {
+            driver.heap_to_stack = false;
+        }
+        if let Some(rounding) = self.division_rounding {
+            driver.division_rounding = rounding.into();
+        }
+        if let Some(overflow) = self.division_overflow {
+            driver.division_overflow = Some(overflow.into());
+        }
+        if self.stack_size.is_some() {
+            driver.stack_size = self.stack_size;
+        }
+        driver.profiling |= self.profiling;
+        driver.universal |= self.universal;
+        driver.keep_temps |= self.keep_temps;
+        if self.bin.is_some() {
+            driver.bin = self.bin.clone();
+        }
+        if self.cc.is_some() {
+            driver.c_compiler = self.cc.clone();
+        }
+        for flags in &self.cflags {
+            driver
+                .c_flags
+                .extend(flags.split_whitespace().map(str::to_string));
+        }
+        if let Some(max_nesting) = self.max_nesting {
+            driver.max_nesting = max_nesting;
+        }
+        for sanitizer in &self.sanitize {
+            let sanitizer = Sanitizer::from(*sanitizer);
+            if !driver.sanitizers.contains(&sanitizer) {
+                driver.sanitizers.push(sanitizer);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct LintArgs {
+    input: PathBuf,
+    /// Allow a lint, `all` for every lint
+    #[arg(short = 'A', value_name = "LINT")]
+    allow: Vec<String>,
+    /// Warn on a lint
+    #[arg(short = 'W', value_name = "LINT")]
+    warn: Vec<String>,
+    /// Make a lint an error
+    #[arg(short = 'D', value_name = "LINT")]
+    deny: Vec<String>,
+    /// File of `lint-name = "level"` lines
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Fail on any warning, for CI. Same as `-D warnings`.
+    #[arg(long)]
+    deny_warnings: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Emit {
+    Exe,
+    Obj,
+    LlvmIr,
+    AstJson,
+    Tokens,
+}
 
-    // Verify module
-    if codegen.get_module().verify().is_err() {
-        return Err(anyhow!("Module verification failed"));
+impl From<Emit> for OutputKind {
+    fn from(emit: Emit) -> Self {
+        match emit {
+            Emit::Exe => OutputKind::Executable,
+            Emit::Obj => OutputKind::Object,
+            Emit::LlvmIr => OutputKind::LlvmIr,
+            Emit::AstJson => OutputKind::AstJson,
+            Emit::Tokens => OutputKind::Tokens,
+        }
     }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Html,
+    Markdown,
+}
 
-    // Write LLVM IR (optional, for debugging)
-    codegen
-        .get_module()
-        .print_to_file(output.with_extension("ll"))
-        .map_err(|e| anyhow!("Failed to write LLVM IR: {}", e.to_string()))?;
+#[derive(Clone, Copy, ValueEnum)]
+enum EditorGrammar {
+    /// TextMate JSON, for VS Code and Sublime Text
+    Tmlanguage,
+    /// A tree-sitter grammar.js
+    TreeSitter,
+}
 
-    // Generate object file
-    let obj_file = output.with_extension("o");
-    codegen.write_object_file(&obj_file)?;
+impl From<EditorGrammar> for GrammarFormat {
+    fn from(format: EditorGrammar) -> Self {
+        match format {
+            EditorGrammar::Tmlanguage => GrammarFormat::TmLanguage,
+            EditorGrammar::TreeSitter => GrammarFormat::TreeSitter,
+        }
+    }
+}
+
+impl From<Format> for DocFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Html => DocFormat::Html,
+            Format::Markdown => DocFormat::Markdown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<ColorWhen> for ColorChoice {
+    fn from(when: ColorWhen) -> Self {
+        match when {
+            ColorWhen::Auto => ColorChoice::Auto,
+            ColorWhen::Always => ColorChoice::Always,
+            ColorWhen::Never => ColorChoice::Never,
+        }
+    }
+}
 
-    // Link the object file
-    let output_exe = output.with_extension(EXE_SUFFIX);
-    link_object_file(&obj_file, &output_exe, &codegen)?;
+/// Run a driver with the target and optimization settings from the command line
+fn compile(mut driver: Driver, codegen: &CodegenArgs) -> Result<PathBuf> {
+    // A profile decides the directory the output goes to
+    codegen.apply(&mut driver)?;
+    let names: Vec<String> = driver.inputs.iter().map(Input::name).collect();
+    println!(
+        "Compiling {} to {}",
+        names.join(", "),
+        driver.output_stem()?.display()
+    );
+    // Write LLVM IR (optional, for debugging), unless a release build
+    // turned it off
+    driver.emit.llvm_ir = driver.dump_ir;
 
-    // Clean up intermediate files
-    std::fs::remove_file(&obj_file)
-        .map_err(|e| anyhow!("Failed to clean up object file: {}", e))?;
+    let output_exe = timed(codegen.time_passes, || driver.run())?;
 
     println!("Successfully compiled to {}", output_exe.display());
+    Ok(output_exe)
+}
+
+/// Run `build`, reporting the time of each pass when `--time-passes` asks
+/// for it
+fn timed<T>(
+    time_passes: Option<TimePassesFormat>,
+    build: impl FnOnce() -> seppolang::Result<T>,
+) -> Result<T> {
+    let Some(format) = time_passes else {
+        return Ok(build()?);
+    };
+    let (result, times) = timing::measure(build);
+    // On stderr, so the report stays apart from the program's output
+    match format {
+        TimePassesFormat::Table => eprint!("{}", times.to_table()),
+        TimePassesFormat::Json => eprintln!("{}", times.to_json()),
+    }
+    Ok(result?)
+}
+
+fn interpret_file(input: Input) -> Result<i64> {
+    let expr = Driver::new(vec![input]).parse()?;
+    Ok(seppolang::interp::eval(&expr)?)
+}
+
+/// Read inputs until standard input ends. An input continues over several
+/// lines until its braces are balanced.
+fn repl_command(color: ColorChoice) -> Result<()> {
+    let mut session = Session::new();
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if input.is_empty() {
+            let command = line.trim();
+            if let Some(command) = command.strip_prefix(':') {
+                let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+                let path = Path::new(arg.trim());
+                let result = match name {
+                    "save" if !arg.is_empty() => session.save(path),
+                    "load" if !arg.is_empty() => session.load(path),
+                    "quit" | "q" => return Ok(()),
+                    _ => {
+                        eprintln!("Commands: :save FILE, :load FILE, :quit");
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    report(&e.into(), color);
+                }
+                continue;
+            }
+            if command.is_empty() {
+                continue;
+            }
+        }
+
+        input.push_str(&line);
+        let depth = input.matches('{').count() as isize - input.matches('}').count() as isize;
+        if depth > 0 {
+            continue;
+        }
+        match session.eval(&std::mem::take(&mut input)) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {}
+            Err(e) => report(&e.into(), color),
+        }
+    }
+}
+
+/// Rewrite a file in canonical style. With `check` the file is left alone
+/// and the return value tells whether it was already formatted.
+fn format_file(input: &Path, check: bool) -> Result<bool> {
+    let source = fs::read_to_string(input)?;
+    let formatted = seppolang::format_source(&source)?;
+    if formatted == source {
+        return Ok(true);
+    }
+    if check {
+        println!("{} is not formatted", input.display());
+    } else {
+        fs::write(input, formatted)?;
+    }
+    Ok(false)
+}
+
+/// Lint a file, exiting with status 1 if a denied lint fires
+fn lint_command(args: &LintArgs, color: ColorChoice) -> Result<()> {
+    let mut config = match &args.config {
+        Some(path) => LintConfig::from_file(path)?,
+        None => LintConfig::new(),
+    };
+    // Command line flags win over the config file
+    for (names, level) in [
+        (&args.allow, Level::Allow),
+        (&args.warn, Level::Warn),
+        (&args.deny, Level::Deny),
+    ] {
+        for name in names {
+            config.set(name, level)?;
+        }
+    }
+    if args.deny_warnings {
+        config.set_deny_warnings(true);
+    }
+
+    let program = Driver::for_file(&args.input).parse()?;
+    let diagnostics = lint::lint(&program, &config);
+    for diagnostic in &diagnostics {
+        eprint!(
+            "{}",
+            Diagnostic::from_lint(diagnostic).render(None, color.use_color())
+        );
+    }
+    if diagnostics.iter().any(|d| d.level == Level::Deny) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+/// `seppoc doc [inputs]`: render the functions of the inputs, or of the
+/// current project, with their doc comments
+fn doc_command(
+    inputs: Vec<PathBuf>,
+    manifest_path: Option<PathBuf>,
+    format: DocFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    let (title, sources) = if inputs.is_empty() {
+        let path = manifest_path
+            .or_else(|| Manifest::find(&std::env::current_dir().ok()?))
+            .ok_or_else(|| anyhow::anyhow!("No input files and no {} found", MANIFEST_NAME))?;
+        let manifest = Manifest::from_file(&path)?;
+        (manifest.name, manifest.sources)
+    } else {
+        let title = inputs[0]
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "seppo".to_string());
+        (title, inputs)
+    };
+
+    let mut functions = Vec::new();
+    for source in sources {
+        functions.extend(doc::collect(&Input::File(source).parse()?));
+    }
+    let rendered = doc::render(&title, &functions, format);
+    match output {
+        Some(path) => fs::write(path, rendered)?,
+        None => print!("{}", rendered),
+    }
     Ok(())
 }
 
-fn link_object_file(obj_file: &Path, output: &Path, codegen: &codegen::CodeGen) -> Result<()> {
-    // Create a basic link command
-    let mut link_command = Command::new("cc");
-    link_command
-        .arg("-v") // Add verbose output for debugging
-        .arg("-o")
-        .arg(output)
-        .arg(obj_file);
+/// `seppoc test input.seppo [filter]`: run every `testi_*` function and
+/// report those returning nonzero as failures
+fn test_command(input: &Path, filter: Option<&str>, coverage: bool) -> Result<()> {
+    // Test files don't need a seppo entry point
+    let input = Input::File(input.to_path_buf());
+    let (outcomes, coverage) = if coverage {
+        let program = input.parse_with_locations()?;
+        let (outcomes, coverage) = testing::run_tests_with_coverage(&program, filter)?;
+        (outcomes, Some(coverage))
+    } else {
+        (testing::run_tests(&input.parse()?, filter)?, None)
+    };
 
-    // Add any C object files from ceppo blocks
-    // We need to pass the CodeGen instance here to access c_object_files
-    for c_obj in codegen.c_object_files() {
-        println!("Adding C object file: {:?}", c_obj);
-        link_command.arg(c_obj);
+    println!("\nrunning {} tests", outcomes.len());
+    let mut failed = Vec::new();
+    for outcome in &outcomes {
+        match outcome.status {
+            TestStatus::Passed => println!("test {} ... ok", outcome.name),
+            TestStatus::Failed(code) => {
+                println!("test {} ... FAILED (returned {})", outcome.name, code);
+                failed.push(&outcome.name);
+            }
+        }
     }
 
-    println!("Running linker command: {:?}", link_command);
-    let output = link_command.output()?;
+    let result = if failed.is_empty() { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        result,
+        outcomes.len() - failed.len(),
+        failed.len()
+    );
+    if let Some(coverage) = coverage {
+        print!("\ncoverage:\n{}", coverage.summary());
+    }
+    if !failed.is_empty() {
+        std::process::exit(101);
+    }
+    Ok(())
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        eprintln!("Linking failed:");
-        eprintln!("stderr: {}", stderr);
-        eprintln!("stdout: {}", stdout);
-        eprintln!("Link command was: {:?}", link_command);
-        return Err(anyhow!("Linking failed: {}", stderr));
+/// `seppoc cov [files] [--show]`: sum coverage files and print the share of
+/// lines that ran, or each source annotated with its counts
+fn cov_command(files: &[PathBuf], show: bool) -> Result<()> {
+    let mut coverage = Coverage::default();
+    for file in files {
+        coverage.merge(&Coverage::from_file(file)?);
     }
 
+    if !show {
+        print!("{}", coverage.summary());
+        return Ok(());
+    }
+    for file in coverage.files.keys() {
+        let source = fs::read_to_string(file)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", file, e))?;
+        println!("{}:", file);
+        print!("{}", coverage.annotate(file, &source));
+        println!();
+    }
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+/// `seppoc bench input.seppo [filter]`: time every `bench_*` function
+fn bench_command(input: &Path, filter: Option<&str>) -> Result<()> {
+    let program = Input::File(input.to_path_buf()).parse()?;
+    let results = bench::run_benches(&program, filter, &BenchOptions::default())?;
+
+    println!("\nrunning {} benchmarks", results.len());
+    for result in &results {
+        println!(
+            "bench {} ... {:.1} ns/iter (+/- {:.1}) min {:.1} max {:.1}, {} iters/sample",
+            result.name,
+            result.median,
+            result.std_dev,
+            result.min,
+            result.max,
+            result.iterations_per_sample
+        );
+    }
+    Ok(())
+}
+
+/// `seppoc watch input.seppo [--run]`: recompile whenever the file changes,
+/// reporting errors instead of exiting
+fn watch_command(input: &Path, run: bool, codegen: &CodegenArgs, color: ColorChoice) -> Result<()> {
+    let changes = FileChanges::new(input)?;
+    loop {
+        rebuild(input, run, codegen, color);
+        println!("Watching {} for changes...", input.display());
+        changes.wait()?;
+    }
+}
+
+/// `seppoc watch input.seppo --hot`: run `seppo` in the JIT on a thread of
+/// its own, and on every change recompile the functions that changed and
+/// swap them in while the program keeps running. Once the program exits,
+/// the next change starts it again.
+fn hot_watch_command(input: &Path, color: ColorChoice) -> Result<()> {
+    let changes = FileChanges::new(input)?;
+    let parse = || Input::File(input.to_path_buf()).parse();
+    let context = Context::create();
+    // Until the file first compiles there is nothing to reload
+    let mut jit = loop {
+        match parse().and_then(|program| Jit::hot(&context, &program)) {
+            Ok(jit) => break jit,
+            Err(e) => report(&e.into(), color),
+        }
+        println!("Watching {} for changes...", input.display());
+        changes.wait()?;
+    };
+
+    loop {
+        // The JIT lives as long as this function, which never returns
+        // while the program runs
+        let running = unsafe { jit.spawn("seppo")? };
+        while !running.is_finished() {
+            println!("Watching {} for changes...", input.display());
+            changes.wait()?;
+            match parse().and_then(|program| jit.reload(&program)) {
+                Ok(changed) if changed.is_empty() => println!("No functions changed"),
+                Ok(changed) => println!("Reloaded {}", changed.join(", ")),
+                Err(e) => report(&e.into(), color),
+            }
+        }
+        match running.join() {
+            Ok(code) => println!("Program exited with {}", code),
+            Err(_) => println!("Program panicked"),
+        }
+    }
+}
+
+/// Changes to one file, for the watch modes
+struct FileChanges {
+    rx: mpsc::Receiver<notify::Result<notify::Event>>,
+    file_name: Option<std::ffi::OsString>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl FileChanges {
+    fn new(input: &Path) -> Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        // Editors often replace the file instead of writing it, so watch the
+        // directory and pick out events for our file
+        let dir = match input.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            rx,
+            file_name: input.file_name().map(|name| name.to_owned()),
+            _watcher: watcher,
+        })
+    }
+
+    /// Wait for a change to the file, then let a burst of events settle
+    fn wait(&self) -> Result<()> {
+        loop {
+            let event: notify::Event = match self.rx.recv()? {
+                Ok(event) => event,
+                Err(e) => {
+                    eprintln!("Watch error: {}", e);
+                    continue;
+                }
+            };
+            let modifies = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_));
+            if modifies
+                && event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name() == self.file_name.as_deref())
+            {
+                break;
+            }
+        }
+        while self.rx.recv_timeout(Duration::from_millis(100)).is_ok() {}
+        Ok(())
+    }
+}
+
+fn rebuild(input: &Path, run: bool, codegen: &CodegenArgs, color: ColorChoice) {
+    let mut driver = Driver::for_file(input);
+    driver.target_dir = Some(PathBuf::from(TargetDir::DEFAULT));
+    let executable = match compile(driver, codegen) {
+        Ok(executable) => executable,
+        Err(e) => {
+            report(&e, color);
+            return;
+        }
+    };
+    if run {
+        match Command::new(&executable).status() {
+            Ok(status) => match status.code() {
+                Some(code) => println!("Program exited with {}", code),
+                None => {
+                    println!("Program terminated by signal");
+                    crash_hint(&status);
+                }
+            },
+            Err(e) => eprintln!("Failed to run {}: {}", executable.display(), e),
+        }
+    }
+}
+
+/// `seppoc run input.seppo [-- args...]`: compile to a temporary directory,
+/// run the program with the given arguments and exit with its status
+fn run_command(input: Input, program_args: &[String], codegen: &CodegenArgs) -> Result<()> {
+    let mut driver = Driver::new(vec![input]);
+    codegen.apply(&mut driver)?;
+    driver.dump_ir = false;
+    // Named like `build` would name it, in a temporary directory
+    let stem = driver.output_stem()?;
+    let name = stem
+        .file_name()
+        .map_or("main".into(), |name| name.to_string_lossy());
+    let program = timed(codegen.time_passes, || compile_driver(driver, &name))?;
+    let status = program.command().args(program_args).status()?;
+    crash_hint(&status);
+    // Remove the temporary build before exiting, which skips destructors
+    drop(program);
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Point at `--stack-size` when a program dies of a segmentation fault,
+/// which is what running out of stack in deep recursion looks like
+fn crash_hint(status: &std::process::ExitStatus) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        const SIGSEGV: i32 = 11;
+        if status.signal() == Some(SIGSEGV) {
+            eprintln!(
+                "note: the program crashed with a segmentation fault, if it recurses deeply \
+                 it may have run out of stack, which --stack-size can raise"
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+/// Print an error as a diagnostic, with the source line when it has one
+fn report(error: &anyhow::Error, color: ColorChoice) {
+    let diagnostic = match error.downcast_ref::<SeppoError>() {
+        Some(error) => Diagnostic::from_error(error),
+        None => Diagnostic::new(Severity::Error, error.to_string()),
+    };
+    let source = diagnostic
+        .location
+        .as_ref()
+        .and_then(|location| location.file.as_ref())
+        .and_then(|file| fs::read_to_string(file).ok());
+    eprint!(
+        "{}",
+        diagnostic.render(source.as_deref(), color.use_color())
+    );
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let color = ColorChoice::from(cli.color);
+    seppolang::ice::install_hook(color);
+    let result = if cli.no_extern {
+        seppolang::sandbox::with_no_extern(|| run(cli, color))
+    } else {
+        run(cli, color)
+    };
+    if let Err(e) = result {
+        report(&e, color);
+        std::process::exit(1);
+    }
+}
 
-    match args.as_slice() {
-        [_, input] => {
-            let input_path = Path::new(input);
-            let output = input_path.with_extension("");
-            compile_file(input_path, &output)?;
+fn run(cli: Cli, color: ColorChoice) -> Result<()> {
+    match cli.command {
+        Some(Commands::Build(args)) => build_command(&args),
+        None if cli.build.inputs.is_empty() && find_manifest(&cli.build).is_none() => {
+            use clap::CommandFactory;
+            Cli::command().print_help()?;
+            Ok(())
+        }
+        None => build_command(&cli.build),
+        Some(Commands::Run {
+            input,
+            codegen,
+            args,
+        }) => run_command(Input::from_arg(&input), &args, &codegen),
+        Some(Commands::Interpret { input }) => {
+            let code = interpret_file(Input::from_arg(&input))?;
+            std::process::exit(code as i32);
         }
-        [_, input, output] => {
-            compile_file(Path::new(input), Path::new(output))?;
+        Some(Commands::Repl) => repl_command(color),
+        Some(Commands::Test {
+            input,
+            filter,
+            coverage,
+        }) => test_command(&input, filter.as_deref(), coverage),
+        Some(Commands::Cov { files, show }) => cov_command(&files, show),
+        Some(Commands::Bench { input, filter }) => bench_command(&input, filter.as_deref()),
+        Some(Commands::Fmt { inputs, check }) => {
+            let mut all_formatted = true;
+            for input in &inputs {
+                all_formatted &= format_file(input, check)?;
+            }
+            if check && !all_formatted {
+                std::process::exit(1);
+            }
+            Ok(())
         }
-        _ => {
-            println!("Usage: seppoc input.seppo [output]");
+        Some(Commands::Lint(args)) => lint_command(&args, color),
+        Some(Commands::Doc {
+            inputs,
+            format,
+            output,
+            manifest_path,
+        }) => doc_command(inputs, manifest_path, format.into(), output.as_deref()),
+        Some(Commands::Grammar { format, output }) => {
+            let rendered = grammar::render(format.into());
+            match output {
+                Some(path) => fs::write(path, rendered)?,
+                None => print!("{}", rendered),
+            }
+            Ok(())
         }
+        Some(Commands::Clean { manifest_path }) => clean_command(manifest_path.as_ref()),
+        Some(Commands::Watch {
+            input, hot: true, ..
+        }) => hot_watch_command(&input, color),
+        Some(Commands::Watch {
+            input,
+            run,
+            codegen,
+            ..
+        }) => watch_command(&input, run, &codegen, color),
+    }
+}
+
+fn build_command(args: &BuildArgs) -> Result<()> {
+    if args.interpret {
+        let [input] = args.inputs.as_slice() else {
+            return Err(anyhow::anyhow!("--interpret takes a single input"));
+        };
+        let code = interpret_file(Input::from_arg(input))?;
+        std::process::exit(code as i32);
     }
 
+    let mut driver = if args.inputs.is_empty() {
+        let path = find_manifest(args)
+            .ok_or_else(|| anyhow::anyhow!("No input files and no {} found", MANIFEST_NAME))?;
+        Manifest::from_file(&path)?.driver()?
+    } else {
+        let mut driver = Driver::new(args.inputs.iter().map(|arg| Input::from_arg(arg)).collect());
+        driver.target_dir = Some(PathBuf::from(TargetDir::DEFAULT));
+        driver
+    };
+    if args.output.is_some() {
+        driver.output = args.output.clone();
+    }
+    if args.target_dir.is_some() {
+        driver.target_dir = args.target_dir.clone();
+    }
+    driver.output_kind = if args.compile_only {
+        OutputKind::Object
+    } else {
+        args.emit.into()
+    };
+    driver.linker_args.extend(args.link_args.iter().cloned());
+    driver.strip |= args.strip;
+    driver.post_link.extend(args.post_link_cmds.iter().cloned());
+    compile(driver, &args.codegen)?;
+    Ok(())
+}
+
+fn find_manifest(args: &BuildArgs) -> Option<PathBuf> {
+    find_manifest_at(args.manifest_path.as_ref())
+}
+
+fn find_manifest_at(manifest_path: Option<&PathBuf>) -> Option<PathBuf> {
+    match manifest_path {
+        Some(path) => Some(path.clone()),
+        None => Manifest::find(&std::env::current_dir().ok()?),
+    }
+}
+
+/// `seppoc clean`: remove `target/seppo` of the project, or of the current
+/// directory outside of one
+fn clean_command(manifest_path: Option<&PathBuf>) -> Result<()> {
+    let target_dir = match find_manifest_at(manifest_path) {
+        Some(path) => Manifest::from_file(&path)?.target_dir,
+        None => PathBuf::from(TargetDir::DEFAULT),
+    };
+    let target_dir = TargetDir::new(target_dir);
+    if target_dir.clean()? {
+        println!("Removed {}", target_dir.seppo_dir().display());
+    }
     Ok(())
 }
@@ -0,0 +1,439 @@
+use crate::driver::{Driver, Input, Profile};
+use crate::error::{Result, SeppoError};
+use crate::output::TargetDir;
+use crate::sandbox::check_spawn;
+use crate::target::TargetSpec;
+use inkwell::OptimizationLevel;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use toml::{Table, Value};
+
+/// File name `seppoc build` looks for
+pub const MANIFEST_NAME: &str = "seppo.toml";
+
+/// A project described by a `seppo.toml`:
+///
+/// ```toml
+/// [package]
+/// name = "hello"
+///
+/// [build]
+/// sources = ["src/main.seppo", "src/util.seppo"]
+/// output = "hello"
+/// target-dir = "target"
+/// libraries = ["m"]
+/// library-paths = ["lib"]
+/// cflags = ["-DDEBUG"]
+/// profile = "release"
+/// lto = true
+/// target = "native"
+/// strip = true
+/// post-link = ["codesign -s -"]
+///
+/// [dependencies]
+/// strings = { path = "../strings" }
+/// hashes = { git = "https://github.com/someone/hashes", rev = "v1.0" }
+/// ```
+///
+/// Everything but the package name is optional. Paths are relative to the
+/// directory of the manifest.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    /// Directory containing the manifest
+    pub dir: PathBuf,
+    pub sources: Vec<PathBuf>,
+    /// Output path without extension, if `build.output` gives one
+    pub output: Option<PathBuf>,
+    /// Where builds go when there is no output path, `target` next to the
+    /// manifest unless `build.target-dir` says otherwise, see `TargetDir`
+    pub target_dir: PathBuf,
+    /// Libraries linked with `-l`
+    pub libraries: Vec<String>,
+    /// Directories searched for libraries with `-L`
+    pub library_paths: Vec<PathBuf>,
+    /// C compiler for ceppo blocks, see `Driver::c_compiler`
+    pub c_compiler: Option<String>,
+    /// Extra C compiler arguments for ceppo blocks
+    pub c_flags: Vec<String>,
+    /// Settings bundle, see `Driver::apply_profile`
+    pub profile: Option<Profile>,
+    pub opt_level: OptimizationLevel,
+    /// Link-time optimization, see `Driver::lto`
+    pub lto: bool,
+    pub target: TargetSpec,
+    /// Strip the executable, see `Driver::strip`
+    pub strip: bool,
+    /// Commands run on the executable after linking, see
+    /// `Driver::post_link`
+    pub post_link: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+}
+
+/// Another seppo package whose sources are compiled into this one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub source: DependencySource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencySource {
+    /// A package directory, relative paths resolved against the manifest
+    Path(PathBuf),
+    /// A git repository cloned into the package cache, optionally pinned to
+    /// a branch, tag or commit
+    Git { url: String, rev: Option<String> },
+}
+
+impl Manifest {
+    /// Look for a manifest in `dir` and its parents
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(MANIFEST_NAME))
+            .find(|path| path.is_file())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| SeppoError::Config(format!("Failed to read {}: {}", path.display(), e)))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        Self::parse(&text, &dir)
+            .map_err(|e| SeppoError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parse manifest text for a project rooted at `dir`
+    pub fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let table: Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| SeppoError::Config(e.message().to_string()))?;
+
+        let package = section(&table, "package")?;
+        let name = package
+            .and_then(|package| package.get("name"))
+            .ok_or_else(|| SeppoError::Config("Missing package.name".to_string()))
+            .and_then(|value| string(value, "package.name"))?;
+
+        let build = section(&table, "build")?;
+        let get = |key: &str| build.and_then(|build| build.get(key));
+
+        let sources = match get("sources") {
+            Some(value) => strings(value, "build.sources")?
+                .into_iter()
+                .map(|source| dir.join(source))
+                .collect(),
+            None => vec![default_source(dir)],
+        };
+        if sources.is_empty() {
+            return Err(SeppoError::Config(
+                "build.sources must list at least one file".to_string(),
+            ));
+        }
+
+        let output = get("output")
+            .map(|value| string(value, "build.output").map(|output| dir.join(output)))
+            .transpose()?;
+        let target_dir = match get("target-dir") {
+            Some(value) => dir.join(string(value, "build.target-dir")?),
+            None => dir.join(TargetDir::DEFAULT),
+        };
+        let libraries = get("libraries")
+            .map(|value| strings(value, "build.libraries"))
+            .transpose()?
+            .unwrap_or_default();
+        let library_paths = get("library-paths")
+            .map(|value| strings(value, "build.library-paths"))
+            .transpose()?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|path| dir.join(path))
+            .collect();
+        let c_compiler = get("cc")
+            .map(|value| string(value, "build.cc"))
+            .transpose()?;
+        let c_flags = get("cflags")
+            .map(|value| strings(value, "build.cflags"))
+            .transpose()?
+            .unwrap_or_default();
+
+        let profile = get("profile")
+            .map(|value| {
+                let name = string(value, "build.profile")?;
+                Profile::from_name(&name).ok_or_else(|| {
+                    SeppoError::Config(format!(
+                        "Unknown build.profile: {} (expected debug or release)",
+                        name
+                    ))
+                })
+            })
+            .transpose()?;
+        let opt_level = profile.map_or(OptimizationLevel::Default, Profile::opt_level);
+        let lto = match get("lto") {
+            Some(value) => value
+                .as_bool()
+                .ok_or_else(|| SeppoError::Config("build.lto must be a boolean".to_string()))?,
+            None => false,
+        };
+        let strip = match get("strip") {
+            Some(value) => value
+                .as_bool()
+                .ok_or_else(|| SeppoError::Config("build.strip must be a boolean".to_string()))?,
+            None => false,
+        };
+        let post_link = get("post-link")
+            .map(|value| strings(value, "build.post-link"))
+            .transpose()?
+            .unwrap_or_default();
+        let target = match get("target") {
+            Some(value) => TargetSpec::from_triple(&string(value, "build.target")?)?,
+            None => TargetSpec::native(),
+        };
+        let dependencies = match section(&table, "dependencies")? {
+            Some(table) => table
+                .iter()
+                .map(|(name, value)| Dependency::parse(name, value, dir))
+                .collect::<Result<_>>()?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            name,
+            dir: dir.to_path_buf(),
+            sources,
+            output,
+            target_dir,
+            libraries,
+            library_paths,
+            c_compiler,
+            c_flags,
+            profile,
+            opt_level,
+            lto,
+            target,
+            strip,
+            post_link,
+            dependencies,
+        })
+    }
+
+    /// Load every package this one depends on, directly or transitively,
+    /// fetching git dependencies as needed. Dependencies come before the
+    /// packages that use them, since a function must be defined before it
+    /// is called.
+    pub fn resolve(&self) -> Result<Vec<Manifest>> {
+        let mut resolved = Vec::new();
+        let mut stack = vec![self.name.clone()];
+        self.resolve_into(&mut resolved, &mut stack)?;
+        Ok(resolved)
+    }
+
+    fn resolve_into(&self, resolved: &mut Vec<Manifest>, stack: &mut Vec<String>) -> Result<()> {
+        for dependency in &self.dependencies {
+            if stack.contains(&dependency.name) {
+                stack.push(dependency.name.clone());
+                return Err(SeppoError::Config(format!(
+                    "Dependency cycle: {}",
+                    stack.join(" -> ")
+                )));
+            }
+            if resolved
+                .iter()
+                .any(|package| package.name == dependency.name)
+            {
+                continue;
+            }
+
+            let manifest = Manifest::from_file(&dependency.fetch()?.join(MANIFEST_NAME))?;
+            if manifest.name != dependency.name {
+                return Err(SeppoError::Config(format!(
+                    "Dependency {} points at package {}",
+                    dependency.name, manifest.name
+                )));
+            }
+            stack.push(dependency.name.clone());
+            manifest.resolve_into(resolved, stack)?;
+            stack.pop();
+            resolved.push(manifest);
+        }
+        Ok(())
+    }
+
+    /// A driver that builds the project's executable together with its
+    /// dependencies, into `build.output` or the target directory
+    pub fn driver(&self) -> Result<Driver> {
+        let mut packages = self.resolve()?;
+        packages.push(self.clone());
+
+        let inputs = packages
+            .iter()
+            .flat_map(|package| package.sources.iter().cloned().map(Input::File))
+            .collect();
+        let mut driver = Driver::new(inputs);
+        if let Some(profile) = self.profile {
+            driver.apply_profile(profile);
+        }
+        driver.output = self.output.clone();
+        driver.target_dir = Some(self.target_dir.clone());
+        driver.package = Some(self.name.clone());
+        driver.opt_level = self.opt_level;
+        driver.lto = self.lto;
+        driver.target = self.target.clone();
+        driver.c_compiler = self.c_compiler.clone();
+        driver.strip |= self.strip;
+        driver.post_link = self.post_link.clone();
+        for package in &packages {
+            driver.c_flags.extend(package.c_flags.iter().cloned());
+            driver.linker_args.extend(
+                package
+                    .library_paths
+                    .iter()
+                    .map(|path| format!("-L{}", path.display())),
+            );
+        }
+        for package in &packages {
+            driver
+                .linker_args
+                .extend(package.libraries.iter().map(|lib| format!("-l{}", lib)));
+        }
+        Ok(driver)
+    }
+}
+
+impl Dependency {
+    fn parse(name: &str, value: &Value, dir: &Path) -> Result<Self> {
+        let key = format!("dependencies.{}", name);
+        let table = value
+            .as_table()
+            .ok_or_else(|| SeppoError::Config(format!("{} must be a table", key)))?;
+        let get = |field: &str| {
+            table
+                .get(field)
+                .map(|value| string(value, &format!("{}.{}", key, field)))
+                .transpose()
+        };
+
+        let source = match (get("path")?, get("git")?) {
+            (Some(path), None) => DependencySource::Path(dir.join(path)),
+            (None, Some(url)) => DependencySource::Git {
+                url,
+                rev: get("rev")?,
+            },
+            _ => {
+                return Err(SeppoError::Config(format!(
+                    "{} needs exactly one of path or git",
+                    key
+                )))
+            }
+        };
+        Ok(Self {
+            name: name.to_string(),
+            source,
+        })
+    }
+
+    /// Directory of the dependency's package, cloning it into the cache
+    /// first for git dependencies
+    pub fn fetch(&self) -> Result<PathBuf> {
+        match &self.source {
+            DependencySource::Path(path) => Ok(path.clone()),
+            DependencySource::Git { url, rev } => {
+                let checkout =
+                    cache_dir()
+                        .join("git")
+                        .join(format!("{}-{:016x}", self.name, fnv1a(url)));
+                if !checkout.join(".git").is_dir() {
+                    fs::create_dir_all(&checkout).map_err(|e| {
+                        SeppoError::Config(format!(
+                            "Failed to create {}: {}",
+                            checkout.display(),
+                            e
+                        ))
+                    })?;
+                    git(&["clone", "--quiet", url, "."], &checkout)?;
+                }
+                if let Some(rev) = rev {
+                    if git(&["checkout", "--quiet", rev], &checkout).is_err() {
+                        git(&["fetch", "--quiet", "origin"], &checkout)?;
+                        git(&["checkout", "--quiet", rev], &checkout)?;
+                    }
+                }
+                Ok(checkout)
+            }
+        }
+    }
+}
+
+/// `src/main.seppo`, or `src/lib.seppo` for library packages without one
+fn default_source(dir: &Path) -> PathBuf {
+    let src = dir.join("src");
+    if !src.join("main.seppo").exists() && src.join("lib.seppo").exists() {
+        src.join("lib.seppo")
+    } else {
+        src.join("main.seppo")
+    }
+}
+
+/// Where git dependencies are cloned: `$SEPPO_HOME`, or `~/.seppo`
+fn cache_dir() -> PathBuf {
+    if let Some(home) = env::var_os("SEPPO_HOME") {
+        return PathBuf::from(home);
+    }
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join(".seppo")
+}
+
+fn git(args: &[&str], dir: &Path) -> Result<()> {
+    let mut command = Command::new("git");
+    command.args(args).current_dir(dir);
+    check_spawn(&command, "Fetching git dependencies")?;
+    let output = command
+        .output()
+        .map_err(|e| SeppoError::Config(format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(SeppoError::Config(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Stable hash keeping cache directories of same-named packages apart
+pub(crate) fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn section<'a>(table: &'a Table, name: &str) -> Result<Option<&'a Table>> {
+    match table.get(name) {
+        None => Ok(None),
+        Some(Value::Table(section)) => Ok(Some(section)),
+        Some(_) => Err(SeppoError::Config(format!("[{}] must be a table", name))),
+    }
+}
+
+fn string(value: &Value, key: &str) -> Result<String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| SeppoError::Config(format!("{} must be a string", key)))
+}
+
+fn strings(value: &Value, key: &str) -> Result<Vec<String>> {
+    let error = || SeppoError::Config(format!("{} must be a list of strings", key));
+    value
+        .as_array()
+        .ok_or_else(error)?
+        .iter()
+        .map(|item| item.as_str().map(str::to_string).ok_or_else(error))
+        .collect()
+}
@@ -0,0 +1,95 @@
+use crate::error::{Result, SeppoError};
+use std::cell::Cell;
+
+/// Blocks and call parentheses a program may nest before it is rejected
+pub const DEFAULT_MAX_NESTING: usize = 64;
+
+thread_local! {
+    static MAX_NESTING: Cell<usize> = const { Cell::new(DEFAULT_MAX_NESTING) };
+}
+
+/// Run `f` with programs parsed on this thread allowed to nest `limit` deep
+pub fn with_max_nesting<T>(limit: usize, f: impl FnOnce() -> T) -> T {
+    let previous = MAX_NESTING.with(|max| max.replace(limit));
+    let result = f();
+    MAX_NESTING.with(|max| max.set(previous));
+    result
+}
+
+/// Reject source nested deeper than the limit before it reaches the parser.
+/// Parsing, the checks and codegen all recurse per level, so a program
+/// like a thousand nested conditionals would otherwise overflow the stack.
+///
+/// This scans the text without recursing, counting `{` and `(` outside
+/// strings and comments. Braces inside ceppo blocks count too, since the
+/// grammar matches them recursively as well.
+pub(crate) fn check_nesting(input: &str) -> Result<()> {
+    let limit = MAX_NESTING.with(Cell::get);
+    let bytes = input.as_bytes();
+    let mut depth = 0;
+    // Depth at which the current ceppo block started
+    let mut ceppo: Option<usize> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if ceppo.is_none() && bytes.get(i + 1) == Some(&b'/') => {
+                i = skip_line(bytes, i);
+                continue;
+            }
+            b'#' if ceppo.is_none() && bytes.get(i + 1) == Some(&b'#') => {
+                i = skip_line(bytes, i);
+                continue;
+            }
+            b'"' if ceppo.is_none() => {
+                i = bytes[i + 1..]
+                    .iter()
+                    .position(|&b| b == b'"')
+                    .map_or(bytes.len(), |end| i + end + 2);
+                continue;
+            }
+            b'{' | b'(' => {
+                if depth == 0 && bytes[i] == b'{' && input[..i].trim_end().ends_with("ceppo") {
+                    ceppo = Some(depth);
+                }
+                depth += 1;
+                if depth > limit {
+                    return Err(too_deep(input, i, limit));
+                }
+            }
+            b'}' | b')' => {
+                depth = depth.saturating_sub(1);
+                if ceppo == Some(depth) {
+                    ceppo = None;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+fn skip_line(bytes: &[u8], start: usize) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |end| start + end)
+}
+
+fn too_deep(input: &str, pos: usize, limit: usize) -> SeppoError {
+    let span = pest::Span::new(input, pos, pos + 1).expect("position is on a bracket");
+    let mut error = SeppoError::parse_at(
+        &span,
+        format!(
+            "program too deeply nested: more than {} levels of blocks and calls",
+            limit
+        ),
+    );
+    if let SeppoError::Parse { help, .. } = &mut error {
+        *help = Some(
+            "move the inner part into a function of its own, or raise the limit with --max-nesting"
+                .to_string(),
+        );
+    }
+    error
+}
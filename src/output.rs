@@ -0,0 +1,137 @@
+use crate::driver::Profile;
+use crate::error::Result;
+use crate::target::TargetSpec;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory of the builds of a profile-less `Driver`, next to `debug`
+/// and `release`
+const DEFAULT_PROFILE_DIR: &str = "default";
+
+/// Where a build puts its artifacts, all named after one stem. Extensions
+/// are appended to the stem rather than replacing the part after its last
+/// dot, so `-o hello.v2` builds `hello.v2` and `hello.v2.ll` instead of
+/// `hello` and `hello.ll`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputPath {
+    stem: PathBuf,
+}
+
+impl OutputPath {
+    pub fn new(stem: impl Into<PathBuf>) -> Self {
+        Self { stem: stem.into() }
+    }
+
+    /// The path given with `-o` for an artifact with `extension`. A name
+    /// that already ends in it is used as is, any other gets it added.
+    pub fn for_artifact(path: &Path, extension: &str) -> Self {
+        match path.extension() {
+            Some(ext) if !extension.is_empty() && ext == extension => {
+                Self::new(path.with_extension(""))
+            }
+            _ => Self::new(path),
+        }
+    }
+
+    /// Named after an input file, without its extension
+    pub fn for_input(path: &Path) -> Self {
+        Self::new(path.with_extension(""))
+    }
+
+    pub fn stem(&self) -> &Path {
+        &self.stem
+    }
+
+    /// The stem with `.extension` appended, or the stem itself for an empty
+    /// extension
+    pub fn with_extension(&self, extension: &str) -> PathBuf {
+        if extension.is_empty() {
+            return self.stem.clone();
+        }
+        let mut name = OsString::from(self.stem.as_os_str());
+        name.push(".");
+        name.push(extension);
+        PathBuf::from(name)
+    }
+
+    /// The stem with `suffix` added to its name, e.g. for the slices of a
+    /// universal binary
+    pub fn with_suffix(&self, suffix: &str) -> Self {
+        let mut name = OsString::from(self.stem.as_os_str());
+        name.push(suffix);
+        Self::new(name)
+    }
+
+    /// The executable for `target`: no extension on Unix, `.exe` on Windows
+    /// and `.wasm` for WebAssembly
+    pub fn executable(&self, target: &TargetSpec) -> PathBuf {
+        self.with_extension(target.exe_extension())
+    }
+
+    pub fn object(&self) -> PathBuf {
+        self.with_extension("o")
+    }
+
+    pub fn llvm_ir(&self) -> PathBuf {
+        self.with_extension("ll")
+    }
+
+    pub fn ast_json(&self) -> PathBuf {
+        self.with_extension("json")
+    }
+
+    pub fn tokens(&self) -> PathBuf {
+        self.with_extension("tokens")
+    }
+}
+
+/// The `target/` directory of a project. Builds go to
+/// `target/seppo/<profile>/`: the executable, its object file and IR named
+/// after the package or input, and the compiled ceppo blocks in `ceppo/`.
+/// Other tools can keep their own directories next to `seppo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetDir {
+    root: PathBuf,
+}
+
+impl TargetDir {
+    /// Name of the target directory, next to a project's manifest or in
+    /// the current directory
+    pub const DEFAULT: &'static str = "target";
+
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Everything seppoc builds, which `clean` removes
+    pub fn seppo_dir(&self) -> PathBuf {
+        self.root.join("seppo")
+    }
+
+    /// Where builds with `profile` put their artifacts
+    pub fn profile_dir(&self, profile: Option<Profile>) -> PathBuf {
+        let name = profile.map_or(DEFAULT_PROFILE_DIR, Profile::name);
+        self.seppo_dir().join(name)
+    }
+
+    /// Compiled ceppo blocks of builds with `profile`, reused by later
+    /// builds of the same C code with the same flags
+    pub fn ceppo_cache(&self, profile: Option<Profile>) -> PathBuf {
+        self.profile_dir(profile).join("ceppo")
+    }
+
+    /// Remove the builds of every profile, returning whether there were any
+    pub fn clean(&self) -> Result<bool> {
+        match fs::remove_dir_all(self.seppo_dir()) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
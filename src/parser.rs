@@ -1,47 +1,151 @@
+use crate::consteval::fold_constants;
+use crate::error::{Result, SeppoError};
+use crate::include::resolve_includes;
+use crate::inline::check_inline_functions;
+use crate::introspect::resolve_introspection;
+use crate::lexer::is_keyword;
+use crate::lint::LINTS;
+use crate::macros::expand_macros;
+use crate::nesting::check_nesting;
+use crate::sandbox::{no_extern, CEPPO_DISABLED};
+use crate::sema::{check_entry, check_program, Definitions};
+use crate::syntax_error::syntax_error;
+use crate::timing::{self, Phase};
 use crate::types::*;
-use anyhow::{anyhow, Result};
 use pest::Parser;
 use pest_derive::Parser;
+use std::path::Path;
 
 #[derive(Parser)]
 #[grammar = "seppo.pest"]
 pub struct SeppoParser;
 
+/// Parse a program, requiring it to define the `seppo` entry function
 pub fn parse_seppo(input: &str) -> Result<SeppoExpr> {
-    println!("Input:\n{}", input);
-    println!("Attempting to parse with Rule::program...");
-
-    // Debug parsing
-    println!("\nTrying program rule with detailed debugging:");
-    let program_result = SeppoParser::parse(Rule::program, input);
-    match &program_result {
-        Ok(pairs) => {
-            for pair in pairs.clone() {
-                println!("\nTop level pair:");
-                println!("Rule: {:?}", pair.as_rule());
-                println!("Text: {}", pair.as_str());
-                println!("Span: {:?}", pair.as_span());
-
-                for inner in pair.into_inner() {
-                    println!("\n  Inner pair:");
-                    println!("  Rule: {:?}", inner.as_rule());
-                    println!("  Text: {}", inner.as_str());
-                    println!("  Span: {:?}", inner.as_span());
-                }
+    let program = parse_module(input)?;
+    check_entry(&program)?;
+    Ok(program)
+}
+
+/// Parse a source file into its top-level items without checking for an
+/// entry point, so several files can be combined into one program
+pub fn parse_module(input: &str) -> Result<SeppoExpr> {
+    parse_program(input, Mode::Program, None)
+}
+
+/// Parse like `parse_module` the source read from `path`, which files
+/// named by `include_str` are relative to
+pub fn parse_file(input: &str, path: &Path) -> Result<SeppoExpr> {
+    parse_program(input, Mode::Program, path.parent())
+}
+
+/// Parse like `parse_module`, with a `Location` marking the line of every
+/// statement in `file`, so coverage counters can be mapped back to the source
+pub fn parse_with_locations(input: &str, file: &str) -> Result<SeppoExpr> {
+    parse_program(input, Mode::Located(file), Path::new(file).parent())
+}
+
+/// Parse keeping comments and blank lines as `Comment`/`BlankLine` nodes,
+/// for tools that print the source back out such as the formatter
+pub fn parse_with_comments(input: &str) -> Result<SeppoExpr> {
+    parse_program(input, Mode::Trivia, None)
+}
+
+/// What the parser keeps besides the program itself
+#[derive(Clone, Copy)]
+enum Mode<'a> {
+    /// Nothing, macros are expanded and constants folded
+    Program,
+    /// Comments and blank lines, with the program as written
+    Trivia,
+    /// The location of every statement in the named file
+    Located(&'a str),
+}
+
+impl Mode<'_> {
+    fn keeps_trivia(self) -> bool {
+        matches!(self, Mode::Trivia)
+    }
+}
+
+/// Parse and check a program, with `include_str` paths relative to `dir`
+fn parse_program(input: &str, mode: Mode, dir: Option<&Path>) -> Result<SeppoExpr> {
+    let program = timing::time(Phase::Parse, || parse_items(input, mode))?;
+    if mode.keeps_trivia() {
+        // Tools printing the source back need the macros as written
+        return Ok(program);
+    }
+    timing::time(Phase::Sema, || {
+        let program = return_trailing_values(expand_macros(program)?);
+        let program = resolve_includes(program, dir)?;
+        let program = resolve_introspection(program)?;
+        check_program(&program)?;
+        let program = fold_constants(program)?;
+        check_inline_functions(&program)?;
+        Ok(program)
+    })
+}
+
+/// Make a function body ending in an expression return it, so that
+/// `fn add(a, b) { a + b }` returns the sum. Bodies ending in anything else
+/// still return 0. Done after macro expansion, where a macro call at the
+/// end of a function is the statements it expands to, and before the
+/// values functions return are checked against their signatures.
+fn return_trailing_values(program: SeppoExpr) -> SeppoExpr {
+    let SeppoExpr::Block(items) = program else {
+        return program;
+    };
+    let items = items
+        .into_iter()
+        .map(|item| match item {
+            SeppoExpr::Function(name, params, body) => {
+                SeppoExpr::Function(name, params, Box::new(return_trailing_value(*body)))
             }
-        }
-        Err(e) => {
-            println!("\nProgram parse error:");
-            println!("Error: {:?}", e);
-            println!("Location: {:?}", e.location);
-            println!("Line/Col: {:?}", e.line_col);
+            SeppoExpr::ConstFunction(name, params, body) => {
+                SeppoExpr::ConstFunction(name, params, Box::new(return_trailing_value(*body)))
+            }
+            other => other,
+        })
+        .collect();
+    SeppoExpr::Block(items)
+}
+
+fn return_trailing_value(body: SeppoExpr) -> SeppoExpr {
+    let SeppoExpr::Block(mut statements) = body else {
+        return body;
+    };
+    if let Some(last) = statements.iter_mut().rfind(|stmt| !stmt.is_trivia()) {
+        if matches!(
+            last,
+            SeppoExpr::Number(_)
+                | SeppoExpr::String(_)
+                | SeppoExpr::Variable(_)
+                | SeppoExpr::Operation(..)
+                | SeppoExpr::FunctionCall(..)
+        ) {
+            let value = std::mem::replace(last, SeppoExpr::Number(0));
+            *last = SeppoExpr::Return(Box::new(value));
         }
     }
+    SeppoExpr::Block(statements)
+}
+
+/// The program as written, only checked for syntax errors, see
+/// `parse_lenient`
+pub(crate) fn parse_syntax(input: &str) -> Result<SeppoExpr> {
+    parse_items(input, Mode::Program)
+}
 
-    let pairs = program_result?;
+/// The program as written, before macro expansion and checks
+fn parse_items(input: &str, mode: Mode) -> Result<SeppoExpr> {
+    check_nesting(input)?;
+    let pairs = SeppoParser::parse(Rule::program, input).map_err(|e| syntax_error(e, input))?;
 
-    let mut functions = Vec::new();
-    let mut has_main = false;
+    let mut items = Items::new(mode.keeps_trivia());
+    let mut functions = Definitions::default();
+    let mut globals = Definitions::default();
+    // The last `#[...]`, while it waits for the function it applies to
+    let mut attribute = None;
 
     for pair in pairs {
         match pair.as_rule() {
@@ -49,22 +153,92 @@ pub fn parse_seppo(input: &str) -> Result<SeppoExpr> {
                 for item in pair.into_inner() {
                     match item.as_rule() {
                         Rule::function => {
-                            let func_expr = parse_function(item)?;
-                            if let SeppoExpr::Function(name, ..) = &func_expr {
-                                if name == "seppo" {
-                                    has_main = true;
+                            attribute = None;
+                            let function = parse_function(item.clone(), mode)?;
+                            if let SeppoExpr::Function(name, ..)
+                            | SeppoExpr::ConstFunction(name, ..) = &function
+                            {
+                                if !mode.keeps_trivia() {
+                                    let what = format!("Function {}", name);
+                                    functions.define(&what, name, name_location(&item))?;
                                 }
                             }
-                            functions.push(func_expr);
+                            for marker in function_markers(&item) {
+                                items.push_marker(&item, marker);
+                            }
+                            if let Some(signature) = function_signature(&item)? {
+                                items.push_marker(&item, signature);
+                            }
+                            items.push(&item, function);
+                        }
+                        Rule::global => {
+                            if let Some(attribute) = attribute.take() {
+                                return Err(SeppoError::parse_at(
+                                    &attribute,
+                                    format!(
+                                        "{} applies to the function below it, not to globals",
+                                        attribute.as_str()
+                                    ),
+                                ));
+                            }
+                            let statement = children(item.clone())
+                                .find(|p| p.as_rule() == Rule::let_stmt)
+                                .unwrap();
+                            let global = parse_global(statement.clone(), mode)?;
+                            if let SeppoExpr::Let { name, .. } = &global {
+                                if !mode.keeps_trivia() {
+                                    let what = format!("Global {}", name);
+                                    globals.define(&what, name, name_location(&statement))?;
+                                }
+                            }
+                            if children(item.clone())
+                                .any(|p| p.as_rule() == Rule::threadlocal_marker)
+                            {
+                                let threadlocal = SeppoExpr::Attribute {
+                                    name: THREADLOCAL_ATTRIBUTE.to_string(),
+                                    args: Vec::new(),
+                                };
+                                items.push_marker(&item, threadlocal);
+                            }
+                            items.push(&item, global);
                         }
                         Rule::extern_block => {
-                            let c_code = item
-                                .into_inner()
+                            attribute = None;
+                            let span = item.as_span();
+                            // Formatting a ceppo block does not run it
+                            if no_extern() && !mode.keeps_trivia() {
+                                return Err(SeppoError::parse_at(&span, CEPPO_DISABLED));
+                            }
+                            let c_code = children(item.clone())
                                 .find(|p| p.as_rule() == Rule::c_code)
                                 .map(|p| p.as_str().trim().to_string())
-                                .ok_or_else(|| anyhow!("Expected C code in ceppo block"))?;
-                            functions.push(SeppoExpr::InlineC(c_code));
+                                .ok_or_else(|| {
+                                    SeppoError::parse_at(&span, "Expected C code in ceppo block")
+                                })?;
+                            items.push(&item, SeppoExpr::InlineC(c_code));
+                        }
+                        Rule::macro_def => {
+                            attribute = None;
+                            items.push(&item, parse_macro(item.clone(), mode)?);
+                        }
+                        Rule::doc_comment => {
+                            let text = item.as_str().trim_end()["##".len()..].to_string();
+                            let text = text.strip_prefix(' ').map(str::to_string).unwrap_or(text);
+                            items.push(&item, SeppoExpr::DocComment(text));
+                        }
+                        Rule::attribute => {
+                            let parsed = parse_attribute(item.clone())?;
+                            if matches!(&parsed, SeppoExpr::Attribute { name, .. } if name == UNROLL_ATTRIBUTE)
+                            {
+                                return Err(SeppoError::parse_at(
+                                    &item.as_span(),
+                                    "#[unroll] applies to the for loop below it, not to functions",
+                                ));
+                            }
+                            items.push(&item, parsed);
+                            attribute = Some(item.as_span());
                         }
+                        Rule::COMMENT => items.push_comment(&item),
                         _ => {}
                     }
                 }
@@ -73,94 +247,496 @@ pub fn parse_seppo(input: &str) -> Result<SeppoExpr> {
         }
     }
 
-    if !has_main {
-        return Err(anyhow!("No seppo function found"));
+    Ok(SeppoExpr::Block(items.finish()))
+}
+
+type Pair<'i> = pest::iterators::Pair<'i, Rule>;
+
+/// Inner pairs of a rule without the comments pest interleaves between them
+fn children(pair: Pair) -> impl Iterator<Item = Pair> {
+    pair.into_inner().filter(|p| p.as_rule() != Rule::COMMENT)
+}
+
+/// First and last source line of a pair, ignoring surrounding whitespace
+fn line_range(pair: &Pair) -> (usize, usize) {
+    let span = pair.as_span();
+    let text = span.as_str();
+    let start = span.start() + (text.len() - text.trim_start().len());
+    let end = span.start() + text.trim_end().len();
+    let line_at = |offset: usize| {
+        pest::Position::new(span.get_input(), offset)
+            .map(|pos| pos.line_col().0)
+            .unwrap_or(0)
+    };
+    (line_at(start), line_at(end))
+}
+
+/// Collects the statements of a block (or the items of a program) and, in
+/// trivia mode, places comments and blank lines between them by source line.
+struct Items {
+    keep_trivia: bool,
+    entries: Vec<(usize, usize, usize, SeppoExpr)>,
+}
+
+impl Items {
+    fn new(keep_trivia: bool) -> Self {
+        Self {
+            keep_trivia,
+            entries: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, pair: &Pair, expr: SeppoExpr) {
+        if !self.keep_trivia {
+            self.entries.push((0, 0, 0, expr));
+            return;
+        }
+
+        let (start_line, end_line) = line_range(pair);
+        self.entries
+            .push((pair.as_span().start(), start_line, end_line, expr));
+
+        // Comments pest attached inside the statement belong after it, except
+        // those in nested blocks which are collected when parsing that block
+        let mut nested = Vec::new();
+        collect_comments(pair.clone(), &mut nested);
+        for comment in nested {
+            self.push_comment(&comment);
+        }
     }
 
-    Ok(SeppoExpr::Block(functions))
+    /// An item for `pair` that comes before the one parsed from it, like
+    /// the attribute of `export fn`
+    fn push_marker(&mut self, pair: &Pair, expr: SeppoExpr) {
+        let (start_line, _) = line_range(pair);
+        self.entries
+            .push((pair.as_span().start(), start_line, start_line, expr));
+    }
+
+    fn push_comment(&mut self, pair: &Pair) {
+        if !self.keep_trivia {
+            return;
+        }
+        let (line, _) = line_range(pair);
+        self.entries.push((
+            pair.as_span().start(),
+            line,
+            line,
+            SeppoExpr::Comment {
+                text: pair.as_str().trim_end().to_string(),
+                trailing: false,
+            },
+        ));
+    }
+
+    fn finish(mut self) -> Vec<SeppoExpr> {
+        if !self.keep_trivia {
+            return self.entries.into_iter().map(|(.., expr)| expr).collect();
+        }
+
+        self.entries.sort_by_key(|(offset, ..)| *offset);
+        let mut result = Vec::new();
+        let mut previous: Option<(usize, bool)> = None;
+        for (_, start_line, end_line, mut expr) in self.entries {
+            if let Some((previous_end, previous_is_code)) = previous {
+                if let SeppoExpr::Comment { trailing, .. } = &mut expr {
+                    *trailing = previous_is_code && start_line == previous_end;
+                }
+                if start_line > previous_end + 1 {
+                    result.push(SeppoExpr::BlankLine);
+                }
+            }
+            let is_code = !matches!(expr, SeppoExpr::Comment { .. } | SeppoExpr::DocComment(_));
+            let end_line = previous.map_or(end_line, |(end, _)| end.max(end_line));
+            previous = Some((end_line, is_code));
+            result.push(expr);
+        }
+        result
+    }
 }
 
-fn parse_function(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
-    println!("Function rule: {:?}", pair.as_rule());
-    assert_eq!(pair.as_rule(), Rule::function);
-    for p in pair.clone().into_inner() {
-        println!("  Child: {:?} = {:?}", p.as_rule(), p.as_str());
+fn collect_comments<'i>(pair: Pair<'i>, comments: &mut Vec<Pair<'i>>) {
+    for child in pair.into_inner() {
+        match child.as_rule() {
+            Rule::COMMENT => comments.push(child),
+            Rule::block => {}
+            _ => collect_comments(child, comments),
+        }
+    }
+}
+
+/// The attributes of the keywords before `fn`, like `export`
+fn function_markers(pair: &Pair) -> Vec<SeppoExpr> {
+    children(pair.clone())
+        .filter(|p| matches!(p.as_rule(), Rule::export_marker | Rule::inline_marker))
+        .map(|p| SeppoExpr::Attribute {
+            name: p.as_str().to_string(),
+            args: Vec::new(),
+        })
+        .collect()
+}
+
+/// The `Signature` of a function, if it declares any types
+fn function_signature(pair: &Pair) -> Result<Option<SeppoExpr>> {
+    let mut signature = Signature::default();
+    for part in children(pair.clone()) {
+        match part.as_rule() {
+            Rule::param_list => {
+                for param in children(part) {
+                    let ty = children(param).find(|p| p.as_rule() == Rule::type_name);
+                    signature
+                        .params
+                        .push(ty.map(|ty| parse_type(&ty)).transpose()?);
+                }
+            }
+            Rule::return_type => {
+                let ty = children(part).find(|p| p.as_rule() == Rule::type_name);
+                signature.returns = ty.map(|ty| parse_type(&ty)).transpose()?;
+            }
+            _ => {}
+        }
+    }
+    let declares = signature.returns.is_some() || signature.params.iter().any(Option::is_some);
+    Ok(declares.then_some(SeppoExpr::Signature(signature)))
+}
+
+fn parse_type(pair: &Pair) -> Result<SeppoType> {
+    let name = pair.as_str();
+    SeppoType::ALL
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, ty)| *ty)
+        .ok_or_else(|| {
+            let known: Vec<&str> = SeppoType::ALL.iter().map(|(known, _)| *known).collect();
+            let (last, rest) = known.split_last().unwrap();
+            SeppoError::parse_at(
+                &pair.as_span(),
+                format!(
+                    "Unknown type {}, expected {} or {}",
+                    name,
+                    rest.join(", "),
+                    last
+                ),
+            )
+        })
+}
+
+fn parse_attribute(pair: Pair) -> Result<SeppoExpr> {
+    let span = pair.as_span();
+    let mut inner = children(pair);
+    let name = inner
+        .next()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let args: Vec<String> = inner.map(|p| p.as_str().replace('_', "-")).collect();
+    match name.as_str() {
+        "allow" => {
+            if args.is_empty() {
+                return Err(SeppoError::parse_at(
+                    &span,
+                    "allow needs the lints to allow, e.g. #[allow(unused-function)]",
+                ));
+            }
+            if let Some(unknown) = args
+                .iter()
+                .find(|arg| *arg != "all" && !LINTS.iter().any(|lint| lint.name == *arg))
+            {
+                return Err(SeppoError::parse_at(
+                    &span,
+                    format!("Unknown lint: {}", unknown),
+                ));
+            }
+        }
+        CONSTANT_TIME_ATTRIBUTE => {
+            if !args.is_empty() {
+                return Err(SeppoError::parse_at(
+                    &span,
+                    "constant_time takes no arguments",
+                ));
+            }
+        }
+        name if LLVM_ATTRIBUTES.iter().any(|(known, _)| *known == name) => {
+            if !args.is_empty() {
+                return Err(SeppoError::parse_at(
+                    &span,
+                    format!("{} takes no arguments", name),
+                ));
+            }
+        }
+        UNROLL_ATTRIBUTE => {
+            let count = match args.as_slice() {
+                [count] => count.parse::<u32>().ok(),
+                _ => None,
+            };
+            if count.is_none_or(|count| count == 0) {
+                return Err(SeppoError::parse_at(
+                    &span,
+                    "unroll takes how many times to unroll the loop, e.g. #[unroll(4)]",
+                ));
+            }
+        }
+        other => {
+            let known: Vec<&str> = ["allow", CONSTANT_TIME_ATTRIBUTE]
+                .into_iter()
+                .chain(LLVM_ATTRIBUTES.iter().map(|(known, _)| *known))
+                .collect();
+            return Err(SeppoError::parse_at(
+                &span,
+                format!(
+                    "Unknown attribute: {} (expected {} or {})",
+                    other,
+                    known.join(", "),
+                    UNROLL_ATTRIBUTE
+                ),
+            ));
+        }
     }
+    Ok(SeppoExpr::Attribute { name, args })
+}
+
+fn parse_function(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    assert_eq!(pair.as_rule(), Rule::function);
 
-    let mut inner = pair.into_inner();
+    let span = pair.as_span();
+    let mut inner = children(pair).peekable();
+
+    while inner
+        .next_if(|p| matches!(p.as_rule(), Rule::export_marker | Rule::inline_marker))
+        .is_some()
+    {}
+    let is_const = inner
+        .next_if(|p| p.as_rule() == Rule::const_marker)
+        .is_some();
 
     // Get function name
     let name = inner
         .next()
-        .ok_or_else(|| anyhow!("Expected function name"))?
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected function name"))?
         .as_str()
         .to_string();
 
-    // Parse parameters (empty for now since we don't have any in the input)
-    let params = Vec::new();
+    let params = parse_params(&mut inner, &format!("function {}", name), mode)?;
+    // Kept as the `Signature` before the function, see `function_signature`
+    inner.next_if(|p| p.as_rule() == Rule::return_type);
 
     // Parse function body (block)
     let body = inner
         .next()
         .filter(|p| p.as_rule() == Rule::block)
-        .ok_or_else(|| anyhow!("Expected function body"))?;
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected function body"))?;
+
+    let body = Box::new(parse_block(body, mode)?);
+    if is_const {
+        Ok(SeppoExpr::ConstFunction(name, params, body))
+    } else {
+        Ok(SeppoExpr::Function(name, params, body))
+    }
+}
+
+/// Names of an optional `param_list` of `owner`, e.g. "function foo",
+/// consuming it from `inner`
+fn parse_params<'i>(
+    inner: &mut std::iter::Peekable<impl Iterator<Item = Pair<'i>>>,
+    owner: &str,
+    mode: Mode,
+) -> Result<Vec<String>> {
+    let Some(list) = inner.next_if(|p| p.as_rule() == Rule::param_list) else {
+        return Ok(Vec::new());
+    };
+    let mut params = Vec::new();
+    let mut definitions = Definitions::default();
+    for param in children(list) {
+        // The name, the type is in the function's `Signature`
+        let Some(identifier) = children(param).next() else {
+            continue;
+        };
+        let name = variable_name(&identifier)?;
+        if !mode.keeps_trivia() {
+            let what = format!("Parameter {} of {}", name, owner);
+            definitions.define(&what, &name, location(&identifier))?;
+        }
+        params.push(name);
+    }
+    Ok(params)
+}
+
+/// The name a variable or parameter is given, which can't be a keyword:
+/// some parse as names, but `seppo = 1` reads like a print and
+/// `perkele = 1` like the end of a conditional
+fn variable_name(pair: &Pair) -> Result<String> {
+    let name = pair.as_str();
+    if !is_keyword(name) {
+        return Ok(name.to_string());
+    }
+    let mut error = SeppoError::parse_at(
+        &pair.as_span(),
+        format!("`{}` is a keyword and can't be used as a name", name),
+    );
+    if let SeppoError::Parse { help, .. } = &mut error {
+        *help = Some(format!("rename it, e.g. `{}_value`", name));
+    }
+    Err(error)
+}
+
+/// `line:column` of a pair, for messages
+fn location(pair: &Pair) -> String {
+    let (line, column) = pair.as_span().start_pos().line_col();
+    format!("{}:{}", line, column)
+}
+
+/// Location of the name of a function or macro
+fn name_location(pair: &Pair) -> String {
+    children(pair.clone())
+        .find(|p| p.as_rule() == Rule::identifier)
+        .map(|name| location(&name))
+        .unwrap_or_else(|| location(pair))
+}
 
-    println!("Body rule: {:?}", body.as_rule());
+fn parse_macro(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    let span = pair.as_span();
+    let typed = children(pair.clone())
+        .filter(|p| p.as_rule() == Rule::param_list)
+        .flat_map(children)
+        .flat_map(children)
+        .find(|p| p.as_rule() == Rule::type_name);
+    if let Some(ty) = typed {
+        return Err(SeppoError::parse_at(
+            &ty.as_span(),
+            "Macro parameters can't have types, the arguments are pasted in as written",
+        ));
+    }
+    let mut inner = children(pair).peekable();
+
+    let name = inner
+        .next()
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected macro name"))?
+        .as_str()
+        .to_string();
 
-    Ok(SeppoExpr::Function(
+    let params = parse_params(&mut inner, &format!("macro {}", name), mode)?;
+
+    let body = inner
+        .next()
+        .filter(|p| p.as_rule() == Rule::block)
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected macro body"))?;
+    Ok(SeppoExpr::Macro(
         name,
         params,
-        Box::new(parse_block(body)?),
+        Box::new(parse_block(body, mode)?),
     ))
 }
 
-fn parse_block(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
-    let mut statements = Vec::new();
+fn parse_block(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    let mut statements = Items::new(mode.keeps_trivia());
+    // An `#[unroll(n)]` still waiting for its loop
+    let mut unroll = None;
     for stmt in pair.into_inner() {
-        statements.push(parse_statement(stmt)?);
+        match stmt.as_rule() {
+            Rule::COMMENT => {
+                statements.push_comment(&stmt);
+                continue;
+            }
+            Rule::separator => continue,
+            _ => {}
+        }
+        let rule = children(stmt.clone()).next().map(|inner| inner.as_rule());
+        if let Some(span) = unroll.take() {
+            if rule != Some(Rule::for_loop) {
+                return Err(SeppoError::parse_at(
+                    &span,
+                    "#[unroll] must be followed by a for loop",
+                ));
+            }
+        }
+        if rule == Some(Rule::attribute) {
+            unroll = Some(stmt.as_span());
+        }
+        let expr = parse_statement(stmt.clone(), mode)?;
+        if let Mode::Located(file) = mode {
+            let location = SeppoExpr::Location {
+                file: file.to_string(),
+                line: line_range(&stmt).0,
+            };
+            statements.push(&stmt, location);
+        }
+        statements.push(&stmt, expr);
+    }
+    if let Some(span) = unroll {
+        return Err(SeppoError::parse_at(
+            &span,
+            "#[unroll] must be followed by a for loop",
+        ));
     }
-    Ok(SeppoExpr::Block(statements))
+    Ok(SeppoExpr::Block(statements.finish()))
 }
 
-fn parse_statement(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
+fn parse_statement(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
     match pair.as_rule() {
         Rule::statement => {
-            let inner = pair.into_inner().next().unwrap();
-            parse_statement(inner)
+            let inner = children(pair).next().unwrap();
+            parse_statement(inner, mode)
         }
-        Rule::conditional_block => parse_conditional_block(pair),
+        Rule::attribute => match parse_attribute(pair.clone())? {
+            SeppoExpr::Attribute { name, .. } if name != UNROLL_ATTRIBUTE => {
+                Err(SeppoError::parse_at(
+                    &pair.as_span(),
+                    format!("#[{}] applies to functions, not to statements", name),
+                ))
+            }
+            attribute => Ok(attribute),
+        },
+        Rule::conditional_block => parse_conditional_block(pair, mode),
+        Rule::for_loop => parse_for_loop(pair, mode),
         Rule::print_stmt => parse_print(pair),
-        Rule::assignment => parse_assignment(pair),
+        Rule::let_stmt => parse_let(pair, mode),
+        Rule::assignment => parse_assignment(pair, mode),
         Rule::expression => parse_expression(pair),
         Rule::return_stmt => {
-            println!("Parsing return: {:?}", pair.as_str()); // Debug
-            let inner = pair
-                .into_inner()
+            let span = pair.as_span();
+            let inner = children(pair)
                 .next()
-                .ok_or_else(|| anyhow!("Expected return value"))?;
-            Ok(SeppoExpr::Return(Box::new(parse_expression(inner)?)))
+                .ok_or_else(|| SeppoError::parse_at(&span, "Expected return value"))?;
+            Ok(SeppoExpr::Return(Box::new(parse_value(inner, mode)?)))
         }
-        Rule::function_call => parse_function(pair),
-        _ => Err(anyhow!(
-            "Unexpected rule in statement: {:?}",
-            pair.as_rule()
+        Rule::become_stmt => {
+            let span = pair.as_span();
+            let call = children(pair)
+                .find(|p| p.as_rule() == Rule::function_call)
+                .ok_or_else(|| SeppoError::parse_at(&span, "Expected a call after become"))?;
+            match parse_expression(call)? {
+                SeppoExpr::FunctionCall(name, args) => Ok(SeppoExpr::Become(name, args)),
+                _ => Err(SeppoError::parse_at(&span, "Expected a call after become")),
+            }
+        }
+        Rule::function_call => parse_function(pair, mode),
+        _ => Err(SeppoError::parse_at(
+            &pair.as_span(),
+            format!("Unexpected rule in statement: {:?}", pair.as_rule()),
         )),
     }
 }
 
-fn parse_conditional_block(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
-    let mut inner = pair.into_inner();
+fn parse_conditional_block(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    let span = pair.as_span();
+    let mut inner = children(pair);
 
     // Parse condition
-    let condition = inner.next().ok_or_else(|| anyhow!("Expected condition"))?;
+    let condition = inner
+        .next()
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected condition"))?;
     let condition_expr = parse_condition(condition)?;
 
     // Parse true block
-    let true_block = inner.next().ok_or_else(|| anyhow!("Expected true block"))?;
-    let true_expr = parse_block(true_block)?;
+    let true_block = inner
+        .next()
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected true block"))?;
+    let true_expr = parse_block(true_block, mode)?;
 
     // Parse optional false block (perkele block)
-    let false_expr = inner.next().map(parse_block).transpose()?;
+    let false_expr = inner
+        .next()
+        .map(|block| parse_block(block, mode))
+        .transpose()?;
 
     Ok(SeppoExpr::Conditional {
         condition: Box::new(condition_expr),
@@ -169,8 +745,68 @@ fn parse_conditional_block(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExp
     })
 }
 
-fn parse_condition(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
-    let mut inner = pair.into_inner();
+fn parse_for_loop(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    let span = pair.as_span();
+    let mut inner =
+        children(pair).filter(|p| !matches!(p.as_rule(), Rule::for_keyword | Rule::in_keyword));
+    let mut next = |what: &str| {
+        inner
+            .next()
+            .ok_or_else(|| SeppoError::parse_at(&span, format!("Expected {}", what)))
+    };
+
+    let variable = variable_name(&next("loop variable")?)?;
+    let iterable = parse_expression(next("value to iterate over")?)?;
+    let body = parse_block(next("loop body")?, mode)?;
+    Ok(SeppoExpr::For {
+        variable,
+        iterable: Box::new(iterable),
+        body: Box::new(body),
+    })
+}
+
+fn parse_condition(pair: Pair) -> Result<SeppoExpr> {
+    let mut inner = children(pair);
+    let mut condition = parse_conjunction(inner.next().unwrap())?;
+    while let (Some(op), Some(conjunction)) = (inner.next(), inner.next()) {
+        let right = parse_conjunction(conjunction)?;
+        condition = SeppoExpr::Operation(
+            op.as_str().to_string(),
+            Box::new(condition),
+            Box::new(right),
+        );
+    }
+    Ok(condition)
+}
+
+fn parse_conjunction(pair: Pair) -> Result<SeppoExpr> {
+    let mut inner = children(pair);
+    let mut conjunction = parse_comparison(inner.next().unwrap())?;
+    while let (Some(op), Some(comparison)) = (inner.next(), inner.next()) {
+        let right = parse_comparison(comparison)?;
+        conjunction = SeppoExpr::Operation(
+            op.as_str().to_string(),
+            Box::new(conjunction),
+            Box::new(right),
+        );
+    }
+    Ok(conjunction)
+}
+
+fn parse_comparison(pair: Pair) -> Result<SeppoExpr> {
+    let mut inner = children(pair).peekable();
+    if let Some(membership) = inner.next_if(|p| p.as_rule() == Rule::membership) {
+        let mut inner = children(membership).filter(|p| p.as_rule() != Rule::in_keyword);
+        let value = parse_expression(inner.next().unwrap())?;
+        let range = parse_expression(inner.next().unwrap())?;
+        return Ok(SeppoExpr::In(Box::new(value), Box::new(range)));
+    }
+    if let Some(name) = inner.next_if(|p| p.as_rule() == Rule::identifier) {
+        return Ok(SeppoExpr::Variable(name.as_str().to_string()));
+    }
+    if let Some(call) = inner.next_if(|p| p.as_rule() == Rule::function_call) {
+        return parse_expression(call);
+    }
     let left = parse_expression(inner.next().unwrap())?;
     let op = inner.next().unwrap().as_str().to_string();
     let right = parse_expression(inner.next().unwrap())?;
@@ -178,50 +814,141 @@ fn parse_condition(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
     Ok(SeppoExpr::Operation(op, Box::new(left), Box::new(right)))
 }
 
-fn parse_print(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
-    let mut inner = pair.into_inner();
+fn parse_print(pair: Pair) -> Result<SeppoExpr> {
+    let span = pair.as_span();
+    let mut inner = children(pair);
 
-    // Get the print command (seppo or 0xseppo)
+    // Get the print command: seppo, 0xseppo, boolseppo, charseppo or eseppo
     let command = inner
         .next()
-        .ok_or_else(|| anyhow!("Expected print command"))?;
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected print command"))?;
     let format = match command.as_str() {
         "0xseppo" => PrintFormat::Hex,
+        "boolseppo" => PrintFormat::Bool,
+        "charseppo" => PrintFormat::Char,
+        "eseppo" => PrintFormat::Stderr,
         _ => PrintFormat::Decimal,
     };
 
     // Get the expression to print
     let expr = inner
         .next()
-        .ok_or_else(|| anyhow!("Expected expression to print"))?
-        .into_inner()
+        .ok_or_else(|| SeppoError::parse_at(&span, "Expected expression to print"))
+        .map(children)?
         .next()
-        .ok_or_else(|| anyhow!("Empty print expression"))?;
+        .ok_or_else(|| SeppoError::parse_at(&span, "Empty print expression"))?;
 
     let expr = parse_expression(expr)?;
     Ok(SeppoExpr::Print(format, Box::new(expr)))
 }
 
-fn parse_assignment(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
-    let mut inner = pair.into_inner();
-    let variable = inner.next().unwrap().as_str().to_string();
-    let value_expr = parse_expression(inner.next().unwrap())?;
+fn parse_assignment(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    let mut inner = children(pair);
+    let variable = variable_name(&inner.next().unwrap())?;
+    let value_expr = parse_value(inner.next().unwrap(), mode)?;
     Ok(SeppoExpr::Assignment(variable, Box::new(value_expr)))
 }
 
-fn parse_expression(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
+fn parse_let(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    let mut inner = children(pair)
+        .filter(|p| p.as_rule() != Rule::let_keyword)
+        .peekable();
+    let mutable = inner
+        .next_if(|p| p.as_rule() == Rule::mut_keyword)
+        .is_some();
+    let name = variable_name(&inner.next().unwrap())?;
+    let value = parse_value(inner.next().unwrap(), mode)?;
+    Ok(SeppoExpr::Let {
+        name,
+        mutable,
+        value: Box::new(value),
+    })
+}
+
+/// The `let` of a global, which has to start as a number so no code runs
+/// before `seppo`
+fn parse_global(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    let span = pair.as_span();
+    let global = parse_let(pair, mode)?;
+    if let SeppoExpr::Let { name, value, .. } = &global {
+        if !matches!(**value, SeppoExpr::Number(_)) {
+            return Err(SeppoError::parse_at(&span, global_not_a_number(name)));
+        }
+    }
+    Ok(global)
+}
+
+/// The right-hand side of an assignment, `let` or `return`: an expression,
+/// or a conditional whose branches both end in the value to use
+fn parse_value(pair: Pair, mode: Mode) -> Result<SeppoExpr> {
+    match pair.as_rule() {
+        Rule::conditional_block => {}
+        Rule::boolean => return parse_condition(children(pair).next().unwrap()),
+        _ => return parse_expression(pair),
+    }
+    let span = pair.as_span();
+    let conditional = parse_conditional_block(pair, mode)?;
+    let SeppoExpr::Conditional {
+        true_block,
+        false_block,
+        ..
+    } = &conditional
+    else {
+        unreachable!("conditional blocks parse to conditionals");
+    };
+    let Some(false_block) = false_block else {
+        return Err(SeppoError::parse_at(
+            &span,
+            "A conditional used as a value needs a perkele block",
+        ));
+    };
+    if leaves_function(true_block) || leaves_function(false_block) {
+        return Err(SeppoError::parse_at(
+            &span,
+            "A conditional used as a value cannot return from the function",
+        ));
+    }
+    Ok(conditional)
+}
+
+fn leaves_function(expr: &SeppoExpr) -> bool {
+    match expr {
+        SeppoExpr::Return(_) | SeppoExpr::Become(..) => true,
+        SeppoExpr::Block(statements) => statements.iter().any(leaves_function),
+        SeppoExpr::Conditional {
+            true_block,
+            false_block,
+            ..
+        } => leaves_function(true_block) || false_block.as_deref().is_some_and(leaves_function),
+        SeppoExpr::For { body, .. } => leaves_function(body),
+        _ => false,
+    }
+}
+
+fn parse_expression(pair: Pair) -> Result<SeppoExpr> {
     match pair.as_rule() {
-        Rule::number => Ok(SeppoExpr::Number(pair.as_str().parse()?)),
+        Rule::number => pair.as_str().parse().map(SeppoExpr::Number).map_err(|e| {
+            SeppoError::parse_at(&pair.as_span(), format!("Invalid number literal: {}", e))
+        }),
         Rule::string_literal => {
             // Remove the quotes and handle escapes
             let str_content = pair.as_str();
             let str_without_quotes = &str_content[1..str_content.len() - 1];
             Ok(SeppoExpr::String(str_without_quotes.to_string()))
         }
+        Rule::strings => {
+            let mut text = String::new();
+            for literal in children(pair) {
+                if let SeppoExpr::String(part) = parse_expression(literal)? {
+                    text.push_str(&part);
+                }
+            }
+            Ok(SeppoExpr::String(text))
+        }
         Rule::variable => Ok(SeppoExpr::Variable(pair.as_str().to_string())),
         Rule::identifier => Ok(SeppoExpr::Variable(pair.as_str().to_string())),
         Rule::operation => {
-            let mut inner = pair.into_inner();
+            let mut inner = children(pair);
             let left = parse_expression(inner.next().unwrap())?;
             let op = inner.next().unwrap().as_str();
             let right = parse_expression(inner.next().unwrap())?;
@@ -232,15 +959,25 @@ fn parse_expression(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
             ))
         }
         Rule::expression => {
-            let inner = pair.into_inner().next().unwrap();
+            let inner = children(pair).next().unwrap();
             parse_expression(inner)
         }
+        Rule::range => {
+            let mut inner = children(pair);
+            let start = parse_expression(inner.next().unwrap())?;
+            let inclusive = inner.next().unwrap().as_str() == "..=";
+            let end = parse_expression(inner.next().unwrap())?;
+            Ok(SeppoExpr::Range {
+                start: Box::new(start),
+                end: Box::new(end),
+                inclusive,
+            })
+        }
         Rule::function_call => {
-            let mut inner = pair.into_inner();
+            let mut inner = children(pair);
             let name = inner.next().unwrap().as_str().to_string();
             let args = if let Some(arg_list) = inner.next() {
-                arg_list
-                    .into_inner()
+                children(arg_list)
                     .map(|arg| parse_expression(arg))
                     .collect::<Result<Vec<_>>>()?
             } else {
@@ -248,6 +985,9 @@ fn parse_expression(pair: pest::iterators::Pair<Rule>) -> Result<SeppoExpr> {
             };
             Ok(SeppoExpr::FunctionCall(name, args))
         }
-        rule => Err(anyhow!("Unexpected rule in expression: {:?}", rule)),
+        rule => Err(SeppoError::parse_at(
+            &pair.as_span(),
+            format!("Unexpected rule in expression: {:?}", rule),
+        )),
     }
 }
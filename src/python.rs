@@ -0,0 +1,133 @@
+use crate::compile::run_str;
+use crate::diagnostic::Diagnostic;
+use crate::driver::{Driver, Input, OutputKind};
+use crate::fmt::format_program;
+use crate::interp;
+use crate::parser::{parse_module, parse_seppo};
+use crate::types::SeppoExpr;
+use inkwell::OptimizationLevel;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use std::path::PathBuf;
+
+create_exception!(
+    seppolang,
+    SeppoError,
+    PyException,
+    "A seppo program failed to parse, compile or run. The message is the rendered diagnostic."
+);
+
+/// A parsed program, from `parse`
+#[pyclass(module = "seppolang", frozen)]
+pub struct Program {
+    program: SeppoExpr,
+}
+
+#[pymethods]
+impl Program {
+    /// Names of the functions the program defines, in source order
+    #[getter]
+    pub fn functions(&self) -> Vec<String> {
+        let SeppoExpr::Block(items) = &self.program else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .filter_map(|item| match item {
+                SeppoExpr::Function(name, ..) => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The syntax tree, as Rust prints it
+    pub fn ast(&self) -> String {
+        format!("{:#?}", self.program)
+    }
+
+    /// The program printed back as seppo source, after macro expansion
+    pub fn __str__(&self) -> PyResult<String> {
+        format_program(&self.program).map_err(|e| SeppoError::new_err(e.to_string()))
+    }
+
+    pub fn __repr__(&self) -> String {
+        format!("<seppolang.Program functions={:?}>", self.functions())
+    }
+}
+
+/// Parse seppo source into a `Program`, without requiring a `seppo` function
+#[pyfunction]
+pub fn parse(source: &str) -> PyResult<Program> {
+    let program = parse_module(source).map_err(|e| error(e, source))?;
+    Ok(Program { program })
+}
+
+/// Compile seppo source to `output`, without extension, and return the path
+/// of the file written. `emit` is "exe", "obj" or "llvm-ir".
+#[pyfunction]
+#[pyo3(signature = (source, output, emit = "exe", opt_level = 2))]
+pub fn compile(source: &str, output: PathBuf, emit: &str, opt_level: u8) -> PyResult<PathBuf> {
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: source.to_string(),
+    }]);
+    driver.output = Some(output);
+    driver.dump_ir = false;
+    driver.output_kind = match emit {
+        "exe" => OutputKind::Executable,
+        "obj" => OutputKind::Object,
+        "llvm-ir" => OutputKind::LlvmIr,
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "emit must be \"exe\", \"obj\" or \"llvm-ir\", not {:?}",
+                other
+            )))
+        }
+    };
+    driver.opt_level = match opt_level {
+        0 => OptimizationLevel::None,
+        1 => OptimizationLevel::Less,
+        2 => OptimizationLevel::Default,
+        3 => OptimizationLevel::Aggressive,
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "opt_level must be 0 to 3, not {}",
+                other
+            )))
+        }
+    };
+    driver.run().map_err(|e| error(e, source))
+}
+
+/// Compile seppo source, run it and return its exit code
+#[pyfunction]
+pub fn run(py: Python<'_>, source: &str) -> PyResult<i64> {
+    py.allow_threads(|| run_str(source))
+        .map_err(|e| error(e, source))
+}
+
+/// Evaluate seppo source with the interpreter, without a C toolchain, and
+/// return the value of its `seppo` function
+#[pyfunction]
+pub fn interpret(source: &str) -> PyResult<i64> {
+    parse_seppo(source)
+        .and_then(|program| interp::eval(&program))
+        .map_err(|e| error(e, source))
+}
+
+fn error(error: crate::error::SeppoError, source: &str) -> PyErr {
+    SeppoError::new_err(Diagnostic::from_error(&error).render(Some(source), false))
+}
+
+/// The `seppolang` Python module
+#[pymodule]
+fn seppolang(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("SeppoError", m.py().get_type::<SeppoError>())?;
+    m.add_class::<Program>()?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add_function(wrap_pyfunction!(interpret, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::error::SeppoError;
+use crate::lexer::{tokenize, TokenKind};
+use crate::parser::parse_syntax;
+use crate::types::SeppoExpr;
+
+/// What `parse_lenient` makes of a file
+#[derive(Debug)]
+pub struct Recovered {
+    /// The top-level items that could be parsed, in source order
+    pub program: SeppoExpr,
+    /// The syntax errors, at most one per item, in source order
+    pub errors: Vec<SeppoError>,
+}
+
+/// Lines starting a top-level item, when they start at the first column
+const ITEM_STARTS: &[&str] = &[
+    "fn ",
+    "const ",
+    "macro ",
+    "ceppo",
+    "export ",
+    "inline ",
+    "let ",
+    "threadlocal",
+    "#[",
+    "##",
+];
+
+/// Parse a file that may have syntax errors, for editors checking a file
+/// while it is being typed. Each top-level item is parsed on its own, so an
+/// error only costs the item it is in: the program holds the items that
+/// parsed, as written, and the errors say what is wrong with the others.
+/// An item with unclosed blocks at its end, the usual state of a function
+/// being written, is parsed as if they were closed and still reported.
+///
+/// Items are found by their first line starting at the first column, as
+/// `fn`, `ceppo` or a doc comment do in formatted code.
+pub fn parse_lenient(input: &str) -> Recovered {
+    if let Ok(program) = parse_syntax(input) {
+        return Recovered {
+            program,
+            errors: Vec::new(),
+        };
+    }
+
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    for (start, end) in item_ranges(input) {
+        // Only the item is kept, in place, so errors point into the file
+        let item = masked(input, start, end);
+        let error = match parse_syntax(&item) {
+            Ok(program) => {
+                items.extend(into_items(program));
+                continue;
+            }
+            Err(error) => error,
+        };
+        let unclosed = unclosed_blocks(&input[start..end]);
+        if unclosed > 0 {
+            if let Ok(program) = parse_syntax(&format!("{}{}", item, "}".repeat(unclosed))) {
+                items.extend(into_items(program));
+            }
+        }
+        errors.push(error);
+    }
+    Recovered {
+        program: SeppoExpr::Block(items),
+        errors,
+    }
+}
+
+/// Byte ranges of the top-level items. Doc comments and attributes belong
+/// to the item they precede, and text before the first item is a range of
+/// its own.
+fn item_ranges(input: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    // Whether the current range has only doc comments and attributes yet
+    let mut header = false;
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        let is_start = ITEM_STARTS.iter().any(|prefix| line.starts_with(prefix));
+        let is_header = line.starts_with("##") || line.starts_with("#[");
+        if is_start && !header && offset > start {
+            ranges.push((start, offset));
+            start = offset;
+        }
+        if is_start {
+            header = is_header;
+        } else if !line.trim().is_empty() {
+            header = false;
+        }
+        offset += line.len();
+    }
+    if offset > start {
+        ranges.push((start, offset));
+    }
+    ranges
+}
+
+/// `input` with everything outside `start..end` blanked out, except line
+/// breaks, keeping the offsets and lines of the range
+fn masked(input: &str, start: usize, end: usize) -> String {
+    let blank = |text: &str| -> String {
+        text.chars()
+            .map(|c| match c {
+                '\n' => "\n".to_string(),
+                c => " ".repeat(c.len_utf8()),
+            })
+            .collect()
+    };
+    format!(
+        "{}{}{}",
+        blank(&input[..start]),
+        &input[start..end],
+        blank(&input[end..])
+    )
+}
+
+/// How many `{` of `text` are never closed, not counting those in strings,
+/// comments and C code
+fn unclosed_blocks(text: &str) -> usize {
+    let mut depth: usize = 0;
+    for token in tokenize(text) {
+        match (token.kind, token.text.as_str()) {
+            (TokenKind::Punctuation, "{") => depth += 1,
+            (TokenKind::Punctuation, "}") => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn into_items(program: SeppoExpr) -> Vec<SeppoExpr> {
+    match program {
+        SeppoExpr::Block(items) => items,
+        other => vec![other],
+    }
+}
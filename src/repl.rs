@@ -0,0 +1,253 @@
+use crate::builtins::NONE;
+use crate::error::Result;
+use crate::fmt::format_program;
+use crate::interp::{Interpreter, Value};
+use crate::parser::{parse_module, parse_with_comments};
+use crate::types::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Function of a saved session whose body restores the REPL's variables
+pub const SESSION_FUNCTION: &str = "repl_session";
+
+/// A REPL session on the interpreter: definitions and statements typed one
+/// at a time, with the statements sharing one scope of variables. Sessions
+/// are saved as seppo source, so a saved session is an ordinary file that
+/// can be read, edited and loaded back.
+pub struct Session {
+    interpreter: Interpreter,
+    /// Top-level items as they were typed, with their doc comments and
+    /// attributes, by name
+    definitions: BTreeMap<String, Vec<SeppoExpr>>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::with_interpreter(Interpreter::new())
+    }
+
+    /// Create a session that writes `seppo` prints to the given writer
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Self::with_interpreter(Interpreter::with_output(output))
+    }
+
+    fn with_interpreter(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter,
+            definitions: BTreeMap::new(),
+        }
+    }
+
+    /// The interpreter the session runs on
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    /// Evaluate one REPL input: either function, const fn and macro
+    /// definitions, which replace earlier ones with the same name, or
+    /// statements. Returns the value of a trailing expression to show.
+    pub fn eval(&mut self, input: &str) -> Result<Option<Value>> {
+        if is_definition(input) {
+            self.define(input)?;
+            return Ok(None);
+        }
+
+        // Statements parse as the body of a function. Starting it on the
+        // first line keeps the line numbers of parse errors right.
+        let wrapped = format!("fn repl_input() {{ {}\n}}", input);
+        let program = parse_module(&wrapped)?;
+        let statements = match &program {
+            SeppoExpr::Block(items) => items.iter().find_map(|item| match item {
+                SeppoExpr::Function(_, _, body) => Some(body.as_ref()),
+                _ => None,
+            }),
+            _ => None,
+        };
+        let statements = match statements {
+            Some(SeppoExpr::Block(statements)) => statements.as_slice(),
+            _ => &[],
+        };
+
+        let mut shown = None;
+        for statement in statements.iter().filter(|stmt| !stmt.is_trivia()) {
+            let value = self.interpreter.eval_statement(statement)?;
+            shown = match statement {
+                SeppoExpr::Assignment(..)
+                | SeppoExpr::Let { .. }
+                | SeppoExpr::Print(..)
+                | SeppoExpr::For { .. } => None,
+                _ => Some(value),
+            };
+        }
+        Ok(shown)
+    }
+
+    /// The session as seppo source: its definitions, then `repl_session`
+    /// assigning every variable its current value
+    pub fn snapshot(&self) -> Result<String> {
+        let mut items: Vec<SeppoExpr> = self.definitions.values().flatten().cloned().collect();
+        let mut assignments = Vec::new();
+        for (name, value) in self.interpreter.variables() {
+            assignments.extend(assign(name, value));
+        }
+        items.push(SeppoExpr::DocComment(
+            "Restores the variables of a saved REPL session".to_string(),
+        ));
+        items.push(SeppoExpr::Function(
+            SESSION_FUNCTION.to_string(),
+            Vec::new(),
+            Box::new(SeppoExpr::Block(assignments)),
+        ));
+        format_program(&SeppoExpr::Block(items))
+    }
+
+    /// Write `snapshot` to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.snapshot()?)?;
+        Ok(())
+    }
+
+    /// Load a file written by `save` into the session, adding its
+    /// definitions and variables to the ones already there
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let source = fs::read_to_string(path)?;
+        self.restore(&source).map_err(|e| e.in_file(path))
+    }
+
+    /// Load saved session source, see `load`
+    pub fn restore(&mut self, source: &str) -> Result<()> {
+        let program = parse_module(source)?;
+        let SeppoExpr::Block(items) = &program else {
+            return Ok(());
+        };
+        let mut session = None;
+        let mut functions = Vec::new();
+        for item in items {
+            match item {
+                SeppoExpr::Function(name, _, body) if name == SESSION_FUNCTION => {
+                    session = Some(body.as_ref())
+                }
+                item => functions.push(item.clone()),
+            }
+        }
+        self.interpreter.load(&SeppoExpr::Block(functions))?;
+        self.record(source, |name| name != SESSION_FUNCTION)?;
+
+        if let Some(SeppoExpr::Block(statements)) = session {
+            for statement in statements.iter().filter(|stmt| !stmt.is_trivia()) {
+                self.interpreter.eval_statement(statement)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, source: &str) -> Result<()> {
+        let program = parse_module(source)?;
+        self.interpreter.load(&program)?;
+        self.record(source, |_| true)
+    }
+
+    /// Remember the definitions of `source` as written, for saving
+    fn record(&mut self, source: &str, keep: impl Fn(&str) -> bool) -> Result<()> {
+        let SeppoExpr::Block(items) = parse_with_comments(source)? else {
+            return Ok(());
+        };
+        let mut leading = Vec::new();
+        for item in items {
+            let name = match &item {
+                SeppoExpr::DocComment(_)
+                | SeppoExpr::Attribute { .. }
+                | SeppoExpr::Signature(_) => {
+                    leading.push(item);
+                    continue;
+                }
+                SeppoExpr::Function(name, ..)
+                | SeppoExpr::ConstFunction(name, ..)
+                | SeppoExpr::Macro(name, ..) => name.clone(),
+                _ => continue,
+            };
+            leading.push(item);
+            let definition = std::mem::take(&mut leading);
+            if keep(&name) {
+                self.definitions.insert(name, definition);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether REPL input defines functions or macros rather than being
+/// statements to run
+fn is_definition(input: &str) -> bool {
+    let input = input.trim_start();
+    let word: String = input
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect();
+    matches!(word.as_str(), "fn" | "const" | "macro" | "ceppo") || input.starts_with('#')
+}
+
+/// Statements that give variable `name` its value again. Number literals
+/// cannot be negative, so negative numbers are written as subtractions.
+fn assign(name: &str, value: &Value) -> Vec<SeppoExpr> {
+    let set = |value: SeppoExpr| SeppoExpr::Assignment(name.to_string(), Box::new(value));
+    let subtract = |left: SeppoExpr, right: i64| {
+        SeppoExpr::Operation(
+            "-".to_string(),
+            Box::new(left),
+            Box::new(SeppoExpr::Number(right)),
+        )
+    };
+    match value {
+        Value::Str(string) => vec![set(SeppoExpr::String(string.to_string()))],
+        Value::Int(n) if *n >= 0 => vec![set(SeppoExpr::Number(*n))],
+        // -i64::MIN does not fit, so take one more off afterwards
+        Value::Int(i64::MIN) => vec![
+            SeppoExpr::Let {
+                name: name.to_string(),
+                mutable: true,
+                value: Box::new(subtract(SeppoExpr::Number(0), i64::MAX)),
+            },
+            set(subtract(SeppoExpr::Variable(name.to_string()), 1)),
+        ],
+        Value::Int(n) => vec![set(subtract(SeppoExpr::Number(0), -n))],
+        Value::Option(_) | Value::Vector(_) => vec![set(expression(value))],
+    }
+}
+
+/// An Option or vector as one expression, as are the values inside. A
+/// negative number is a subtraction like above, and `i64::MIN`, which
+/// would take two, wraps around from `i64::MAX` instead.
+fn expression(value: &Value) -> SeppoExpr {
+    let operation = |op: &str, left: i64, right: i64| {
+        SeppoExpr::Operation(
+            op.to_string(),
+            Box::new(SeppoExpr::Number(left)),
+            Box::new(SeppoExpr::Number(right)),
+        )
+    };
+    let number = |n: i64| match n {
+        0.. => SeppoExpr::Number(n),
+        i64::MIN => operation("+", i64::MAX, 1),
+        n => operation("-", 0, -n),
+    };
+    match value {
+        Value::Str(string) => SeppoExpr::String(string.to_string()),
+        Value::Int(n) => number(*n),
+        Value::Option(None) => SeppoExpr::Variable(NONE.to_string()),
+        Value::Option(Some(value)) => {
+            SeppoExpr::FunctionCall("Some".to_string(), vec![expression(value)])
+        }
+        Value::Vector(lanes) => {
+            SeppoExpr::FunctionCall("i64x4".to_string(), lanes.map(number).to_vec())
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::error::{Result, SeppoError};
+use std::cell::Cell;
+use std::process::Command;
+
+thread_local! {
+    static NO_EXTERN: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` with ceppo blocks and every feature that starts another program
+/// disabled on this thread, for compiling untrusted source such as in a
+/// playground. Code can still be run through the JIT or the interpreter.
+pub fn with_no_extern<T>(f: impl FnOnce() -> T) -> T {
+    let previous = NO_EXTERN.with(|no_extern| no_extern.replace(true));
+    let result = f();
+    NO_EXTERN.with(|no_extern| no_extern.set(previous));
+    result
+}
+
+/// Whether this thread is inside `with_no_extern`
+pub fn no_extern() -> bool {
+    NO_EXTERN.with(Cell::get)
+}
+
+pub(crate) const CEPPO_DISABLED: &str = "ceppo blocks are not allowed with --no-extern";
+
+/// Fail instead of running `command` inside `with_no_extern`
+pub(crate) fn check_spawn(command: &Command, purpose: &str) -> Result<()> {
+    if !no_extern() {
+        return Ok(());
+    }
+    Err(SeppoError::Config(format!(
+        "{} runs `{}`, which --no-extern does not allow",
+        purpose,
+        command.get_program().to_string_lossy()
+    )))
+}
@@ -0,0 +1,478 @@
+use crate::error::{Result, SeppoError};
+use crate::types::{
+    globals, marked_functions, SeppoExpr, ALWAYS_INLINE_ATTRIBUTE, COLD_ATTRIBUTE,
+    CONSTANT_TIME_ATTRIBUTE, HOT_ATTRIBUTE, INLINE_ATTRIBUTE, NOINLINE_ATTRIBUTE,
+};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    /// `let x = ...` and `x = ...` of a new variable, assigned exactly once
+    Immutable,
+    /// `let mut x` and parameters
+    Mutable,
+    /// The variable of a `for` loop, set by the loop itself
+    Loop,
+}
+
+/// Checks on function bodies after macro expansion:
+///
+/// - assignments to variables declared without `mut` and `let`s that
+///   redeclare a variable already in scope are rejected, including
+///   assignments a macro makes to its arguments
+/// - globals are in scope in every function, and neither parameters nor
+///   loop variables may take their names
+/// - `become` must call the function it is in with all of its parameters
+/// - `#[constant_time]` functions don't branch or divide on their data, see
+///   `check_constant_time`
+pub(crate) fn check_program(program: &SeppoExpr) -> Result<()> {
+    let SeppoExpr::Block(items) = program else {
+        return Ok(());
+    };
+    check_attribute_conflicts(program)?;
+    let constant_time = marked_functions(program, CONSTANT_TIME_ATTRIBUTE);
+    let globals: HashMap<String, Binding> = globals(program)?
+        .into_iter()
+        .map(|global| {
+            let binding = if global.mutable {
+                Binding::Mutable
+            } else {
+                Binding::Immutable
+            };
+            (global.name, binding)
+        })
+        .collect();
+    for item in items {
+        if let SeppoExpr::Function(name, params, body)
+        | SeppoExpr::ConstFunction(name, params, body) = item
+        {
+            if let Some(param) = params.iter().find(|param| globals.contains_key(*param)) {
+                return Err(SeppoError::Sema(format!(
+                    "Parameter {} of function {} has the name of a global",
+                    param, name
+                )));
+            }
+            let arity = params.len();
+            let params = params
+                .iter()
+                .map(|param| (param.clone(), Binding::Mutable))
+                .collect();
+            let mut checker = Checker {
+                function: name,
+                arity,
+                scopes: vec![globals.clone(), params],
+            };
+            checker.check(body)?;
+            if constant_time.contains(name) {
+                check_constant_time(name, body)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Attributes that can't be on the same function
+const CONFLICTING_ATTRIBUTES: &[(&str, &str)] = &[
+    (NOINLINE_ATTRIBUTE, INLINE_ATTRIBUTE),
+    (NOINLINE_ATTRIBUTE, ALWAYS_INLINE_ATTRIBUTE),
+    (COLD_ATTRIBUTE, HOT_ATTRIBUTE),
+];
+
+fn check_attribute_conflicts(program: &SeppoExpr) -> Result<()> {
+    for (first, second) in CONFLICTING_ATTRIBUTES {
+        let marked = marked_functions(program, second);
+        if let Some(name) = marked_functions(program, first).intersection(&marked).min() {
+            return Err(SeppoError::Sema(format!(
+                "fn {} can't be both {} and {}",
+                name, first, second
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Reject what takes time depending on the data in a `#[constant_time]`
+/// function: divisions, loops over anything but a range of literals and
+/// conditionals other than a choice between two values, which are
+/// compiled to a select instead of a branch
+fn check_constant_time(function: &str, expr: &SeppoExpr) -> Result<()> {
+    let check = |expr: &SeppoExpr| check_constant_time(function, expr);
+    match expr {
+        SeppoExpr::Operation(op, ..) if op == "/" => Err(SeppoError::Sema(format!(
+            "constant_time fn {} divides, which takes time depending on the operands",
+            function
+        ))),
+        SeppoExpr::Let { value, .. } | SeppoExpr::Assignment(_, value) | SeppoExpr::Return(value)
+            if matches!(value.as_ref(), SeppoExpr::Conditional { .. }) =>
+        {
+            let SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block: Some(false_block),
+            } = value.as_ref()
+            else {
+                return Err(branches(function));
+            };
+            if !is_selectable(condition) || !is_selectable(true_block) || !is_selectable(false_block)
+            {
+                return Err(branches(function));
+            }
+            check(condition)?;
+            check(true_block)?;
+            check(false_block)
+        }
+        SeppoExpr::Conditional { .. } => Err(branches(function)),
+        SeppoExpr::For { iterable, body, .. } => match iterable.as_ref() {
+            SeppoExpr::Range { start, end, .. }
+                if matches!(
+                    (start.as_ref(), end.as_ref()),
+                    (SeppoExpr::Number(_), SeppoExpr::Number(_))
+                ) =>
+            {
+                check(body)
+            }
+            _ => Err(SeppoError::Sema(format!(
+                "constant_time fn {} loops a number of times that can depend on its data, loop over a range of literals instead",
+                function
+            ))),
+        },
+        SeppoExpr::Block(statements) => statements.iter().try_for_each(check),
+        SeppoExpr::Operation(_, left, right) | SeppoExpr::In(left, right) => {
+            check(left)?;
+            check(right)
+        }
+        SeppoExpr::Let { value, .. }
+        | SeppoExpr::Assignment(_, value)
+        | SeppoExpr::Return(value)
+        | SeppoExpr::Print(_, value) => check(value),
+        SeppoExpr::FunctionCall(_, args) | SeppoExpr::Become(_, args) => {
+            args.iter().try_for_each(check)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn branches(function: &str) -> SeppoError {
+    SeppoError::Sema(format!(
+        "constant_time fn {} branches on its data, only a conditional choosing between two values without calls is allowed",
+        function
+    ))
+}
+
+/// Whether `expr` can be evaluated whether or not it is chosen: a value
+/// built from literals, variables and operators, or a block of just one
+fn is_selectable(expr: &SeppoExpr) -> bool {
+    match expr {
+        SeppoExpr::Number(_) | SeppoExpr::String(_) | SeppoExpr::Variable(_) => true,
+        SeppoExpr::Operation(_, left, right) | SeppoExpr::In(left, right) => {
+            is_selectable(left) && is_selectable(right)
+        }
+        SeppoExpr::Range { start, end, .. } => is_selectable(start) && is_selectable(end),
+        SeppoExpr::Block(statements) => {
+            let mut statements = statements.iter().filter(|s| !s.is_trivia());
+            match (statements.next(), statements.next()) {
+                (Some(value), None) => is_selectable(value),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// `argc` and `argv`, the arguments of `main` passed on to `seppo`
+pub(crate) const MAX_ENTRY_PARAMS: usize = 2;
+
+/// Check that a program defines the `seppo` entry function, which `main`
+/// calls with no arguments, `argc` or `argc, argv`
+pub(crate) fn check_entry(program: &SeppoExpr) -> Result<()> {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        item => std::slice::from_ref(item),
+    };
+    let params = items.iter().find_map(|item| match item {
+        SeppoExpr::Function(name, params, _) if name == "seppo" => Some(params),
+        _ => None,
+    });
+    match params {
+        None if !entry_candidates(items).is_empty() => Err(SeppoError::Sema(format!(
+            "No seppo function found, choose one of the entry points with --bin: {}",
+            entry_candidates(items).join(", ")
+        ))),
+        None => Err(SeppoError::Sema("No seppo function found".to_string())),
+        Some(params) if params.len() > MAX_ENTRY_PARAMS => Err(SeppoError::Sema(format!(
+            "The seppo function is called with at most argc and argv, so it cannot take more parameters: seppo({})",
+            params.join(", ")
+        ))),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Suffix of the functions `--bin` chooses between, e.g. `fn hello_seppo`
+/// for `--bin hello`
+pub(crate) const ENTRY_SUFFIX: &str = "_seppo";
+
+/// The names `--bin` accepts for the entry points of a program, sorted
+fn entry_candidates(items: &[SeppoExpr]) -> Vec<&str> {
+    let mut names: Vec<&str> = items
+        .iter()
+        .filter_map(|item| match item {
+            SeppoExpr::Function(name, ..) => name.strip_suffix(ENTRY_SUFFIX),
+            _ => None,
+        })
+        .filter(|bin| !bin.is_empty())
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+/// Make `<bin>_seppo` the entry point of `program` by adding a `seppo`
+/// that calls it with the same arguments
+pub(crate) fn select_entry(program: SeppoExpr, bin: &str) -> Result<SeppoExpr> {
+    let mut items = match program {
+        SeppoExpr::Block(items) => items,
+        item => vec![item],
+    };
+    let entry = format!("{}{}", bin, ENTRY_SUFFIX);
+    let params = items.iter().find_map(|item| match item {
+        SeppoExpr::Function(name, params, _) if *name == entry => Some(params.clone()),
+        _ => None,
+    });
+    let Some(params) = params else {
+        let candidates = entry_candidates(&items);
+        let known = if candidates.is_empty() {
+            "the program has none".to_string()
+        } else {
+            format!("expected one of {}", candidates.join(", "))
+        };
+        return Err(SeppoError::Sema(format!(
+            "No entry point {} for --bin {}, {}",
+            entry, bin, known
+        )));
+    };
+    if items
+        .iter()
+        .any(|item| matches!(item, SeppoExpr::Function(name, ..) if name == "seppo"))
+    {
+        return Err(SeppoError::Sema(format!(
+            "--bin {} needs the program not to define seppo itself",
+            bin
+        )));
+    }
+    let args = params.iter().cloned().map(SeppoExpr::Variable).collect();
+    let body = SeppoExpr::Block(vec![SeppoExpr::Return(Box::new(SeppoExpr::FunctionCall(
+        entry, args,
+    )))]);
+    items.push(SeppoExpr::Function(
+        "seppo".to_string(),
+        params,
+        Box::new(body),
+    ));
+    Ok(SeppoExpr::Block(items))
+}
+
+/// Where each name of a scope, like the functions of a module or the
+/// parameters of one function, is defined
+#[derive(Default)]
+pub(crate) struct Definitions {
+    locations: HashMap<String, String>,
+}
+
+impl Definitions {
+    /// Record `what`, e.g. "Function foo", defined as `name` at `location`,
+    /// failing with both locations if `name` is already defined
+    pub(crate) fn define(&mut self, what: &str, name: &str, location: String) -> Result<()> {
+        match self.locations.entry(name.to_string()) {
+            Entry::Occupied(first) => Err(SeppoError::Sema(format!(
+                "{} is defined twice, at {} and {}",
+                what,
+                first.get(),
+                location
+            ))),
+            Entry::Vacant(entry) => {
+                entry.insert(location);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Error for `name` used as a `kind` ("variable", "function") without a
+/// definition, suggesting the closest of the names that are defined there
+pub(crate) fn undefined<'a>(
+    kind: &str,
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> SeppoError {
+    match closest(name, candidates) {
+        Some(suggestion) => SeppoError::Sema(format!(
+            "Undefined {}: {} (did you mean `{}`?)",
+            kind, name, suggestion
+        )),
+        None => SeppoError::Sema(format!("Undefined {}: {}", kind, name)),
+    }
+}
+
+/// The candidate with the smallest edit distance to `name`, if it is close
+/// enough to be a typo: a third of the name, at least one edit
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance, ignoring case
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+struct Checker<'a> {
+    function: &'a str,
+    arity: usize,
+    /// Innermost last, after the globals and then the parameters. Branches
+    /// and loop bodies get their own scope, like variables first assigned
+    /// in them do at runtime.
+    scopes: Vec<HashMap<String, Binding>>,
+}
+
+impl Checker<'_> {
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn declare(&mut self, name: &str, binding: Binding) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), binding);
+    }
+
+    fn scoped(&mut self, binding: Option<(&str, Binding)>, body: &SeppoExpr) -> Result<()> {
+        self.scopes.push(HashMap::new());
+        if let Some((name, binding)) = binding {
+            self.declare(name, binding);
+        }
+        let result = self.check(body);
+        self.scopes.pop();
+        result
+    }
+
+    fn check(&mut self, expr: &SeppoExpr) -> Result<()> {
+        match expr {
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } => {
+                self.check(value)?;
+                if self.lookup(name).is_some() {
+                    return Err(SeppoError::Sema(format!(
+                        "Variable {} is already declared in function {}",
+                        name, self.function
+                    )));
+                }
+                let binding = if *mutable {
+                    Binding::Mutable
+                } else {
+                    Binding::Immutable
+                };
+                self.declare(name, binding);
+                Ok(())
+            }
+            SeppoExpr::Assignment(name, value) => {
+                self.check(value)?;
+                match self.lookup(name) {
+                    Some(Binding::Immutable) => Err(SeppoError::Sema(format!(
+                        "Cannot assign twice to immutable variable {} in function {} (declare it with `let mut {}`)",
+                        name, self.function, name
+                    ))),
+                    Some(Binding::Loop) => Err(SeppoError::Sema(format!(
+                        "Cannot assign to loop variable {} in function {}",
+                        name, self.function
+                    ))),
+                    Some(Binding::Mutable) => Ok(()),
+                    None => {
+                        self.declare(name, Binding::Immutable);
+                        Ok(())
+                    }
+                }
+            }
+            SeppoExpr::Block(statements) => statements.iter().try_for_each(|s| self.check(s)),
+            SeppoExpr::Conditional {
+                condition,
+                true_block,
+                false_block,
+            } => {
+                self.check(condition)?;
+                self.scoped(None, true_block)?;
+                match false_block {
+                    Some(block) => self.scoped(None, block),
+                    None => Ok(()),
+                }
+            }
+            SeppoExpr::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.check(iterable)?;
+                if self.scopes[0].contains_key(variable) {
+                    return Err(SeppoError::Sema(format!(
+                        "Loop variable {} in function {} has the name of a global",
+                        variable, self.function
+                    )));
+                }
+                self.scoped(Some((variable, Binding::Loop)), body)
+            }
+            SeppoExpr::Operation(_, left, right)
+            | SeppoExpr::In(left, right)
+            | SeppoExpr::Range {
+                start: left,
+                end: right,
+                ..
+            } => {
+                self.check(left)?;
+                self.check(right)
+            }
+            SeppoExpr::Become(name, args) => {
+                if name != self.function {
+                    return Err(SeppoError::Sema(format!(
+                        "become can only call the function it is in: {} calls {}",
+                        self.function, name
+                    )));
+                }
+                if args.len() != self.arity {
+                    return Err(SeppoError::Sema(format!(
+                        "Function {} expects {} arguments, got {}",
+                        name,
+                        self.arity,
+                        args.len()
+                    )));
+                }
+                args.iter().try_for_each(|arg| self.check(arg))
+            }
+            SeppoExpr::Print(_, value) | SeppoExpr::Return(value) => self.check(value),
+            SeppoExpr::FunctionCall(_, args) => args.iter().try_for_each(|arg| self.check(arg)),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use crate::error::SeppoError;
+use crate::lexer::is_keyword;
+use crate::parser::Rule;
+use pest::error::{Error, ErrorVariant, InputLocation};
+
+/// Turn a pest error into one that says what was expected in words, rather
+/// than grammar rule names, with a hint for the usual mistakes
+pub(crate) fn syntax_error(error: Error<Rule>, input: &str) -> SeppoError {
+    let ErrorVariant::ParsingError { positives, .. } = &error.variant else {
+        return error.into();
+    };
+    let pos = match error.location {
+        InputLocation::Pos(pos) => pos,
+        InputLocation::Span((start, _)) => start,
+    };
+    let expected: Vec<Rule> = positives
+        .iter()
+        .copied()
+        .filter(|rule| !matches!(rule, Rule::COMMENT | Rule::WHITESPACE))
+        .collect();
+    let found = token_at(input, pos);
+    let (message, help) = explain(&expected, input, pos, found);
+
+    let mut error: SeppoError = error.into();
+    if let SeppoError::Parse {
+        message: old_message,
+        help: old_help,
+        span,
+        ..
+    } = &mut error
+    {
+        *old_message = message;
+        *old_help = help;
+        span.end = span.start + found.len();
+    }
+    error
+}
+
+fn explain(expected: &[Rule], input: &str, pos: usize, found: &str) -> (String, Option<String>) {
+    let found_text = if found.is_empty() {
+        "end of input".to_string()
+    } else if is_line_break(found) {
+        "the end of the line".to_string()
+    } else {
+        format!("`{}`", found)
+    };
+    let help = |text: &str| Some(text.to_string());
+
+    if let Some(keyword) = keyword_as_name(expected, input, pos, found) {
+        return (
+            format!("`{}` is a keyword and can't be used as a name", keyword),
+            Some(format!("rename it, e.g. `{}_value`", keyword)),
+        );
+    }
+
+    if expected.contains(&Rule::program) || expected.contains(&Rule::EOI) {
+        return (
+            format!(
+                "expected a function, global, macro or ceppo block, found {}",
+                found_text
+            ),
+            help("statements have to be inside a function, e.g. `fn seppo() { ... }`"),
+        );
+    }
+
+    if expected.contains(&Rule::statement) {
+        if found.is_empty() {
+            return (
+                "unexpected end of input, expected a statement or `}`".to_string(),
+                help("a block is missing its closing `}`"),
+            );
+        }
+        // Words of other languages parse as variables, so the statement
+        // only fails after them
+        let (line_start, first_word) = leading_word(input, pos);
+        if first_word == "seppo" && found == "{" {
+            return comparison_expected(&found_text);
+        }
+        let message = if after_conditional(input, line_start) {
+            format!(
+                "expected a statement, `perkele` block or `}}`, found {}",
+                found_text
+            )
+        } else {
+            format!("expected a statement or `}}`, found {}", found_text)
+        };
+        return (message, statement_hint(first_word, found));
+    }
+
+    // More on the line after a complete statement
+    if expected.contains(&Rule::separator) {
+        let (_, first_word) = leading_word(input, pos);
+        if first_word == "seppo" && found == "{" {
+            return comparison_expected(&found_text);
+        }
+        let message = if after_conditional(input, pos) {
+            format!(
+                "expected a line break, `;` or `perkele` block, found {}",
+                found_text
+            )
+        } else {
+            format!("expected a line break or `;`, found {}", found_text)
+        };
+        return (message, statement_hint(first_word, found));
+    }
+
+    if expected.contains(&Rule::comparison_op) {
+        return comparison_expected(&found_text);
+    }
+
+    if expected == [Rule::identifier] && input[..pos].trim_end().ends_with('(') {
+        return (
+            format!("expected a parameter name or `)`, found {}", found_text),
+            None,
+        );
+    }
+
+    let mut phrases: Vec<&str> = expected.iter().map(|rule| phrase(*rule)).collect();
+    phrases.dedup();
+    let expected = match phrases.as_slice() {
+        [] => "something else".to_string(),
+        [only] => only.to_string(),
+        [rest @ .., last] => format!("{} or {}", rest.join(", "), last),
+    };
+    let hint = if is_line_break(found) {
+        help("the line break ends the statement, only the arguments of a call can go on several lines")
+    } else {
+        None
+    };
+    (format!("expected {}, found {}", expected, found_text), hint)
+}
+
+/// A hint for a statement going wrong at `found`, on a line starting with
+/// `first_word`
+fn statement_hint(first_word: &str, found: &str) -> Option<String> {
+    let help = |text: &str| Some(text.to_string());
+    match (first_word, found) {
+        ("else" | "elif", _) => {
+            help("the other branch of a conditional is written `perkele { ... }`")
+        }
+        ("if", _) => help("conditionals are written `seppo x > 0 { ... }`"),
+        ("while" | "loop", _) => help("loops are written `for i in 0..n { ... }`"),
+        ("var" | "const", _) => help("variables are declared with `let x = ...` or `x = ...`"),
+        (_, "\"") => help("the string is missing its closing `\"`"),
+        // The operand before it ended the statement on the line above
+        (word, "+" | "-" | "*" | "/") if word == found => help(
+            "the line break ends the statement, keep an operation on one line",
+        ),
+        (_, "+" | "-" | "*" | "/") => help(
+            "an operation takes exactly two operands, store the first part in a variable, e.g. `t = a * b`",
+        ),
+        _ => None,
+    }
+}
+
+fn is_line_break(found: &str) -> bool {
+    found == "\n" || found == "\r"
+}
+
+/// The keyword a failed parse used as a name: in place of one, as in
+/// `let ceppo = 1`, or assigned to, as in `return = 1`
+fn keyword_as_name<'a>(
+    expected: &[Rule],
+    input: &'a str,
+    pos: usize,
+    found: &'a str,
+) -> Option<&'a str> {
+    let is_assigned = |rest: &str| {
+        let rest = rest.trim_start();
+        rest.starts_with('=') && !rest.starts_with("==")
+    };
+    if is_keyword(found) {
+        let wants_name = expected.iter().any(|rule| {
+            matches!(
+                rule,
+                Rule::identifier | Rule::variable | Rule::param_list | Rule::param
+            )
+        });
+        return (wants_name || is_assigned(&input[pos + found.len()..])).then_some(found);
+    }
+    if !is_assigned(&input[pos..]) {
+        return None;
+    }
+    let before = input[..pos].trim_end();
+    let word = &before[before
+        .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_')
+        .len()..];
+    is_keyword(word).then_some(word)
+}
+
+fn comparison_expected(found: &str) -> (String, Option<String>) {
+    (
+        format!(
+            "expected a comparison (`==`, `!=`, `<`, `>`, `<=` or `>=`) or `in`, found {}",
+            found
+        ),
+        Some("a condition compares two values, e.g. `seppo x != 0 { ... }`".to_string()),
+    )
+}
+
+/// How a rule reads in "expected ..."
+fn phrase(rule: Rule) -> &'static str {
+    match rule {
+        Rule::block => "`{`",
+        // Conditionals are expressions where a value is expected
+        Rule::expression
+        | Rule::print_item
+        | Rule::range_bound
+        | Rule::conditional_block
+        | Rule::boolean
+        | Rule::comparison => "an expression",
+        Rule::identifier | Rule::variable => "a name",
+        Rule::param_list | Rule::param => "a parameter name",
+        Rule::type_name => "a type",
+        Rule::return_type => "`->`",
+        Rule::arg_list => "an argument",
+        Rule::number => "a number",
+        Rule::string_literal | Rule::strings => "a string",
+        Rule::operator => "an operator",
+        Rule::range_op => "`..` or `..=`",
+        Rule::in_keyword => "`in`",
+        Rule::comparison_op => "a comparison",
+        Rule::and_op => "`&&`",
+        Rule::or_op => "`||`",
+        Rule::function_call => "a function call",
+        Rule::c_code => "C code",
+        Rule::attribute_arg => "a lint name",
+        Rule::separator => "a line break or `;`",
+        Rule::EOI => "end of input",
+        _ => "a statement",
+    }
+}
+
+/// The token starting at `pos`: a word, or a single other character
+fn token_at(input: &str, pos: usize) -> &str {
+    let rest = &input[pos.min(input.len())..];
+    let word = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    if word > 0 {
+        &rest[..word]
+    } else {
+        rest.chars().next().map_or("", |c| &rest[..c.len_utf8()])
+    }
+}
+
+/// The first word of the line `pos` is on, after any closing braces, and
+/// where that word starts
+fn leading_word(input: &str, pos: usize) -> (usize, &str) {
+    let line_start = input[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let line = &input[line_start..pos];
+    let trimmed = line.trim_start_matches(|c: char| c.is_whitespace() || c == '}');
+    let start = line_start + line.len() - trimmed.len();
+    (start, token_at(input, start))
+}
+
+/// Whether `pos` follows the closing brace of a `seppo condition { ... }`,
+/// where a `perkele` branch could start
+fn after_conditional(input: &str, pos: usize) -> bool {
+    let before = input[..pos].trim_end();
+    if !before.ends_with('}') {
+        return false;
+    }
+    // Find the brace that opens the block just closed
+    let mut depth = 0;
+    for (i, c) in before.char_indices().rev() {
+        match c {
+            '}' => depth += 1,
+            '{' => {
+                depth -= 1;
+                if depth == 0 {
+                    let line_start = before[..i].rfind('\n').map_or(0, |n| n + 1);
+                    return before[line_start..i].trim_start().starts_with("seppo ");
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
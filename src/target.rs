@@ -0,0 +1,260 @@
+use crate::error::{Result, SeppoError};
+use inkwell::targets::{InitializationConfig, Target, TargetMachine};
+use std::env;
+use std::process::Command;
+
+/// The platform seppoc is generating code for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSpec {
+    triple: String,
+}
+
+impl TargetSpec {
+    /// The host machine the compiler is running on.
+    pub fn native() -> Self {
+        Self {
+            triple: TargetMachine::get_default_triple()
+                .as_str()
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+
+    pub fn from_triple(triple: &str) -> Result<Self> {
+        // Accept the short rustc-style spellings for the wasm targets
+        let musl;
+        let triple = match triple {
+            "wasm32-wasi" | "wasm32-wasip1" => "wasm32-unknown-wasi",
+            "wasm32-unknown" => "wasm32-unknown-unknown",
+            "native" => return Ok(Self::native()),
+            // Static Linux binaries for the host architecture
+            "musl" => {
+                musl = format!("{}-unknown-linux-musl", env::consts::ARCH);
+                &musl
+            }
+            other => other,
+        };
+
+        if triple.split('-').count() < 2 {
+            return Err(SeppoError::Codegen(format!(
+                "Invalid target triple: {}",
+                triple
+            )));
+        }
+
+        Ok(Self {
+            triple: triple.to_string(),
+        })
+    }
+
+    pub fn triple(&self) -> &str {
+        &self.triple
+    }
+
+    pub fn is_native(&self) -> bool {
+        *self == Self::native()
+    }
+
+    pub fn is_wasm(&self) -> bool {
+        self.triple.starts_with("wasm32") || self.triple.starts_with("wasm64")
+    }
+
+    pub fn is_wasi(&self) -> bool {
+        self.is_wasm() && self.triple.contains("wasi")
+    }
+
+    pub fn is_apple(&self) -> bool {
+        self.triple.contains("-apple-")
+    }
+
+    /// Architecture name for `-arch` on Apple platforms
+    fn apple_arch(&self) -> Option<&str> {
+        if !self.is_apple() {
+            return None;
+        }
+        match self.triple.split('-').next() {
+            Some("aarch64" | "arm64") => Some("arm64"),
+            arch => arch,
+        }
+    }
+
+    /// Linux with musl libc, linked statically into a binary that runs on
+    /// any distribution
+    pub fn is_musl(&self) -> bool {
+        self.triple.ends_with("-musl")
+    }
+
+    /// Extension of the final linked artifact
+    pub fn exe_extension(&self) -> &'static str {
+        if self.is_wasm() {
+            "wasm"
+        } else {
+            env::consts::EXE_EXTENSION
+        }
+    }
+
+    /// Register the LLVM backend needed for this target
+    pub fn initialize(&self) -> Result<()> {
+        if self.is_wasm() {
+            Target::initialize_webassembly(&InitializationConfig::default());
+            Ok(())
+        } else {
+            // Cross compiling needs the backend of the target, e.g. both
+            // halves of a universal binary
+            let config = InitializationConfig::default();
+            match self.triple.split('-').next() {
+                Some("aarch64" | "arm64") if !self.is_native() => {
+                    Target::initialize_aarch64(&config);
+                    Ok(())
+                }
+                Some("x86_64" | "i386" | "i686") if !self.is_native() => {
+                    Target::initialize_x86(&config);
+                    Ok(())
+                }
+                _ => Target::initialize_native(&config)
+                    .map_err(|e| SeppoError::Codegen(format!("Failed to initialize LLVM: {}", e))),
+            }
+        }
+    }
+
+    /// CPU name and feature string handed to the target machine
+    pub fn cpu_and_features(&self) -> (String, String) {
+        if self.is_native() {
+            (
+                TargetMachine::get_host_cpu_name().to_string(),
+                TargetMachine::get_host_cpu_features().to_string(),
+            )
+        } else {
+            ("generic".to_string(), String::new())
+        }
+    }
+
+    /// Compiler invocation used for ceppo blocks, without input/output
+    /// arguments: `$CC` or `cc`, clang for wasm, and for musl `musl-gcc` or
+    /// clang when `$MUSL_SYSROOT` is set
+    pub fn c_compiler(&self) -> Command {
+        let compiler = if self.is_wasm() {
+            "clang".to_string()
+        } else if self.is_musl() {
+            match musl_sysroot() {
+                Some(_) => "clang".to_string(),
+                None => "musl-gcc".to_string(),
+            }
+        } else {
+            env::var("CC").unwrap_or_else(|_| "cc".to_string())
+        };
+        self.c_compiler_with(&compiler)
+    }
+
+    /// Like `c_compiler` with another compiler. `compiler` may carry
+    /// arguments, e.g. `ccache cc`. Native builds add the flags in `$CFLAGS`.
+    pub fn c_compiler_with(&self, compiler: &str) -> Command {
+        let mut words = compiler.split_whitespace();
+        let mut cmd = Command::new(words.next().unwrap_or("cc"));
+        cmd.args(words);
+        if self.is_wasm() {
+            cmd.arg(format!("--target={}", self.triple));
+            if let Ok(sysroot) = env::var("WASI_SYSROOT") {
+                cmd.arg(format!("--sysroot={}", sysroot));
+            }
+            cmd.arg("-c");
+            cmd
+        } else {
+            cmd.arg("-c").arg("-fPIC");
+            if let Some(arch) = self.apple_arch() {
+                cmd.arg("-arch").arg(arch);
+            }
+            if let Some(sysroot) = self.musl_clang_sysroot() {
+                cmd.arg(format!("--target={}", self.triple));
+                cmd.arg(format!("--sysroot={}", sysroot));
+            }
+            if let Ok(flags) = env::var("CFLAGS") {
+                cmd.args(flags.split_whitespace());
+            }
+            cmd
+        }
+    }
+
+    /// Clang invocation compiling C to LLVM bitcode, for ceppo blocks that
+    /// are linked into the module for link-time optimization
+    pub fn bitcode_compiler(&self) -> Command {
+        let mut cmd = Command::new("clang");
+        cmd.arg(format!("--target={}", self.triple));
+        if self.is_wasm() {
+            if let Ok(sysroot) = env::var("WASI_SYSROOT") {
+                cmd.arg(format!("--sysroot={}", sysroot));
+            }
+        } else {
+            cmd.arg("-fPIC");
+            if let Some(sysroot) = self.musl_clang_sysroot() {
+                cmd.arg(format!("--sysroot={}", sysroot));
+            }
+            if let Ok(flags) = env::var("CFLAGS") {
+                cmd.args(flags.split_whitespace());
+            }
+        }
+        cmd.arg("-c").arg("-emit-llvm");
+        cmd
+    }
+
+    /// Linker invocation, without input/output arguments
+    pub fn linker(&self) -> Command {
+        if self.is_wasm() {
+            let mut cmd = Command::new(env::var("WASM_LD").unwrap_or_else(|_| "wasm-ld".into()));
+            if self.is_wasi() {
+                cmd.arg("--entry=_start");
+            } else {
+                // No WASI entry point, the host calls the exported seppo directly
+                cmd.arg("--no-entry").arg("--export=seppo");
+            }
+            cmd
+        } else if self.is_musl() {
+            // Static, so the binary needs no libc on the machine it runs on
+            let mut cmd = match self.musl_clang_sysroot() {
+                Some(sysroot) => {
+                    let mut cmd = Command::new("clang");
+                    cmd.arg(format!("--target={}", self.triple))
+                        .arg(format!("--sysroot={}", sysroot))
+                        .arg("-fuse-ld=lld");
+                    cmd
+                }
+                None => Command::new("musl-gcc"),
+            };
+            cmd.arg("-static");
+            cmd
+        } else {
+            let mut cmd = Command::new("cc");
+            cmd.arg("-v"); // Add verbose output for debugging
+            if let Some(arch) = self.apple_arch() {
+                cmd.arg("-arch").arg(arch);
+            }
+            cmd
+        }
+    }
+
+    /// Strip invocation, without the file: `$STRIP` or `strip`, and
+    /// `llvm-strip` for wasm
+    pub fn strip(&self) -> Command {
+        let strip = if self.is_wasm() {
+            "llvm-strip".to_string()
+        } else {
+            env::var("STRIP").unwrap_or_else(|_| "strip".to_string())
+        };
+        Command::new(strip)
+    }
+
+    fn musl_clang_sysroot(&self) -> Option<String> {
+        musl_sysroot().filter(|_| self.is_musl())
+    }
+}
+
+/// musl headers and libraries for building with clang instead of musl-gcc
+fn musl_sysroot() -> Option<String> {
+    env::var("MUSL_SYSROOT").ok()
+}
+
+impl Default for TargetSpec {
+    fn default() -> Self {
+        Self::native()
+    }
+}
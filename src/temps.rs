@@ -0,0 +1,61 @@
+use crate::error::Result;
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::{Builder, TempDir};
+
+/// The temporary directories and intermediate files of one compilation.
+/// They are removed when this is dropped, also when compiling fails,
+/// unless `keep` is set, as with `--keep-temps`.
+#[derive(Debug, Default)]
+pub(crate) struct TempArtifacts {
+    keep: bool,
+    dirs: RefCell<Vec<TempDir>>,
+    files: RefCell<Vec<PathBuf>>,
+}
+
+impl TempArtifacts {
+    pub(crate) fn new(keep: bool) -> Self {
+        Self {
+            keep,
+            dirs: RefCell::default(),
+            files: RefCell::default(),
+        }
+    }
+
+    /// Create a fresh directory under the system temp dir whose name starts
+    /// with `prefix`
+    pub(crate) fn dir(&self, prefix: &str) -> Result<PathBuf> {
+        let dir = Builder::new()
+            .prefix(&format!("{}_", prefix))
+            .disable_cleanup(self.keep)
+            .tempdir()?;
+        let path = dir.path().to_path_buf();
+        self.dirs.borrow_mut().push(dir);
+        Ok(path)
+    }
+
+    /// Remove `path` along with the directories, for intermediate files
+    /// written next to the output, like the object file before linking
+    pub(crate) fn file(&self, path: &Path) {
+        self.files.borrow_mut().push(path.to_path_buf());
+    }
+}
+
+impl Drop for TempArtifacts {
+    fn drop(&mut self) {
+        if self.keep {
+            for dir in self.dirs.get_mut() {
+                eprintln!("Kept temporary files in {}", dir.path().display());
+            }
+            for file in self.files.get_mut() {
+                eprintln!("Kept {}", file.display());
+            }
+            return;
+        }
+        for file in self.files.get_mut() {
+            let _ = fs::remove_file(file);
+        }
+        // The directories remove themselves when dropped
+    }
+}
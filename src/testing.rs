@@ -0,0 +1,176 @@
+use crate::compile::CompileOptions;
+use crate::coverage::Coverage;
+use crate::driver::{Driver, Input};
+use crate::error::{Result, SeppoError};
+use crate::jit::Jit;
+use crate::temps::TempArtifacts;
+use crate::types::SeppoExpr;
+use inkwell::context::Context;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "arbitrary")]
+pub use crate::generate::{arbitrary_program, ArbitraryProgram};
+
+/// Functions whose name starts with this are run by `seppoc test`
+pub const TEST_PREFIX: &str = "testi_";
+
+/// Result of running one test function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestStatus {
+    Passed,
+    /// The test returned something other than zero
+    Failed(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct TestOutcome {
+    pub name: String,
+    pub status: TestStatus,
+    pub duration: Duration,
+}
+
+/// Names of the test functions in a program, in source order
+pub fn test_functions(program: &SeppoExpr) -> Vec<String> {
+    functions_with_prefix(program, TEST_PREFIX)
+}
+
+pub(crate) fn functions_with_prefix(program: &SeppoExpr, prefix: &str) -> Vec<String> {
+    match program {
+        SeppoExpr::Block(items) => items
+            .iter()
+            .flat_map(|item| functions_with_prefix(item, prefix))
+            .collect(),
+        SeppoExpr::Function(name, _, _) if name.starts_with(prefix) => vec![name.clone()],
+        _ => Vec::new(),
+    }
+}
+
+/// Compile a program with the JIT and run its tests whose name contains
+/// `filter`, or all of them
+pub fn run_tests(program: &SeppoExpr, filter: Option<&str>) -> Result<Vec<TestOutcome>> {
+    let context = Context::create();
+    let jit = Jit::new(&context, program)?;
+    run_jit_tests(&jit, program, filter)
+}
+
+/// `run_tests` with coverage counters, also returning how often each line
+/// ran across all the tests. The program should come from
+/// `parse_with_locations`.
+pub fn run_tests_with_coverage(
+    program: &SeppoExpr,
+    filter: Option<&str>,
+) -> Result<(Vec<TestOutcome>, Coverage)> {
+    let context = Context::create();
+    let jit = Jit::with_coverage(&context, program)?;
+    let outcomes = run_jit_tests(&jit, program, filter)?;
+    Ok((outcomes, jit.coverage()?))
+}
+
+fn run_jit_tests(jit: &Jit, program: &SeppoExpr, filter: Option<&str>) -> Result<Vec<TestOutcome>> {
+    let mut outcomes = Vec::new();
+    for name in test_functions(program) {
+        if filter.is_some_and(|filter| !name.contains(filter)) {
+            continue;
+        }
+        let start = Instant::now();
+        let code = jit.call(&name, &[])?;
+        let status = if code == 0 {
+            TestStatus::Passed
+        } else {
+            TestStatus::Failed(code)
+        };
+        outcomes.push(TestOutcome {
+            name,
+            status,
+            duration: start.elapsed(),
+        });
+    }
+    Ok(outcomes)
+}
+
+/// What a program compiled to and did when run, for snapshot tests of the
+/// compiler. `Display` renders all three as one snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Golden {
+    /// The module's LLVM IR, see `normalize_ir`
+    pub ir: String,
+    pub stdout: String,
+    pub exit_code: i64,
+}
+
+impl fmt::Display for Golden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "exit code: {}", self.exit_code)?;
+        writeln!(f, "--- stdout")?;
+        write!(f, "{}", self.stdout)?;
+        if !self.stdout.is_empty() && !self.stdout.ends_with('\n') {
+            writeln!(f)?;
+        }
+        writeln!(f, "--- ir")?;
+        write!(f, "{}", self.ir)
+    }
+}
+
+/// Compile seppo source like `compile_str`, run the executable and collect
+/// its normalized IR, standard output and exit code
+pub fn golden(source: &str, options: &CompileOptions) -> Result<Golden> {
+    let temps = TempArtifacts::new(options.keep_temps);
+    golden_in(&temps.dir("seppolang_golden")?, source, options)
+}
+
+fn golden_in(dir: &Path, source: &str, options: &CompileOptions) -> Result<Golden> {
+    let mut driver = Driver::new(vec![Input::Source {
+        name: options.module_name.clone(),
+        text: source.to_string(),
+    }]);
+    driver.output = Some(dir.join(&options.module_name));
+    driver.target = options.target.clone();
+    driver.opt_level = options.opt_level;
+    driver.keep_temps = options.keep_temps;
+    driver.dump_ir = false;
+    driver.emit.llvm_ir = true;
+    let executable = driver.run()?;
+
+    let ir = fs::read_to_string(driver.output_path()?.llvm_ir())?;
+    let run = Command::new(&executable)
+        .output()
+        .map_err(|e| SeppoError::Runtime(format!("Failed to execute binary: {}", e)))?;
+    let exit_code = run
+        .status
+        .code()
+        .ok_or_else(|| SeppoError::Runtime("Process terminated by signal".to_string()))?;
+    Ok(Golden {
+        ir: normalize_ir(&ir),
+        stdout: String::from_utf8_lossy(&run.stdout).replace("\r\n", "\n"),
+        exit_code: exit_code as i64,
+    })
+}
+
+/// Drop the parts of printed LLVM IR that depend on the machine rather than
+/// the program: the module header, the target's data layout and triple,
+/// and trailing whitespace
+pub fn normalize_ir(ir: &str) -> String {
+    let mut normalized = String::new();
+    for line in ir.lines() {
+        if line.starts_with("; ModuleID")
+            || line.starts_with("source_filename")
+            || line.starts_with("target datalayout")
+            || line.starts_with("target triple")
+        {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && (normalized.is_empty() || normalized.ends_with("\n\n")) {
+            continue;
+        }
+        normalized.push_str(line);
+        normalized.push('\n');
+    }
+    normalized.truncate(normalized.trim_end().len());
+    normalized.push('\n');
+    normalized
+}
@@ -0,0 +1,160 @@
+use crate::ice;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Stages of a build that `--time-passes` reports separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Parse,
+    /// Macro expansion, checks and constant folding
+    Sema,
+    /// Building LLVM IR
+    Codegen,
+    /// The C compiler building ceppo blocks
+    Ceppo,
+    /// Optimization and instrumentation passes and object emission
+    Llvm,
+    Link,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Phase::Parse,
+        Phase::Sema,
+        Phase::Codegen,
+        Phase::Ceppo,
+        Phase::Llvm,
+        Phase::Link,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Sema => "sema",
+            Phase::Codegen => "codegen",
+            Phase::Ceppo => "ceppo",
+            Phase::Llvm => "llvm",
+            Phase::Link => "link",
+        }
+    }
+}
+
+/// Wall time spent in each phase. A phase running inside another, like
+/// ceppo blocks compiled during codegen, only counts towards itself.
+#[derive(Debug, Clone, Default)]
+pub struct PassTimes {
+    times: Vec<(Phase, Duration)>,
+}
+
+impl PassTimes {
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.times
+            .iter()
+            .filter(|(p, _)| *p == phase)
+            .map(|(_, time)| *time)
+            .sum()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.times.iter().map(|(_, time)| *time).sum()
+    }
+
+    fn add(&mut self, phase: Phase, time: Duration) {
+        match self.times.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += time,
+            None => self.times.push((phase, time)),
+        }
+    }
+
+    /// One line per phase with its time and share of the total
+    pub fn to_table(&self) -> String {
+        let total = self.total().as_secs_f64();
+        let mut out = String::new();
+        for phase in Phase::ALL {
+            let time = self.get(phase).as_secs_f64();
+            let share = if total > 0.0 {
+                time / total * 100.0
+            } else {
+                0.0
+            };
+            let _ = writeln!(
+                out,
+                "{:<8} {:>10.3}ms {:>6.1}%",
+                phase.name(),
+                time * 1000.0,
+                share
+            );
+        }
+        let _ = writeln!(out, "{:<8} {:>10.3}ms", "total", total * 1000.0);
+        out
+    }
+
+    /// `{"parse": seconds, ..., "total": seconds}`
+    pub fn to_json(&self) -> String {
+        let mut fields: Vec<String> = Phase::ALL
+            .iter()
+            .map(|phase| format!("\"{}\": {}", phase.name(), self.get(*phase).as_secs_f64()))
+            .collect();
+        fields.push(format!("\"total\": {}", self.total().as_secs_f64()));
+        format!("{{{}}}", fields.join(", "))
+    }
+}
+
+struct Timer {
+    times: PassTimes,
+    /// Time spent in nested phases, one entry per phase being timed
+    nested: Vec<Duration>,
+}
+
+thread_local! {
+    static TIMER: RefCell<Option<Timer>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, timing the phases it goes through on this thread
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, PassTimes) {
+    let previous = TIMER.with(|timer| {
+        timer.borrow_mut().replace(Timer {
+            times: PassTimes::default(),
+            nested: Vec::new(),
+        })
+    });
+    let result = f();
+    let timer = TIMER.with(|timer| std::mem::replace(&mut *timer.borrow_mut(), previous));
+    (result, timer.map(|timer| timer.times).unwrap_or_default())
+}
+
+/// Count the time `f` takes towards `phase`, when inside `measure`
+pub(crate) fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let previous = ice::enter_phase(Some(phase));
+    let result = time_phase(phase, f);
+    ice::enter_phase(previous);
+    result
+}
+
+fn time_phase<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let active = TIMER.with(|timer| match timer.borrow_mut().as_mut() {
+        Some(timer) => {
+            timer.nested.push(Duration::ZERO);
+            true
+        }
+        None => false,
+    });
+    if !active {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    TIMER.with(|timer| {
+        if let Some(timer) = timer.borrow_mut().as_mut() {
+            let nested = timer.nested.pop().unwrap_or_default();
+            timer.times.add(phase, elapsed.saturating_sub(nested));
+            if let Some(parent) = timer.nested.last_mut() {
+                *parent += elapsed;
+            }
+        }
+    });
+    result
+}
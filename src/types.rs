@@ -1,25 +1,358 @@
-#[derive(Debug, Clone)]
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum SeppoExpr {
     Number(i64),
     String(String),
     Variable(String),
     Operation(String, Box<SeppoExpr>, Box<SeppoExpr>),
     Assignment(String, Box<SeppoExpr>),
+    /// `let name = value`, or `let mut name = value` when the variable may be
+    /// assigned again later
+    Let {
+        name: String,
+        mutable: bool,
+        value: Box<SeppoExpr>,
+    },
     Print(PrintFormat, Box<SeppoExpr>),
     Block(Vec<SeppoExpr>),
     Function(String, Vec<String>, Box<SeppoExpr>),
+    /// `const fn`, whose calls with constant arguments are evaluated at
+    /// compile time. Folded into a plain `Function` after parsing.
+    ConstFunction(String, Vec<String>, Box<SeppoExpr>),
+    /// `macro name(params) { ... }`, replaced by its expansion before the
+    /// program reaches codegen
+    Macro(String, Vec<String>, Box<SeppoExpr>),
     FunctionCall(String, Vec<SeppoExpr>),
     Return(Box<SeppoExpr>),
+    /// `become f(args)`, a call of the enclosing function in tail position
+    /// that reuses the current stack frame
+    Become(String, Vec<SeppoExpr>),
     InlineC(String),
     Conditional {
         condition: Box<SeppoExpr>,
         true_block: Box<SeppoExpr>,
         false_block: Option<Box<SeppoExpr>>,
     },
+    /// `start..end`, or `start..=end` when `inclusive`
+    Range {
+        start: Box<SeppoExpr>,
+        end: Box<SeppoExpr>,
+        inclusive: bool,
+    },
+    /// Membership test `value in range`, 1 when the value is in the range
+    In(Box<SeppoExpr>, Box<SeppoExpr>),
+    /// `for variable in iterable { body }`. Strings are iterated byte by
+    /// byte, ranges by number.
+    For {
+        variable: String,
+        iterable: Box<SeppoExpr>,
+        body: Box<SeppoExpr>,
+    },
+    /// A `//` comment, only produced by `parse_with_comments`. `trailing`
+    /// comments sit at the end of the line of the preceding statement.
+    Comment {
+        text: String,
+        trailing: bool,
+    },
+    /// An empty line between statements, also only kept for the formatter
+    BlankLine,
+    /// Source line of the statement that follows, only produced by
+    /// `parse_with_locations` for coverage instrumentation
+    Location {
+        file: String,
+        line: usize,
+    },
+    /// One line of a `##` doc comment, without the marker, documenting the
+    /// function that follows. Unlike comments these are always kept.
+    DocComment(String),
+    /// `#[name(args)]` on the function that follows, such as
+    /// `#[allow(unused-function)]`. `export fn` and `inline fn` are an
+    /// `export` and an `inline` attribute. In a block, `#[unroll(n)]` on
+    /// the `for` loop that follows.
+    Attribute {
+        name: String,
+        args: Vec<String>,
+    },
+    /// The types declared by `fn f(a: int, b: string) -> int`, on the
+    /// function that follows. Only there for functions that declare any.
+    Signature(Signature),
+}
+
+/// Types a function signature can name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SeppoType {
+    /// `int`, a 64-bit integer
+    Int,
+    /// `string`, the address of a NUL-terminated string
+    String,
+    /// `option`, `Some(value)` or `None`, unwrapped before use
+    Option,
+    /// `i64x4`, four 64-bit integers operated on at once
+    I64x4,
+}
+
+impl SeppoType {
+    /// The types by the names they are written with
+    pub(crate) const ALL: &'static [(&'static str, SeppoType)] = &[
+        ("int", SeppoType::Int),
+        ("string", SeppoType::String),
+        ("option", SeppoType::Option),
+        ("i64x4", SeppoType::I64x4),
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SeppoType::Int => "int",
+            SeppoType::String => "string",
+            SeppoType::Option => "option",
+            SeppoType::I64x4 => "i64x4",
+        }
+    }
+}
+
+/// Declared types of a function's parameters, `None` for those without
+/// one, and of its result
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Signature {
+    pub params: Vec<Option<SeppoType>>,
+    pub returns: Option<SeppoType>,
+}
+
+impl Signature {
+    /// `(a: int, b) -> int`, the parameters `params` with the types
+    /// declared for them and the return type, as written in the source
+    pub fn format(&self, params: &[String]) -> String {
+        let params: Vec<String> = params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| match self.params.get(i).copied().flatten() {
+                Some(ty) => format!("{}: {}", param, ty.name()),
+                None => param.clone(),
+            })
+            .collect();
+        match self.returns {
+            Some(ty) => format!("({}) -> {}", params.join(", "), ty.name()),
+            None => format!("({})", params.join(", ")),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Ranges have no runtime representation yet, they are lowered where used
+pub(crate) const RANGE_OUTSIDE_LOOP: &str = "Ranges can only be used in for loops and `in` tests";
+
+/// Operators of conditions, which produce a truth value rather than a
+/// number
+pub(crate) fn is_comparison(op: &str) -> bool {
+    matches!(op, ">" | "<" | ">=" | "<=" | "==" | "!=")
+}
+
+/// Operators joining conditions, which only evaluate their right side
+/// when the left one doesn't decide the result
+pub(crate) fn is_logical(op: &str) -> bool {
+    matches!(op, "&&" | "||")
+}
+
+/// Name of the attribute `export fn` puts on the function, which keeps
+/// its unmangled name
+pub(crate) const EXPORT_ATTRIBUTE: &str = "export";
+
+/// Name of the attribute of `inline fn`, see `inline::inline_functions`
+pub(crate) const INLINE_ATTRIBUTE: &str = "inline";
+
+/// Name of `#[constant_time]`, see `sema::check_constant_time`
+pub(crate) const CONSTANT_TIME_ATTRIBUTE: &str = "constant_time";
+
+/// Name of `#[noinline]`, which also keeps `inline::inline_functions`
+/// from inlining the function
+pub(crate) const NOINLINE_ATTRIBUTE: &str = "noinline";
+
+/// Name of `#[always_inline]`
+pub(crate) const ALWAYS_INLINE_ATTRIBUTE: &str = "always_inline";
+
+/// Name of `#[cold]`
+pub(crate) const COLD_ATTRIBUTE: &str = "cold";
+
+/// Name of `#[hot]`
+pub(crate) const HOT_ATTRIBUTE: &str = "hot";
+
+/// The attributes that are LLVM function attributes, by the name of the
+/// LLVM one
+pub(crate) const LLVM_ATTRIBUTES: &[(&str, &str)] = &[
+    (NOINLINE_ATTRIBUTE, "noinline"),
+    (ALWAYS_INLINE_ATTRIBUTE, "alwaysinline"),
+    (COLD_ATTRIBUTE, "cold"),
+    (HOT_ATTRIBUTE, "hot"),
+];
+
+/// Name of `#[unroll(n)]`, the one attribute of loops rather than
+/// functions
+pub(crate) const UNROLL_ATTRIBUTE: &str = "unroll";
+
+/// The keywords before `fn` that are kept as attributes of the function,
+/// in the order they are written
+pub(crate) const FUNCTION_MARKERS: &[&str] = &[EXPORT_ATTRIBUTE, INLINE_ATTRIBUTE];
+
+/// Name of the attribute of `threadlocal let`, see `globals`
+pub(crate) const THREADLOCAL_ATTRIBUTE: &str = "threadlocal";
+
+/// A `let` outside of functions, which every function of the program sees
+pub(crate) struct Global {
+    pub(crate) name: String,
+    pub(crate) mutable: bool,
+    /// The number it starts as
+    pub(crate) value: i64,
+    /// `threadlocal let`: each thread has its own, starting at `value`
+    pub(crate) threadlocal: bool,
+}
+
+/// The globals of a program, in the order they are declared
+pub(crate) fn globals(program: &SeppoExpr) -> crate::error::Result<Vec<Global>> {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    let mut globals = Vec::new();
+    let mut threadlocal = false;
+    for item in items {
+        match item {
+            SeppoExpr::Attribute { name, .. } if name == THREADLOCAL_ATTRIBUTE => {
+                threadlocal = true
+            }
+            SeppoExpr::Let {
+                name,
+                mutable,
+                value,
+            } => {
+                // Trees loaded from JSON haven't been through the parser's check
+                let SeppoExpr::Number(value) = **value else {
+                    return Err(crate::error::SeppoError::Sema(global_not_a_number(name)));
+                };
+                globals.push(Global {
+                    name: name.clone(),
+                    mutable: *mutable,
+                    value,
+                    threadlocal: std::mem::take(&mut threadlocal),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(globals)
+}
+
+/// Error for a global starting as anything but a number, which would have
+/// to run before `seppo`
+pub(crate) fn global_not_a_number(name: &str) -> String {
+    format!(
+        "Global {} has to start as a number, e.g. `let {} = 0`",
+        name, name
+    )
+}
+
+/// The functions of a program declared with `export fn`
+pub(crate) fn exported_functions(program: &SeppoExpr) -> HashSet<String> {
+    marked_functions(program, EXPORT_ATTRIBUTE)
+}
+
+/// The functions of a program with the attribute `marker`, e.g. those
+/// declared `inline fn`
+pub(crate) fn marked_functions(program: &SeppoExpr, marker: &str) -> HashSet<String> {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    let mut marked = HashSet::new();
+    let mut mark_next = false;
+    for item in items {
+        match item {
+            SeppoExpr::Attribute { name, .. } if name == marker => mark_next = true,
+            SeppoExpr::Function(name, ..) | SeppoExpr::ConstFunction(name, ..) => {
+                if mark_next {
+                    marked.insert(name.clone());
+                }
+                mark_next = false;
+            }
+            _ => {}
+        }
+    }
+    marked
+}
+
+/// The signatures of the functions of a program that declare types
+pub(crate) fn declared_signatures(program: &SeppoExpr) -> HashMap<String, Signature> {
+    let items = match program {
+        SeppoExpr::Block(items) => items.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    let mut signatures = HashMap::new();
+    let mut next = None;
+    for item in items {
+        match item {
+            SeppoExpr::Signature(signature) => next = Some(signature.clone()),
+            SeppoExpr::Function(name, ..) | SeppoExpr::ConstFunction(name, ..) => {
+                if let Some(signature) = next.take() {
+                    signatures.insert(name.clone(), signature);
+                }
+            }
+            _ => {}
+        }
+    }
+    signatures
+}
+
+impl SeppoExpr {
+    /// Comments, blank lines and locations, which carry no meaning for
+    /// evaluation
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self,
+            SeppoExpr::Comment { .. }
+                | SeppoExpr::BlankLine
+                | SeppoExpr::DocComment(_)
+                | SeppoExpr::Location { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PrintFormat {
     Decimal,
     Hex,
+    /// `true` or `false`, for nonzero and zero
+    Bool,
+    /// The value as a Unicode code point
+    Char,
+    /// Decimal, to standard error
+    Stderr,
+}
+
+/// Byte range in the source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[cfg(feature = "serde")]
+impl SeppoExpr {
+    /// Pretty-printed JSON form of the tree, for external tooling
+    pub fn to_json(&self) -> crate::error::Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| {
+            crate::error::SeppoError::Codegen(format!("Failed to serialize AST: {}", e))
+        })
+    }
+
+    /// Load a tree produced by `to_json` or by another frontend
+    pub fn from_json(json: &str) -> crate::error::Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| crate::error::SeppoError::Sema(format!("Invalid AST JSON: {}", e)))
+    }
 }
@@ -0,0 +1,66 @@
+use crate::error::{Result, SeppoError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The architectures of a macOS universal binary
+pub(crate) const UNIVERSAL_TRIPLES: [&str; 2] = ["arm64-apple-darwin", "x86_64-apple-darwin"];
+
+const FAT_MAGIC: u32 = 0xcafe_babe;
+/// Mach-O magic of a 64-bit slice, as read little-endian
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+
+/// Merge single-architecture Mach-O files into one fat file, like
+/// `lipo -create`, without needing Xcode's tools
+pub(crate) fn write_fat_binary(slices: &[PathBuf], output: &Path) -> Result<()> {
+    let mut headers = Vec::new();
+    let mut contents = Vec::new();
+    for path in slices {
+        let data = fs::read(path)?;
+        let (cpu_type, cpu_subtype) = cpu_of(&data).ok_or_else(|| {
+            SeppoError::Link(format!("{} is not a 64-bit Mach-O file", path.display()))
+        })?;
+        // Slices start on a page boundary of their architecture
+        let align = if cpu_type == CPU_TYPE_ARM64 { 14 } else { 12 };
+        headers.push((cpu_type, cpu_subtype, align));
+        contents.push(data);
+    }
+
+    let mut out = Vec::new();
+    out.extend(FAT_MAGIC.to_be_bytes());
+    out.extend((slices.len() as u32).to_be_bytes());
+    // Architecture entries follow the header, the slices come after them
+    let mut offset = 8 + 20 * slices.len();
+    let mut offsets = Vec::new();
+    for ((cpu_type, cpu_subtype, align), data) in headers.iter().zip(&contents) {
+        offset = offset.next_multiple_of(1 << align);
+        offsets.push(offset);
+        for field in [
+            *cpu_type,
+            *cpu_subtype,
+            offset as u32,
+            data.len() as u32,
+            *align,
+        ] {
+            out.extend(field.to_be_bytes());
+        }
+        offset += data.len();
+    }
+    for (data, offset) in contents.iter().zip(offsets) {
+        out.resize(offset, 0);
+        out.extend(data);
+    }
+    fs::write(output, out)?;
+    Ok(())
+}
+
+/// CPU type and subtype from the header of a 64-bit Mach-O file
+fn cpu_of(data: &[u8]) -> Option<(u32, u32)> {
+    let word = |at: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+    };
+    if word(0)? != MH_MAGIC_64 {
+        return None;
+    }
+    Some((word(4)?, word(8)?))
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "arbitrary")]
+
+use anyhow::Result;
+use arbitrary::Unstructured;
+use inkwell::context::Context;
+use seppolang::testing::arbitrary_program;
+use seppolang::{interp, parse_module, Jit};
+
+/// Deterministic pseudo-random bytes standing in for fuzzer input
+fn input(seed: u64) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    (0..4096)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_arbitrary_programs_round_trip() -> Result<()> {
+    for seed in 0..200 {
+        let data = input(seed);
+        let generated = arbitrary_program(&mut Unstructured::new(&data))?;
+        let parsed = parse_module(&generated.source)?;
+        assert_eq!(parsed, generated.program, "{}", generated.source);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_arbitrary_programs_agree_between_interpreter_and_jit() -> Result<()> {
+    let context = Context::create();
+    for seed in 0..100 {
+        let data = input(seed);
+        let generated = arbitrary_program(&mut Unstructured::new(&data))?;
+        let expected = interp::eval(&generated.program)?;
+        let jit = Jit::new(&context, &generated.program)?;
+        assert_eq!(jit.call("seppo", &[])?, expected, "{}", generated.source);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_arbitrary_program_from_empty_input() -> Result<()> {
+    let generated = arbitrary_program(&mut Unstructured::new(&[]))?;
+    assert_eq!(generated.source, "fn seppo() {\n    return 0\n}\n");
+    Ok(())
+}
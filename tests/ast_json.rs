@@ -0,0 +1,43 @@
+#![cfg(feature = "serde")]
+
+use seppolang::{interp, parse_seppo, SeppoExpr};
+
+#[test]
+fn test_ast_serializes_to_json() {
+    let input = r#"
+        fn seppo() {
+            x = 42
+            0xseppo x
+            return x
+        }
+    "#;
+    let expr = parse_seppo(input).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&expr.to_json().unwrap()).unwrap();
+
+    let function = &json["Block"][0]["Function"];
+    assert_eq!(function[0], "seppo");
+    let body = &function[2]["Block"];
+    assert_eq!(body[0]["Assignment"][0], "x");
+    assert_eq!(body[1]["Print"][0], "Hex");
+}
+
+#[test]
+fn test_ast_round_trips_through_json() {
+    let input = r#"
+        fn seppo() {
+            let mut x = 40
+            seppo x > 10 {
+                x = x + 2
+            }
+            return x
+        }
+    "#;
+    let expr = parse_seppo(input).unwrap();
+    let loaded = SeppoExpr::from_json(&expr.to_json().unwrap()).unwrap();
+    assert_eq!(interp::eval(&loaded).unwrap(), 42);
+}
+
+#[test]
+fn test_invalid_ast_json_is_rejected() {
+    assert!(SeppoExpr::from_json(r#"{"Nope": 1}"#).is_err());
+}
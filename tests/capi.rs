@@ -0,0 +1,52 @@
+#![cfg(feature = "capi")]
+
+use seppolang::capi::{
+    seppo_compile, seppo_string_free, SeppoOptions, SEPPO_EMIT_LLVM_IR, SEPPO_ERROR, SEPPO_OK,
+};
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+fn options(output: &CString) -> SeppoOptions {
+    SeppoOptions {
+        output: output.as_ptr(),
+        emit: SEPPO_EMIT_LLVM_IR,
+        opt_level: -1,
+        target: ptr::null(),
+        no_extern: 0,
+    }
+}
+
+#[test]
+fn test_capi_compiles_source() {
+    let dir = std::env::temp_dir().join(format!("seppo_capi_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = CString::new(dir.join("program").to_str().unwrap()).unwrap();
+    let source = CString::new("fn seppo() {\n    return 42\n}\n").unwrap();
+
+    let mut error = ptr::null_mut();
+    let status = unsafe { seppo_compile(source.as_ptr(), &options(&output), &mut error) };
+    assert_eq!(status, SEPPO_OK);
+    assert!(error.is_null());
+    let ir = std::fs::read_to_string(dir.join("program.ll")).unwrap();
+    assert!(ir.contains("define i64 @seppo()"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_capi_reports_diagnostics() {
+    let output = CString::new("unused").unwrap();
+    let source = CString::new("fn seppo() {\n    return 42\n").unwrap();
+    let mut error = ptr::null_mut();
+    let status = unsafe { seppo_compile(source.as_ptr(), &options(&output), &mut error) };
+    assert_eq!(status, SEPPO_ERROR);
+    let message = unsafe { CStr::from_ptr(error) }
+        .to_str()
+        .unwrap()
+        .to_string();
+    unsafe { seppo_string_free(error) };
+    assert!(message.starts_with("error[E0001]"), "{}", message);
+
+    // Without anywhere to put the message only the status is returned
+    let status = unsafe { seppo_compile(source.as_ptr(), ptr::null(), ptr::null_mut()) };
+    assert_eq!(status, SEPPO_ERROR);
+}
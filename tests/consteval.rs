@@ -0,0 +1,127 @@
+use seppolang::{format_source, interp, parse_seppo, SeppoError, SeppoExpr};
+
+/// The body of function `name` in a parsed program
+fn body_of(program: &SeppoExpr, name: &str) -> String {
+    let SeppoExpr::Block(items) = program else {
+        panic!("program is not a block");
+    };
+    items
+        .iter()
+        .find_map(|item| match item {
+            SeppoExpr::Function(n, _, body) if n == name => Some(format!("{:?}", body)),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_const_calls_are_folded() {
+    let source = "
+        const fn square(n) {
+            return n * n
+        }
+
+        const fn table_size() {
+            return square(12)
+        }
+
+        fn seppo() {
+            x = table_size()
+            return x
+        }
+    ";
+    let program = parse_seppo(source).unwrap();
+    assert_eq!(
+        body_of(&program, "seppo"),
+        format!(
+            "{:?}",
+            SeppoExpr::Block(vec![
+                SeppoExpr::Assignment("x".to_string(), Box::new(SeppoExpr::Number(144))),
+                SeppoExpr::Return(Box::new(SeppoExpr::Variable("x".to_string()))),
+            ])
+        )
+    );
+    assert_eq!(interp::eval(&program).unwrap(), 144);
+}
+
+#[test]
+fn test_const_fn_with_runtime_arguments() {
+    // Calls with non-constant arguments stay calls to an ordinary function
+    let source = "
+        const fn double(n) {
+            return n + n
+        }
+
+        fn seppo() {
+            x = 4
+            y = double(x)
+            return y
+        }
+    ";
+    let program = parse_seppo(source).unwrap();
+    assert!(body_of(&program, "seppo").contains("FunctionCall(\"double\""));
+    assert_eq!(interp::eval(&program).unwrap(), 8);
+}
+
+#[test]
+fn test_const_calls_depending_on_division_are_not_folded() {
+    // -7 / 2 is -3 or -4 depending on --division-rounding
+    let source = "
+        const fn half() {
+            a = 0 - 7
+            return a / 2
+        }
+
+        const fn third() {
+            return 9 / 3
+        }
+
+        fn seppo() {
+            h = half()
+            t = third()
+            return h + t
+        }
+    ";
+    let program = parse_seppo(source).unwrap();
+    let body = body_of(&program, "seppo");
+    assert!(body.contains("FunctionCall(\"half\""), "{}", body);
+    assert!(!body.contains("FunctionCall(\"third\""), "{}", body);
+    assert_eq!(interp::eval(&program).unwrap(), 0);
+}
+
+#[test]
+fn test_const_fn_errors() {
+    for (source, expected) in [
+        (
+            "const fn f() { seppo 1\n return 1 }\nfn seppo() { return f() }",
+            "const fn f can't print",
+        ),
+        (
+            "fn g() { return 1 }\nconst fn f() { return g() }\nfn seppo() { return f() }",
+            "const fn f calls g, which is not a const fn",
+        ),
+        (
+            "const fn f(n) { return n / 0 }\nfn seppo() { return f(1) }",
+            "Failed to evaluate f(1) at compile time",
+        ),
+        (
+            "let base = 1\nconst fn f() { return base }\nfn seppo() { return f() }",
+            "const fn f can't use global base",
+        ),
+    ] {
+        match parse_seppo(source) {
+            Err(SeppoError::Sema(message)) => {
+                assert!(message.contains(expected), "{}", message)
+            }
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_fmt_keeps_const_marker() {
+    assert_eq!(
+        format_source("const fn one() { return 1 }").unwrap(),
+        "const fn one() {\n    return 1\n}\n"
+    );
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use inkwell::context::Context;
+use seppolang::coverage::{Coverage, COVERAGE_FILE_VAR};
+use seppolang::testing::{self, TestStatus};
+use seppolang::{parse_with_locations, Driver, Input, Jit};
+
+const SOURCE: &str = "fn sign(x) {
+    seppo x < 0 {
+        return 1
+    }
+    return 0
+}
+
+fn testi_positive() {
+    s = sign(5)
+    return s
+}
+";
+
+#[test]
+fn test_parse_and_merge_coverage_files() -> Result<()> {
+    let mut coverage = Coverage::parse("3\t2\tmain.seppo\n0\t3\tmain.seppo\n")?;
+    coverage.merge(&Coverage::parse("1\t2\tmain.seppo\n2\t1\tlib.seppo\n")?);
+
+    assert_eq!(coverage.files["main.seppo"][&2], 4);
+    assert_eq!(coverage.files["main.seppo"][&3], 0);
+    assert_eq!(coverage.lines_covered("main.seppo"), (1, 2));
+    assert_eq!(
+        coverage.summary(),
+        "lib.seppo: 1/1 lines (100.0%)\nmain.seppo: 1/2 lines (50.0%)\nTOTAL: 2/3 lines (66.7%)\n"
+    );
+
+    let err = Coverage::parse("three\t2\tmain.seppo").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Invalid coverage record on line 1"));
+    Ok(())
+}
+
+#[test]
+fn test_annotate_source() -> Result<()> {
+    let coverage = Coverage::parse("2\t2\tmain.seppo\n")?;
+    assert_eq!(
+        coverage.annotate("main.seppo", "fn seppo() {\n    return 0\n}"),
+        "        | 1 | fn seppo() {\n      2 | 2 |     return 0\n        | 3 | }\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_jit_counts_lines_run() -> Result<()> {
+    let program = parse_with_locations(SOURCE, "sign.seppo")?;
+    let context = Context::create();
+    let jit = Jit::with_coverage(&context, &program)?;
+    jit.call("sign", &[-1])?;
+    jit.call("sign", &[2])?;
+    jit.call("sign", &[3])?;
+
+    let lines = &jit.coverage()?.files["sign.seppo"];
+    assert_eq!(lines[&2], 3);
+    assert_eq!(lines[&3], 1);
+    assert_eq!(lines[&5], 2);
+    assert!(!lines.contains_key(&1));
+    Ok(())
+}
+
+#[test]
+fn test_tests_with_coverage_leave_branch_uncovered() -> Result<()> {
+    let program = parse_with_locations(SOURCE, "sign.seppo")?;
+    let (outcomes, coverage) = testing::run_tests_with_coverage(&program, None)?;
+    assert_eq!(outcomes[0].status, TestStatus::Passed);
+    assert_eq!(coverage.files["sign.seppo"][&3], 0);
+    assert_eq!(coverage.lines_covered("sign.seppo"), (4, 5));
+    Ok(())
+}
+
+#[test]
+fn test_instrumented_executable_writes_counts() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_coverage_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let cov_file = dir.join("run.cov");
+
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main.seppo".to_string(),
+        text: format!("{}\nfn seppo() {{\n    return sign(0)\n}}\n", SOURCE),
+    }]);
+    driver.output = Some(dir.join("main"));
+    driver.coverage = true;
+    let executable = driver.run()?;
+
+    // Each run appends to the file
+    for _ in 0..2 {
+        let status = std::process::Command::new(&executable)
+            .env(COVERAGE_FILE_VAR, &cov_file)
+            .status()?;
+        assert_eq!(status.code(), Some(0));
+    }
+    let coverage = Coverage::from_file(&cov_file)?;
+    std::fs::remove_dir_all(&dir)?;
+
+    let lines = &coverage.files["main.seppo"];
+    assert_eq!(lines[&2], 2);
+    assert_eq!(lines[&3], 0);
+    assert_eq!(lines[&14], 2);
+    assert_eq!(lines[&9], 0);
+    Ok(())
+}
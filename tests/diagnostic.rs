@@ -0,0 +1,106 @@
+use seppolang::diagnostic::{ColorChoice, Diagnostic, Severity};
+use seppolang::lint::{lint, LintConfig};
+use seppolang::{ice, parse_seppo, parse_with_locations, CodeGen, SeppoError, TargetSpec};
+
+#[test]
+fn test_parse_error_rendering() {
+    let input = "fn seppo() {\n    @\n}\n";
+    let error = parse_seppo(input).unwrap_err();
+    let rendered = Diagnostic::from_error(&error).render(Some(input), false);
+
+    let lines: Vec<&str> = rendered.lines().collect();
+    assert!(lines[0].starts_with("error[E0001]: "), "{}", rendered);
+    assert_eq!(lines[1], " --> <input>:2:5");
+    assert_eq!(lines[2], "  |");
+    assert_eq!(lines[3], "2 |     @");
+    assert_eq!(lines[4], "  |     ^");
+}
+
+#[test]
+fn test_error_codes_and_colors() {
+    let error = SeppoError::Sema("No seppo function found".to_string());
+    let diagnostic = Diagnostic::from_error(&error);
+    assert_eq!(diagnostic.code.as_deref(), Some("E0002"));
+    assert_eq!(
+        diagnostic.render(None, false),
+        "error[E0002]: No seppo function found\n"
+    );
+
+    let colored = diagnostic.render(None, true);
+    assert!(colored.starts_with("\x1b[1;31merror[E0002]\x1b[0m"));
+}
+
+#[test]
+fn test_lint_rendering() {
+    let program = parse_seppo("fn seppo() { x = 3\n return 0 }").unwrap();
+    let findings = lint(&program, &LintConfig::new());
+    let diagnostic = Diagnostic::from_lint(&findings[0]);
+    assert_eq!(diagnostic.severity, Severity::Warning);
+    assert_eq!(
+        diagnostic.render(None, false),
+        "warning[unused-variable]: variable `x` is assigned but never read\n  = note: in function `seppo`\n"
+    );
+}
+
+#[test]
+fn test_escalated_lint_rendering() {
+    let program = parse_seppo("fn seppo() { x = 3\n return 0 }").unwrap();
+    let mut config = LintConfig::new();
+    config.set_deny_warnings(true);
+    let findings = lint(&program, &config);
+    let diagnostic = Diagnostic::from_lint(&findings[0]);
+    assert_eq!(diagnostic.severity, Severity::Error);
+    assert_eq!(
+        diagnostic.render(None, false),
+        "error[unused-variable]: variable `x` is assigned but never read\n  = note: in function `seppo`\n  = note: warnings are errors because of `--deny-warnings`\n"
+    );
+}
+
+#[test]
+fn test_color_choice() {
+    assert_eq!(ColorChoice::from_name("never").unwrap(), ColorChoice::Never);
+    assert!(ColorChoice::Always.use_color());
+    assert!(!ColorChoice::Never.use_color());
+    assert!(matches!(
+        ColorChoice::from_name("sometimes"),
+        Err(SeppoError::Config(_))
+    ));
+}
+
+#[test]
+fn test_internal_compiler_error_report() {
+    let rendered = ice::diagnostic("boom").render(None, false);
+    assert!(
+        rendered.starts_with("error: internal compiler error: boom\n"),
+        "{}",
+        rendered
+    );
+    assert!(rendered.contains(env!("CARGO_PKG_VERSION")), "{}", rendered);
+    assert!(
+        rendered.contains("https://github.com/thevilledev/seppolang/issues"),
+        "{}",
+        rendered
+    );
+}
+
+#[test]
+fn test_internal_compiler_error_names_statement() {
+    let input = "fn seppo() {\n    x = 1 + 2\n    return x\n}\n";
+    let program = parse_with_locations(input, "main.seppo").unwrap();
+    TargetSpec::native().initialize().unwrap();
+    let context = inkwell::context::Context::create();
+    let mut codegen = CodeGen::new(&context, "ice");
+    codegen.compile(&program).unwrap();
+
+    // What a panic in the last statement compiled would report
+    let diagnostic = ice::diagnostic("boom");
+    let location = diagnostic.location.as_ref().unwrap();
+    assert_eq!(location.line, 3);
+    assert!(
+        diagnostic
+            .notes
+            .contains(&"while compiling `return x` in function `seppo`".to_string()),
+        "{:?}",
+        diagnostic.notes
+    );
+}
@@ -0,0 +1,96 @@
+use seppolang::doc::{self, DocFormat};
+use seppolang::{format_source, parse_module, SeppoError};
+
+const SOURCE: &str = "\
+## Returns `1`.
+##
+## Never overflows <probably>.
+fn inc() {
+    return 1
+}
+
+// not a doc comment
+fn helper() {
+    return 2
+}
+
+## Tests are left out
+fn testi_inc() {
+    return 0
+}
+";
+
+#[test]
+fn test_doc_comments_attach_to_functions() {
+    let program = parse_module(SOURCE).unwrap();
+    let functions = doc::collect(&program);
+    let names: Vec<&str> = functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["inc", "helper"]);
+    assert_eq!(
+        functions[0].docs,
+        "Returns `1`.\n\nNever overflows <probably>."
+    );
+    assert_eq!(functions[1].docs, "");
+}
+
+#[test]
+fn test_doc_rendering() {
+    let functions = doc::collect(&parse_module(SOURCE).unwrap());
+
+    let markdown = doc::render("math", &functions, DocFormat::Markdown);
+    assert!(markdown.starts_with("# math\n"));
+    assert!(markdown.contains("## `inc`\n\n```\nfn inc()\n```\n\nReturns `1`."));
+
+    let html = doc::render("math", &functions, DocFormat::Html);
+    assert!(html.contains("<title>math</title>"));
+    assert!(html.contains("<section id=\"fn.inc\">"));
+    assert!(html.contains("<p>Returns <code>1</code>.</p>"));
+    assert!(html.contains("<p>Never overflows &lt;probably&gt;.</p>"));
+    assert!(!html.contains("testi_inc"));
+}
+
+#[test]
+fn test_doc_signatures_show_declared_types() {
+    let source = "## Repeats `s`\nfn repeat(s: string, times) -> string {\n    return s\n}\n";
+    let functions = doc::collect(&parse_module(source).unwrap());
+    assert_eq!(
+        functions[0].signature(),
+        "fn repeat(s: string, times) -> string"
+    );
+}
+
+#[test]
+fn test_doc_format_names() {
+    assert_eq!(DocFormat::from_name("md").unwrap(), DocFormat::Markdown);
+    assert!(matches!(
+        DocFormat::from_name("pdf"),
+        Err(SeppoError::Config(_))
+    ));
+}
+
+#[test]
+fn test_fmt_keeps_doc_comments() {
+    let source = "##Adds one\nfn inc() { return 1 }\n";
+    assert_eq!(
+        format_source(source).unwrap(),
+        "## Adds one\nfn inc() {\n    return 1\n}\n"
+    );
+}
+
+#[test]
+fn test_doc_command_writes_only_the_docs() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("math.seppo");
+    std::fs::write(&path, SOURCE).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_seppoc"))
+        .args(["doc", "--format", "markdown"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let functions = doc::collect(&parse_module(SOURCE).unwrap());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        doc::render("math", &functions, DocFormat::Markdown)
+    );
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use inkwell::context::Context;
+use seppolang::{parse_seppo, Engine, SeppoError};
+use std::sync::{Arc, Mutex};
+
+const PROGRAM: &str = r#"
+    fn seppo() {
+        emit(7)
+        emit(answer())
+        return scale(3, 5)
+    }
+"#;
+
+/// An engine whose `emit` records its arguments in the returned list
+fn engine() -> (Engine, Arc<Mutex<Vec<i64>>>) {
+    let emitted = Arc::new(Mutex::new(Vec::new()));
+    let mut engine = Engine::new();
+    let sink = emitted.clone();
+    engine
+        .register_fn("emit", move |x: i64| {
+            sink.lock().unwrap().push(x);
+            0
+        })
+        .register_fn("answer", || 42)
+        .register_fn("scale", |x: i64, factor: i64| x * factor);
+    (engine, emitted)
+}
+
+#[test]
+fn test_host_functions_in_interpreter() -> Result<()> {
+    let (engine, emitted) = engine();
+    assert_eq!(engine.eval(PROGRAM)?, 15);
+    assert_eq!(*emitted.lock().unwrap(), [7, 42]);
+
+    let wrong = "fn seppo() {\n    return scale(1)\n}";
+    assert!(matches!(engine.eval(wrong), Err(SeppoError::Sema(_))));
+    Ok(())
+}
+
+#[test]
+fn test_host_functions_in_jit() -> Result<()> {
+    let (engine, emitted) = engine();
+    let context = Context::create();
+    let jit = engine.jit(&context, &parse_seppo(PROGRAM)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 15);
+    assert_eq!(jit.call("seppo", &[])?, 15);
+    assert_eq!(*emitted.lock().unwrap(), [7, 42, 7, 42]);
+    Ok(())
+}
+
+#[test]
+fn test_program_cannot_redefine_host_function() -> Result<()> {
+    let (engine, _) = engine();
+    let program =
+        parse_seppo("fn answer() {\n    return 1\n}\n\nfn seppo() {\n    return answer()\n}")?;
+    match engine.interpreter(&program) {
+        Err(SeppoError::Sema(message)) => assert_eq!(
+            message,
+            "Function answer is defined by the program and registered by the host"
+        ),
+        _ => panic!("expected a sema error"),
+    }
+    Ok(())
+}
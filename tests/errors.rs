@@ -0,0 +1,509 @@
+use seppolang::{interp, nesting, parse_seppo, Driver, Input, SeppoError};
+
+#[test]
+fn test_parse_error_has_location() {
+    let input = "fn seppo() {\n    @\n}\n";
+    match parse_seppo(input) {
+        Err(SeppoError::Parse {
+            line, column, span, ..
+        }) => {
+            assert_eq!((line, column), (2, 5));
+            assert_eq!(span.start, input.find('@').unwrap());
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_missing_seppo_is_sema_error() {
+    let input = "fn not_seppo() { return 1 }";
+    assert!(matches!(parse_seppo(input), Err(SeppoError::Sema(_))));
+}
+
+#[test]
+fn test_undefined_variable_suggestion() {
+    let input = r#"
+        fn seppo() {
+            counter = 1
+            total = 2
+            return countr
+        }
+    "#;
+    let expr = parse_seppo(input).unwrap();
+    match interp::eval(&expr) {
+        Err(SeppoError::Sema(message)) => assert_eq!(
+            message,
+            "Undefined variable: countr (did you mean `counter`?)"
+        ),
+        other => panic!("expected a semantic error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_undefined_function_suggestions() {
+    let message = |input: &str| match interp::eval(&parse_seppo(input).unwrap()) {
+        Err(SeppoError::Sema(message)) => message,
+        other => panic!("expected a semantic error, got {:?}", other),
+    };
+    // Builtins are candidates too
+    assert_eq!(
+        message("fn seppo() {\n    return lenn(\"seppo\")\n}\n"),
+        "Undefined function: lenn (did you mean `len`?)"
+    );
+    // Nothing close enough
+    assert_eq!(
+        message("fn seppo() {\n    return perkele_sauna(1)\n}\n"),
+        "Undefined function: perkele_sauna"
+    );
+}
+
+#[test]
+fn test_runtime_error_kind() {
+    let input = r#"
+        fn seppo() {
+            x = 0
+            return 1 / x
+        }
+    "#;
+    let expr = parse_seppo(input).unwrap();
+    match interp::eval(&expr) {
+        Err(SeppoError::Runtime(message)) => assert!(message.contains("Division by zero")),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+fn syntax_error(input: &str) -> (String, Option<String>) {
+    match parse_seppo(input) {
+        Err(SeppoError::Parse { message, help, .. }) => (message, help),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_syntax_errors_name_what_was_expected() {
+    let (message, help) = syntax_error("fn seppo() {\n    x =\n}\n");
+    assert_eq!(message, "expected an expression, found the end of the line");
+    assert!(help.unwrap().contains("arguments of a call"));
+
+    let (message, help) = syntax_error("fn seppo() {\n    return 1\n");
+    assert_eq!(
+        message,
+        "unexpected end of input, expected a statement or `}`"
+    );
+    assert_eq!(help.as_deref(), Some("a block is missing its closing `}`"));
+
+    let (message, _) = syntax_error("x = 1\n");
+    assert_eq!(
+        message,
+        "expected a function, global, macro or ceppo block, found `x`"
+    );
+}
+
+#[test]
+fn test_syntax_error_hints() {
+    let hint = |input: &str| syntax_error(input).1.unwrap_or_default();
+
+    assert!(hint("fn seppo() {\n    x = 1\n        + 2\n}\n").contains("on one line"));
+    assert!(hint("fn seppo() {\n    x = 1 * 2 * 3\n}\n").contains("exactly two operands"));
+    assert!(hint("fn seppo() {\n    if x > 1 {\n    }\n}\n").contains("seppo x > 0"));
+    assert!(hint("fn seppo() {\n    seppo 1 {\n    }\n}\n").contains("compares two values"));
+
+    let (message, help) =
+        syntax_error("fn seppo() {\n    seppo 1 > 0 {\n        return 1\n    } else {\n    }\n}\n");
+    assert_eq!(
+        message,
+        "expected a line break, `;` or `perkele` block, found `else`"
+    );
+    assert!(help.unwrap().contains("perkele { ... }"));
+}
+
+#[test]
+fn test_keywords_cant_be_names() {
+    for (source, word) in [
+        ("fn seppo() {\n    seppo = 1\n}\n", "seppo"),
+        ("fn seppo() {\n    perkele = 1\n}\n", "perkele"),
+        ("fn seppo() {\n    let ceppo = 1\n}\n", "ceppo"),
+        ("fn seppo() {\n    return = 1\n}\n", "return"),
+        ("fn seppo() {\n    fn = 1\n}\n", "fn"),
+        ("fn seppo() {\n    for in in 0..3 {\n    }\n}\n", "in"),
+        ("fn double(let) {\n    return 2\n}\n", "let"),
+    ] {
+        let (message, help) = syntax_error(source);
+        assert_eq!(
+            message,
+            format!("`{}` is a keyword and can't be used as a name", word),
+            "{}",
+            source
+        );
+        assert_eq!(help, Some(format!("rename it, e.g. `{}_value`", word)));
+    }
+
+    // Names may start with a keyword, and use letters beyond ASCII
+    assert!(parse_seppo("fn seppo() {\n    returned = 1\n    määrä = returned\n}\n").is_ok());
+}
+
+/// `seppo` with `depth` conditionals nested inside it
+fn nested_conditionals(depth: usize) -> String {
+    let mut source = String::from("fn seppo() {\n    let mut x = 1\n");
+    source.push_str(&"seppo x > 0 {\n".repeat(depth));
+    source.push_str("x = 2\n");
+    source.push_str(&"}\n".repeat(depth));
+    source.push_str("    return x\n}\n");
+    source
+}
+
+#[test]
+fn test_nesting_limit() {
+    // The function body is the first level
+    let limit = nesting::DEFAULT_MAX_NESTING;
+    let program = parse_seppo(&nested_conditionals(limit - 1)).unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 2);
+
+    match parse_seppo(&nested_conditionals(10_000)) {
+        Err(SeppoError::Parse {
+            message,
+            line,
+            help,
+            ..
+        }) => {
+            assert!(
+                message.starts_with("program too deeply nested"),
+                "{}",
+                message
+            );
+            assert_eq!(line, limit + 2);
+            assert!(help.unwrap().contains("--max-nesting"));
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+
+    let source = nested_conditionals(10);
+    assert!(nesting::with_max_nesting(5, || parse_seppo(&source)).is_err());
+    assert!(nesting::with_max_nesting(11, || parse_seppo(&source)).is_ok());
+}
+
+#[test]
+fn test_nesting_ignores_strings_and_comments() {
+    let input = r#"
+        ceppo {
+            int64_t one(void) { { { return 1; } } }
+        }
+
+        fn seppo() {
+            // ((((((
+            seppo "{{{{{{"
+            return one()
+        }
+    "#;
+    assert!(nesting::with_max_nesting(4, || parse_seppo(input)).is_ok());
+    assert!(nesting::with_max_nesting(3, || parse_seppo(input)).is_err());
+}
+
+#[test]
+fn test_duplicate_definitions_show_both_locations() {
+    let message = |input: &str| match parse_seppo(input) {
+        Err(SeppoError::Sema(message)) => message,
+        other => panic!("expected a semantic error, got {:?}", other),
+    };
+    assert_eq!(
+        message("fn foo() {\n    return 1\n}\n\nfn foo() {\n    return 2\n}\n\nfn seppo() {\n    return foo()\n}\n"),
+        "Function foo is defined twice, at 1:4 and 5:4"
+    );
+    assert_eq!(
+        message(
+            "fn add(a, b, a) {\n    return a + b\n}\n\nfn seppo() {\n    return add(1, 2, 3)\n}\n"
+        ),
+        "Parameter a of function add is defined twice, at 1:8 and 1:14"
+    );
+}
+
+#[test]
+fn test_duplicate_function_across_inputs() {
+    let source = |name: &str, text: &str| Input::Source {
+        name: name.to_string(),
+        text: text.to_string(),
+    };
+    let driver = Driver::new(vec![
+        source("a.seppo", "fn helper() {\n    return 1\n}\n"),
+        source(
+            "b.seppo",
+            "fn helper() {\n    return 2\n}\n\nfn seppo() {\n    return helper()\n}\n",
+        ),
+    ]);
+    match driver.parse() {
+        Err(SeppoError::Sema(message)) => assert_eq!(
+            message,
+            "Function helper is defined twice, at a.seppo and b.seppo"
+        ),
+        other => panic!("expected a semantic error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_seppo_takes_at_most_argc_and_argv() {
+    match parse_seppo("fn seppo(x, y, z) {\n    return x\n}\n") {
+        Err(SeppoError::Sema(message)) => assert_eq!(
+            message,
+            "The seppo function is called with at most argc and argv, so it cannot take more parameters: seppo(x, y, z)"
+        ),
+        other => panic!("expected a semantic error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_conditional_values_need_both_branches_and_no_return() {
+    let error = |input: &str| match parse_seppo(input) {
+        Err(SeppoError::Parse { message, line, .. }) => (line, message),
+        other => panic!("expected a parse error, got {:?}", other),
+    };
+    assert_eq!(
+        error("fn seppo() {\n    x = seppo 1 > 0 {\n        1\n    }\n    return x\n}\n"),
+        (
+            2,
+            "A conditional used as a value needs a perkele block".to_string()
+        )
+    );
+    assert_eq!(
+        error("fn seppo() {\n    return seppo 1 > 0 {\n        return 1\n    }\n    perkele {\n        2\n    }\n}\n"),
+        (
+            2,
+            "A conditional used as a value cannot return from the function".to_string()
+        )
+    );
+}
+
+#[test]
+fn test_constant_time_functions_dont_branch_or_divide() {
+    let error = |body: &str| {
+        let input = format!(
+            "#[constant_time]\nfn f(a, b) {{\n{}\n}}\n\nfn seppo() {{\n    return f(1, 2)\n}}\n",
+            body
+        );
+        match parse_seppo(&input) {
+            Err(SeppoError::Sema(message)) => message,
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    };
+    assert_eq!(
+        error("    return a / b"),
+        "constant_time fn f divides, which takes time depending on the operands"
+    );
+    let branches = "constant_time fn f branches on its data, only a conditional choosing between two values without calls is allowed";
+    assert_eq!(
+        error("    seppo a > b {\n        return a\n    }\n    return b"),
+        branches
+    );
+    assert_eq!(
+        error("    x = seppo a > b {\n        f(b, a)\n    }\n    perkele {\n        b\n    }\n    return x"),
+        branches
+    );
+    assert_eq!(
+        error("    for i in 0..a {\n        b = b + i\n    }\n    return b"),
+        "constant_time fn f loops a number of times that can depend on its data, loop over a range of literals instead"
+    );
+
+    let program = parse_seppo(
+        "#[constant_time]\nfn max(a, b) {\n    for i in 0..4 {\n        a = a + 0\n    }\n    return seppo a > b {\n        a\n    }\n    perkele {\n        b\n    }\n}\n\nfn seppo() {\n    return max(3, 7)\n}\n",
+    )
+    .unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 7);
+}
+
+#[test]
+fn test_conflicting_function_attributes() {
+    let error = |attributes: &str| {
+        let input = format!(
+            "{}\nfn f(a) {{\n    return a\n}}\n\nfn seppo() {{\n    return f(1)\n}}\n",
+            attributes
+        );
+        match parse_seppo(&input) {
+            Err(SeppoError::Sema(message)) => message,
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    };
+    assert_eq!(
+        error("#[noinline]\n#[always_inline]"),
+        "fn f can't be both noinline and always_inline"
+    );
+    assert_eq!(
+        error("#[noinline]\ninline"),
+        "fn f can't be both noinline and inline"
+    );
+    assert_eq!(error("#[hot]\n#[cold]"), "fn f can't be both cold and hot");
+
+    match parse_seppo("#[cold(1)]\nfn seppo() {\n    return 0\n}\n") {
+        Err(SeppoError::Parse { message, .. }) => {
+            assert_eq!(message, "cold takes no arguments")
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_bools_are_checked() {
+    let error = |input: &str| match parse_seppo(input) {
+        Err(SeppoError::Sema(message)) => message,
+        other => panic!("expected a semantic error, got {:?}", other),
+    };
+    assert_eq!(
+        error("fn seppo() {\n    n = 1\n    seppo n {\n        return 1\n    }\n    return 0\n}\n"),
+        "Condition n is an integer, not a bool, compare it instead, e.g. n != 0"
+    );
+    assert_eq!(
+        error("fn check(x) {\n    return x > 0 && x\n}\n\nfn seppo() {\n    return check(1)\n}\n"),
+        "Condition x is an integer or a string, not a bool, compare it instead, e.g. x != 0"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    let mut done = 1 > 0\n    done = 2\n    return 0\n}\n"),
+        "Variable done holds a bool, it can't be assigned an integer"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    seppo len(\"a\") {\n        return 1\n    }\n    return 0\n}\n"),
+        "Condition len(...) is an integer, not a bool, compare it instead, e.g. len(...) != 0"
+    );
+
+    // Bools pass where integers are declared, as 0 and 1
+    let program = parse_seppo("fn twice(x: int) -> int {\n    return x * 2\n}\n\nfn seppo() {\n    big = 3 > 2\n    return twice(big)\n}\n").unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 2);
+}
+
+#[test]
+fn test_options_are_unwrapped_before_use() {
+    let error = |input: &str| match parse_seppo(input) {
+        Err(SeppoError::Sema(message)) => message,
+        other => panic!("expected a semantic error, got {:?}", other),
+    };
+    assert_eq!(
+        error("fn seppo() {\n    x = Some(1)\n    y = x + 1\n    return y\n}\n"),
+        "x is an Option, unwrap it before use with unwrap or unwrap_or"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    seppo Some(1)\n    return 0\n}\n"),
+        "The result of Some is an Option, unwrap it before use with unwrap or unwrap_or"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    return unwrap(1)\n}\n"),
+        "unwrap takes an Option, got an integer"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    let mut x = None\n    x = 1\n    return 0\n}\n"),
+        "Variable x holds an Option, it can't be assigned an integer"
+    );
+    assert_eq!(
+        error("fn find() {\n    return None\n}\n\nfn seppo() {\n    return 0\n}\n"),
+        "Function find returns an Option, which it has to declare with -> option"
+    );
+    assert_eq!(
+        error("fn find(n) -> option {\n    seppo n > 0 {\n        return Some(n)\n    }\n}\n\nfn seppo() {\n    return 0\n}\n"),
+        "Function find should return an Option, but can end without a return, which gives 0"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    None = 1\n    return 0\n}\n"),
+        "None is the empty Option and can't be assigned"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    x = seppo 1 > 0 {\n        Some(1)\n    } perkele {\n        2\n    }\n    return 0\n}\n"),
+        "The branches of a conditional give an Option and an integer, both have to give the same"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    x = seppo 1 > 0 {\n        seppo 2 > 1 {\n            Some(1)\n        }\n    } perkele {\n        None\n    }\n    return 0\n}\n"),
+        "A conditional giving an Option needs an else branch giving one too"
+    );
+
+    // Unwrapped values are used as usual
+    let program = parse_seppo("fn half(n) -> option {\n    seppo n > 0 {\n        return Some(n / 2)\n    }\n    return None\n}\n\nfn seppo() {\n    x = half(8)\n    y = unwrap(x)\n    z = y + 1\n    return z\n}\n").unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 5);
+}
+
+#[test]
+fn test_vectors_are_kept_apart() {
+    let error = |input: &str| match parse_seppo(input) {
+        Err(SeppoError::Sema(message)) => message,
+        other => panic!("expected a semantic error, got {:?}", other),
+    };
+    assert_eq!(
+        error("fn seppo() {\n    v = i64x4(1, 2, 3, 4)\n    x = v + 1\n    return x\n}\n"),
+        "Both sides of + have to be i64x4, got an integer"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    v = i64x4(1, 2, 3, 4)\n    x = v / v\n    return 0\n}\n"),
+        "i64x4 supports +, - and *, not /"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    v = i64x4(1, 2, 3, 4)\n    return v\n}\n"),
+        "Function seppo returns an i64x4, which it has to declare with -> i64x4"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    v = i64x4(1, 2, 3, 4)\n    seppo v\n    return 0\n}\n"),
+        "v is an i64x4, take its elements with lane, e.g. lane(v, 0)"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    return lane(7, 0)\n}\n"),
+        "lane takes an i64x4, got an integer"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    v = i64x4(1, 2, 3, 4)\n    return lane(v, 4)\n}\n"),
+        "lane takes element numbers from 0 to 3 as literals"
+    );
+    assert_eq!(
+        error("fn pick(i) {\n    v = i64x4(1, 2, 3, 4)\n    w = shuffle(v, v, i, 0, 0, 0)\n    return 0\n}\n\nfn seppo() {\n    return 0\n}\n"),
+        "shuffle takes element numbers from 0 to 7 as literals"
+    );
+    assert_eq!(
+        error("fn seppo() {\n    let mut v = i64x4(1, 2, 3, 4)\n    v = 1\n    return 0\n}\n"),
+        "Variable v holds an i64x4, it can't be assigned an integer"
+    );
+
+    let program = parse_seppo("fn sum(v: i64x4) {\n    a = lane(v, 0)\n    b = lane(v, 3)\n    return a + b\n}\n\nfn seppo() {\n    v = i64x4(1, 2, 3, 4)\n    return sum(v)\n}\n").unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 5);
+}
+
+#[test]
+fn test_signatures_are_checked() {
+    let error = |input: &str| match parse_seppo(input) {
+        Err(SeppoError::Sema(message)) => message,
+        other => panic!("expected a semantic error, got {:?}", other),
+    };
+    assert_eq!(
+        error("fn twice(x: int) -> int {\n    return x * 2\n}\n\nfn seppo() {\n    return twice(\"2\")\n}\n"),
+        "Argument 1 of twice should be an integer, got a string"
+    );
+    assert_eq!(
+        error(
+            "fn name() -> string {\n    return 1\n}\n\nfn seppo() {\n    return len(name())\n}\n"
+        ),
+        "Function name should return a string, got an integer"
+    );
+    assert_eq!(
+        error("fn greet(name: string) {\n    name = 0\n    return 0\n}\n\nfn seppo() {\n    return 0\n}\n"),
+        "Parameter name of greet should hold a string, got an integer"
+    );
+    assert_eq!(
+        error("fn name(n) -> string {\n    seppo n > 0 {\n        return \"yes\"\n    }\n}\n\nfn seppo() {\n    return 0\n}\n"),
+        "Function name should return a string, but can end without a return, which gives 0"
+    );
+
+    // Declared types are known to typeof, undeclared ones may be either
+    let program = parse_seppo("fn kind(s: string, n) -> int {\n    return typeof(s)\n}\n\nfn seppo() {\n    a = kind(\"a\", 1)\n    b = kind(\"b\", \"c\")\n    return a + b\n}\n").unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 2);
+
+    match parse_seppo("fn f(x: float) {\n    return 0\n}\n") {
+        Err(SeppoError::Parse {
+            message, column, ..
+        }) => {
+            assert_eq!(
+                message,
+                "Unknown type float, expected int, string, option or i64x4"
+            );
+            assert_eq!(column, 9);
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    match parse_seppo("macro show(x: int) {\n    seppo x\n}\n") {
+        Err(SeppoError::Parse { message, .. }) => assert_eq!(
+            message,
+            "Macro parameters can't have types, the arguments are pasted in as written"
+        ),
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
@@ -0,0 +1,208 @@
+use seppolang::{format_program, format_source, interp, parse_seppo, SeppoExpr};
+
+#[test]
+fn test_fmt_canonical_layout() {
+    let input = r#"fn helper(){return 7}
+fn seppo() {
+x=1+2
+  seppo x>2 {
+0xseppo x
+        } perkele { seppo x }
+return helper()
+}
+"#;
+    let expected = r#"fn helper() {
+    return 7
+}
+
+fn seppo() {
+    x = 1 + 2
+    seppo x > 2 {
+        0xseppo x
+    }
+    perkele {
+        seppo x
+    }
+    return helper()
+}
+"#;
+    assert_eq!(format_source(input).unwrap(), expected);
+}
+
+#[test]
+fn test_fmt_conditional_values() {
+    let input = "fn seppo() {\nx = 3\nlet y = seppo x > 2 { 10 } perkele { 20 }\nreturn seppo y == 10 { x } perkele {\nz = y - 1\nz }\n}\n";
+    let expected = r#"fn seppo() {
+    x = 3
+    let y = seppo x > 2 {
+        10
+    }
+    perkele {
+        20
+    }
+    return seppo y == 10 {
+        x
+    }
+    perkele {
+        z = y - 1
+        z
+    }
+}
+"#;
+    let formatted = format_source(input).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted).unwrap(), formatted);
+    assert_eq!(interp::eval(&parse_seppo(&formatted).unwrap()).unwrap(), 3);
+}
+
+#[test]
+fn test_fmt_preserves_comments() {
+    let input = r#"// Entry point
+fn seppo() {
+    // setup
+    x = 1 // one
+
+
+    y = 2
+    return x + y // sum
+}
+// done
+"#;
+    let expected = r#"// Entry point
+fn seppo() {
+    // setup
+    x = 1 // one
+
+    y = 2
+    return x + y // sum
+}
+
+// done
+"#;
+    assert_eq!(format_source(input).unwrap(), expected);
+}
+
+#[test]
+fn test_fmt_reindents_ceppo() {
+    let input = "ceppo {\n  int64_t seven() {\n    return 7;\n  }\n}\nfn seppo() {\n    return seven()\n}\n";
+    // The C code keeps its own relative indentation
+    let expected = "ceppo {\n    int64_t seven() {\n      return 7;\n    }\n}\n\nfn seppo() {\n    return seven()\n}\n";
+    assert_eq!(format_source(input).unwrap(), expected);
+}
+
+#[test]
+fn test_fmt_is_idempotent_and_keeps_meaning() {
+    let input = std::fs::read_to_string("examples/hello.seppo").unwrap();
+    let once = format_source(&input).unwrap();
+    assert_eq!(format_source(&once).unwrap(), once);
+
+    let before = interp::eval(&parse_seppo(&input).unwrap()).unwrap();
+    let after = interp::eval(&parse_seppo(&once).unwrap()).unwrap();
+    assert_eq!(before, after);
+}
+
+#[test]
+fn test_fmt_conditions() {
+    let input = "fn seppo() {\nx = 3\ndone = x>=2&&x<5\nseppo x==0||done&&x in 0..4 {\nreturn 1\n}\nreturn x!=3\n}\n";
+    let expected = r#"fn seppo() {
+    x = 3
+    done = x >= 2 && x < 5
+    seppo x == 0 || done && x in 0..4 {
+        return 1
+    }
+    return x != 3
+}
+"#;
+    let formatted = format_source(input).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted).unwrap(), formatted);
+
+    // `&&` binds tighter, so an `||` inside one would need parentheses,
+    // which seppo doesn't have
+    let variable = |name: &str| Box::new(SeppoExpr::Variable(name.to_string()));
+    let either = SeppoExpr::Operation("||".to_string(), variable("a"), variable("b"));
+    let both = SeppoExpr::Operation("&&".to_string(), variable("c"), Box::new(either));
+    let program = SeppoExpr::Block(vec![SeppoExpr::Function(
+        "seppo".to_string(),
+        Vec::new(),
+        Box::new(SeppoExpr::Return(Box::new(both))),
+    )]);
+    assert!(format_program(&program).is_err());
+}
+
+#[test]
+fn test_fmt_rejects_invalid_source() {
+    assert!(format_source("fn seppo() {").is_err());
+}
+
+#[test]
+fn test_fmt_rejects_trees_it_cannot_write() {
+    let function = |statement: SeppoExpr| {
+        SeppoExpr::Block(vec![SeppoExpr::Function(
+            "seppo".to_string(),
+            Vec::new(),
+            Box::new(SeppoExpr::Block(vec![statement])),
+        )])
+    };
+    let conditional = SeppoExpr::Conditional {
+        condition: Box::new(SeppoExpr::Operation(
+            ">".to_string(),
+            Box::new(SeppoExpr::Variable("x".to_string())),
+            Box::new(SeppoExpr::Number(1)),
+        )),
+        true_block: Box::new(SeppoExpr::Block(vec![])),
+        false_block: None,
+    };
+    let nested = SeppoExpr::Operation(
+        "+".to_string(),
+        Box::new(SeppoExpr::Number(1)),
+        Box::new(SeppoExpr::Operation(
+            "*".to_string(),
+            Box::new(SeppoExpr::Number(2)),
+            Box::new(SeppoExpr::Number(3)),
+        )),
+    );
+    for statement in [
+        SeppoExpr::FunctionCall("f".to_string(), vec![conditional]),
+        SeppoExpr::Return(Box::new(SeppoExpr::Block(vec![]))),
+        SeppoExpr::Return(Box::new(nested)),
+        SeppoExpr::Return(Box::new(SeppoExpr::Number(-1))),
+        SeppoExpr::Return(Box::new(SeppoExpr::String("say \"hei\"".to_string()))),
+    ] {
+        assert!(
+            format_program(&function(statement.clone())).is_err(),
+            "{:?}",
+            statement
+        );
+    }
+}
+
+#[test]
+fn test_fmt_keeps_export() {
+    let input = "#[allow(unused-function)]\nexport   fn api(x) { return x }\nexport const fn one() { return 1 }\n";
+    let expected = "#[allow(unused-function)]\nexport fn api(x) {\n    return x\n}\n\nexport const fn one() {\n    return 1\n}\n";
+    assert_eq!(format_source(input).unwrap(), expected);
+}
+
+#[test]
+fn test_fmt_keeps_inline() {
+    let input =
+        "inline   fn twice(x) { return x * 2 }\nexport inline fn half(x) { return x / 2 }\n";
+    let expected = "inline fn twice(x) {\n    return x * 2\n}\n\nexport inline fn half(x) {\n    return x / 2\n}\n";
+    assert_eq!(format_source(input).unwrap(), expected);
+}
+
+#[test]
+fn test_fmt_keeps_signatures() {
+    let input = "export fn label(n:int,prefix:string,pad)->string{\nreturn prefix\n}\n";
+    let expected =
+        "export fn label(n: int, prefix: string, pad) -> string {\n    return prefix\n}\n";
+    assert_eq!(format_source(input).unwrap(), expected);
+}
+
+#[test]
+fn test_fmt_keeps_attributes() {
+    let input = "## Spare\n#[allow( unused_function,magic-number )]\nfn spare() { return 42 }\n\nfn seppo() { return 0 }\n";
+    let expected = "## Spare\n#[allow(unused-function, magic-number)]\nfn spare() {\n    return 42\n}\n\nfn seppo() {\n    return 0\n}\n";
+    assert_eq!(format_source(input).unwrap(), expected);
+}
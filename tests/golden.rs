@@ -0,0 +1,23 @@
+use anyhow::Result;
+use seppolang::testing::{golden, normalize_ir};
+use seppolang::CompileOptions;
+
+#[test]
+fn test_golden_collects_ir_stdout_and_exit_code() -> Result<()> {
+    let source = "fn seppo() {\n    seppo 42\n    return 7\n}\n";
+    let golden = golden(source, &CompileOptions::default())?;
+    assert_eq!(golden.exit_code, 7);
+    assert_eq!(golden.stdout, "42\n");
+    assert!(golden.ir.contains("define i64 @seppo()"), "{}", golden.ir);
+    assert!(!golden.ir.contains("target triple"), "{}", golden.ir);
+    Ok(())
+}
+
+#[test]
+fn test_normalize_ir_drops_machine_specific_lines() {
+    let ir = "; ModuleID = 'main'\nsource_filename = \"main\"\ntarget datalayout = \"e-m:e\"\ntarget triple = \"x86_64-unknown-linux-gnu\"\n\ndefine i64 @seppo() {  \nentry:\n  ret i64 7\n}\n\n\n";
+    assert_eq!(
+        normalize_ir(ir),
+        "define i64 @seppo() {\nentry:\n  ret i64 7\n}\n"
+    );
+}
@@ -0,0 +1,46 @@
+use seppolang::grammar::{self, GrammarFormat};
+use seppolang::lexer::{tokenize, TokenKind};
+
+#[test]
+fn test_tmlanguage_keywords_follow_the_grammar() {
+    let tmlanguage = grammar::render(GrammarFormat::TmLanguage);
+    assert!(tmlanguage.contains(r#""scopeName": "source.seppo""#));
+    assert!(tmlanguage.contains(
+        r#""match": "\\b(?:0xseppo|become|boolseppo|ceppo|charseppo|const|eseppo|export|fn|for|in|inline|let|macro|mut|perkele|return|seppo|threadlocal)\\b""#
+    ));
+    assert!(tmlanguage
+        .contains(r#""match": "\\.\\.=|!=|&&|\\.\\.|<=|==|>=|\\|\\||\\*|\\+|\\-|\\/|<|=|>""#));
+    // C inside ceppo blocks is highlighted as C
+    assert!(tmlanguage.contains(r#""contentName": "source.c""#));
+
+    // The lexer's keywords are the grammar's
+    let words =
+        "0xseppo become boolseppo ceppo charseppo const eseppo export fn for in inline let macro mut perkele return seppo threadlocal";
+    for token in tokenize(words) {
+        assert_eq!(token.kind, TokenKind::Keyword, "{}", token.text);
+    }
+}
+
+#[test]
+fn test_tree_sitter_grammar_translates_rules() {
+    let grammar_js = grammar::render(GrammarFormat::TreeSitter);
+    let rules: Vec<&str> = grammar_js
+        .lines()
+        .filter_map(|line| line.strip_prefix("    ")?.split_once(": $ =>"))
+        .map(|(name, _)| name)
+        .collect();
+    // The rule that matches the whole file comes first, whitespace is left
+    // to `extras` and silent rules are hidden
+    assert_eq!(rules.first(), Some(&"program"));
+    assert!(!rules.contains(&"WHITESPACE"));
+    assert!(rules.contains(&"_value"));
+    assert!(grammar_js.contains("extras: $ => [/\\s/, $.comment],"));
+
+    assert!(grammar_js.contains("    comment: $ => token(seq('//', repeat(/[^\\n]/))),\n"));
+    assert!(grammar_js.contains("    return_stmt: $ => seq('return', $._value),\n"));
+    // c_code may be empty, which tree-sitter only allows for the start rule
+    assert!(
+        grammar_js.contains("    extern_block: $ => seq('ceppo', '{', optional($.c_code), '}'),\n")
+    );
+    assert!(grammar_js.contains("    c_code: $ => repeat1("));
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use inkwell::context::Context;
+use seppolang::{parse_module, Jit, SeppoError};
+
+const PROGRAM: &str = r#"
+    fn step(x) {
+        return x + 1
+    }
+
+    fn run(x) {
+        y = step(x)
+        return y * 10
+    }
+"#;
+
+#[test]
+fn test_reload_swaps_changed_functions() -> Result<()> {
+    let context = Context::create();
+    let mut jit = Jit::hot(&context, &parse_module(PROGRAM)?)?;
+    assert_eq!(jit.call("run", &[1])?, 20);
+
+    // Nothing changed, nothing is compiled
+    assert!(jit.reload(&parse_module(PROGRAM)?)?.is_empty());
+
+    // `run` is not recompiled, but calls the new `step`
+    let changed = PROGRAM.replace("x + 1", "x + 5");
+    assert_eq!(jit.reload(&parse_module(&changed)?)?, ["step"]);
+    assert_eq!(jit.call("step", &[1])?, 6);
+    assert_eq!(jit.call("run", &[1])?, 60);
+
+    // Functions can be added, and reloaded again
+    let added = changed.replace("x + 5", "double(x)") + "\nfn double(x) {\n    return x * 2\n}\n";
+    assert_eq!(jit.reload(&parse_module(&added)?)?, ["double", "step"]);
+    assert!(jit.has_function("double"));
+    assert_eq!(jit.call("run", &[4])?, 80);
+    Ok(())
+}
+
+#[test]
+fn test_reload_rejects_what_cannot_change() -> Result<()> {
+    let context = Context::create();
+    let mut jit = Jit::hot(&context, &parse_module(PROGRAM)?)?;
+
+    let params = PROGRAM.replace("fn step(x)", "fn step(x, z)");
+    match jit.reload(&parse_module(&params)?) {
+        Err(SeppoError::Sema(message)) => assert_eq!(
+            message,
+            "Reloading cannot change the parameters of step, restart the program to change them"
+        ),
+        other => panic!("expected a sema error, got {:?}", other),
+    }
+    let ceppo = format!(
+        "{}\nceppo {{\n    int64_t one() {{ return 1; }}\n}}\n",
+        PROGRAM
+    );
+    assert!(matches!(
+        jit.reload(&parse_module(&ceppo)?),
+        Err(SeppoError::Codegen(_))
+    ));
+    // The failed reloads changed nothing
+    assert_eq!(jit.call("run", &[1])?, 20);
+
+    let mut plain = Jit::new(&context, &parse_module(PROGRAM)?)?;
+    assert!(matches!(
+        plain.reload(&parse_module(PROGRAM)?),
+        Err(SeppoError::Codegen(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_spawn_runs_on_a_thread() -> Result<()> {
+    let context = Context::create();
+    let jit = Jit::hot(
+        &context,
+        &parse_module("fn seppo() {\n    x = 6\n    return x * 7\n}\n")?,
+    )?;
+    // The JIT lives until the end of the test, after the thread is joined
+    let handle = unsafe { jit.spawn("seppo")? };
+    assert_eq!(handle.join().unwrap(), 42);
+    assert!(unsafe { jit.spawn("missing") }.is_err());
+    Ok(())
+}
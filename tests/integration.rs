@@ -1,127 +1,18 @@
 use anyhow::Result;
+use inkwell::attributes::{Attribute, AttributeLoc};
 use inkwell::context::Context;
-use seppolang::{parse_seppo, CodeGen};
-use std::env;
-use std::fs;
-use std::process;
-use std::time::{SystemTime, UNIX_EPOCH};
+use seppolang::bench::{self, BenchOptions};
+use seppolang::testing::{self, TestStatus};
+use seppolang::{
+    compile_input, compile_str, interp, parse_module, parse_seppo, parse_with_locations, post_link,
+    run_str, CodeGen, CodeGenOptions, CompileOptions, DivisionOverflow, DivisionRounding, Driver,
+    Input, Jit, OptimizationLevel, OutputKind, OutputPath, PassPreset, Profile, Sanitizer,
+    SeppoError, SeppoExpr, StackProtector, StdoutBuffering, TargetDir, TargetSpec,
+};
+use std::path::Path;
 
 fn compile_and_run(input: &str) -> Result<i64> {
-    // Initialize LLVM targets
-    inkwell::targets::Target::initialize_all(&inkwell::targets::InitializationConfig {
-        asm_parser: true,
-        asm_printer: true,
-        base: true,
-        disassembler: true,
-        info: true,
-        machine_code: true,
-    });
-
-    // Initialize native target
-    inkwell::targets::Target::initialize_native(&inkwell::targets::InitializationConfig::default())
-        .map_err(|e| anyhow::anyhow!("Failed to initialize native target: {}", e))?;
-
-    // Parse
-    let expr = parse_seppo(input)?;
-
-    // Create a unique temporary directory for this test run
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
-    let pid = process::id();
-    let temp_dir = env::temp_dir().join(format!("seppolang_test_{}_{}", pid, timestamp));
-    fs::create_dir_all(&temp_dir)?;
-
-    let obj_file = temp_dir.join("test.o");
-    let exe_file = temp_dir.join("test");
-
-    println!("Using temp directory: {:?}", temp_dir);
-    println!("Object file path: {:?}", obj_file);
-    println!("Executable file path: {:?}", exe_file);
-
-    // Use a closure to ensure cleanup happens even on error
-    let result = (|| {
-        // Generate code
-        let context = Context::create();
-        let mut codegen = CodeGen::new(&context, "test");
-        codegen.compile(&expr)?;
-
-        // Write object file
-        codegen.write_object_file(&obj_file)?;
-
-        // Debug: Print object file size
-        println!("Object file size: {} bytes", fs::metadata(&obj_file)?.len());
-
-        // Verify object file was created
-        if !obj_file.exists() {
-            return Err(anyhow::anyhow!("Object file was not created"));
-        }
-
-        // Link with more verbose error handling
-        let mut link_command = std::process::Command::new("cc");
-        link_command
-            .arg("-v") // Add verbose output
-            .arg("-o")
-            .arg(&exe_file)
-            .arg(&obj_file);
-
-        // Add any C object files
-        for c_obj in codegen.c_object_files() {
-            println!("Adding C object file: {:?}", c_obj);
-            link_command.arg(c_obj);
-        }
-
-        println!("Link command: {:?}", link_command);
-        let output = link_command.output()?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            eprintln!("Linking failed:");
-            eprintln!("stderr: {}", stderr);
-            eprintln!("stdout: {}", stdout);
-            eprintln!("Link command was: {:?}", link_command);
-            fs::remove_file(&obj_file)?;
-            return Err(anyhow::anyhow!("Linking failed: {}", stderr));
-        }
-
-        // Verify executable was created
-        if !exe_file.exists() {
-            return Err(anyhow::anyhow!("Executable file was not created"));
-        }
-
-        // Run with more verbose error handling
-        let output = std::process::Command::new(&exe_file)
-            .output()
-            .map_err(|e| anyhow::anyhow!("Failed to execute binary: {}", e))?;
-
-        // Get the exit code, ensuring it's properly captured
-        let exit_code = output
-            .status
-            .code()
-            .ok_or_else(|| anyhow::anyhow!("Process terminated by signal"))?;
-
-        // Print debug information
-        println!(
-            "Program stdout: {}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-        println!(
-            "Program stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        println!("Exit code: {}", exit_code);
-
-        Ok(exit_code as i64)
-    })();
-
-    // Always clean up
-    let _ = fs::remove_file(&obj_file);
-    let _ = fs::remove_file(&exe_file);
-    let _ = fs::remove_dir(&temp_dir);
-
-    result
+    Ok(run_str(input)?)
 }
 
 #[test]
@@ -146,6 +37,20 @@ fn test_seppo_implicit_return() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_trailing_expression_is_returned() -> Result<()> {
+    let input = r#"
+        fn add(a, b) { a + b }
+
+        fn seppo() {
+            x = add(40, 1)
+            add(x, 1)
+        }
+    "#;
+    assert_eq!(compile_and_run(input)?, 42);
+    Ok(())
+}
+
 #[test]
 fn test_variable_assignment() -> Result<()> {
     let input = r#"
@@ -171,6 +76,131 @@ fn test_print_statement() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_print_booleans_and_characters() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            boolseppo 7
+            boolseppo 0
+            charseppo 65
+            charseppo 228
+            charseppo 8364
+            charseppo 128512
+            charseppo 0 - 1
+            return 0
+        }
+    "#;
+    let program = compile_str(input, &CompileOptions::default())?;
+    let output = program.command().output()?;
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "true\nfalse\nA\nä\n€\n😀\n\u{FFFD}\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_eseppo_prints_to_stderr() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            seppo 1
+            eseppo 2
+            return 0
+        }
+    "#;
+    let program = compile_str(input, &CompileOptions::default())?;
+    let output = program.command().output()?;
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "2\n");
+    Ok(())
+}
+
+#[test]
+fn test_stdout_buffering_and_flush() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            seppo 1
+            flush()
+            return 0
+        }
+    "#;
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+    let linux = TargetSpec::from_triple("x86_64-unknown-linux-gnu")?;
+    let mut codegen = CodeGen::with_target(&context, "buffering", linux);
+    codegen.set_stdout_buffering(Some(StdoutBuffering::Full));
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("@stdout = external global ptr"), "{}", ir);
+    assert!(
+        ir.contains("call i32 @setvbuf(ptr %stdout, ptr null, i32 0, i64 0)"),
+        "{}",
+        ir
+    );
+    assert!(ir.contains("call i32 @fflush(ptr null)"), "{}", ir);
+
+    let options = CompileOptions {
+        stdout_buffering: Some(StdoutBuffering::Unbuffered),
+        ..CompileOptions::default()
+    };
+    let output = compile_str(input, &options)?.command().output()?;
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+
+    let wasm = TargetSpec::from_triple("wasm32-wasi")?;
+    wasm.initialize()?;
+    let mut codegen = CodeGen::with_target(&context, "wasm", wasm);
+    codegen.set_stdout_buffering(Some(StdoutBuffering::Line));
+    let err = codegen.compile(&parse_seppo(input)?).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--stdout-buffering is not supported on wasm targets, which write output unbuffered"
+    );
+    Ok(())
+}
+
+const DIVISION: &str = r#"
+    fn seppo() {
+        a = 0 - 7
+        q = a / 2
+        seppo q
+        b = 0 - 9223372036854775807
+        min = b - 1
+        c = 0 - 1
+        w = min / c
+        seppo w
+        return 0
+    }
+"#;
+
+#[test]
+fn test_division_rounding_and_overflow() -> Result<()> {
+    let options = CompileOptions {
+        division_rounding: DivisionRounding::Floor,
+        division_overflow: Some(DivisionOverflow::Wrap),
+        ..CompileOptions::default()
+    };
+    let output = compile_str(DIVISION, &options)?.command().output()?;
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "-4\n-9223372036854775808\n"
+    );
+
+    let options = CompileOptions {
+        division_overflow: Some(DivisionOverflow::Trap),
+        ..CompileOptions::default()
+    };
+    let output = compile_str(DIVISION, &options)?.command().output()?;
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "-3\n");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("attempt to divide by zero or with overflow"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
 #[test]
 fn test_arithmetic() -> Result<()> {
     let input = r#"
@@ -221,6 +251,28 @@ fn test_undefined_variable() {
     compile_and_run(input).unwrap();
 }
 
+#[test]
+fn test_undefined_names_suggest_closest() -> Result<()> {
+    let input = r#"
+        fn double(n) {
+            return n * 2
+        }
+
+        fn seppo() {
+            counter = 1
+            return doubel(counter)
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "suggestions");
+    let error = codegen.compile(&parse_seppo(input)?).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Undefined function: doubel (did you mean `double`?)"
+    );
+    Ok(())
+}
+
 #[test]
 fn test_inline_c_function() -> Result<()> {
     let input = r#"
@@ -259,6 +311,52 @@ fn test_ceppo_with_whitespace() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_ceppo_compiler_and_flags() -> Result<()> {
+    let input = r#"
+        ceppo {
+            #ifndef SEPPO_VALUE
+            #error SEPPO_VALUE is not set
+            #endif
+            int64_t value() { return SEPPO_VALUE; }
+        }
+
+        fn seppo() {
+            return value()
+        }
+    "#;
+    let program = parse_seppo(input)?;
+    let context = Context::create();
+
+    let mut codegen = CodeGen::new(&context, "cflags");
+    let error = codegen.compile(&program).unwrap_err();
+    assert!(
+        error.to_string().contains("SEPPO_VALUE is not set"),
+        "{}",
+        error
+    );
+
+    let mut codegen = CodeGen::new(&context, "cflags");
+    codegen.set_c_compiler(Some("cc".to_string()));
+    codegen.set_c_flags(vec!["-O2".to_string(), "-DSEPPO_VALUE=7".to_string()]);
+    codegen.compile(&program)?;
+    let [build] = codegen.ceppo_builds() else {
+        panic!("expected one ceppo build, got {:?}", codegen.ceppo_builds());
+    };
+    assert_eq!(build.compiler, "cc");
+    assert!(build
+        .flags
+        .ends_with(&["-O2".to_string(), "-DSEPPO_VALUE=7".to_string()]));
+    assert!(build.object.exists());
+
+    // A compiler given with arguments, like a CC of `ccache cc`
+    let command = TargetSpec::native().c_compiler_with("ccache cc -m64");
+    assert_eq!(command.get_program(), "ccache");
+    let args: Vec<_> = command.get_args().take(3).collect();
+    assert_eq!(args, ["cc", "-m64", "-c"]);
+    Ok(())
+}
+
 #[test]
 fn test_ceppo_complex_function() -> Result<()> {
     let input = r#"
@@ -383,7 +481,7 @@ fn test_ceppo_chibihash() -> Result<()> {
 fn test_conditional_format() -> Result<()> {
     let input = r#"
         fn seppo() {
-            x = 42
+            let mut x = 42
             seppo x > 40 {
                 x = 1
             }
@@ -401,7 +499,7 @@ fn test_conditional_format() -> Result<()> {
 fn test_conditional_less_than() -> Result<()> {
     let input = r#"
         fn seppo() {
-            x = 30
+            let mut x = 30
             seppo x < 40 {
                 x = 1
             }
@@ -419,7 +517,7 @@ fn test_conditional_less_than() -> Result<()> {
 fn test_conditional_equals() -> Result<()> {
     let input = r#"
         fn seppo() {
-            x = 42
+            let mut x = 42
             seppo x == 42 {
                 x = 1
             }
@@ -432,3 +530,2291 @@ fn test_conditional_equals() -> Result<()> {
     assert_eq!(compile_and_run(input)?, 1);
     Ok(())
 }
+
+#[test]
+fn test_nested_conditionals() -> Result<()> {
+    let input = r#"
+        fn classify(x) {
+            let mut r = 0
+            seppo x > 10 {
+                seppo x > 100 {
+                    r = 3
+                }
+                perkele {
+                    r = 2
+                }
+            }
+            perkele {
+                seppo x == 0 {
+                    r = 1
+                }
+            }
+            return r
+        }
+
+        fn seppo() {
+            let mut a = classify(500)
+            let mut b = classify(50)
+            let mut c = classify(0)
+            d = classify(5)
+            a = a * 64
+            b = b * 16
+            c = c * 4
+            x = a + b
+            y = c + d
+            return x + y
+        }
+    "#;
+    // 3, 2, 1 and 0 as base 4 digits, small enough for an exit code
+    assert_eq!(compile_and_run(input)?, 0b11_10_01_00);
+    Ok(())
+}
+
+#[test]
+fn test_conditional_with_early_return() -> Result<()> {
+    let input = r#"
+        fn first_over(limit) {
+            for i in 0..10 {
+                seppo i > limit {
+                    return i
+                }
+            }
+            return 99
+        }
+
+        fn sign(x) {
+            seppo x < 0 {
+                return 1
+            }
+            perkele {
+                seppo x == 0 {
+                    return 2
+                }
+            }
+            return 3
+        }
+
+        fn seppo() {
+            a = first_over(4)
+            b = first_over(20)
+            c = sign(0)
+            d = sign(7)
+            x = a + b
+            y = c * d
+            return x + y
+        }
+    "#;
+    // 5 + 99 + 2 * 3
+    assert_eq!(compile_and_run(input)?, 110);
+    Ok(())
+}
+
+#[test]
+fn test_conditional_in_loop_accumulates() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            let mut evens = 0
+            for i in 0..10 {
+                half = i / 2
+                twice = half * 2
+                seppo twice == i {
+                    evens = evens + 1
+                    seppo i > 5 {
+                        evens = evens + 10
+                    }
+                }
+            }
+            return evens
+        }
+    "#;
+    // Five even numbers, two of them above 5
+    assert_eq!(compile_and_run(input)?, 25);
+    Ok(())
+}
+
+#[test]
+fn test_conditional_values() -> Result<()> {
+    let input = r#"
+        fn max(a, b) {
+            return seppo a > b {
+                a
+            }
+            perkele {
+                b
+            }
+        }
+
+        fn seppo() {
+            x = 7
+            let size = seppo x in 0..5 {
+                1
+            }
+            perkele {
+                seppo x > 100 {
+                    3
+                }
+                perkele {
+                    doubled = x * 2
+                    doubled
+                }
+            }
+            m = max(size, 9)
+            return m + size
+        }
+    "#;
+    // size is 14, the larger of 14 and 9
+    assert_eq!(compile_and_run(input)?, 28);
+    assert_eq!(interp::eval(&parse_seppo(input)?)?, 28);
+    Ok(())
+}
+
+#[test]
+fn test_conditions_branch_on_comparison() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            let mut x = 42
+            seppo x > 40 {
+                x = 1
+            }
+            seppo x in 0..10 {
+                x = x + 1
+            }
+            return x
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    // The comparison result goes to the branch as is, without a round trip
+    // through i64
+    assert!(ir.contains("br i1 %cmptmp"), "{}", ir);
+    assert!(ir.contains("br i1 %in_range"), "{}", ir);
+    assert!(!ir.contains("zext"), "{}", ir);
+    Ok(())
+}
+
+#[test]
+fn test_wasm_target_uses_wasi_imports() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            x = 42
+            seppo x
+            return x
+        }
+    "#;
+
+    let target = TargetSpec::from_triple("wasm32-wasi")?;
+    target.initialize()?;
+
+    let expr = parse_seppo(input)?;
+    let context = Context::create();
+    let mut codegen = CodeGen::with_target(&context, "test", target);
+    codegen.compile(&expr)?;
+
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("define void @_start()"));
+    assert!(ir.contains("\"wasm-import-name\"=\"fd_write\""));
+    assert!(!ir.contains("printf"));
+    Ok(())
+}
+
+#[test]
+fn test_musl_target_links_statically() -> Result<()> {
+    let target = TargetSpec::from_triple("musl")?;
+    assert_eq!(
+        target.triple(),
+        format!("{}-unknown-linux-musl", std::env::consts::ARCH)
+    );
+    let target = TargetSpec::from_triple("x86_64-unknown-linux-musl")?;
+    assert!(target.is_musl() && !target.is_wasm());
+
+    if std::env::var_os("MUSL_SYSROOT").is_none() {
+        assert_eq!(target.c_compiler().get_program(), "musl-gcc");
+        let linker = target.linker();
+        assert_eq!(linker.get_program(), "musl-gcc");
+        assert_eq!(linker.get_args().collect::<Vec<_>>(), ["-static"]);
+    }
+
+    let context = Context::create();
+    let mut codegen = CodeGen::with_target(&context, "musl", target);
+    codegen.compile(&parse_seppo("fn seppo() {\n    return 0\n}\n")?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("target triple = \"x86_64-unknown-linux-musl\""));
+
+    let mut codegen = CodeGen::with_target(&context, "musl_asan", TargetSpec::from_triple("musl")?);
+    codegen.set_sanitizers(vec![Sanitizer::Address]);
+    let error = codegen
+        .compile(&parse_seppo("fn seppo() {\n    return 0\n}\n")?)
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Sanitizers are not supported on musl targets"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_universal_object_has_both_slices() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_universal_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: "fn seppo() {\n    return 0\n}".to_string(),
+    }]);
+    driver.output = Some(dir.join("main"));
+    driver.output_kind = OutputKind::Object;
+    driver.target = TargetSpec::from_triple("x86_64-apple-darwin")?;
+    driver.universal = true;
+    let object = driver.run()?;
+    let data = std::fs::read(&object)?;
+    let left: Vec<_> = std::fs::read_dir(&dir)?.collect();
+    std::fs::remove_dir_all(&dir)?;
+
+    // Only the merged file is left
+    assert_eq!(left.len(), 1);
+    let word = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+    assert_eq!(word(0), 0xcafebabe);
+    assert_eq!(word(4), 2);
+    // arm64, then x86_64, each slice a Mach-O file at its offset
+    assert_eq!(word(8), 0x0100000c);
+    assert_eq!(word(28), 0x01000007);
+    for entry in [8, 28] {
+        let offset = word(entry + 8) as usize;
+        assert_eq!(offset % (1 << word(entry + 16)), 0);
+        assert_eq!(data[offset..offset + 4], [0xcf, 0xfa, 0xed, 0xfe]);
+    }
+
+    driver.target = TargetSpec::from_triple("x86_64-unknown-linux-gnu")?;
+    let error = driver.run().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "--universal builds for macOS, not x86_64-unknown-linux-gnu"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_post_link_strips_then_runs_commands() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_post_link_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    // Any executable will do, this test's own is at hand
+    let executable = dir.join("program");
+    std::fs::copy(std::env::current_exe()?, &executable)?;
+    let size = std::fs::metadata(&executable)?.len();
+    let marker = dir.join("marker");
+    let target = TargetSpec::native();
+
+    let hook = format!("touch {}", marker.display());
+    post_link(&executable, &target, true, &[hook])?;
+    assert!(std::fs::metadata(&executable)?.len() < size);
+    // The hook ran after stripping
+    assert!(marker.exists());
+
+    let result = post_link(&executable, &target, false, &["false".to_string()]);
+    let strip = post_link(&dir.join("missing"), &target, true, &[]);
+    std::fs::remove_dir_all(&dir)?;
+    match result {
+        Err(SeppoError::Link(message)) => {
+            assert!(
+                message.starts_with("Post-link command failed"),
+                "{}",
+                message
+            )
+        }
+        other => panic!("expected a link error, got {:?}", other),
+    }
+    assert!(matches!(strip, Err(SeppoError::Link(_))));
+    Ok(())
+}
+
+#[test]
+fn test_compile_str_cleans_up() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            seppo 42
+            return 7
+        }
+    "#;
+
+    let program = compile_str(input, &CompileOptions::default())?;
+    let executable = program.executable().to_path_buf();
+    assert!(executable.exists());
+
+    let output = program.command().output()?;
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "42\n");
+    assert_eq!(program.run()?, 7);
+
+    drop(program);
+    assert!(!executable.exists());
+    Ok(())
+}
+
+#[test]
+fn test_failed_link_removes_object_file() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_failed_link_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: "fn seppo() {\n    return 0\n}\n".to_string(),
+    }]);
+    driver.output = Some(dir.join("main"));
+    driver.linker_args = vec!["-lseppolang_does_not_exist".to_string()];
+    let result = driver.run();
+    let left: Vec<_> = std::fs::read_dir(&dir)?.collect();
+    std::fs::remove_dir_all(&dir)?;
+
+    assert!(matches!(result, Err(SeppoError::Link(_))));
+    assert!(left.is_empty(), "{:?}", left);
+    Ok(())
+}
+
+#[test]
+fn test_compile_str_keeps_temps() -> Result<()> {
+    let options = CompileOptions {
+        keep_temps: true,
+        ..CompileOptions::default()
+    };
+    let program = compile_str("fn seppo() {\n    return 3\n}\n", &options)?;
+    let dir = program.executable().parent().unwrap().to_path_buf();
+    assert_eq!(program.run()?, 3);
+
+    drop(program);
+    assert!(dir.exists());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+const ARGS: &str = r#"
+    fn seppo(argc, argv) {
+        last = argc - 1
+        offset = last * 8
+        arg = load(argv + offset)
+        n = len(arg)
+        tens = argc * 10
+        return tens + n
+    }
+"#;
+
+#[test]
+fn test_seppo_takes_argc_and_argv() -> Result<()> {
+    let program = compile_str(ARGS, &CompileOptions::default())?;
+    let status = program.command().args(["a", "seppo"]).status()?;
+    // Three arguments with the program name, the last one five bytes long
+    assert_eq!(status.code(), Some(35));
+
+    let argc_only = compile_str(
+        "fn seppo(argc) {\n    return argc\n}\n",
+        &CompileOptions::default(),
+    )?;
+    assert_eq!(argc_only.command().arg("x").status()?.code(), Some(2));
+
+    let err = interp::eval(&parse_module(ARGS)?).expect_err("argv in the interpreter");
+    assert_eq!(
+        err.to_string(),
+        "seppo(argc, argv) needs compiled code, the interpreter runs seppo without arguments"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_driver_links_multiple_inputs() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_driver_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let mut driver = Driver::new(vec![
+        Input::Source {
+            name: "helpers".to_string(),
+            text: "fn answer() { return 42 }".to_string(),
+        },
+        Input::Source {
+            name: "main".to_string(),
+            text: "fn seppo() { return answer() }".to_string(),
+        },
+    ]);
+    driver.output = Some(dir.join("multi"));
+
+    let executable = driver.run()?;
+    let status = std::process::Command::new(&executable).status()?;
+    std::fs::remove_dir_all(&dir)?;
+
+    assert_eq!(status.code(), Some(42));
+    Ok(())
+}
+
+const EXAMPLES: &str = r#"
+    fn hello_seppo() {
+        return 1
+    }
+
+    fn sum_seppo(argc) {
+        return argc + 40
+    }
+"#;
+
+const LAYOUT: &str = r#"
+    ceppo {
+        int64_t one() { return 1; }
+    }
+
+    fn seppo() {
+        return one()
+    }
+"#;
+
+#[test]
+fn test_driver_builds_into_the_target_directory() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_target_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("layout.seppo");
+    std::fs::write(&path, LAYOUT)?;
+    let target = TargetDir::new(dir.join("target"));
+
+    let mut driver = Driver::for_file(&path);
+    driver.target_dir = Some(target.root().to_path_buf());
+    driver.output_kind = OutputKind::Object;
+    driver.apply_profile(Profile::Release);
+    assert_eq!(
+        driver.output_stem()?,
+        dir.join("target/seppo/release/layout")
+    );
+    let object = driver.run()?;
+    assert_eq!(object, dir.join("target/seppo/release/layout.o"));
+    assert!(object.exists());
+
+    // The ceppo block is compiled once and reused by the next build
+    let cache = target.ceppo_cache(Some(Profile::Release));
+    let blocks: Vec<_> = std::fs::read_dir(&cache)?.collect::<std::io::Result<_>>()?;
+    assert_eq!(blocks.len(), 1);
+    let cached = blocks[0].path().join("inline.o");
+    let compiled_at = std::fs::metadata(&cached)?.modified()?;
+    driver.run()?;
+    assert_eq!(std::fs::metadata(&cached)?.modified()?, compiled_at);
+
+    assert!(target.clean()?);
+    assert!(!target.seppo_dir().exists());
+    assert!(!target.clean()?);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_ceppo_blocks_with_local_headers_are_not_cached() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_header_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("header.seppo");
+    std::fs::write(
+        &path,
+        "ceppo {\n    #include \"answer.h\"\n    int64_t answer() { return ANSWER; }\n}\n\nfn seppo() {\n    return answer()\n}\n",
+    )?;
+    let target = TargetDir::new(dir.join("target"));
+
+    let mut driver = Driver::for_file(&path);
+    driver.target_dir = Some(target.root().to_path_buf());
+    driver.c_flags = vec![format!("-I{}", dir.display())];
+    let mut codes = Vec::new();
+    for answer in [1, 2] {
+        std::fs::write(dir.join("answer.h"), format!("#define ANSWER {}\n", answer))?;
+        let executable = driver.run()?;
+        codes.push(std::process::Command::new(&executable).status()?.code());
+    }
+    let cache = target.ceppo_cache(None);
+    let cached = cache.exists() && std::fs::read_dir(&cache)?.next().is_some();
+    std::fs::remove_dir_all(&dir)?;
+
+    assert_eq!(codes, [Some(1), Some(2)]);
+    assert!(!cached);
+    Ok(())
+}
+
+#[test]
+fn test_build_names_the_directory_of_the_profile() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_profile_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("layout.seppo");
+    std::fs::write(&path, "fn seppo() {\n    return 0\n}\n")?;
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_seppoc"))
+        .args(["build", "-c", "--profile", "release", "--target-dir"])
+        .arg(dir.join("target"))
+        .arg(&path)
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout)?;
+    let release = dir.join("target/seppo/release/layout");
+    assert!(
+        stdout.contains(&format!("to {}\n", release.display())),
+        "{}",
+        stdout
+    );
+    assert!(dir.join("target/seppo/release/layout.o").exists());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_run_applies_the_codegen_flags() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_run_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("nested.seppo");
+    std::fs::write(
+        &path,
+        "fn seppo() {\n    seppo 1 == 1 {\n        seppo 2 == 2 {\n            return 3\n        }\n    }\n    return 0\n}\n",
+    )?;
+    let run = |flags: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_seppoc"))
+            .arg("run")
+            .args(flags)
+            .arg(&path)
+            .output()
+    };
+
+    assert_eq!(run(&[])?.status.code(), Some(3));
+    let output = run(&["--max-nesting", "2"])?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("program too deeply nested: more than 2 levels"),
+        "{}",
+        stderr
+    );
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_driver_builds_the_chosen_entry_point() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_bin_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("examples.seppo");
+    std::fs::write(&path, EXAMPLES)?;
+
+    let mut driver = Driver::for_file(&path);
+    driver.bin = Some("sum".to_string());
+    // Named after the entry point rather than the file
+    assert_eq!(driver.output_stem()?, dir.join("sum"));
+    let executable = driver.run()?;
+    let status = std::process::Command::new(&executable).arg("x").status()?;
+
+    driver.bin = None;
+    let unchosen = driver.parse().expect_err("no seppo without --bin");
+    driver.bin = Some("goodbye".to_string());
+    let missing = driver.parse().expect_err("no goodbye_seppo");
+    std::fs::remove_dir_all(&dir)?;
+
+    assert_eq!(status.code(), Some(42));
+    assert_eq!(
+        unchosen.to_string(),
+        "No seppo function found, choose one of the entry points with --bin: hello, sum"
+    );
+    assert_eq!(
+        missing.to_string(),
+        "No entry point goodbye_seppo for --bin goodbye, expected one of hello, sum"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_undefined_sanitizer_reports_overflow() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_ubsan_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let text = "fn add(a, b) {\n    return a + b\n}\n\nfn seppo() {\n    x = add(9223372036854775807, 1)\n    return 3\n}\n";
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "overflow.seppo".to_string(),
+        text: text.to_string(),
+    }]);
+    driver.output = Some(dir.join("overflow"));
+    driver.sanitizers = vec![Sanitizer::Undefined];
+    let executable = driver.run()?;
+    let output = std::process::Command::new(&executable).output()?;
+    std::fs::remove_dir_all(&dir)?;
+
+    // Reported, then the program carries on
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("overflow.seppo:2") && stderr.contains("signed integer overflow"),
+        "{}",
+        stderr
+    );
+    assert_eq!(output.status.code(), Some(3));
+    Ok(())
+}
+
+#[test]
+fn test_address_sanitizer_instruments_functions() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_asan_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: "fn seppo() {\n    return 0\n}".to_string(),
+    }]);
+    driver.output = Some(dir.join("main"));
+    driver.output_kind = OutputKind::LlvmIr;
+    driver.sanitizers = vec![Sanitizer::Address];
+    let ir = std::fs::read_to_string(driver.run()?)?;
+    std::fs::remove_dir_all(&dir)?;
+
+    assert!(ir.contains("sanitize_address"), "{}", ir);
+    assert!(ir.contains("asan.module_ctor"), "{}", ir);
+    Ok(())
+}
+
+#[test]
+fn test_profiling_keeps_frame_pointers() -> Result<()> {
+    let input = "fn seppo() {\n    return 0\n}";
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+
+    let mut codegen = CodeGen::new(&context, "frame_pointers");
+    codegen.set_frame_pointers(true);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("\"frame-pointer\"=\"all\""), "{}", ir);
+    assert!(!ir.contains("uwtable"), "{}", ir);
+    assert!(codegen.link_flags().is_empty());
+
+    let mut codegen = CodeGen::new(&context, "profiling");
+    codegen.set_profiling(true);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("\"frame-pointer\"=\"all\""), "{}", ir);
+    assert!(ir.contains("uwtable"), "{}", ir);
+    assert_eq!(codegen.link_flags(), vec!["-rdynamic"]);
+    Ok(())
+}
+
+#[test]
+fn test_stack_protector_guards_seppo_and_ceppo() -> Result<()> {
+    let input = "fn seppo() {\n    return 0\n}\n\nceppo {\n    int64_t one() { return 1; }\n}\n";
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "stack_protector");
+    codegen.set_stack_protector(Some(StackProtector::Strong));
+    codegen.set_stack_probes(true);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("sspstrong"), "{}", ir);
+    assert!(ir.contains("\"probe-stack\"=\"inline-asm\""), "{}", ir);
+    let flags = &codegen.ceppo_builds()[0].flags;
+    assert!(flags.contains(&"-fstack-protector-strong".to_string()));
+    assert!(flags.contains(&"-fstack-clash-protection".to_string()));
+
+    let mut codegen = CodeGen::new(&context, "stack_protector_all");
+    codegen.set_stack_protector(Some(StackProtector::All));
+    codegen.compile(&parse_seppo("fn seppo() {\n    return 0\n}\n")?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("sspreq"), "{}", ir);
+    assert!(!ir.contains("probe-stack"), "{}", ir);
+
+    let wasm = TargetSpec::from_triple("wasm32-wasi")?;
+    let mut codegen = CodeGen::with_target(&context, "wasm", wasm);
+    codegen.set_stack_protector(Some(StackProtector::Strong));
+    let err = codegen
+        .compile(&parse_seppo("fn seppo() {\n    return 0\n}\n")?)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Stack protectors and probes are not supported on wasm targets"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_pie_sets_relocation_model_and_link_flags() -> Result<()> {
+    let input = "fn seppo() {\n    return 0\n}\n";
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+    let linux = TargetSpec::from_triple("x86_64-unknown-linux-gnu")?;
+    let mut codegen = CodeGen::with_target(&context, "pie", linux.clone());
+    codegen.set_pie(Some(true));
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("!\"PIE Level\", i32 2"), "{}", ir);
+    assert_eq!(codegen.link_flags(), vec!["-pie"]);
+
+    let mut codegen = CodeGen::with_target(&context, "no_pie", linux);
+    codegen.set_pie(Some(false));
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(!ir.contains("PIE Level"), "{}", ir);
+    assert_eq!(codegen.link_flags(), vec!["-no-pie"]);
+
+    let musl = TargetSpec::from_triple("x86_64-unknown-linux-musl")?;
+    let mut codegen = CodeGen::with_target(&context, "musl", musl);
+    codegen.set_pie(Some(true));
+    let err = codegen.compile(&parse_seppo(input)?).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--pie is not supported on x86_64-unknown-linux-musl, musl builds are linked statically"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_stack_size_sizes_main_and_spawned_threads() -> Result<()> {
+    let input = "fn work(n) {\n    return n\n}\n\nfn seppo() {\n    t = spawn(work, 3)\n    return join(t)\n}\n";
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+    let linux = TargetSpec::from_triple("x86_64-unknown-linux-gnu")?;
+    let mut codegen = CodeGen::with_target(&context, "stack_size", linux);
+    codegen.set_stack_size(Some(100_000));
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("@seppo.main_thread"), "{}", ir);
+    assert!(
+        ir.contains("@pthread_attr_setstacksize(ptr %thread_attributes, i64 114688)"),
+        "{}",
+        ir
+    );
+    assert_eq!(codegen.link_flags(), vec!["-pthread"]);
+
+    let apple = TargetSpec::from_triple("aarch64-apple-darwin")?;
+    let mut codegen = CodeGen::with_target(&context, "apple", apple);
+    codegen.set_stack_size(Some(1 << 24));
+    codegen.compile(&parse_seppo("fn seppo() {\n    return 0\n}\n")?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(!ir.contains("seppo.main_thread"), "{}", ir);
+    assert_eq!(codegen.link_flags(), vec!["-Wl,-stack_size,0x1000000"]);
+
+    let windows = TargetSpec::from_triple("x86_64-pc-windows-gnu")?;
+    let mut codegen = CodeGen::with_target(&context, "windows", windows);
+    codegen.set_stack_size(Some(1 << 24));
+    let err = codegen
+        .compile(&parse_seppo("fn seppo() {\n    return 0\n}\n")?)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--stack-size is not supported on x86_64-pc-windows-gnu, which has no POSIX threads"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_debug_info_describes_functions() -> Result<()> {
+    let input = "fn add(a, b) {\n    return a + b\n}\n\nfn seppo() {\n    x = add(1, 2)\n    return x\n}\n\nceppo {\n    int64_t one() { return 1; }\n}\n";
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "debug");
+    codegen.set_debug_info(true);
+    codegen.compile(&parse_with_locations(input, "src/main.seppo")?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(
+        ir.contains("!DIFile(filename: \"main.seppo\", directory: \"src\")"),
+        "{}",
+        ir
+    );
+    assert!(ir.contains("!DISubprogram(name: \"add\""), "{}", ir);
+    assert!(ir.contains("!DISubprogram(name: \"seppo\""), "{}", ir);
+    // The call is on line 6
+    assert!(ir.contains("!DILocation(line: 6"), "{}", ir);
+    assert!(codegen.ceppo_builds()[0].flags.contains(&"-g".to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_overflow_checks_trap() -> Result<()> {
+    let input = "fn seppo() {\n    x = 9223372036854775807\n    y = x + 1\n    return y / x\n}\n";
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "overflow");
+    codegen.set_overflow_checks(true);
+    codegen.compile(&parse_with_locations(input, "overflow.seppo")?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(
+        ir.contains("overflow.seppo:3: attempt to add with overflow"),
+        "{}",
+        ir
+    );
+    assert!(
+        ir.contains("overflow.seppo:4: attempt to divide by zero or with overflow"),
+        "{}",
+        ir
+    );
+    assert!(ir.contains("@llvm.trap()"), "{}", ir);
+    assert!(!ir.contains("__ubsan"), "{}", ir);
+    Ok(())
+}
+
+#[test]
+fn test_profiles() {
+    assert_eq!(Profile::from_name("release"), Some(Profile::Release));
+    assert_eq!(Profile::from_name("fast"), None);
+
+    let mut driver = Driver::new(Vec::new());
+    driver.c_flags = vec!["-DNDEBUG".to_string()];
+    driver.apply_profile(Profile::Release);
+    assert_eq!(driver.opt_level, OptimizationLevel::Aggressive);
+    assert_eq!(driver.c_flags, vec!["-O3", "-DNDEBUG"]);
+    assert!(driver.strip && !driver.dump_ir);
+
+    let mut driver = Driver::new(Vec::new());
+    driver.apply_profile(Profile::Debug);
+    assert_eq!(driver.opt_level, OptimizationLevel::None);
+    assert!(driver.debug_info && driver.overflow_checks && !driver.strip);
+}
+
+#[test]
+fn test_sanitizers_not_supported_on_wasm() -> Result<()> {
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: "fn seppo() { return 0 }".to_string(),
+    }]);
+    driver.target = TargetSpec::from_triple("wasm32-wasi")?;
+    driver.output_kind = OutputKind::LlvmIr;
+    driver.sanitizers = vec![Sanitizer::Undefined];
+    let err = driver.run().unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Sanitizers are not supported on wasm targets"));
+    Ok(())
+}
+
+#[test]
+fn test_driver_requires_seppo_in_some_input() {
+    let driver = Driver::new(vec![Input::Source {
+        name: "helpers".to_string(),
+        text: "fn answer() { return 42 }".to_string(),
+    }]);
+    let err = driver.parse().unwrap_err();
+    assert!(err.to_string().contains("No seppo function found"));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_compile_ast_json_from_other_frontend() {
+    // What a non-pest frontend would hand over: fn seppo() { return 6 * 7 }
+    let json = r#"{"Block": [{"Function": ["seppo", [], {"Block": [
+        {"Return": {"Operation": ["*", {"Number": 6}, {"Number": 7}]}}
+    ]}]}]}"#;
+
+    TargetSpec::native().initialize().unwrap();
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "json");
+    codegen.compile_ast_json(json).unwrap();
+    assert!(codegen.get_module().get_function("seppo").is_some());
+}
+
+#[test]
+fn test_link_time_optimize() -> Result<()> {
+    let input = r#"
+        fn double(x) {
+            return x + x
+        }
+
+        fn seppo() {
+            y = double(21)
+            return y
+        }
+    "#;
+    TargetSpec::native().initialize()?;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "lto");
+    codegen.set_lto(true);
+    codegen.compile(&parse_seppo(input)?)?;
+    codegen.link_time_optimize()?;
+
+    // double is internal after LTO, so it's inlined and removed
+    let module = codegen.get_module();
+    assert!(module.get_function("double").is_none());
+    assert!(module.get_function("seppo").is_some());
+    assert!(module.verify().is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_runner_reports_failures() -> Result<()> {
+    let input = r#"
+        fn add() {
+            return 2 + 3
+        }
+
+        fn testi_add() {
+            seppo add() == 5 {
+                return 0
+            }
+            return 1
+        }
+
+        fn testi_broken() {
+            return 7
+        }
+    "#;
+    let program = parse_module(input)?;
+    assert_eq!(
+        testing::test_functions(&program),
+        vec!["testi_add", "testi_broken"]
+    );
+
+    let outcomes = testing::run_tests(&program, None)?;
+    let statuses: Vec<_> = outcomes.iter().map(|o| o.status.clone()).collect();
+    assert_eq!(statuses, vec![TestStatus::Passed, TestStatus::Failed(7)]);
+
+    let filtered = testing::run_tests(&program, Some("add"))?;
+    assert_eq!(filtered.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_bench_harness_measures_time() -> Result<()> {
+    let input = r#"
+        fn bench_sum() {
+            x = 1 + 2
+            return x * 3
+        }
+
+        fn helper() {
+            return 0
+        }
+    "#;
+    let program = parse_module(input)?;
+    assert_eq!(bench::bench_functions(&program), vec!["bench_sum"]);
+
+    let options = BenchOptions {
+        warmup_samples: 1,
+        samples: 3,
+        sample_time: std::time::Duration::from_micros(100),
+    };
+    let results = bench::run_benches(&program, None, &options)?;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].iterations_per_sample >= 1);
+    assert!(results[0].min <= results[0].median && results[0].median <= results[0].max);
+    Ok(())
+}
+
+#[test]
+fn test_compile_input_from_file() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("seppo_run_{}.seppo", std::process::id()));
+    std::fs::write(&path, "fn seppo() { return 12 }")?;
+
+    let program = compile_input(Input::File(path.clone()), &CompileOptions::default());
+    std::fs::remove_file(&path)?;
+    assert_eq!(program?.run()?, 12);
+    Ok(())
+}
+
+#[test]
+fn test_include_str_and_adjacent_strings() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("data"))?;
+    std::fs::write(dir.path().join("data/input.txt"), "line one\nline two\n")?;
+    let path = dir.path().join("main.seppo");
+    std::fs::write(
+        &path,
+        "fn seppo() {\n    s = \"ab\" \"cd\"  \"e\"\n    t = include_str(\"data/input.txt\")\n    a = len(s)\n    b = a * 100\n    c = len(t)\n    return b + c\n}\n",
+    )?;
+
+    // The path is relative to the source file, not the working directory
+    let program = Input::File(path).parse()?;
+    assert_eq!(interp::eval(&program)?, 518);
+
+    let err =
+        parse_seppo("fn seppo() {\n    return len(include_str(\"no/such.txt\"))\n}\n").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("include_str can't read no/such.txt"),
+        "{}",
+        err
+    );
+    Ok(())
+}
+
+#[test]
+fn test_stdin_input_naming() -> Result<()> {
+    let input = Input::from_arg("-");
+    assert!(matches!(input, Input::Stdin));
+    assert_eq!(input.name(), "stdin");
+    assert_eq!(
+        Driver::new(vec![input]).output_stem()?,
+        std::path::PathBuf::from("main")
+    );
+    assert!(matches!(Input::from_arg("a.seppo"), Input::File(_)));
+    Ok(())
+}
+
+#[test]
+fn test_output_naming_keeps_dots() -> Result<()> {
+    let native = TargetSpec::native();
+    let mut driver = Driver::new(vec![Input::File("dir/hello.v2.seppo".into())]);
+    let output = driver.output_path()?;
+    assert_eq!(output.stem(), Path::new("dir/hello.v2"));
+    assert_eq!(output.object(), Path::new("dir/hello.v2.o"));
+
+    driver.output = Some("app.v2".into());
+    let executable = format!("app.v2{}", std::env::consts::EXE_SUFFIX);
+    assert_eq!(
+        driver.output_path()?.executable(&native),
+        Path::new(&executable)
+    );
+    assert_eq!(driver.output_path()?.llvm_ir(), Path::new("app.v2.ll"));
+
+    // A name already ending in the extension of the output kind keeps it
+    driver.output_kind = OutputKind::Object;
+    driver.output = Some("app.o".into());
+    assert_eq!(driver.output_path()?.object(), Path::new("app.o"));
+    driver.output_kind = OutputKind::LlvmIr;
+    driver.output = Some("app.o".into());
+    assert_eq!(driver.output_path()?.llvm_ir(), Path::new("app.o.ll"));
+
+    let wasm = TargetSpec::from_triple("wasm32-wasi")?;
+    assert_eq!(
+        OutputPath::new("app").executable(&wasm),
+        Path::new("app.wasm")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_output_with_dot_builds_executable_of_that_name() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_dotted_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: "fn seppo() {\n    return 5\n}\n".to_string(),
+    }]);
+    driver.output = Some(dir.join("app.v2"));
+    let executable = driver.run()?;
+    let status = std::process::Command::new(&executable).status()?;
+    std::fs::remove_dir_all(&dir)?;
+
+    let name = format!("app.v2{}", std::env::consts::EXE_SUFFIX);
+    assert_eq!(executable, dir.join(name));
+    assert_eq!(status.code(), Some(5));
+    Ok(())
+}
+
+#[test]
+fn test_string_builtins() -> Result<()> {
+    let input = r#"
+        fn length() {
+            return len("seppo")
+        }
+
+        fn middle() {
+            s = substr("perkele", 2, 3)
+            c = char_at(s, 0)
+            n = len(s)
+            return c + n
+        }
+
+        fn out_of_range() {
+            c = char_at("abc", 3)
+            n = len(substr("abc", 5, 10))
+            return c + n
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("length", &[])?, 5);
+    assert_eq!(jit.call("middle", &[])?, 'r' as i64 + 3);
+    assert_eq!(jit.call("out_of_range", &[])?, -1);
+    Ok(())
+}
+
+#[test]
+fn test_spawn_and_join_threads() -> Result<()> {
+    let input = r#"
+        fn sum_to(n) {
+            let mut total = 0
+            for i in 0..=n {
+                total = total + i
+            }
+            return total
+        }
+
+        fn seppo() {
+            first = spawn(sum_to, 10)
+            second = spawn(sum_to, 100)
+            a = join(first)
+            b = join(second)
+            return a + b
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 55 + 5050);
+    Ok(())
+}
+
+#[test]
+fn test_globals_and_thread_locals() -> Result<()> {
+    let input = r#"
+        let mut count = 0
+        threadlocal let mut depth = 5
+        let step = 2
+
+        fn bump(n) {
+            count = count + n
+            depth = depth + step
+            return depth
+        }
+
+        fn seppo() {
+            bump(1)
+            h = spawn(bump, 10)
+            inner = join(h)
+            total = count + inner
+            return total + depth
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("@seppo.global.count = global i64 0"), "{}", ir);
+    assert!(
+        ir.contains("@seppo.global.depth = thread_local global i64 5"),
+        "{}",
+        ir
+    );
+    assert!(ir.contains("@seppo.global.step = constant i64 2"), "{}", ir);
+
+    // The spawned thread starts depth at 5 again: 11 + 7 + 7
+    assert_eq!(compile_and_run(input)?, 25);
+    assert_eq!(interp::eval(&parse_seppo(input)?)?, 25);
+    Ok(())
+}
+
+#[test]
+fn test_mutex_and_condvar() -> Result<()> {
+    let input = r#"
+        ceppo {
+            #include <stdint.h>
+
+            static int64_t counter, ready, sync_mutex, sync_cond;
+
+            void bump(void) { int64_t seen = counter; counter = seen + 1; }
+            int64_t count(void) { return counter; }
+            void set_ready(void) { ready = 1; }
+            int64_t is_ready(void) { return ready; }
+            void share(int64_t m, int64_t c) { sync_mutex = m; sync_cond = c; }
+            int64_t shared_mutex(void) { return sync_mutex; }
+            int64_t shared_cond(void) { return sync_cond; }
+        }
+
+        fn bumper(m) {
+            for i in 0..1000 {
+                lock(m)
+                bump()
+                unlock(m)
+            }
+            return 0
+        }
+
+        fn waiter(unused) {
+            m = shared_mutex()
+            c = shared_cond()
+            lock(m)
+            for i in 0..1000 {
+                seppo is_ready() == 0 {
+                    wait(c, m)
+                }
+            }
+            unlock(m)
+            return is_ready()
+        }
+
+        fn seppo() {
+            m = mutex()
+            c = condvar()
+            share(m, c)
+            w = spawn(waiter, 0)
+            a = spawn(bumper, m)
+            b = spawn(bumper, m)
+            x = join(a)
+            y = join(b)
+            lock(m)
+            set_ready()
+            notify(c)
+            unlock(m)
+            woken = join(w)
+            total = count()
+            return total + woken
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 2001);
+    Ok(())
+}
+
+const ATOMICS: &str = r#"
+    fn count_up(counter) {
+        for i in 0..1000 {
+            atomic_add(counter, 1)
+        }
+        return 0
+    }
+
+    fn seppo() {
+        counter = atomic(0)
+        a = spawn(count_up, counter)
+        b = spawn(count_up, counter)
+        x = join(a)
+        y = join(b)
+        total = atomic_load(counter)
+
+        flag = atomic(5)
+        missed = cas(flag, 4, 9)
+        swapped = cas(flag, 5, 7)
+        sum = swapped + missed
+        atomic_store(flag, sum)
+        stored = atomic_load(flag)
+        return total + stored
+    }
+"#;
+
+#[test]
+fn test_atomics() -> Result<()> {
+    let context = Context::create();
+    let program = parse_module(ATOMICS)?;
+    let jit = Jit::new(&context, &program)?;
+    assert_eq!(jit.call("seppo", &[])?, 2000 + 10);
+    assert_eq!(interp::eval(&program)?, 2010);
+    Ok(())
+}
+
+const LINKED_LIST: &str = r#"
+    fn push(list, value) {
+        node = alloc(16)
+        store(node, value)
+        next = node + 8
+        store(next, list)
+        return node
+    }
+
+    fn sum(node) {
+        seppo node == 0 {
+            return 0
+        }
+        value = load(node)
+        next = node + 8
+        rest = sum(load(next))
+        return value + rest
+    }
+
+    fn free_list(node) {
+        seppo node == 0 {
+            return 0
+        }
+        next = node + 8
+        rest = load(next)
+        free(node)
+        return free_list(rest)
+    }
+
+    fn seppo() {
+        let mut list = 0
+        for i in 1..=5 {
+            list = push(list, i)
+        }
+        total = sum(list)
+        free_list(list)
+
+        let mut array = alloc(8)
+        store(array, 100)
+        array = realloc(array, 16)
+        second = array + 8
+        store(second, 7)
+        first = load(array)
+        seven = load(second)
+        free(array)
+        both = first + seven
+        return total + both
+    }
+"#;
+
+#[test]
+fn test_heap_allocation() -> Result<()> {
+    let context = Context::create();
+    let program = parse_module(LINKED_LIST)?;
+    let jit = Jit::new(&context, &program)?;
+    assert_eq!(jit.call("seppo", &[])?, 15 + 107);
+    assert_eq!(interp::eval(&program)?, 122);
+    Ok(())
+}
+
+const LOCAL_ALLOCATIONS: &str = r#"
+    fn pair_sum(a, b) {
+        pair = alloc(sizeof(i64) * 2)
+        second = pair + 8
+        store(pair, a)
+        store(second, b)
+        sum = load(pair) + load(second)
+        free(pair)
+        return sum
+    }
+
+    fn leak(value) {
+        cell = alloc(8)
+        store(cell, value)
+        return cell
+    }
+
+    fn seppo() {
+        cell = leak(4)
+        four = load(cell)
+        free(cell)
+        return pair_sum(3, four)
+    }
+"#;
+
+#[test]
+fn test_allocations_that_dont_escape_move_to_the_stack() -> Result<()> {
+    let ir = |heap_to_stack| -> Result<String> {
+        let context = Context::create();
+        let mut codegen = CodeGen::new(&context, "main");
+        codegen.set_dump_ir(false);
+        codegen.set_opt_level(OptimizationLevel::Default);
+        codegen.set_heap_to_stack(heap_to_stack);
+        codegen.compile(&parse_seppo(LOCAL_ALLOCATIONS)?)?;
+        Ok(codegen.get_module().print_to_string().to_string())
+    };
+    let promoted = ir(true)?;
+    let pair_sum = &promoted[promoted.find("@seppo.fn.pair_sum(").unwrap()..];
+    let pair_sum = &pair_sum[..pair_sum.find("\n}").unwrap()];
+    assert!(
+        pair_sum.contains("alloca [16 x i8], align 16"),
+        "{}",
+        promoted
+    );
+    assert!(!pair_sum.contains("@malloc"), "{}", promoted);
+    assert!(!pair_sum.contains("@free"), "{}", promoted);
+    // leak returns its allocation, which stays on the heap
+    let leak = &promoted[promoted.find("@seppo.fn.leak(").unwrap()..];
+    assert!(leak.contains("call ptr @malloc(i64 8)"), "{}", promoted);
+
+    let kept = ir(false)?;
+    assert!(!kept.contains("alloca [16 x i8]"), "{}", kept);
+    assert!(kept.contains("call ptr @malloc(i64 16)"), "{}", kept);
+
+    let status = compile_str(LOCAL_ALLOCATIONS, &CompileOptions::default())?
+        .command()
+        .status()?;
+    assert_eq!(status.code(), Some(7));
+    Ok(())
+}
+
+#[test]
+fn test_allocations_stay_put_when_alloc_is_redefined() -> Result<()> {
+    // The program's own alloc runs instead of the builtin, and what it
+    // returns is no allocation the pass could move
+    let input = r#"
+        fn alloc(n) {
+            seppo n
+            return atomic(0)
+        }
+
+        fn seppo() {
+            cell = alloc(8)
+            store(cell, 7)
+            return load(cell)
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(!ir.contains("alloca [8 x i8]"), "{}", ir);
+    assert!(ir.contains("@seppo.fn.alloc(i64 8)"), "{}", ir);
+
+    let output = compile_str(input, &CompileOptions::default())?
+        .command()
+        .output()?;
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "8\n");
+    assert_eq!(output.status.code(), Some(7));
+    Ok(())
+}
+
+#[test]
+fn test_constant_time_conditionals_are_selects() -> Result<()> {
+    let input = r#"
+        #[constant_time]
+        fn pick(mask, a, b) {
+            return seppo mask == 0 {
+                a
+            }
+            perkele {
+                b
+            }
+        }
+
+        fn seppo() {
+            return pick(1, 3, 4)
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    let pick = &ir[ir.find("@seppo.fn.pick(").unwrap()..];
+    let pick = &pick[..pick.find("\n}").unwrap()];
+    assert!(pick.contains(" = select i1 "), "{}", ir);
+    assert!(!pick.contains("br i1"), "{}", ir);
+
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 4);
+    Ok(())
+}
+
+#[test]
+fn test_constant_time_overflow_checks_dont_branch() -> Result<()> {
+    let input = r#"
+        #[constant_time]
+        fn mix(a, b) {
+            c = a + b
+            d = c * a
+            return d - b
+        }
+
+        fn seppo() {
+            return mix(3, 4)
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_overflow_checks(true);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    let mix = &ir[ir.find("@seppo.fn.mix(").unwrap()..];
+    let mix = &mix[..mix.find("\n}").unwrap()];
+    assert!(mix.contains("@llvm.sadd.with.overflow.i64"), "{}", ir);
+    assert!(mix.contains("@llvm.smul.with.overflow.i64"), "{}", ir);
+    // Overflow is only checked on the way out
+    assert_eq!(mix.matches("br i1").count(), 1, "{}", ir);
+    assert!(
+        ir.contains("attempt to do arithmetic with overflow"),
+        "{}",
+        ir
+    );
+    Ok(())
+}
+
+#[test]
+fn test_bools_stay_i1_and_logical_operators_short_circuit() -> Result<()> {
+    let input = r#"
+        fn check(x) {
+            big = x > 10
+            seppo big || x < 0 && x != 3 {
+                return 1
+            }
+            return 0
+        }
+
+        fn seppo() {
+            a = check(11)
+            b = check(5)
+            return a + b
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    let check = &ir[ir.find("@seppo.fn.check(").unwrap()..];
+    let check = &check[..check.find("\n}").unwrap()];
+    assert!(check.contains("%big = alloca i1"), "{}", ir);
+    assert!(check.contains("store i1"), "{}", ir);
+    // The condition is never widened and compared with zero again
+    assert!(!check.contains("icmp ne i64"), "{}", ir);
+    // The right sides only run when the left ones don't decide
+    assert_eq!(check.matches("\nrhs").count(), 2, "{}", ir);
+    assert!(check.contains("phi i1"), "{}", ir);
+
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 1);
+    Ok(())
+}
+
+#[test]
+fn test_options_are_i128_and_unwrap_checks_for_none() -> Result<()> {
+    let input = r#"
+        fn half(n) -> option {
+            seppo n > 0 {
+                return Some(n / 2)
+            }
+            return None
+        }
+
+        fn seppo() {
+            found = half(8)
+            missing = half(0)
+            seppo is_none(missing) {
+                a = unwrap(found)
+                b = unwrap_or(missing, 10)
+                return a + b
+            }
+            return 0
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("define i128 @seppo.fn.half(i64"), "{}", ir);
+    assert!(ir.contains("%found = alloca i128"), "{}", ir);
+    // Some sets bit 64 above the value
+    assert!(ir.contains("or i128"), "{}", ir);
+    assert!(ir.contains("unwrap_none"), "{}", ir);
+
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 14);
+
+    // A conditional giving an Option from both branches merges them
+    let chosen = "fn half(n) -> option {\n    x = seppo n > 0 {\n        Some(n / 2)\n    } perkele {\n        None\n    }\n    return x\n}\n\nfn seppo() {\n    a = half(8)\n    b = half(0)\n    c = unwrap(a)\n    d = unwrap_or(b, 10)\n    return c + d\n}\n";
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.compile(&parse_seppo(chosen)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("phi i128"), "{}", ir);
+    let jit = Jit::new(&context, &parse_module(chosen)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 14);
+
+    let unwraps_none = "fn seppo() {\n    x = None\n    return unwrap(x)\n}\n";
+    let output = compile_str(unwraps_none, &CompileOptions::default())?
+        .command()
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("called unwrap on None"));
+    Ok(())
+}
+
+#[test]
+fn test_vectors_lower_to_llvm_vector_instructions() -> Result<()> {
+    let input = r#"
+        fn twice(v: i64x4) -> i64x4 {
+            doubled = v + v
+            return doubled
+        }
+
+        fn seppo() {
+            p = alloc(sizeof(i64x4))
+            store_i64x4(p, i64x4(1, 2, 3, 4))
+            v = load_i64x4(p)
+            doubled = twice(v)
+            squares = doubled * doubled
+            reversed = shuffle(squares, v, 3, 2, 1, 4)
+            free(p)
+            a = lane(reversed, 0)
+            b = lane(reversed, 3)
+            return a + b
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("define i256 @seppo.fn.twice(i256"), "{}", ir);
+    assert!(ir.contains("add <4 x i64>"), "{}", ir);
+    assert!(ir.contains("mul <4 x i64>"), "{}", ir);
+    assert!(ir.contains("shufflevector <4 x i64>"), "{}", ir);
+    assert!(ir.contains("insertelement <4 x i64>"), "{}", ir);
+    assert!(ir.contains("extractelement <4 x i64>"), "{}", ir);
+    assert!(
+        ir.contains("load <4 x i64>, ptr %pointer, align 8"),
+        "{}",
+        ir
+    );
+    assert!(ir.contains("store <4 x i64>"), "{}", ir);
+
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    // 8 * 8 from the last element of squares and 1 from the first of v
+    assert_eq!(jit.call("seppo", &[])?, 65);
+    Ok(())
+}
+
+#[test]
+fn test_unroll_hint_sets_loop_metadata() -> Result<()> {
+    let input = r#"
+        fn sum(n) {
+            let mut total = 0
+            #[unroll(4)]
+            for i in 0..n {
+                total = total + i
+            }
+            return total
+        }
+
+        fn seppo() {
+            return sum(10)
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.set_opt_level(OptimizationLevel::None);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains(", !llvm.loop !"), "{}", ir);
+    assert!(
+        ir.contains("!{!\"llvm.loop.unroll.count\", i32 4}"),
+        "{}",
+        ir
+    );
+
+    assert_eq!(compile_and_run(input)?, 45);
+    Ok(())
+}
+
+#[test]
+fn test_function_attributes() -> Result<()> {
+    let input = r#"
+        #[cold]
+        #[noinline]
+        fn slow(x) {
+            return x + 1
+        }
+
+        #[always_inline]
+        fn twice(x) {
+            y = x * 2
+            return y
+        }
+
+        #[hot]
+        fn seppo() {
+            return slow(twice(20))
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(input)?)?;
+    let module = codegen.get_module();
+    let has = |function: &str, kind: &str| {
+        module
+            .get_function(function)
+            .and_then(|f| {
+                f.get_enum_attribute(
+                    AttributeLoc::Function,
+                    Attribute::get_named_enum_kind_id(kind),
+                )
+            })
+            .is_some()
+    };
+    assert!(has("seppo.fn.slow", "cold"));
+    assert!(has("seppo.fn.slow", "noinline"));
+    assert!(has("seppo", "hot"));
+
+    // `slow` is small enough to inline, but isn't, while `twice` is
+    // inlined by LLVM
+    let ir = module.print_to_string().to_string();
+    let seppo = ir
+        .split("\ndefine ")
+        .find(|f| {
+            f.lines()
+                .next()
+                .is_some_and(|line| line.contains("@seppo("))
+        })
+        .unwrap();
+    let seppo = &seppo[..seppo.find("\n}").unwrap()];
+    assert!(seppo.contains("@seppo.fn.slow("), "{}", ir);
+    assert!(!seppo.contains("@seppo.fn.twice("), "{}", ir);
+
+    assert_eq!(compile_and_run(input)?, 41);
+    Ok(())
+}
+
+#[test]
+fn test_sleep_and_yield() -> Result<()> {
+    let input = r#"
+        fn setter(flag) {
+            sleep_ms(20)
+            atomic_store(flag, 1)
+            return 0
+        }
+
+        fn seppo() {
+            flag = atomic(0)
+            thread = spawn(setter, flag)
+            sleep_ms(0 - 5)
+            let mut waited = 0
+            for i in 0..1000 {
+                seen = atomic_load(flag)
+                seppo seen == 0 {
+                    yield()
+                    sleep_ms(1)
+                    waited = waited + 1
+                }
+            }
+            done = join(thread)
+            seppo waited > 0 {
+                return atomic_load(flag)
+            }
+            return 0
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    let start = std::time::Instant::now();
+    assert_eq!(jit.call("seppo", &[])?, 1);
+    assert!(start.elapsed() >= std::time::Duration::from_millis(20));
+    Ok(())
+}
+
+#[test]
+fn test_dlopen_and_call() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            lib = dlopen(0)
+            labs = dlsym(lib, "labs")
+            missing = dlsym(lib, "seppo_no_such_symbol")
+            seppo missing == 0 {
+                return call(labs, 0 - 42)
+            }
+            return 0
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 42);
+
+    let no_function = "fn seppo() {\n    return call()\n}\n";
+    let err = Jit::new(&context, &parse_module(no_function)?)
+        .err()
+        .expect("call without a function");
+    assert_eq!(
+        err.to_string(),
+        "Builtin call expects at least 1 arguments, got 0"
+    );
+    Ok(())
+}
+
+const INTROSPECTION: &str = r#"
+    fn seppo() {
+        s = "seppo"
+        n = len(s)
+        word = sizeof(i64)
+        node = alloc(word * 2)
+        free(node)
+        let mut small = sizeof(i32)
+        byte = sizeof(u8)
+        small = small + byte
+        let mut kinds = typeof(s)
+        kinds = kinds * 10
+        int_kind = typeof(n)
+        total = kinds + int_kind
+        return total + small
+    }
+"#;
+
+#[test]
+fn test_sizeof_and_typeof() -> Result<()> {
+    let context = Context::create();
+    let program = parse_module(INTROSPECTION)?;
+    let jit = Jit::new(&context, &program)?;
+    assert_eq!(jit.call("seppo", &[])?, 15);
+    assert_eq!(interp::eval(&program)?, 15);
+
+    let struct_type = "fn seppo() {\n    return sizeof(Point)\n}\n";
+    let err = parse_module(struct_type).expect_err("sizeof of an unknown type");
+    assert_eq!(
+        err.to_string(),
+        "sizeof: unknown type Point, expected one of i8, u8, i16, u16, i32, u32, i64, u64, int, ptr, string, i64x4"
+    );
+
+    // A parameter may be passed either
+    let parameter = "fn kind(x) {\n    return typeof(x)\n}\n";
+    let err = parse_module(parameter).expect_err("typeof of a parameter");
+    assert_eq!(
+        err.to_string(),
+        "typeof can't tell the type of x at compile time, it may be an integer or a string"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_spawn_needs_a_function_of_one_argument() -> Result<()> {
+    let context = Context::create();
+    let not_a_function = "fn seppo() {\n    h = spawn(1, 2)\n    return join(h)\n}\n";
+    let err = Jit::new(&context, &parse_module(not_a_function)?)
+        .err()
+        .expect("spawn of a number");
+    assert_eq!(
+        err.to_string(),
+        "The first argument of spawn must be the name of a function"
+    );
+
+    let two_params = "fn add(a, b) {\n    return a + b\n}\n\nfn seppo() {\n    h = spawn(add, 2)\n    return join(h)\n}\n";
+    let err = Jit::new(&context, &parse_module(two_params)?)
+        .err()
+        .expect("spawn of a two-argument function");
+    assert_eq!(
+        err.to_string(),
+        "spawn runs functions taking one argument, but add takes 2"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_string_literals_are_interned() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            a = len("hyvää päivää")
+            b = len("hyvää päivää")
+            seppo a
+            seppo b
+            return a + b
+        }
+    "#;
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    // One for the literal, one for the print format
+    assert_eq!(
+        ir.matches("private unnamed_addr constant").count(),
+        2,
+        "{}",
+        ir
+    );
+    assert!(
+        ir.contains("c\"hyv\\C3\\A4\\C3\\A4 p\\C3\\A4iv\\C3\\A4\\C3\\A4\\00\""),
+        "{}",
+        ir
+    );
+
+    // `len` counts bytes, two for each ä
+    let jit = Jit::new(&context, &parse_seppo(input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 34);
+
+    let nul = SeppoExpr::Block(vec![SeppoExpr::Function(
+        "seppo".to_string(),
+        Vec::new(),
+        Box::new(SeppoExpr::Block(vec![SeppoExpr::Return(Box::new(
+            SeppoExpr::FunctionCall(
+                "len".to_string(),
+                vec![SeppoExpr::String("a\0b".to_string())],
+            ),
+        ))])),
+    )]);
+    let mut codegen = CodeGen::new(&context, "nul");
+    codegen.set_dump_ir(false);
+    assert!(matches!(codegen.compile(&nul), Err(SeppoError::Sema(_))));
+    Ok(())
+}
+
+#[test]
+fn test_for_loop_codegen() -> Result<()> {
+    let input = r#"
+        fn count_l() {
+            let mut n = 0
+            for c in "hello world" {
+                seppo c == 108 {
+                    n = n + 1
+                }
+            }
+            return n
+        }
+
+        fn first_space() {
+            let mut i = 0
+            for c in "seppo lang" {
+                seppo c == 32 {
+                    return i
+                }
+                i = i + 1
+            }
+            return 0 - 1
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("count_l", &[])?, 3);
+    assert_eq!(jit.call("first_space", &[])?, 5);
+    Ok(())
+}
+
+#[test]
+fn test_tail_calls_codegen() -> Result<()> {
+    // Deep enough to overflow the stack if the calls weren't loops
+    let input = r#"
+        fn count(n, acc) {
+            seppo n > 0 {
+                m = n - 1
+                k = acc + 2
+                return count(m, k)
+            } perkele {
+                return acc
+            }
+        }
+
+        fn sum(n, acc) {
+            seppo n == 0 {
+                return acc
+            }
+            become sum(n - 1, acc + n)
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("count", &[10_000_000, 0])?, 20_000_000);
+    assert_eq!(jit.call("sum", &[10_000_000, 0])?, 50_000_005_000_000);
+    Ok(())
+}
+
+#[test]
+fn test_range_codegen() -> Result<()> {
+    let input = r#"
+        fn sum_to(n) {
+            let mut sum = 0
+            for i in 1..=n {
+                sum = sum + i
+            }
+            for i in 0..0 {
+                sum = sum + 1000
+            }
+            return sum
+        }
+
+        fn digit(x) {
+            seppo x in 0..10 {
+                return 1
+            }
+            return 0
+        }
+
+        fn max_inclusive(x) {
+            seppo x in 0..=9223372036854775807 {
+                return 1
+            }
+            return 0
+        }
+    "#;
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(input)?)?;
+    assert_eq!(jit.call("sum_to", &[4])?, 10);
+    assert_eq!(jit.call("sum_to", &[0])?, 0);
+    assert_eq!(jit.call("digit", &[9])?, 1);
+    assert_eq!(jit.call("digit", &[10])?, 0);
+    assert_eq!(jit.call("digit", &[-1])?, 0);
+    assert_eq!(jit.call("max_inclusive", &[i64::MAX])?, 1);
+    Ok(())
+}
+
+const MANGLING: &str = r#"
+    fn abs(x) {
+        return 100
+    }
+
+    export fn twice(x) {
+        return x * 2
+    }
+
+    fn seppo() {
+        a = abs(1)
+        b = twice(a)
+        return b
+    }
+"#;
+
+#[test]
+fn test_functions_are_mangled_unless_exported() -> Result<()> {
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(MANGLING)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    // `abs` can't be mistaken for the one in libc
+    assert!(ir.contains("define i64 @seppo.fn.abs(i64 %0)"), "{}", ir);
+    assert!(ir.contains("define i64 @twice(i64 %0)"), "{}", ir);
+    assert!(ir.contains("define i64 @seppo()"), "{}", ir);
+
+    // Functions are still called by their seppo names
+    let jit = Jit::new(&context, &parse_module(MANGLING)?)?;
+    assert_eq!(jit.call("abs", &[-3])?, 100);
+    assert_eq!(jit.call("seppo", &[])?, 200);
+    Ok(())
+}
+
+const SIGNATURES: &str = r#"
+    fn pick(first: string, second: string, take_first: int) -> string {
+        seppo take_first == 1 {
+            return first
+        }
+        return second
+    }
+
+    fn length(s: string) -> int {
+        return len(s)
+    }
+
+    fn seppo() {
+        word = pick("seppo", "perkele", 0)
+        return length(word)
+    }
+"#;
+
+#[test]
+fn test_signatures_give_function_types() -> Result<()> {
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(SIGNATURES)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    // Strings are passed and returned as pointers
+    assert!(
+        ir.contains("define ptr @seppo.fn.pick(ptr %0, ptr %1, i64 %2)"),
+        "{}",
+        ir
+    );
+    assert!(ir.contains("define i64 @seppo.fn.length(ptr %0)"), "{}", ir);
+
+    let program = parse_module(SIGNATURES)?;
+    let jit = Jit::new(&context, &program)?;
+    assert_eq!(jit.call("seppo", &[])?, 7);
+    assert_eq!(interp::eval(&program)?, 7);
+    Ok(())
+}
+
+const INVARIANT: &str = r#"
+    fn sum(n, k) {
+        let mut total = 0
+        for i in 0..n {
+            m = k * 3
+            total = total + m
+        }
+        return total
+    }
+
+    fn seppo() {
+        return sum(4, 5)
+    }
+"#;
+
+#[test]
+fn test_pass_presets_optimize_the_module() -> Result<()> {
+    TargetSpec::native().initialize()?;
+    let ir = |passes| -> Result<String> {
+        let context = Context::create();
+        let mut codegen = CodeGen::new(&context, "main");
+        codegen.set_dump_ir(false);
+        codegen.set_options(CodeGenOptions { passes });
+        codegen.compile(&parse_seppo(INVARIANT)?)?;
+        let ir = codegen.get_module().print_to_string().to_string();
+        let start = ir.find("define i64 @seppo.fn.sum").unwrap();
+        Ok(ir[start..].split("\n}\n").next().unwrap().to_string())
+    };
+    // Without passes the variables live on the stack
+    assert!(ir(PassPreset::None)?.contains("alloca"));
+
+    let sum = ir(PassPreset::Default)?;
+    assert!(!sum.contains("alloca"), "{}", sum);
+    // k * 3 is computed once, before the loop
+    let mul = sum.find("mul i64").unwrap();
+    let phi = sum.find("phi i64").unwrap();
+    assert!(mul < phi, "{}", sum);
+
+    for passes in [PassPreset::Default, PassPreset::Aggressive] {
+        let context = Context::create();
+        let mut codegen = CodeGen::new(&context, "jit");
+        codegen.set_dump_ir(false);
+        codegen.set_options(CodeGenOptions { passes });
+        codegen.compile_library(&parse_module(INVARIANT)?)?;
+        let jit = Jit::from_codegen(codegen)?;
+        assert_eq!(jit.call("sum", &[4, 5])?, 60);
+        assert_eq!(jit.call("sum", &[0, 5])?, 0);
+    }
+    Ok(())
+}
+
+const INLINING: &str = r#"
+    fn square(x) {
+        return x * x
+    }
+
+    fn add(a, b) {
+        return a + b
+    }
+
+    inline fn mix(a, b) {
+        return add(a * 3, b * 5)
+    }
+
+    fn seppo() {
+        n = 6
+        s = square(n)
+        m = mix(s, 2)
+        return m
+    }
+"#;
+
+#[test]
+fn test_small_functions_are_inlined_when_optimizing() -> Result<()> {
+    let ir = |level| -> Result<String> {
+        let context = Context::create();
+        let mut codegen = CodeGen::new(&context, "main");
+        codegen.set_dump_ir(false);
+        codegen.set_opt_level(level);
+        codegen.compile(&parse_seppo(INLINING)?)?;
+        Ok(codegen.get_module().print_to_string().to_string())
+    };
+    let optimized = ir(OptimizationLevel::Default)?;
+    assert!(
+        !optimized.contains("call i64 @seppo.fn.square"),
+        "{}",
+        optimized
+    );
+    assert!(
+        !optimized.contains("call i64 @seppo.fn.mix"),
+        "{}",
+        optimized
+    );
+    assert!(
+        !optimized.contains("call i64 @seppo.fn.add"),
+        "{}",
+        optimized
+    );
+    // The functions stay defined for other callers
+    assert!(
+        optimized.contains("define i64 @seppo.fn.mix("),
+        "{}",
+        optimized
+    );
+    let unoptimized = ir(OptimizationLevel::None)?;
+    assert!(
+        unoptimized.contains("call i64 @seppo.fn.square"),
+        "{}",
+        unoptimized
+    );
+
+    let context = Context::create();
+    let jit = Jit::new(&context, &parse_module(INLINING)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 118);
+    assert_eq!(jit.call("mix", &[1, 1])?, 8);
+    Ok(())
+}
+
+#[test]
+fn test_inline_fn_must_be_a_single_return() {
+    let err = parse_seppo(
+        r#"
+        inline fn twice(x) {
+            y = x * 2
+            return y
+        }
+
+        fn seppo() {
+            return twice(2)
+        }
+    "#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "inline fn twice can't be inlined, its body must be a single return of an expression of its parameters"
+    );
+}
+
+#[test]
+fn test_ceppo_calls_use_c_types() -> Result<()> {
+    let ceppo = r#"
+        ceppo {
+            int8_t neg(int8_t x) { return -x; }
+
+            // Counts bytes like strlen
+            size_t length(const char *s) {
+                size_t n = 0;
+                while (s[n]) n++;
+                return n;
+            }
+
+            double half(double x) { return x / 2; }
+        }
+    "#;
+    let input = format!(
+        "{}\nfn seppo() {{\n    a = length(\"hello\")\n    b = neg(200)\n    return a + b\n}}\n",
+        ceppo
+    );
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "main");
+    codegen.set_dump_ir(false);
+    codegen.compile(&parse_seppo(&input)?)?;
+    let ir = codegen.get_module().print_to_string().to_string();
+    assert!(ir.contains("declare signext i8 @neg(i8 signext)"), "{}", ir);
+    assert!(ir.contains("declare i64 @length(ptr)"), "{}", ir);
+    assert!(!ir.contains("ptrtoint"), "{}", ir);
+
+    // 200 wraps to -56 as an int8_t
+    let jit = Jit::new(&context, &parse_seppo(&input)?)?;
+    assert_eq!(jit.call("seppo", &[])?, 61);
+
+    for (call, message) in [
+        (
+            "length(\"a\", 2)",
+            "Function length expects 1 arguments, got 2",
+        ),
+        (
+            "neg(\"a\")",
+            "Argument 1 of neg is a string, but the C function takes an integer",
+        ),
+        (
+            "half(4)",
+            "C function half uses the type double, which seppo cannot pass or return",
+        ),
+    ] {
+        let input = format!("{}\nfn seppo() {{\n    return {}\n}}\n", ceppo, call);
+        let mut codegen = CodeGen::new(&context, "main");
+        codegen.set_dump_ir(false);
+        match codegen.compile(&parse_seppo(&input)?) {
+            Err(SeppoError::Sema(error)) => assert_eq!(error, message),
+            other => panic!("expected a Sema error for {}, got {:?}", call, other.err()),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_verification_failure_names_function_and_quotes_ir() -> Result<()> {
+    // Nested functions don't parse, but an AST can contain one. Codegen
+    // leaves the builder inside the inner function, producing invalid IR.
+    let function = |name: &str, body: Vec<SeppoExpr>| {
+        SeppoExpr::Function(
+            name.to_string(),
+            Vec::new(),
+            Box::new(SeppoExpr::Block(body)),
+        )
+    };
+    let location = SeppoExpr::Location {
+        file: "nested.seppo".to_string(),
+        line: 2,
+    };
+    let program = SeppoExpr::Block(vec![function(
+        "seppo",
+        vec![
+            location,
+            function(
+                "inner",
+                vec![SeppoExpr::Return(Box::new(SeppoExpr::Number(1)))],
+            ),
+            SeppoExpr::Return(Box::new(SeppoExpr::Number(2))),
+        ],
+    )]);
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "nested");
+    codegen.set_dump_ir(false);
+    match codegen.compile(&program) {
+        Err(SeppoError::Codegen(message)) => {
+            assert!(
+                message.starts_with(
+                    "Module verification failed in function seppo at nested.seppo:2: "
+                ),
+                "{}",
+                message
+            );
+            assert!(message.contains("does not have terminator"), "{}", message);
+            assert!(message.contains("> start:"), "{}", message);
+            assert!(message.contains("please file an issue"), "{}", message);
+        }
+        other => panic!("expected a codegen error, got {:?}", other),
+    }
+    Ok(())
+}
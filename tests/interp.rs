@@ -0,0 +1,462 @@
+use anyhow::Result;
+use seppolang::interp::Interpreter;
+use seppolang::{parse_seppo, DivisionOverflow, DivisionRounding};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Collects interpreter output so tests can assert on it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn interpret(input: &str) -> Result<(i64, String)> {
+    let expr = parse_seppo(input)?;
+    let buffer = SharedBuffer::default();
+    let mut interpreter = Interpreter::with_output(Box::new(buffer.clone()));
+    let result = interpreter.run(&expr)?;
+    let output = String::from_utf8(buffer.0.borrow().clone())?;
+    Ok((result, output))
+}
+
+#[test]
+fn test_interp_return() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            return 42
+        }
+    "#;
+    assert_eq!(interpret(input)?.0, 42);
+    Ok(())
+}
+
+#[test]
+fn test_interp_implicit_return() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            x = 42
+        }
+    "#;
+    assert_eq!(interpret(input)?.0, 0);
+    Ok(())
+}
+
+#[test]
+fn test_interp_trailing_expression() -> Result<()> {
+    let input = r#"
+        fn add(a, b) { a + b }
+
+        fn seppo() {
+            seppo add(1, 2)
+            add(40, 2)
+        }
+    "#;
+    assert_eq!(interpret(input)?, (42, "3\n".to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_interp_statement_separators() -> Result<()> {
+    let input = r#"
+        fn add(a,
+               b) {
+            return a + b
+        }
+
+        fn seppo() {
+            x = 1; y = 2;
+            seppo add(
+                x,
+                y
+            )
+            return 0
+        }
+    "#;
+    assert_eq!(interpret(input)?.1, "3\n");
+    Ok(())
+}
+
+#[test]
+fn test_interp_unicode_names() -> Result<()> {
+    let input = r#"
+        fn kerro(määrä, kertoja) {
+            return määrä * kertoja
+        }
+
+        fn seppo() {
+            määrä = 6
+            for_ = 7
+            seppo kerro(määrä, for_)
+            return 0
+        }
+    "#;
+    assert_eq!(interpret(input)?.1, "42\n");
+    Ok(())
+}
+
+#[test]
+fn test_interp_print_formats() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            x = 40 + 2
+            seppo x
+            0xseppo x
+            return 0
+        }
+    "#;
+    assert_eq!(interpret(input)?, (0, "42\n0x2a\n".to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_interp_prints_booleans_and_characters() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            boolseppo 3
+            boolseppo 0
+            charseppo 65
+            charseppo 228
+            charseppo 55296
+            return 0
+        }
+    "#;
+    assert_eq!(interpret(input)?.1, "true\nfalse\nA\nä\n\u{FFFD}\n");
+    Ok(())
+}
+
+#[test]
+fn test_interp_eseppo_prints_to_errors() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            seppo 1
+            eseppo 2
+            return 0
+        }
+    "#;
+    let output = SharedBuffer::default();
+    let errors = SharedBuffer::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()))
+        .with_error_output(Box::new(errors.clone()));
+    interpreter.run(&parse_seppo(input)?)?;
+    assert_eq!(String::from_utf8(output.0.borrow().clone())?, "1\n");
+    assert_eq!(String::from_utf8(errors.0.borrow().clone())?, "2\n");
+    Ok(())
+}
+
+#[test]
+fn test_interp_flush() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            seppo 1
+            return flush()
+        }
+    "#;
+    assert_eq!(interpret(input)?, (0, "1\n".to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_interp_division_modes() -> Result<()> {
+    let program = parse_seppo(
+        r#"
+        fn seppo() {
+            a = 0 - 7
+            return a / 2
+        }
+    "#,
+    )?;
+    assert_eq!(Interpreter::new().run(&program)?, -3);
+    let mut flooring = Interpreter::new().with_division(DivisionRounding::Floor, None);
+    assert_eq!(flooring.run(&program)?, -4);
+
+    let overflow = parse_seppo(
+        r#"
+        fn seppo() {
+            a = 0 - 9223372036854775807
+            min = a - 1
+            b = 0 - 1
+            return min / b
+        }
+    "#,
+    )?;
+    assert_eq!(Interpreter::new().run(&overflow)?, i64::MIN);
+    let err = Interpreter::new()
+        .with_division(DivisionRounding::Truncate, Some(DivisionOverflow::Trap))
+        .run(&overflow)
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Division overflow");
+    Ok(())
+}
+
+#[test]
+fn test_interp_conditional() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            let mut x = 30
+            seppo x < 40 {
+                x = 1
+            }
+            perkele {
+                x = 0
+            }
+            return x
+        }
+    "#;
+    assert_eq!(interpret(input)?.0, 1);
+    Ok(())
+}
+
+#[test]
+fn test_interp_options() -> Result<()> {
+    let input = r#"
+        fn find(s, c) -> option {
+            for i in 0..len(s) {
+                seppo char_at(s, i) == c {
+                    return Some(i)
+                }
+            }
+            return None
+        }
+
+        fn seppo() {
+            found = find("seppo", 112)
+            missing = find("seppo", 120)
+            seppo unwrap(found)
+            seppo unwrap_or(missing, 99)
+            seppo is_some(missing) || is_none(found) {
+                return 1
+            }
+            kind = typeof(found)
+            return kind
+        }
+    "#;
+    assert_eq!(interpret(input)?, (3, "2\n99\n".to_string()));
+
+    // A conditional gives an Option when both branches do
+    let input = "fn half(n) -> option {\n    x = seppo n > 0 {\n        Some(n / 2)\n    } perkele {\n        None\n    }\n    return x\n}\n\nfn seppo() {\n    a = half(8)\n    b = half(0)\n    c = unwrap(a)\n    d = unwrap_or(b, 10)\n    return c + d\n}\n";
+    assert_eq!(interpret(input)?.0, 14);
+
+    let input = "fn seppo() {\n    x = None\n    return unwrap(x)\n}\n";
+    assert_eq!(
+        interpret(input).unwrap_err().to_string(),
+        "called unwrap on None"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_interp_vectors() -> Result<()> {
+    let input = r#"
+        fn scale(v: i64x4, n) -> i64x4 {
+            factors = i64x4(n, n, n, n)
+            scaled = v * factors
+            return scaled
+        }
+
+        fn seppo() {
+            p = alloc(sizeof(i64x4))
+            store_i64x4(p, i64x4(1, 2, 3, 4))
+            v = load_i64x4(p)
+            scaled = scale(v, 10)
+            ones = i64x4(1, 1, 1, 1)
+            sum = scaled + ones
+            difference = sum - v
+            mixed = shuffle(difference, v, 7, 0, 5, 2)
+            store_i64x4(p, mixed)
+            free(p)
+            seppo lane(mixed, 0)
+            seppo lane(mixed, 1)
+            seppo lane(mixed, 2)
+            seppo lane(mixed, 3)
+            kind = typeof(mixed)
+            return kind
+        }
+    "#;
+    assert_eq!(interpret(input)?, (4, "4\n10\n2\n28\n".to_string()));
+
+    let input = "fn seppo() {\n    v = i64x4(9223372036854775807, 0, 0, 0)\n    w = v + v\n    return lane(w, 0)\n}\n";
+    assert_eq!(interpret(input)?.0, -2);
+
+    let input = "fn seppo() {\n    p = alloc(16)\n    v = load_i64x4(p)\n    return 0\n}\n";
+    assert!(interpret(input)
+        .unwrap_err()
+        .to_string()
+        .contains("load_i64x4"));
+    Ok(())
+}
+
+#[test]
+fn test_interp_logical_operators_short_circuit() -> Result<()> {
+    let input = r#"
+        fn loud(n) {
+            seppo n
+            return n
+        }
+
+        fn seppo() {
+            let mut x = 0
+            seppo x > 0 && loud(1) > 0 {
+                x = 10
+            }
+            seppo x == 0 || loud(2) > 0 {
+                x = x + 1
+            }
+            seppo x < 0 || loud(3) > 0 && x >= 1 {
+                x = x + 1
+            }
+            done = x >= 2
+            seppo done {
+                kind = typeof(done)
+                x = x + kind
+            }
+            return x
+        }
+    "#;
+    assert_eq!(interpret(input)?, (4, "3\n".to_string()));
+    Ok(())
+}
+
+#[test]
+fn test_interp_rejects_ceppo() {
+    let input = r#"
+        ceppo {
+            long my_rand() {
+                return 42;
+            }
+        }
+
+        fn seppo() {
+            return my_rand()
+        }
+    "#;
+    let err = interpret(input).unwrap_err();
+    assert!(err.to_string().contains("ceppo"));
+}
+
+#[test]
+#[should_panic(expected = "Undefined variable")]
+fn test_interp_undefined_variable() {
+    let input = r#"
+        fn seppo() {
+            return x
+        }
+    "#;
+    interpret(input).unwrap();
+}
+
+#[test]
+fn test_interp_string_builtins() -> Result<()> {
+    let input = r#"
+        fn seppo() {
+            s = substr("perkele", 2, 3)
+            seppo len(s)
+            seppo char_at(s, 0)
+            seppo char_at(s, 10)
+            start = 0 - 1
+            seppo len(substr(s, start, 100))
+            return 0
+        }
+    "#;
+    let (result, output) = interpret(input)?;
+    assert_eq!(result, 0);
+    assert_eq!(output, "3\n114\n-1\n3\n");
+    Ok(())
+}
+
+#[test]
+fn test_interp_spawn_and_join() -> Result<()> {
+    let input = r#"
+        fn double(x) {
+            seppo x
+            return x * 2
+        }
+
+        fn seppo() {
+            h = spawn(double, 21)
+            seppo 1
+            return join(h)
+        }
+    "#;
+    // Spawned functions run right away in the interpreter
+    let (result, output) = interpret(input)?;
+    assert_eq!(result, 42);
+    assert_eq!(output, "21\n1\n");
+
+    let joined_twice = "fn one(x) {\n    return 1\n}\n\nfn seppo() {\n    h = spawn(one, 0)\n    a = join(h)\n    return join(h)\n}\n";
+    let err = interpret(joined_twice).unwrap_err();
+    assert_eq!(err.to_string(), "No thread to join with handle 0");
+    Ok(())
+}
+
+#[test]
+fn test_interp_globals() -> Result<()> {
+    let input = r#"
+        let mut count = 0
+        threadlocal let mut depth = 5
+
+        fn bump(n) {
+            count = count + n
+            depth = depth + 1
+            return depth
+        }
+
+        fn seppo() {
+            bump(2)
+            h = spawn(bump, 10)
+            seppo join(h)
+            seppo depth
+            return count
+        }
+    "#;
+    // The spawned call starts depth over and keeps what it did to it
+    let (result, output) = interpret(input)?;
+    assert_eq!(result, 12);
+    assert_eq!(output, "6\n6\n");
+    Ok(())
+}
+
+#[test]
+fn test_interp_heap_errors() -> Result<()> {
+    let past_the_end = "fn seppo() {\n    p = alloc(8)\n    q = p + 8\n    return load(q)\n}\n";
+    assert_eq!(
+        interpret(past_the_end).unwrap_err().to_string(),
+        "load: 0x10008 is not inside an allocation"
+    );
+    let freed_twice = "fn seppo() {\n    p = alloc(8)\n    free(p)\n    return free(p)\n}\n";
+    assert_eq!(
+        interpret(freed_twice).unwrap_err().to_string(),
+        "free: 0x10000 is not the start of an allocation"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_interp_mutex() -> Result<()> {
+    let input =
+        "fn seppo() {\n    m = mutex()\n    lock(m)\n    unlock(m)\n    lock(m)\n    return m\n}\n";
+    assert_eq!(interpret(input)?.0, 0);
+
+    // Nothing else could unlock or notify
+    let locked_twice = "fn seppo() {\n    m = mutex()\n    lock(m)\n    return lock(m)\n}\n";
+    assert_eq!(
+        interpret(locked_twice).unwrap_err().to_string(),
+        "lock: mutex 0 is already locked, which without threads would wait forever"
+    );
+    let waits =
+        "fn seppo() {\n    m = mutex()\n    c = condvar()\n    lock(m)\n    return wait(c, m)\n}\n";
+    assert_eq!(
+        interpret(waits).unwrap_err().to_string(),
+        "wait: without threads nothing can notify, so this would wait forever"
+    );
+    Ok(())
+}
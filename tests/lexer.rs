@@ -0,0 +1,99 @@
+use anyhow::Result;
+use seppolang::lexer::{tokenize, TokenKind};
+use seppolang::{Driver, Input, OutputKind};
+
+#[test]
+fn test_tokenize_program() {
+    let source = "## Doubles\n#[allow(unused-function)]\nfn double(x) {\n    y = x * 2 // twice\n    for i in 0..=3 {\n        0xseppo \"ä\"\n    }\n    return y\n}\n\nceppo {\n    int one(void) { return 1; }\n}\n";
+    let tokens = tokenize(source);
+    let kinds: Vec<(TokenKind, &str)> = tokens
+        .iter()
+        .map(|token| (token.kind, token.text.as_str()))
+        .collect();
+    use TokenKind::*;
+    assert_eq!(
+        kinds,
+        [
+            (DocComment, "## Doubles"),
+            (Attribute, "#[allow(unused-function)]"),
+            (Keyword, "fn"),
+            (Identifier, "double"),
+            (Punctuation, "("),
+            (Identifier, "x"),
+            (Punctuation, ")"),
+            (Punctuation, "{"),
+            (Identifier, "y"),
+            (Operator, "="),
+            (Identifier, "x"),
+            (Operator, "*"),
+            (Number, "2"),
+            (Comment, "// twice"),
+            (Keyword, "for"),
+            (Identifier, "i"),
+            (Keyword, "in"),
+            (Number, "0"),
+            (Operator, "..="),
+            (Number, "3"),
+            (Punctuation, "{"),
+            (Keyword, "0xseppo"),
+            (String, "\"ä\""),
+            (Punctuation, "}"),
+            (Keyword, "return"),
+            (Identifier, "y"),
+            (Punctuation, "}"),
+            (Keyword, "ceppo"),
+            (Punctuation, "{"),
+            (CCode, "\n    int one(void) { return 1; }\n"),
+            (Punctuation, "}"),
+        ]
+    );
+
+    // Spans index the source, lines and columns count from 1
+    let string = &tokens[22];
+    assert_eq!(&source[string.span.start..string.span.end], "\"ä\"");
+    assert_eq!((string.line, string.column), (6, 17));
+    let ceppo = &tokens[27];
+    assert_eq!((ceppo.line, ceppo.column), (11, 1));
+}
+
+#[test]
+fn test_tokenize_does_not_need_valid_syntax() {
+    let tokens = tokenize("fn seppo( {\n    x = 1 + 2 + @\n\"open");
+    let last: Vec<(TokenKind, &str)> = tokens
+        .iter()
+        .rev()
+        .take(3)
+        .map(|token| (token.kind, token.text.as_str()))
+        .collect();
+    assert_eq!(
+        last,
+        [
+            (TokenKind::String, "\"open"),
+            (TokenKind::Unknown, "@"),
+            (TokenKind::Operator, "+"),
+        ]
+    );
+}
+
+#[test]
+fn test_emit_tokens() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "broken.seppo".to_string(),
+        text: "fn seppo() {\n    return 1 +\n".to_string(),
+    }]);
+    driver.output_kind = OutputKind::Tokens;
+    driver.output = Some(dir.path().join("broken"));
+    let path = driver.run()?;
+    assert_eq!(path, dir.path().join("broken.tokens"));
+    let tokens = std::fs::read_to_string(&path)?;
+    assert!(
+        tokens.starts_with("broken.seppo:1:1 keyword \"fn\"\nbroken.seppo:1:4 keyword \"seppo\"\n")
+    );
+    assert!(
+        tokens.ends_with("broken.seppo:2:14 operator \"+\"\n"),
+        "{}",
+        tokens
+    );
+    Ok(())
+}
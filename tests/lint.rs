@@ -0,0 +1,238 @@
+use seppolang::lint::{lint, Level, LintConfig, LintDiagnostic};
+use seppolang::{parse_seppo, SeppoError};
+
+fn lint_source(input: &str, config: &LintConfig) -> Vec<LintDiagnostic> {
+    lint(&parse_seppo(input).unwrap(), config)
+}
+
+fn names(diagnostics: &[LintDiagnostic]) -> Vec<&str> {
+    diagnostics.iter().map(|d| d.lint).collect()
+}
+
+#[test]
+fn test_default_lints() {
+    let input = r#"
+        fn helper() {
+            return 1
+        }
+
+        fn seppo() {
+            unused = 5
+            helper = 1
+            seppo 2 > 1 {
+                seppo helper
+            }
+            return 0
+        }
+    "#;
+    let diagnostics = lint_source(input, &LintConfig::new());
+    assert_eq!(
+        names(&diagnostics),
+        vec![
+            "unused-function",
+            "unused-variable",
+            "shadowed-variable",
+            "constant-condition"
+        ]
+    );
+    assert!(diagnostics.iter().all(|d| d.level == Level::Warn));
+    assert!(diagnostics[1].message.contains("`unused`"));
+    assert_eq!(diagnostics[1].function, "seppo");
+}
+
+#[test]
+fn test_assigned_global_is_used() {
+    let input = r#"
+        let mut calls = 0
+
+        fn count() {
+            calls = calls + 1
+        }
+
+        fn reset() {
+            calls = 0
+        }
+
+        fn seppo() {
+            count()
+            reset()
+            return calls
+        }
+    "#;
+    assert!(lint_source(input, &LintConfig::new()).is_empty());
+}
+
+#[test]
+fn test_unused_function() {
+    let input = r#"
+        ceppo {
+            int64_t from_c(void) { return called_from_c(); }
+        }
+
+        fn called_from_c() {
+            return 1
+        }
+
+        fn used() {
+            return 2
+        }
+
+        fn countdown(n) {
+            seppo n > 0 {
+                return countdown(n - 1)
+            }
+            return 0
+        }
+
+        fn testi_used() {
+            x = used()
+            return x - 2
+        }
+
+        fn spawned(x) {
+            return x
+        }
+
+        export fn called_from_outside() {
+            return 3
+        }
+
+        fn demo_seppo() {
+            return 4
+        }
+
+        fn seppo() {
+            thread = spawn(spawned, 0)
+            return join(thread)
+        }
+    "#;
+    let diagnostics = lint_source(input, &LintConfig::new());
+    // Calling itself does not count
+    assert_eq!(names(&diagnostics), vec!["unused-function"]);
+    assert_eq!(diagnostics[0].function, "countdown");
+    assert_eq!(
+        diagnostics[0].message,
+        "function `countdown` is never called"
+    );
+}
+
+#[test]
+fn test_allow_attribute() {
+    let input = r#"
+        ## Kept for later
+        #[allow(unused-function, unused_variable)]
+        fn spare() {
+            x = 1
+            return 0
+        }
+
+        fn other() {
+            y = 1
+            return 0
+        }
+
+        fn seppo() {
+            return 0
+        }
+    "#;
+    let diagnostics = lint_source(input, &LintConfig::new());
+    assert_eq!(
+        names(&diagnostics),
+        vec!["unused-function", "unused-variable"]
+    );
+    assert!(diagnostics.iter().all(|d| d.function == "other"));
+
+    for (attribute, message) in [
+        ("#[allow(no-such-lint)]", "Unknown lint: no-such-lint"),
+        ("#[allow]", "allow needs the lints to allow"),
+        ("#[inline]", "Unknown attribute: inline"),
+    ] {
+        let input = format!("{}\nfn seppo() {{\n    return 0\n}}\n", attribute);
+        match parse_seppo(&input) {
+            Err(SeppoError::Parse {
+                message: m, line, ..
+            }) => {
+                assert!(m.starts_with(message), "{}", m);
+                assert_eq!(line, 1);
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_magic_number_is_opt_in() {
+    let input = r#"
+        fn seppo() {
+            x = 7
+            return x * 60
+        }
+    "#;
+    assert!(lint_source(input, &LintConfig::new()).is_empty());
+
+    let mut config = LintConfig::new();
+    config.set("magic-number", Level::Deny).unwrap();
+    let diagnostics = lint_source(input, &config);
+    assert_eq!(names(&diagnostics), vec!["magic-number"]);
+    assert_eq!(diagnostics[0].level, Level::Deny);
+    assert!(diagnostics[0].message.contains("60"));
+}
+
+#[test]
+fn test_allow_silences_lint() {
+    let input = "fn seppo() { x = 1\n return 0 }";
+    let mut config = LintConfig::new();
+    config.set("unused-variable", Level::Allow).unwrap();
+    assert!(lint_source(input, &config).is_empty());
+}
+
+#[test]
+fn test_deny_warnings() {
+    let input = "fn seppo() { x = 3\n y = x * 7\n return 0 }";
+    let mut config = LintConfig::new();
+    config.set("magic-number", Level::Deny).unwrap();
+    config.set_deny_warnings(true);
+    let diagnostics = lint_source(input, &config);
+    assert_eq!(names(&diagnostics), vec!["unused-variable", "magic-number"]);
+    assert!(diagnostics.iter().all(|d| d.level == Level::Deny));
+    // Only the warning was escalated, magic-number was denied already
+    assert!(diagnostics[0].escalated);
+    assert!(!diagnostics[1].escalated);
+
+    // Allowed lints stay silent
+    let mut config = LintConfig::new();
+    config
+        .apply_str("warnings = \"deny\"\nunused-variable = \"allow\"")
+        .unwrap();
+    assert!(lint_source(input, &config).is_empty());
+    assert!(matches!(
+        config.set("warnings", Level::Allow),
+        Err(SeppoError::Config(_))
+    ));
+}
+
+#[test]
+fn test_config_text() {
+    let mut config = LintConfig::new();
+    config
+        .apply_str("[lints]\n# everything is an error\nall = \"deny\"\nmagic-number = \"allow\"\n")
+        .unwrap();
+
+    let input = "fn seppo() { x = 3 + 4\n return 0 }";
+    let diagnostics = lint_source(input, &config);
+    assert_eq!(names(&diagnostics), vec!["unused-variable"]);
+    assert_eq!(diagnostics[0].level, Level::Deny);
+}
+
+#[test]
+fn test_unknown_lint_is_config_error() {
+    let mut config = LintConfig::new();
+    assert!(matches!(
+        config.set("no-such-lint", Level::Warn),
+        Err(SeppoError::Config(_))
+    ));
+    assert!(matches!(
+        config.apply_str("unused-variable = \"loud\""),
+        Err(SeppoError::Config(_))
+    ));
+}
@@ -0,0 +1,148 @@
+use seppolang::{format_source, interp, parse_seppo};
+
+#[test]
+fn test_for_over_string() {
+    let source = r#"
+        fn seppo() {
+            let mut sum = 0
+            for c in "abc" {
+                sum = sum + c
+            }
+            return sum
+        }
+    "#;
+    let program = parse_seppo(source).unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 97 + 98 + 99);
+}
+
+#[test]
+fn test_return_from_for() {
+    let source = r#"
+        fn seppo() {
+            s = "seppo"
+            for c in s {
+                seppo c == 112 {
+                    return c
+                }
+            }
+            return 0
+        }
+    "#;
+    let program = parse_seppo(source).unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 112);
+}
+
+#[test]
+fn test_for_keyword_boundary() {
+    // Identifiers starting with `for` are still assignments
+    let source = "fn seppo() {\n    format = 3\n    return format\n}";
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 3);
+}
+
+#[test]
+fn test_fmt_for_loop() {
+    assert_eq!(
+        format_source("fn seppo() { for c in \"ab\" { seppo c } }").unwrap(),
+        "fn seppo() {\n    for c in \"ab\" {\n        seppo c\n    }\n}\n"
+    );
+}
+
+#[test]
+fn test_for_over_range() {
+    let source = r#"
+        fn seppo() {
+            let mut sum = 0
+            for i in 0..5 {
+                sum = sum + i
+            }
+            n = 3
+            for i in 1..=n {
+                sum = sum + i
+            }
+            return sum
+        }
+    "#;
+    let program = parse_seppo(source).unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 10 + 6);
+}
+
+#[test]
+fn test_range_membership() {
+    let source = r#"
+        fn inside(x) {
+            seppo x in 0..10 {
+                return 1
+            }
+            return 0
+        }
+
+        fn seppo() {
+            a = inside(0)
+            b = inside(9)
+            c = inside(10)
+            d = inside(0 - 1)
+            e = a + b
+            f = c + d
+            g = e * 10
+            return g + f
+        }
+    "#;
+    let program = parse_seppo(source).unwrap();
+    assert_eq!(interp::eval(&program).unwrap(), 20);
+}
+
+#[test]
+fn test_range_outside_loop() {
+    let source = "fn seppo() {\n    r = 0..3\n    return 0\n}";
+    let err = interp::eval(&parse_seppo(source).unwrap()).unwrap_err();
+    assert!(
+        err.to_string().contains("for loops and `in` tests"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_fmt_ranges() {
+    assert_eq!(
+        format_source("fn seppo() { for i in 0 ..= 3 { seppo i in 1..3 { seppo i } } }").unwrap(),
+        "fn seppo() {\n    for i in 0..=3 {\n        seppo i in 1..3 {\n            seppo i\n        }\n    }\n}\n"
+    );
+}
+
+#[test]
+fn test_unroll_hint() {
+    let source = "fn seppo() {\n    let mut sum = 0\n    #[unroll(4)]\n    for i in 0..10 {\n        sum = sum + i\n    }\n    return sum\n}\n";
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 45);
+    assert_eq!(format_source(source).unwrap(), source);
+
+    for (source, message) in [
+        (
+            "fn seppo() {\n    #[unroll]\n    for i in 0..3 {}\n}",
+            "unroll takes how many times",
+        ),
+        (
+            "fn seppo() {\n    #[unroll(0)]\n    for i in 0..3 {}\n}",
+            "unroll takes how many times",
+        ),
+        (
+            "fn seppo() {\n    #[unroll(2)]\n    x = 1\n}",
+            "#[unroll] must be followed by a for loop",
+        ),
+        (
+            "fn seppo() {\n    #[unroll(2)]\n}",
+            "#[unroll] must be followed by a for loop",
+        ),
+        (
+            "fn seppo() {\n    #[constant_time]\n    for i in 0..3 {}\n}",
+            "#[constant_time] applies to functions",
+        ),
+        (
+            "#[unroll(2)]\nfn seppo() {\n    return 0\n}",
+            "#[unroll] applies to the for loop below it",
+        ),
+    ] {
+        let err = parse_seppo(source).unwrap_err();
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+}
@@ -0,0 +1,106 @@
+use seppolang::{format_source, interp, parse_seppo, SeppoError};
+
+fn sema_error(source: &str) -> String {
+    match parse_seppo(source) {
+        Err(SeppoError::Sema(message)) => message,
+        other => panic!("expected a semantic error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_macro_expands_in_place() {
+    let source = "
+        macro add_to(target, amount) {
+            target = target + amount
+        }
+
+        fn seppo() {
+            let mut x = 1
+            add_to(x, 2)
+            add_to(x, 3)
+            return x
+        }
+    ";
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 6);
+}
+
+#[test]
+fn test_macro_as_value() {
+    let source = "
+        macro double(n) { n + n }
+
+        fn seppo() {
+            x = double(21)
+            return x
+        }
+    ";
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 42);
+}
+
+#[test]
+fn test_macro_variables_are_hygienic() {
+    // The macro's own `x` must not overwrite the caller's
+    let source = "
+        macro clobber(n) {
+            x = n
+        }
+
+        fn seppo() {
+            x = 5
+            clobber(100)
+            return x
+        }
+    ";
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 5);
+}
+
+#[test]
+fn test_nested_macros() {
+    let source = "
+        macro inc(v) { v = v + 1 }
+        macro inc_twice(v) {
+            inc(v)
+            inc(v)
+        }
+
+        fn seppo() {
+            let mut x = 1
+            inc_twice(x)
+            return x
+        }
+    ";
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 3);
+}
+
+#[test]
+fn test_macro_errors() {
+    let message =
+        sema_error("macro forever(n) { forever(n) }\nfn seppo() { forever(1)\n return 0 }");
+    assert!(message.contains("Recursion limit"), "{}", message);
+    assert!(
+        message.contains("expanded from function `seppo`"),
+        "{}",
+        message
+    );
+
+    let message = sema_error("macro one(n) { n }\nfn seppo() { return one(1, 2) }");
+    assert!(
+        message.contains("Macro one expects 1 arguments, got 2 (in function `seppo`)"),
+        "{}",
+        message
+    );
+
+    let message = sema_error("macro set(n) { n = 1 }\nfn seppo() { set(3)\n return 0 }");
+    assert!(message.contains("must be a variable"), "{}", message);
+
+    let message = sema_error("macro two() { seppo 1\n seppo 2 }\nfn seppo() { return two() }");
+    assert!(message.contains("can't be used as a value"), "{}", message);
+}
+
+#[test]
+fn test_fmt_keeps_macro_definitions() {
+    assert_eq!(
+        format_source("macro double(n) { n + n }\nfn seppo() { return double(2) }").unwrap(),
+        "macro double(n) {\n    n + n\n}\n\nfn seppo() {\n    return double(2)\n}\n"
+    );
+}
@@ -0,0 +1,192 @@
+use seppolang::manifest::Manifest;
+use seppolang::{OptimizationLevel, Profile, SeppoError};
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_manifest_defaults() {
+    let manifest = Manifest::parse("[package]\nname = \"hello\"\n", Path::new("proj")).unwrap();
+    assert_eq!(manifest.name, "hello");
+    assert_eq!(manifest.sources, vec![PathBuf::from("proj/src/main.seppo")]);
+    assert_eq!(manifest.output, None);
+    assert_eq!(manifest.target_dir, PathBuf::from("proj/target"));
+    assert_eq!(manifest.opt_level, OptimizationLevel::Default);
+    assert!(!manifest.lto);
+    assert!(!manifest.strip);
+    assert!(manifest.post_link.is_empty());
+    assert!(manifest.target.is_native());
+
+    // Built into the target directory, named after the package
+    let driver = manifest.driver().unwrap();
+    assert_eq!(
+        driver.output_stem().unwrap(),
+        PathBuf::from("proj/target/seppo/default/hello")
+    );
+}
+
+#[test]
+fn test_manifest_build_settings() {
+    let text = r#"
+        [package]
+        name = "calc"
+
+        [build]
+        sources = ["main.seppo", "math.seppo"]
+        output = "bin/calc"
+        libraries = ["m"]
+        library-paths = ["lib"]
+        cflags = ["-DFAST"]
+        cc = "clang"
+        profile = "debug"
+        lto = true
+        strip = true
+        post-link = ["codesign -s -", "upx"]
+    "#;
+    let manifest = Manifest::parse(text, Path::new("proj")).unwrap();
+    assert_eq!(
+        manifest.sources,
+        vec![
+            PathBuf::from("proj/main.seppo"),
+            PathBuf::from("proj/math.seppo")
+        ]
+    );
+    assert_eq!(manifest.opt_level, OptimizationLevel::None);
+
+    let driver = manifest.driver().unwrap();
+    assert_eq!(driver.inputs.len(), 2);
+    assert_eq!(driver.output, Some(PathBuf::from("proj/bin/calc")));
+    assert_eq!(driver.linker_args, vec!["-Lproj/lib", "-lm"]);
+    // The debug profile's flags come first
+    assert_eq!(driver.c_flags, vec!["-O0", "-DFAST"]);
+    assert!(driver.debug_info && driver.overflow_checks);
+    assert_eq!(driver.c_compiler.as_deref(), Some("clang"));
+    assert!(driver.lto);
+    assert!(driver.strip);
+    assert_eq!(driver.post_link, vec!["codesign -s -", "upx"]);
+}
+
+#[test]
+fn test_manifest_release_profile() {
+    let text = "[package]\nname = \"fast\"\n[build]\nprofile = \"release\"\n";
+    let manifest = Manifest::parse(text, Path::new(".")).unwrap();
+    assert_eq!(manifest.profile, Some(Profile::Release));
+    assert_eq!(manifest.opt_level, OptimizationLevel::Aggressive);
+
+    let driver = manifest.driver().unwrap();
+    assert_eq!(driver.opt_level, OptimizationLevel::Aggressive);
+    assert_eq!(driver.c_flags, vec!["-O3"]);
+    assert!(driver.strip && !driver.dump_ir && !driver.emit.llvm_ir);
+    assert!(!driver.debug_info && !driver.overflow_checks);
+}
+
+#[test]
+fn test_manifest_errors() {
+    for text in [
+        "[build]\nsources = [\"a.seppo\"]\n",
+        "[package]\nname = 3\n",
+        "[package]\nname = \"x\"\n[build]\nprofile = \"fast\"\n",
+        "[package]\nname = \"x\"\n[build]\nsources = \"a.seppo\"\n",
+        "[package]\nname = \"x\"\n[build]\nlto = \"yes\"\n",
+        "[package]\nname = \"x\"\n[build]\nstrip = 1\n",
+        "[package]\nname = \"x\"\n[build]\npost-link = \"upx\"\n",
+        "not toml at all",
+    ] {
+        assert!(
+            matches!(
+                Manifest::parse(text, Path::new(".")),
+                Err(SeppoError::Config(_))
+            ),
+            "{}",
+            text
+        );
+    }
+}
+
+#[test]
+fn test_manifest_find_searches_parents() {
+    let root = std::env::temp_dir().join(format!("seppo_manifest_{}", std::process::id()));
+    let nested = root.join("src").join("deep");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(root.join("seppo.toml"), "[package]\nname = \"x\"\n").unwrap();
+
+    let found = Manifest::find(&nested);
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(found, Some(root.join("seppo.toml")));
+}
+
+/// Write a package with a single source file under `root/name`
+fn write_package(root: &Path, name: &str, manifest: &str) -> PathBuf {
+    let dir = root.join(name);
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("seppo.toml"), manifest).unwrap();
+    std::fs::write(
+        dir.join("src").join("lib.seppo"),
+        "fn helper() { return 1 }\n",
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn test_manifest_path_dependencies() {
+    let root = std::env::temp_dir().join(format!("seppo_deps_{}", std::process::id()));
+    write_package(
+        &root,
+        "base",
+        "[package]\nname = \"base\"\n[build]\nlibraries = [\"m\"]\n",
+    );
+    write_package(
+        &root,
+        "util",
+        "[package]\nname = \"util\"\n[dependencies]\nbase = { path = \"../base\" }\n",
+    );
+    let app = write_package(
+        &root,
+        "app",
+        "[package]\nname = \"app\"\n[build]\nsources = [\"main.seppo\"]\n\
+         [dependencies]\nutil = { path = \"../util\" }\nbase = { path = \"../base\" }\n",
+    );
+
+    let manifest = Manifest::from_file(&app.join("seppo.toml")).unwrap();
+    let names: Vec<String> = manifest
+        .resolve()
+        .unwrap()
+        .into_iter()
+        .map(|package| package.name)
+        .collect();
+    let driver = manifest.driver().unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(names, vec!["base", "util"]);
+    assert_eq!(driver.inputs.len(), 3);
+    assert_eq!(driver.linker_args, vec!["-lm"]);
+}
+
+#[test]
+fn test_manifest_dependency_errors() {
+    let root = std::env::temp_dir().join(format!("seppo_cycle_{}", std::process::id()));
+    write_package(
+        &root,
+        "a",
+        "[package]\nname = \"a\"\n[dependencies]\nb = { path = \"../b\" }\n",
+    );
+    write_package(
+        &root,
+        "b",
+        "[package]\nname = \"b\"\n[dependencies]\na = { path = \"../a\" }\n",
+    );
+    let cycle = Manifest::from_file(&root.join("a").join("seppo.toml"))
+        .unwrap()
+        .resolve();
+    std::fs::remove_dir_all(&root).unwrap();
+    assert!(
+        matches!(&cycle, Err(SeppoError::Config(message)) if message.contains("a -> b -> a")),
+        "{:?}",
+        cycle
+    );
+
+    let both = "[package]\nname = \"x\"\n[dependencies]\ny = { path = \"y\", git = \"z\" }\n";
+    assert!(matches!(
+        Manifest::parse(both, Path::new(".")),
+        Err(SeppoError::Config(_))
+    ));
+}
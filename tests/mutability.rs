@@ -0,0 +1,157 @@
+use seppolang::{format_source, interp, parse_seppo};
+
+fn sema_error(source: &str) -> String {
+    parse_seppo(source).unwrap_err().to_string()
+}
+
+#[test]
+fn test_let_and_let_mut() {
+    let source = r#"
+        fn seppo() {
+            let mut total = 1
+            let step = 2
+            total = total + step
+            total = total * step
+            return total
+        }
+    "#;
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 6);
+}
+
+#[test]
+fn test_assign_to_immutable() {
+    let err = sema_error("fn seppo() {\n    let x = 1\n    x = 2\n    return x\n}");
+    assert!(
+        err.contains("Cannot assign twice to immutable variable x in function seppo"),
+        "{}",
+        err
+    );
+    assert!(err.contains("let mut x"), "{}", err);
+
+    // Nested blocks see the declarations around them
+    let err = sema_error("fn seppo() {\n    let x = 1\n    seppo x > 0 {\n        x = 0\n    }\n}");
+    assert!(err.contains("immutable variable x"), "{}", err);
+
+    // Assigning a new name declares it immutable too
+    let err = sema_error("fn seppo() {\n    x = 1\n    x = 2\n    return x\n}");
+    assert!(err.contains("immutable variable x"), "{}", err);
+}
+
+#[test]
+fn test_parameters_can_be_reassigned() {
+    let source =
+        "fn twice(n) {\n    n = n * 2\n    return n\n}\n\nfn seppo() {\n    return twice(3)\n}\n";
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 6);
+}
+
+#[test]
+fn test_redeclaration_and_loop_variables() {
+    let err = sema_error("fn seppo() {\n    x = 1\n    let x = 2\n}");
+    assert!(
+        err.contains("Variable x is already declared in function seppo"),
+        "{}",
+        err
+    );
+
+    let err = sema_error("fn seppo() {\n    for c in \"ab\" {\n        c = 0\n    }\n}");
+    assert!(err.contains("Cannot assign to loop variable c"), "{}", err);
+}
+
+#[test]
+fn test_let_scoped_to_branch() {
+    let source = r#"
+        fn pick(a) {
+            seppo a > 0 {
+                let t = 1
+                return t
+            } perkele {
+                let t = 2
+                return t
+            }
+        }
+
+        fn seppo() {
+            let t = pick(0)
+            return t
+        }
+    "#;
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 2);
+}
+
+#[test]
+fn test_macro_assigns_to_immutable() {
+    let source = r#"
+        macro bump(target) {
+            target = target + 1
+        }
+
+        fn seppo() {
+            let x = 1
+            bump(x)
+            return x
+        }
+    "#;
+    assert!(sema_error(source).contains("immutable variable x"));
+}
+
+#[test]
+fn test_fmt_let() {
+    assert_eq!(
+        format_source("fn seppo() { let   mut x = 1; let y = x; letter = y }").unwrap(),
+        "fn seppo() {\n    let mut x = 1\n    let y = x\n    letter = y\n}\n"
+    );
+}
+
+#[test]
+fn test_globals() {
+    let source = r#"
+        let mut total = 1
+        let step = 2
+
+        fn add() {
+            total = total + step
+        }
+
+        fn seppo() {
+            add()
+            add()
+            return total
+        }
+    "#;
+    assert_eq!(interp::eval(&parse_seppo(source).unwrap()).unwrap(), 5);
+
+    let err = sema_error("let x = 1\n\nfn seppo() {\n    x = 2\n    return x\n}\n");
+    assert!(
+        err.contains("Cannot assign twice to immutable variable x in function seppo"),
+        "{}",
+        err
+    );
+    assert_eq!(
+        sema_error("let x = 1\n\nfn seppo(x) {\n    return x\n}\n"),
+        "Parameter x of function seppo has the name of a global"
+    );
+    assert_eq!(
+        sema_error(
+            "let mut x = 1\n\nfn seppo() {\n    for x in 0..2 {\n        seppo x\n    }\n}\n"
+        ),
+        "Loop variable x in function seppo has the name of a global"
+    );
+    assert_eq!(
+        sema_error("let x = 1\nlet x = 2\n\nfn seppo() {\n    return x\n}\n"),
+        "Global x is defined twice, at 1:5 and 2:5"
+    );
+    assert!(sema_error("let x = y\n\nfn seppo() {\n    return x\n}\n")
+        .contains("Global x has to start as a number, e.g. `let x = 0`"));
+    assert!(
+        sema_error("#[cold]\nlet x = 1\n\nfn seppo() {\n    return x\n}\n")
+            .contains("#[cold] applies to the function below it, not to globals")
+    );
+}
+
+#[test]
+fn test_fmt_globals() {
+    assert_eq!(
+        format_source("let   mut a = 1\nthreadlocal\nlet b = 2\nfn seppo() { return a }").unwrap(),
+        "let mut a = 1\nthreadlocal let b = 2\n\nfn seppo() {\n    return a\n}\n"
+    );
+}
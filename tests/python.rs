@@ -0,0 +1,30 @@
+#![cfg(feature = "python")]
+
+use pyo3::prelude::*;
+use seppolang::python::{self, SeppoError};
+
+const PROGRAM: &str =
+    "fn double(x) {\n    return x * 2\n}\n\nfn seppo() {\n    return double(21)\n}\n";
+
+#[test]
+fn test_python_parse_and_interpret() -> PyResult<()> {
+    let program = python::parse(PROGRAM)?;
+    assert_eq!(program.functions(), ["double", "seppo"]);
+    assert_eq!(program.__str__()?, PROGRAM);
+    assert_eq!(python::interpret(PROGRAM)?, 42);
+    Ok(())
+}
+
+#[test]
+fn test_python_errors_carry_diagnostics() {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let error = python::interpret("fn seppo() {\n    return x\n}\n").unwrap_err();
+        assert!(error.is_instance_of::<SeppoError>(py));
+        let message = error.value(py).to_string();
+        assert!(message.starts_with("error[E0002]"), "{}", message);
+
+        let error = python::compile(PROGRAM, "out".into(), "wasm", 2).unwrap_err();
+        assert!(error.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+    });
+}
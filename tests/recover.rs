@@ -0,0 +1,54 @@
+use seppolang::{parse_lenient, SeppoError, SeppoExpr};
+
+fn function_names(program: &SeppoExpr) -> Vec<&str> {
+    let SeppoExpr::Block(items) = program else {
+        panic!("expected a block, got {:?}", program);
+    };
+    items
+        .iter()
+        .filter_map(|item| match item {
+            SeppoExpr::Function(name, ..) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_lenient_parse_keeps_the_items_around_an_error() {
+    let input = "fn one() {\n    return 1\n}\n\nfn broken() {\n    x = @\n}\n\n## Two\nfn two() {\n    return 2\n}\n";
+    let recovered = parse_lenient(input);
+    assert_eq!(function_names(&recovered.program), vec!["one", "two"]);
+    let SeppoExpr::Block(items) = &recovered.program else {
+        unreachable!()
+    };
+    // The doc comment stays with its function
+    assert!(items.contains(&SeppoExpr::DocComment("Two".to_string())));
+
+    let [SeppoError::Parse { line, column, .. }] = recovered.errors.as_slice() else {
+        panic!("expected one parse error, got {:?}", recovered.errors);
+    };
+    assert_eq!((*line, *column), (6, 9));
+}
+
+#[test]
+fn test_lenient_parse_closes_an_unfinished_function() {
+    let input =
+        "fn done() {\n    return 1\n}\n\nfn seppo() {\n    for i in 0..3 {\n        print(i)\n";
+    let recovered = parse_lenient(input);
+    assert_eq!(function_names(&recovered.program), vec!["done", "seppo"]);
+    assert_eq!(recovered.errors.len(), 1);
+    assert!(
+        recovered.errors[0]
+            .to_string()
+            .contains("unexpected end of input"),
+        "{}",
+        recovered.errors[0]
+    );
+}
+
+#[test]
+fn test_lenient_parse_of_valid_source_has_no_errors() {
+    let recovered = parse_lenient("fn seppo() {\n    return 0\n}\n");
+    assert!(recovered.errors.is_empty());
+    assert_eq!(function_names(&recovered.program), vec!["seppo"]);
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use seppolang::interp::Value;
+use seppolang::repl::Session;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Collects session output so tests can assert on it
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_repl_keeps_variables_between_inputs() -> Result<()> {
+    let buffer = SharedBuffer::default();
+    let mut session = Session::with_output(Box::new(buffer.clone()));
+    assert_eq!(session.eval("fn double(x) {\n    return x * 2\n}")?, None);
+    assert_eq!(session.eval("x = 21")?, None);
+    assert_eq!(session.eval("double(x)")?, Some(Value::Int(42)));
+    assert_eq!(session.eval("y = double(x)\nseppo y")?, None);
+    assert_eq!(String::from_utf8(buffer.0.borrow().clone())?, "42\n");
+
+    // Redefining a function replaces it
+    session.eval("fn double(x) {\n    return x + x\n}")?;
+    assert_eq!(session.eval("double(y)")?, Some(Value::Int(84)));
+    assert!(session.eval("missing").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_repl_session_round_trips() -> Result<()> {
+    let mut session = Session::new();
+    session.eval("## Doubles a number\nfn double(x) {\n    return x * 2\n}")?;
+    session.eval("macro twice(v) {\n    v * 2\n}")?;
+    session.eval("n = 0 - 5")?;
+    session.eval("let mut min = 0 - 9223372036854775807\nmin = min - 1")?;
+    session.eval("greeting = \"hei\"")?;
+    session.eval("result = Some(0 - 3)\nslot = None")?;
+    session.eval("vector = i64x4(1, 0 - 2, 3, 4)")?;
+    let snapshot = session.snapshot()?;
+    assert!(snapshot.contains("## Doubles a number\nfn double(x) {"));
+    assert!(snapshot.contains("fn repl_session() {\n    greeting = \"hei\"\n"));
+    assert!(snapshot.contains("    n = 0 - 5\n"));
+    assert!(snapshot.contains("    result = Some(0 - 3)\n"));
+    assert!(snapshot.contains("    vector = i64x4(1, 0 - 2, 3, 4)\n"));
+
+    let path = std::env::temp_dir().join(format!("repl_session_{}.seppo", std::process::id()));
+    session.save(&path)?;
+    let mut restored = Session::new();
+    restored.load(&path)?;
+    std::fs::remove_file(&path)?;
+
+    assert_eq!(restored.eval("double(n)")?, Some(Value::Int(-10)));
+    assert_eq!(restored.eval("min")?, Some(Value::Int(i64::MIN)));
+    assert_eq!(restored.eval("greeting")?, Some(Value::Str("hei".into())));
+    assert_eq!(restored.eval("unwrap(result)")?, Some(Value::Int(-3)));
+    assert_eq!(restored.eval("slot")?.unwrap().to_string(), "None");
+    assert_eq!(restored.eval("vector")?, Some(Value::Vector([1, -2, 3, 4])));
+    assert_eq!(restored.interpreter().function_names(), ["double"]);
+    // Saving again gives the same file
+    assert_eq!(restored.snapshot()?, snapshot);
+    Ok(())
+}
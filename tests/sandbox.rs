@@ -0,0 +1,106 @@
+use anyhow::Result;
+use inkwell::context::Context;
+use seppolang::sandbox::{self, with_no_extern};
+use seppolang::{
+    format_source, interp, parse_seppo, CodeGen, Driver, Input, OutputKind, SeppoError, SeppoExpr,
+};
+
+const WITH_CEPPO: &str =
+    "ceppo {\n    int64_t one(void) { return 1; }\n}\n\nfn seppo() {\n    return one()\n}\n";
+
+#[test]
+fn test_ceppo_rejected_at_parse() {
+    assert!(parse_seppo(WITH_CEPPO).is_ok());
+    match with_no_extern(|| parse_seppo(WITH_CEPPO)) {
+        Err(SeppoError::Parse { message, line, .. }) => {
+            assert_eq!(message, "ceppo blocks are not allowed with --no-extern");
+            assert_eq!(line, 1);
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    assert!(!sandbox::no_extern());
+
+    // Formatting does not run anything
+    assert!(with_no_extern(|| format_source(WITH_CEPPO)).is_ok());
+}
+
+#[test]
+fn test_include_str_rejected() {
+    let source = "fn seppo() {\n    return len(include_str(\"/etc/passwd\"))\n}\n";
+    match with_no_extern(|| parse_seppo(source)) {
+        Err(SeppoError::Sema(message)) => {
+            assert_eq!(message, "include_str is not allowed with --no-extern")
+        }
+        other => panic!("expected a semantic error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_ceppo_rejected_at_codegen() {
+    // An AST that never went through the parser
+    let program = SeppoExpr::Block(vec![
+        SeppoExpr::InlineC("int64_t one(void) { return 1; }".to_string()),
+        SeppoExpr::Function(
+            "seppo".to_string(),
+            Vec::new(),
+            Box::new(SeppoExpr::Return(Box::new(SeppoExpr::Number(0)))),
+        ),
+    ]);
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "sandbox");
+    assert!(matches!(
+        with_no_extern(|| codegen.compile(&program)),
+        Err(SeppoError::Codegen(_))
+    ));
+}
+
+#[test]
+fn test_dynamic_loading_rejected() {
+    let source = "fn seppo() {\n    handle = dlopen(0)\n    system = dlsym(handle, \"system\")\n    return call(system, \"true\")\n}\n";
+    let program = parse_seppo(source).unwrap();
+    let context = Context::create();
+    let mut codegen = CodeGen::new(&context, "sandbox");
+    match with_no_extern(|| codegen.compile(&program)) {
+        Err(SeppoError::Codegen(message)) => {
+            assert_eq!(message, "dlopen is not allowed with --no-extern")
+        }
+        other => panic!("expected a codegen error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_driver_refuses_linking() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_sandbox_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: "fn seppo() {\n    return 3\n}\n".to_string(),
+    }]);
+    driver.output = Some(dir.join("main"));
+    driver.no_extern = true;
+
+    match driver.run() {
+        Err(SeppoError::Config(message)) => {
+            assert_eq!(
+                message,
+                "Linking runs `cc`, which --no-extern does not allow"
+            )
+        }
+        other => panic!("expected a config error, got {:?}", other),
+    }
+
+    // Emitting IR needs no other program
+    driver.output_kind = OutputKind::LlvmIr;
+    assert!(driver.run()?.exists());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_interpreter_runs_in_sandbox() {
+    let result = with_no_extern(|| {
+        let program = parse_seppo("fn seppo() {\n    x = 6\n    return x * 7\n}\n")?;
+        interp::eval(&program)
+    });
+    assert_eq!(result.unwrap(), 42);
+}
@@ -0,0 +1,50 @@
+use seppolang::{format_source, interp, parse_seppo};
+
+#[test]
+fn test_become_does_not_use_call_depth() {
+    // Far deeper than the interpreter's call depth limit
+    let source = r#"
+        fn sum(n, acc) {
+            seppo n == 0 {
+                return acc
+            }
+            become sum(n - 1, acc + n)
+        }
+
+        fn seppo() {
+            let total = sum(100000, 0)
+            return total
+        }
+    "#;
+    assert_eq!(
+        interp::eval(&parse_seppo(source).unwrap()).unwrap(),
+        5000050000
+    );
+}
+
+#[test]
+fn test_become_errors() {
+    let source = "fn f(n) {\n    return n\n}\n\nfn seppo() {\n    become f(1)\n}";
+    let err = parse_seppo(source).unwrap_err().to_string();
+    assert!(
+        err.contains("become can only call the function it is in: seppo calls f"),
+        "{}",
+        err
+    );
+
+    let source = "fn f(n) {\n    become f(n, 1)\n}\n\nfn seppo() {\n    return 0\n}";
+    let err = parse_seppo(source).unwrap_err().to_string();
+    assert!(
+        err.contains("Function f expects 1 arguments, got 2"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_fmt_become() {
+    assert_eq!(
+        format_source("fn f(n) { become   f(n) }").unwrap(),
+        "fn f(n) {\n    become f(n)\n}\n"
+    );
+}
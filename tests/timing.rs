@@ -0,0 +1,57 @@
+use anyhow::Result;
+use seppolang::timing::{self, Phase};
+use seppolang::{parse_seppo, Driver, Input, OutputKind};
+use std::time::Duration;
+
+#[test]
+fn test_measure_parse_and_sema() -> Result<()> {
+    let (program, times) = timing::measure(|| parse_seppo("fn seppo() { return 1 + 2 }"));
+    program?;
+    assert!(times.get(Phase::Parse) > Duration::ZERO);
+    assert!(times.get(Phase::Sema) > Duration::ZERO);
+    assert_eq!(times.get(Phase::Codegen), Duration::ZERO);
+    assert_eq!(
+        times.total(),
+        times.get(Phase::Parse) + times.get(Phase::Sema)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_nested_phases_count_once() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("seppolang_timing_{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let mut driver = Driver::new(vec![Input::Source {
+        name: "main".to_string(),
+        text: "ceppo {\n    int64_t one() { return 1; }\n}\n\nfn seppo() {\n    return one()\n}"
+            .to_string(),
+    }]);
+    driver.output = Some(dir.join("main"));
+    driver.output_kind = OutputKind::LlvmIr;
+
+    let start = std::time::Instant::now();
+    let (result, times) = timing::measure(|| driver.run());
+    let elapsed = start.elapsed();
+    result?;
+    std::fs::remove_dir_all(&dir)?;
+
+    // The C compiler runs during codegen but is reported on its own
+    assert!(times.get(Phase::Ceppo) > Duration::ZERO);
+    assert!(times.get(Phase::Codegen) > Duration::ZERO);
+    assert!(times.total() <= elapsed);
+    Ok(())
+}
+
+#[test]
+fn test_report_formats() {
+    let ((), times) = timing::measure(|| ());
+    let table = times.to_table();
+    for phase in Phase::ALL {
+        assert!(table.contains(phase.name()), "{}", table);
+    }
+    assert!(table.ends_with("total         0.000ms\n"), "{}", table);
+    assert_eq!(
+        times.to_json(),
+        r#"{"parse": 0, "sema": 0, "codegen": 0, "ceppo": 0, "llvm": 0, "link": 0, "total": 0}"#
+    );
+}